@@ -0,0 +1,174 @@
+//! The chain backend lnode and soul sync against, picked at runtime from
+//! `~/.bdk-example/chain_backend.json` and falling back to the local bitcoind.
+
+use bdk::bitcoin::Network;
+use bdk::blockchain::any::{AnyBlockchain, AnyBlockchainConfig};
+use bdk::blockchain::electrum::ElectrumBlockchainConfig;
+use bdk::blockchain::rpc::{Auth, RpcConfig};
+use bdk::blockchain::ConfigurableBlockchain;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const STOP_GAP: usize = 20;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChainBackendConfig {
+    /// bitcoind JSON-RPC, `url` being `host:port`.
+    Rpc {
+        url: String,
+        username: String,
+        password: String,
+    },
+    /// An Esplora HTTP API such as electrs, e.g. `http://127.0.0.1:3002`.
+    Esplora { url: String },
+    /// An Electrum server, e.g. `tcp://127.0.0.1:50001`.
+    Electrum { url: String },
+    /// BIP157 compact block filters fetched from the given peers, e.g. `127.0.0.1:18444`. They
+    /// only sync the on-chain wallets.
+    Cbf { peers: Vec<String> },
+}
+
+impl Default for ChainBackendConfig {
+    fn default() -> Self {
+        ChainBackendConfig::Rpc {
+            url: "127.0.0.1:18443".to_string(),
+            username: "admin".to_string(),
+            password: "password".to_string(),
+        }
+    }
+}
+
+impl ChainBackendConfig {
+    pub fn path() -> PathBuf {
+        let mut path = dirs_next::home_dir().unwrap();
+        path.push(".bdk-example");
+        path.push("chain_backend.json");
+        path
+    }
+
+    /// Reads the configured backend, using bitcoind at 18443 when nothing is configured.
+    pub fn load() -> Result<Self, String> {
+        match fs::read_to_string(Self::path()) {
+            Ok(config) => Self::parse(&config),
+            Err(_) => Ok(ChainBackendConfig::default()),
+        }
+    }
+
+    /// Parses a backend this build can sync the on-chain wallets with.
+    fn parse(config: &str) -> Result<Self, String> {
+        let config: Self = serde_json::from_str(config)
+            .map_err(|e| format!("invalid chain backend config: {}", e))?;
+        let feature = match config {
+            ChainBackendConfig::Esplora { .. } if !cfg!(feature = "esplora") => "esplora",
+            ChainBackendConfig::Cbf { .. } if !cfg!(feature = "cbf") => "cbf",
+            _ => return Ok(config),
+        };
+        Err(format!(
+            "built without the {} feature, the on-chain wallet can't sync with {:?}",
+            feature, config
+        ))
+    }
+
+    pub fn persist(&self) -> io::Result<()> {
+        let path = Self::path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Builds the blockchain the BDK wallet `wallet_name` syncs with.
+    pub fn wallet_blockchain(
+        &self,
+        wallet_name: &str,
+        network: Network,
+    ) -> Result<AnyBlockchain, bdk::Error> {
+        let config = match self {
+            ChainBackendConfig::Rpc {
+                url,
+                username,
+                password,
+            } => AnyBlockchainConfig::Rpc(RpcConfig {
+                url: format!("http://{}", url),
+                auth: Auth::UserPass {
+                    username: username.clone(),
+                    password: password.clone(),
+                },
+                network,
+                wallet_name: wallet_name.to_string(),
+                sync_params: None,
+            }),
+            ChainBackendConfig::Electrum { url } => {
+                AnyBlockchainConfig::Electrum(ElectrumBlockchainConfig {
+                    url: url.clone(),
+                    socks5: None,
+                    retry: 3,
+                    timeout: Some(30),
+                    stop_gap: STOP_GAP,
+                    validate_domain: true,
+                })
+            }
+            #[cfg(feature = "esplora")]
+            ChainBackendConfig::Esplora { url } => AnyBlockchainConfig::Esplora(
+                bdk::blockchain::esplora::EsploraBlockchainConfig::new(url.clone(), STOP_GAP),
+            ),
+            #[cfg(feature = "cbf")]
+            ChainBackendConfig::Cbf { peers } => {
+                use bdk::blockchain::compact_filters::{
+                    BitcoinPeerConfig, CompactFiltersBlockchainConfig,
+                };
+                let mut storage_dir = dirs_next::home_dir().unwrap();
+                storage_dir.push(".bdk-example");
+                storage_dir.push("cbf");
+                storage_dir.push(wallet_name);
+                AnyBlockchainConfig::CompactFilters(CompactFiltersBlockchainConfig {
+                    peers: peers
+                        .iter()
+                        .map(|address| BitcoinPeerConfig {
+                            address: address.clone(),
+                            socks5: None,
+                            socks5_credentials: None,
+                        })
+                        .collect(),
+                    network,
+                    storage_dir: storage_dir.to_string_lossy().to_string(),
+                    skip_blocks: None,
+                })
+            }
+            #[allow(unreachable_patterns)]
+            backend => {
+                return Err(bdk::Error::Generic(format!(
+                    "built without support for {:?}",
+                    backend
+                )))
+            }
+        };
+        AnyBlockchain::from_config(&config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_backends_this_build_can_sync_with_parse() {
+        assert_eq!(
+            ChainBackendConfig::parse(r#"{"type": "electrum", "url": "tcp://127.0.0.1:50001"}"#),
+            Ok(ChainBackendConfig::Electrum {
+                url: "tcp://127.0.0.1:50001".to_string()
+            })
+        );
+        assert_eq!(
+            ChainBackendConfig::parse(r#"{"type": "esplora", "url": "http://127.0.0.1:3002"}"#)
+                .is_ok(),
+            cfg!(feature = "esplora")
+        );
+        assert_eq!(
+            ChainBackendConfig::parse(r#"{"type": "cbf", "peers": ["127.0.0.1:18444"]}"#).is_ok(),
+            cfg!(feature = "cbf")
+        );
+        assert!(ChainBackendConfig::parse("{").is_err());
+    }
+}
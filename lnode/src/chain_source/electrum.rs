@@ -0,0 +1,192 @@
+use super::ChainClient;
+use bdk::electrum_client::{Client, ElectrumApi, Param};
+use bitcoin::{BlockHash, BlockHeader, OutPoint, Script, Transaction, Txid};
+use std::io;
+use std::str::FromStr;
+
+pub struct ElectrumClient {
+    client: Client,
+}
+
+fn electrum_error(e: bdk::electrum_client::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+impl ElectrumClient {
+    pub fn new(url: &str) -> io::Result<Self> {
+        Ok(ElectrumClient {
+            client: Client::new(url).map_err(electrum_error)?,
+        })
+    }
+
+    /// Confirmation height of `txid` among the history of `script_pubkey`.
+    fn confirmation_height(&self, txid: &Txid, script_pubkey: &Script) -> io::Result<Option<u32>> {
+        let history = self
+            .client
+            .script_get_history(script_pubkey)
+            .map_err(electrum_error)?;
+        Ok(history
+            .iter()
+            .find(|h| h.tx_hash == *txid && h.height > 0)
+            .map(|h| h.height as u32))
+    }
+}
+
+impl ChainClient for ElectrumClient {
+    fn tip(&self) -> io::Result<(BlockHeader, u32)> {
+        let notification = self
+            .client
+            .block_headers_subscribe()
+            .map_err(electrum_error)?;
+        Ok((notification.header, notification.height as u32))
+    }
+
+    fn header(&self, height: u32) -> io::Result<BlockHeader> {
+        self.client
+            .block_header(height as usize)
+            .map_err(electrum_error)
+    }
+
+    fn tx(&self, txid: &Txid) -> io::Result<Option<Transaction>> {
+        // Electrum servers answer unknown transactions with an error rather than a null.
+        Ok(self.client.transaction_get(txid).ok())
+    }
+
+    fn tx_status(&self, txid: &Txid) -> io::Result<Option<(u32, BlockHash)>> {
+        // Electrum indexes by script, so look the transaction up through one of its outputs.
+        let tx = match self.tx(txid)? {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let script_pubkey = match tx.output.first() {
+            Some(output) => &output.script_pubkey,
+            None => return Ok(None),
+        };
+        match self.confirmation_height(txid, script_pubkey)? {
+            Some(height) => Ok(Some((height, self.header(height)?.block_hash()))),
+            None => Ok(None),
+        }
+    }
+
+    fn merkle_pos(&self, txid: &Txid, height: u32) -> io::Result<usize> {
+        self.client
+            .transaction_get_merkle(txid, height as usize)
+            .map(|merkle| merkle.pos)
+            .map_err(electrum_error)
+    }
+
+    fn txid_at(&self, height: u32, tx_index: usize) -> io::Result<Option<Txid>> {
+        // Like unknown transactions, positions past the end of a block come back as errors.
+        let txid = match self.client.raw_call(
+            "blockchain.transaction.id_from_pos",
+            vec![Param::Usize(height as usize), Param::Usize(tx_index)],
+        ) {
+            Ok(txid) => txid,
+            Err(_) => return Ok(None),
+        };
+        match txid.as_str().map(Txid::from_str) {
+            Some(Ok(txid)) => Ok(Some(txid)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid txid {}", txid),
+            )),
+        }
+    }
+
+    fn output_spender(
+        &self,
+        outpoint: &OutPoint,
+        script_pubkey: &Script,
+    ) -> io::Result<Option<Txid>> {
+        let history = self
+            .client
+            .script_get_history(script_pubkey)
+            .map_err(electrum_error)?;
+        for entry in history.iter().filter(|h| h.tx_hash != outpoint.txid) {
+            let tx = self
+                .client
+                .transaction_get(&entry.tx_hash)
+                .map_err(electrum_error)?;
+            if tx.input.iter().any(|i| i.previous_output == *outpoint) {
+                return Ok(Some(entry.tx_hash));
+            }
+        }
+        Ok(None)
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> io::Result<()> {
+        self.client
+            .transaction_broadcast(tx)
+            .map(|_| ())
+            .map_err(electrum_error)
+    }
+
+    fn estimate_fee(&self, target_blocks: usize) -> io::Result<Option<f64>> {
+        // Electrum reports BTC/kvB, or -1 when it has no estimate.
+        let btc_per_kvb = self
+            .client
+            .estimate_fee(target_blocks)
+            .map_err(electrum_error)?;
+        if btc_per_kvb <= 0.0 {
+            return Ok(None);
+        }
+        Ok(Some(btc_per_kvb * 100_000_000.0 / 1000.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_source::test_utils::StandInServer;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::consensus::encode;
+    use bitcoin::Network;
+
+    #[test]
+    fn electrum_against_stand_in() {
+        let genesis = genesis_block(Network::Regtest);
+        let tx = &genesis.txdata[0];
+        let txid = tx.txid();
+        let header_hex = encode::serialize_hex(&genesis.header);
+        let server = StandInServer::electrum(vec![
+            (
+                "blockchain.headers.subscribe".to_string(),
+                format!("{{\"height\":0,\"hex\":\"{}\"}}", header_hex),
+            ),
+            (
+                "blockchain.block.header".to_string(),
+                format!("\"{}\"", header_hex),
+            ),
+            (
+                "blockchain.transaction.get".to_string(),
+                format!("\"{}\"", encode::serialize_hex(tx)),
+            ),
+            (
+                "blockchain.scripthash.get_history".to_string(),
+                format!("[{{\"height\":1,\"tx_hash\":\"{}\"}}]", txid),
+            ),
+            (
+                "blockchain.transaction.get_merkle".to_string(),
+                "{\"block_height\":1,\"pos\":3,\"merkle\":[]}".to_string(),
+            ),
+            ("blockchain.estimatefee".to_string(), "0.0001".to_string()),
+            (
+                "blockchain.transaction.id_from_pos".to_string(),
+                format!("\"{}\"", txid),
+            ),
+        ]);
+        let client = ElectrumClient::new(&server.tcp_url()).unwrap();
+
+        let (header, height) = client.tip().unwrap();
+        assert_eq!(header, genesis.header);
+        assert_eq!(height, 0);
+        assert_eq!(client.tx(&txid).unwrap(), Some(tx.clone()));
+        assert_eq!(
+            client.tx_status(&txid).unwrap(),
+            Some((1, genesis.block_hash()))
+        );
+        assert_eq!(client.merkle_pos(&txid, 1).unwrap(), 3);
+        assert_eq!(client.txid_at(1, 3).unwrap(), Some(txid));
+        assert_eq!(client.estimate_fee(6).unwrap(), Some(10.0));
+    }
+}
@@ -0,0 +1,221 @@
+use super::http::HttpClient;
+use super::ChainClient;
+use bitcoin::consensus::encode;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{BlockHash, BlockHeader, OutPoint, Script, Transaction, Txid};
+use std::io;
+use std::str::FromStr;
+
+pub struct EsploraClient {
+    http: HttpClient,
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, path.to_string())
+}
+
+fn decode_hex<T: encode::Decodable>(hex: &str) -> io::Result<T> {
+    let bytes = Vec::<u8>::from_hex(hex).map_err(invalid_data)?;
+    encode::deserialize(&bytes).map_err(invalid_data)
+}
+
+impl EsploraClient {
+    pub fn new(url: &str) -> io::Result<Self> {
+        Ok(EsploraClient {
+            http: HttpClient::new(url)?,
+        })
+    }
+
+    fn header_by_hash(&self, hash: &str) -> io::Result<BlockHeader> {
+        let path = format!("/block/{}/header", hash);
+        let hex = self
+            .http
+            .get_string(&path)?
+            .ok_or_else(|| not_found(&path))?;
+        decode_hex(&hex)
+    }
+}
+
+impl ChainClient for EsploraClient {
+    fn tip(&self) -> io::Result<(BlockHeader, u32)> {
+        let hash = self
+            .http
+            .get_string("/blocks/tip/hash")?
+            .ok_or_else(|| not_found("/blocks/tip/hash"))?;
+        let path = format!("/block/{}", hash);
+        let block = self.http.get_json(&path)?.ok_or_else(|| not_found(&path))?;
+        let height = block["height"]
+            .as_u64()
+            .ok_or_else(|| invalid_data("Block without height"))?;
+        Ok((self.header_by_hash(&hash)?, height as u32))
+    }
+
+    fn header(&self, height: u32) -> io::Result<BlockHeader> {
+        let path = format!("/block-height/{}", height);
+        let hash = self
+            .http
+            .get_string(&path)?
+            .ok_or_else(|| not_found(&path))?;
+        self.header_by_hash(&hash)
+    }
+
+    fn tx(&self, txid: &Txid) -> io::Result<Option<Transaction>> {
+        match self.http.get_string(&format!("/tx/{}/hex", txid))? {
+            Some(hex) => decode_hex(&hex).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn tx_status(&self, txid: &Txid) -> io::Result<Option<(u32, BlockHash)>> {
+        let status = match self.http.get_json(&format!("/tx/{}/status", txid))? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        if !status["confirmed"].as_bool().unwrap_or(false) {
+            return Ok(None);
+        }
+        let height = status["block_height"]
+            .as_u64()
+            .ok_or_else(|| invalid_data("Confirmed status without height"))?;
+        let hash = status["block_hash"]
+            .as_str()
+            .ok_or_else(|| invalid_data("Confirmed status without block hash"))?;
+        Ok(Some((
+            height as u32,
+            BlockHash::from_str(hash).map_err(invalid_data)?,
+        )))
+    }
+
+    fn merkle_pos(&self, txid: &Txid, _height: u32) -> io::Result<usize> {
+        let path = format!("/tx/{}/merkle-proof", txid);
+        let proof = self.http.get_json(&path)?.ok_or_else(|| not_found(&path))?;
+        proof["pos"]
+            .as_u64()
+            .map(|pos| pos as usize)
+            .ok_or_else(|| invalid_data("Merkle proof without position"))
+    }
+
+    fn txid_at(&self, height: u32, tx_index: usize) -> io::Result<Option<Txid>> {
+        let hash = match self.http.get_string(&format!("/block-height/{}", height))? {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+        match self
+            .http
+            .get_string(&format!("/block/{}/txid/{}", hash, tx_index))?
+        {
+            Some(txid) => Ok(Some(Txid::from_str(txid.trim()).map_err(invalid_data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn output_spender(
+        &self,
+        outpoint: &OutPoint,
+        _script_pubkey: &Script,
+    ) -> io::Result<Option<Txid>> {
+        let path = format!("/tx/{}/outspend/{}", outpoint.txid, outpoint.vout);
+        let outspend = match self.http.get_json(&path)? {
+            Some(outspend) => outspend,
+            None => return Ok(None),
+        };
+        if !outspend["spent"].as_bool().unwrap_or(false) {
+            return Ok(None);
+        }
+        match outspend["txid"].as_str() {
+            Some(txid) => Ok(Some(Txid::from_str(txid).map_err(invalid_data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> io::Result<()> {
+        self.http
+            .post("/tx", encode::serialize_hex(tx).as_bytes())
+            .map(|_| ())
+    }
+
+    fn estimate_fee(&self, target_blocks: usize) -> io::Result<Option<f64>> {
+        let estimates = match self.http.get_json("/fee-estimates")? {
+            Some(serde_json::Value::Object(estimates)) => estimates,
+            _ => return Ok(None),
+        };
+        // Use the closest target that is at least as fast as the one asked for.
+        let mut best: Option<(usize, f64)> = None;
+        for (target, rate) in estimates.iter() {
+            if let (Ok(target), Some(rate)) = (target.parse::<usize>(), rate.as_f64()) {
+                if target <= target_blocks && best.is_none_or(|(t, _)| target > t) {
+                    best = Some((target, rate));
+                }
+            }
+        }
+        Ok(best.map(|(_, rate)| rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_source::test_utils::StandInServer;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::Network;
+
+    #[test]
+    fn esplora_against_stand_in() {
+        let genesis = genesis_block(Network::Regtest);
+        let hash = genesis.block_hash().to_string();
+        let txid = genesis.txdata[0].txid();
+        let header_hex = encode::serialize_hex(&genesis.header);
+        let tx_hex = encode::serialize_hex(&genesis.txdata[0]);
+        let server = StandInServer::http(vec![
+            ("GET /blocks/tip/hash".to_string(), hash.clone()),
+            (format!("GET /block/{}/header", hash), header_hex),
+            (format!("GET /block/{}", hash), "{\"height\":0}".to_string()),
+            (format!("GET /tx/{}/hex", txid), tx_hex),
+            ("GET /block-height/0".to_string(), hash.clone()),
+            (format!("GET /block/{}/txid/0", hash), txid.to_string()),
+            (
+                format!("GET /tx/{}/status", txid),
+                format!(
+                    "{{\"confirmed\":true,\"block_height\":0,\"block_hash\":\"{}\"}}",
+                    hash
+                ),
+            ),
+            (
+                format!("GET /tx/{}/outspend/0", txid),
+                "{\"spent\":false}".to_string(),
+            ),
+            (
+                "GET /fee-estimates".to_string(),
+                "{\"1\":20.5,\"6\":10.0,\"144\":1.0}".to_string(),
+            ),
+            ("POST /tx".to_string(), txid.to_string()),
+        ]);
+        let client = EsploraClient::new(&server.http_url()).unwrap();
+
+        let (header, height) = client.tip().unwrap();
+        assert_eq!(header, genesis.header);
+        assert_eq!(height, 0);
+        assert_eq!(client.tx(&txid).unwrap(), Some(genesis.txdata[0].clone()));
+        assert_eq!(
+            client.tx_status(&txid).unwrap(),
+            Some((0, genesis.block_hash()))
+        );
+        assert_eq!(client.txid_at(0, 0).unwrap(), Some(txid));
+        assert_eq!(client.txid_at(1, 0).unwrap(), None);
+        let unknown = Txid::from_str(&"00".repeat(32)).unwrap();
+        assert_eq!(client.tx_status(&unknown).unwrap(), None);
+        assert_eq!(
+            client
+                .output_spender(&OutPoint { txid, vout: 0 }, &Script::new())
+                .unwrap(),
+            None
+        );
+        assert_eq!(client.estimate_fee(12).unwrap(), Some(10.0));
+        assert_eq!(client.estimate_fee(1).unwrap(), Some(20.5));
+        client.broadcast(&genesis.txdata[0]).unwrap();
+    }
+}
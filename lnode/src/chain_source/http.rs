@@ -0,0 +1,104 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A small blocking HTTP/1.0 client, enough to talk to a plain `http://` Esplora instance such
+/// as a local electrs. Put a TLS terminating proxy in front of remote servers.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    host: String,
+    port: u16,
+    base_path: String,
+}
+
+impl HttpClient {
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid url {}", url));
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, base_path) = match rest.find('/') {
+            Some(index) => (&rest[..index], rest[index..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        Ok(HttpClient {
+            host: host.to_string(),
+            port,
+            base_path: base_path.to_string(),
+        })
+    }
+
+    /// Returns the response body, or `None` if the server answered 404.
+    pub fn get(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.request("GET", path, &[])? {
+            (404, _) => Ok(None),
+            (_, body) => Ok(Some(body)),
+        }
+    }
+
+    pub fn get_string(&self, path: &str) -> io::Result<Option<String>> {
+        match self.get(path)? {
+            Some(body) => String::from_utf8(body)
+                .map(|s| Some(s.trim().to_string()))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_json(&self, path: &str) -> io::Result<Option<serde_json::Value>> {
+        match self.get(path)? {
+            Some(body) => serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn post(&self, path: &str, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self.request("POST", path, body)? {
+            (404, _) => Err(io::Error::new(io::ErrorKind::NotFound, path.to_string())),
+            (_, body) => Ok(body),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "{} {}{} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            method,
+            self.base_path,
+            path,
+            self.host,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        let header_end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed HTTP response"))?;
+        let status = String::from_utf8_lossy(&response[..header_end])
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed HTTP status"))?;
+        let body = response[header_end + 4..].to_vec();
+        match status {
+            200..=299 | 404 => Ok((status, body)),
+            _ => Err(io::Error::other(format!(
+                "HTTP {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            ))),
+        }
+    }
+}
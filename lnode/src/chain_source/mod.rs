@@ -0,0 +1,120 @@
+//! Chain backends the node can sync against, configured through [`ChainBackendConfig`] like the
+//! on-chain wallet's.
+
+pub mod electrum;
+pub mod esplora;
+pub mod http;
+pub mod sync;
+#[cfg(test)]
+mod test_utils;
+
+use bitcoin::{BlockHash, BlockHeader, OutPoint, Script, Transaction, TxOut, Txid};
+pub use chain_backend::ChainBackendConfig;
+use std::io;
+
+/// Builds the client the lightning node syncs with when it does not talk to bitcoind.
+pub fn chain_client(backend: &ChainBackendConfig) -> io::Result<Box<dyn ChainClient>> {
+    match backend {
+        ChainBackendConfig::Esplora { url } => Ok(Box::new(esplora::EsploraClient::new(url)?)),
+        ChainBackendConfig::Electrum { url } => Ok(Box::new(electrum::ElectrumClient::new(url)?)),
+        ChainBackendConfig::Rpc { .. } => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "bitcoind is used as a block source, not a chain client",
+        )),
+        ChainBackendConfig::Cbf { .. } => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Compact block filters can only sync the on-chain wallet, run the node against rpc, esplora or electrum",
+        )),
+    }
+}
+
+/// The queries the lightning node needs from an indexing backend to follow the transactions and
+/// outputs its channels care about, see [`sync::TxSync`].
+pub trait ChainClient: Send + Sync {
+    fn tip(&self) -> io::Result<(BlockHeader, u32)>;
+
+    fn header(&self, height: u32) -> io::Result<BlockHeader>;
+
+    fn tx(&self, txid: &Txid) -> io::Result<Option<Transaction>>;
+
+    /// Height and hash of the block confirming `txid`, `None` while it is unconfirmed or unknown.
+    fn tx_status(&self, txid: &Txid) -> io::Result<Option<(u32, BlockHash)>>;
+
+    /// Position of `txid` in the block at `height`.
+    fn merkle_pos(&self, txid: &Txid, height: u32) -> io::Result<usize>;
+
+    /// The transaction at `tx_index` in the block at `height`, as a short channel id encodes it.
+    fn txid_at(&self, height: u32, tx_index: usize) -> io::Result<Option<Txid>>;
+
+    /// The transaction spending `outpoint`, whose output pays to `script_pubkey`.
+    fn output_spender(
+        &self,
+        outpoint: &OutPoint,
+        script_pubkey: &Script,
+    ) -> io::Result<Option<Txid>>;
+
+    fn broadcast(&self, tx: &Transaction) -> io::Result<()>;
+
+    /// Fee rate in sat/vB to confirm within `target_blocks`, if the backend has an estimate.
+    fn estimate_fee(&self, target_blocks: usize) -> io::Result<Option<f64>>;
+
+    /// Output `vout` of the transaction at `tx_index` in the block at `height`, `None` if there
+    /// is no such output or it was spent.
+    fn unspent_output(&self, height: u32, tx_index: usize, vout: u32) -> io::Result<Option<TxOut>> {
+        let txid = match self.txid_at(height, tx_index)? {
+            Some(txid) => txid,
+            None => return Ok(None),
+        };
+        let output = match self
+            .tx(&txid)?
+            .and_then(|tx| tx.output.get(vout as usize).cloned())
+        {
+            Some(output) => output,
+            None => return Ok(None),
+        };
+        let outpoint = OutPoint { txid, vout };
+        match self.output_spender(&outpoint, &output.script_pubkey)? {
+            Some(_) => Ok(None),
+            None => Ok(Some(output)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::MockChain;
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::{Network, TxIn, Witness};
+
+    #[test]
+    fn unspent_output_by_position() {
+        let genesis = genesis_block(Network::Regtest);
+        let funding = genesis.txdata[0].clone();
+        let mut chain = MockChain {
+            tip: (genesis.header, 10),
+            confirmed: vec![(funding.txid(), (3, funding.clone()))]
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(
+            chain.unspent_output(3, 1, 0).unwrap(),
+            Some(funding.output[0].clone())
+        );
+        assert_eq!(chain.unspent_output(3, 0, 0).unwrap(), None);
+        assert_eq!(chain.unspent_output(3, 1, 1).unwrap(), None);
+
+        let mut spend = funding.clone();
+        spend.input = vec![TxIn {
+            previous_output: OutPoint {
+                txid: funding.txid(),
+                vout: 0,
+            },
+            script_sig: Script::new(),
+            sequence: bitcoin::Sequence::MAX,
+            witness: Witness::new(),
+        }];
+        chain.confirmed.insert(spend.txid(), (5, spend));
+        assert_eq!(chain.unspent_output(3, 1, 0).unwrap(), None);
+    }
+}
@@ -0,0 +1,212 @@
+use super::ChainClient;
+use bitcoin::{BlockHash, OutPoint, Script, Transaction, Txid};
+use lightning::chain::{Confirm, Filter, WatchedOutput};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Mutex;
+
+/// Feeds LDK through its [`Confirm`] interface from a [`ChainClient`], for backends that can't
+/// serve full blocks. LDK registers what it needs watched through [`Filter`].
+pub struct TxSync {
+    client: Box<dyn ChainClient>,
+    watched_txs: Mutex<HashSet<Txid>>,
+    watched_outputs: Mutex<HashMap<OutPoint, Script>>,
+    last_tip: Mutex<Option<BlockHash>>,
+}
+
+impl TxSync {
+    pub fn new(client: Box<dyn ChainClient>) -> Self {
+        TxSync {
+            client,
+            watched_txs: Mutex::new(HashSet::new()),
+            watched_outputs: Mutex::new(HashMap::new()),
+            last_tip: Mutex::new(None),
+        }
+    }
+
+    pub fn client(&self) -> &dyn ChainClient {
+        self.client.as_ref()
+    }
+
+    pub fn sync(&self, confirmables: &[&(dyn Confirm + Sync + Send)]) -> io::Result<()> {
+        let (tip_header, tip_height) = self.client.tip()?;
+        let tip_hash = tip_header.block_hash();
+
+        let mut known: HashMap<Txid, Option<BlockHash>> = HashMap::new();
+        for confirmable in confirmables {
+            known.extend(confirmable.get_relevant_txids());
+        }
+
+        if *self.last_tip.lock().unwrap() != Some(tip_hash) {
+            // A new tip may come with a reorg, so drop anything no longer in its block first.
+            for (txid, block_hash) in known.iter() {
+                let still_confirmed = match self.client.tx_status(txid)? {
+                    Some((_, hash)) => block_hash.is_none_or(|b| b == hash),
+                    None => false,
+                };
+                if !still_confirmed {
+                    for confirmable in confirmables {
+                        confirmable.transaction_unconfirmed(txid);
+                    }
+                }
+            }
+            for confirmable in confirmables {
+                confirmable.best_block_updated(&tip_header, tip_height);
+            }
+        }
+
+        let mut candidates: Vec<Txid> = self.watched_txs.lock().unwrap().iter().cloned().collect();
+        let watched_outputs: Vec<(OutPoint, Script)> = self
+            .watched_outputs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(o, s)| (*o, s.clone()))
+            .collect();
+        for (outpoint, script_pubkey) in watched_outputs.iter() {
+            if let Some(spender) = self.client.output_spender(outpoint, script_pubkey)? {
+                candidates.push(spender);
+            }
+        }
+
+        let mut confirmed: Vec<(u32, usize, Transaction)> = Vec::new();
+        for txid in candidates {
+            let (height, block_hash) = match self.client.tx_status(&txid)? {
+                Some(status) => status,
+                None => continue,
+            };
+            if known.get(&txid) == Some(&Some(block_hash))
+                || confirmed.iter().any(|(_, _, tx)| tx.txid() == txid)
+            {
+                continue;
+            }
+            if let Some(tx) = self.client.tx(&txid)? {
+                confirmed.push((height, self.client.merkle_pos(&txid, height)?, tx));
+            }
+        }
+        // LDK expects confirmations in chain order.
+        confirmed.sort_by_key(|(height, pos, _)| (*height, *pos));
+        for (height, pos, tx) in confirmed.iter() {
+            let header = self.client.header(*height)?;
+            for confirmable in confirmables {
+                confirmable.transactions_confirmed(&header, &[(*pos, tx)], *height);
+            }
+        }
+
+        *self.last_tip.lock().unwrap() = Some(tip_hash);
+        Ok(())
+    }
+}
+
+impl Filter for TxSync {
+    fn register_tx(&self, txid: &Txid, _script_pubkey: &Script) {
+        self.watched_txs.lock().unwrap().insert(*txid);
+    }
+
+    fn register_output(&self, output: WatchedOutput) {
+        self.watched_outputs.lock().unwrap().insert(
+            output.outpoint.into_bitcoin_outpoint(),
+            output.script_pubkey,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_source::test_utils::MockChain;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHeader, Network, TxIn, Witness};
+
+    #[derive(Default)]
+    struct Recorder {
+        relevant: Mutex<Vec<(Txid, Option<BlockHash>)>>,
+        confirmed: Mutex<Vec<(Txid, u32)>>,
+        unconfirmed: Mutex<Vec<Txid>>,
+        best_height: Mutex<Option<u32>>,
+    }
+
+    impl Confirm for Recorder {
+        fn transactions_confirmed(
+            &self,
+            header: &BlockHeader,
+            txdata: &lightning::chain::transaction::TransactionData,
+            height: u32,
+        ) {
+            for (_, tx) in txdata.iter() {
+                self.confirmed.lock().unwrap().push((tx.txid(), height));
+                self.relevant
+                    .lock()
+                    .unwrap()
+                    .push((tx.txid(), Some(header.block_hash())));
+            }
+        }
+        fn transaction_unconfirmed(&self, txid: &Txid) {
+            self.unconfirmed.lock().unwrap().push(*txid);
+        }
+        fn best_block_updated(&self, _header: &BlockHeader, height: u32) {
+            *self.best_height.lock().unwrap() = Some(height);
+        }
+        fn get_relevant_txids(&self) -> Vec<(Txid, Option<BlockHash>)> {
+            self.relevant.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn confirms_watched_txs_and_spends() {
+        let genesis = genesis_block(Network::Regtest);
+        let funding = genesis.txdata[0].clone();
+        let funding_outpoint = OutPoint {
+            txid: funding.txid(),
+            vout: 0,
+        };
+        let mut spend = funding.clone();
+        spend.input = vec![TxIn {
+            previous_output: funding_outpoint,
+            script_sig: Script::new(),
+            sequence: bitcoin::Sequence::MAX,
+            witness: Witness::new(),
+        }];
+        let reorged = Txid::from_slice(&[7; 32]).unwrap();
+
+        let chain = MockChain {
+            tip: (genesis.header, 5),
+            confirmed: vec![
+                (funding.txid(), (2, funding.clone())),
+                (spend.txid(), (4, spend.clone())),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let tx_sync = TxSync::new(Box::new(chain));
+        tx_sync.register_tx(&funding.txid(), &Script::new());
+        tx_sync.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint: lightning::chain::transaction::OutPoint {
+                txid: funding_outpoint.txid,
+                index: 0,
+            },
+            script_pubkey: Script::new(),
+        });
+
+        let recorder = Recorder::default();
+        recorder
+            .relevant
+            .lock()
+            .unwrap()
+            .push((reorged, Some(BlockHash::from_slice(&[1; 32]).unwrap())));
+        tx_sync.sync(&[&recorder]).unwrap();
+
+        assert_eq!(*recorder.best_height.lock().unwrap(), Some(5));
+        assert_eq!(*recorder.unconfirmed.lock().unwrap(), vec![reorged]);
+        assert_eq!(
+            *recorder.confirmed.lock().unwrap(),
+            vec![(funding.txid(), 2), (spend.txid(), 4)]
+        );
+
+        // Nothing changed, so a second pass must not report the same confirmations again.
+        tx_sync.sync(&[&recorder]).unwrap();
+        assert_eq!(recorder.confirmed.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,149 @@
+use super::ChainClient;
+use bitcoin::{BlockHash, BlockHeader, OutPoint, Script, Transaction, Txid};
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+/// A local server answering canned responses, standing in for Esplora or Electrum in tests.
+pub struct StandInServer {
+    address: SocketAddr,
+}
+
+impl StandInServer {
+    /// Answers `"<METHOD> <path>"` requests with the matching body, and 404 otherwise.
+    pub fn http(routes: Vec<(String, String)>) -> Self {
+        Self::spawn(routes, |routes, stream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+                if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+            }
+            reader.read_exact(&mut vec![0; content_length]).unwrap();
+            let route = request_line
+                .split_whitespace()
+                .take(2)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let response = match routes.get(&route) {
+                Some(body) => format!("HTTP/1.0 200 OK\r\n\r\n{}", body),
+                None => "HTTP/1.0 404 Not Found\r\n\r\n".to_string(),
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        })
+    }
+
+    /// Answers Electrum JSON-RPC calls with the canned result registered for their method.
+    pub fn electrum(results: Vec<(String, String)>) -> Self {
+        Self::spawn(results, |results, stream| {
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => return,
+                };
+                let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let method = request["method"].as_str().unwrap_or_default();
+                let response = match results.get(method) {
+                    Some(result) => format!(
+                        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}\n",
+                        request["id"], result
+                    ),
+                    None => format!(
+                        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":-1,\"message\":\"unknown method {}\"}}}}\n",
+                        request["id"], method
+                    ),
+                };
+                if stream.write_all(response.as_bytes()).is_err() {
+                    return;
+                }
+            }
+        })
+    }
+
+    fn spawn(
+        responses: Vec<(String, String)>,
+        handler: fn(&HashMap<String, String>, &mut TcpStream),
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let responses: Arc<HashMap<String, String>> = Arc::new(responses.into_iter().collect());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let responses = Arc::clone(&responses);
+                thread::spawn(move || handler(&responses, &mut stream));
+            }
+        });
+        StandInServer { address }
+    }
+
+    pub fn http_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn tcp_url(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+}
+
+/// An in-memory chain where every confirmed transaction sits at position 1 of its block.
+pub struct MockChain {
+    pub tip: (BlockHeader, u32),
+    pub confirmed: HashMap<Txid, (u32, Transaction)>,
+}
+
+impl ChainClient for MockChain {
+    fn tip(&self) -> io::Result<(BlockHeader, u32)> {
+        Ok(self.tip)
+    }
+    fn header(&self, _height: u32) -> io::Result<BlockHeader> {
+        Ok(self.tip.0)
+    }
+    fn tx(&self, txid: &Txid) -> io::Result<Option<Transaction>> {
+        Ok(self.confirmed.get(txid).map(|(_, tx)| tx.clone()))
+    }
+    fn tx_status(&self, txid: &Txid) -> io::Result<Option<(u32, BlockHash)>> {
+        Ok(self
+            .confirmed
+            .get(txid)
+            .map(|(height, _)| (*height, self.tip.0.block_hash())))
+    }
+    fn merkle_pos(&self, _txid: &Txid, _height: u32) -> io::Result<usize> {
+        Ok(1)
+    }
+    fn txid_at(&self, height: u32, tx_index: usize) -> io::Result<Option<Txid>> {
+        Ok(self
+            .confirmed
+            .iter()
+            .find(|(_, (h, _))| *h == height && tx_index == 1)
+            .map(|(txid, _)| *txid))
+    }
+    fn output_spender(&self, outpoint: &OutPoint, _: &Script) -> io::Result<Option<Txid>> {
+        Ok(self
+            .confirmed
+            .iter()
+            .find(|(_, (_, tx))| tx.input.iter().any(|i| i.previous_output == *outpoint))
+            .map(|(txid, _)| *txid))
+    }
+    fn broadcast(&self, _tx: &Transaction) -> io::Result<()> {
+        Ok(())
+    }
+    fn estimate_fee(&self, _target_blocks: usize) -> io::Result<Option<f64>> {
+        Ok(None)
+    }
+}
@@ -1,42 +1,55 @@
+use crate::ldk::announce::Announcer;
+use crate::ldk::balances;
+use crate::ldk::channels::{self, ChannelOpenConfig};
+use crate::ldk::context::NodeContext;
+use crate::ldk::fee_policy::{self, FeePolicy, FeePolicyStore};
+use crate::ldk::forwards::GroupBy;
+use crate::ldk::graph;
+use crate::ldk::lsp::{self, RouteHintView};
+use crate::ldk::onion_messages;
+use crate::ldk::payments::{self, PaymentOptions, RetryPolicy};
+use crate::ldk::peer_messages;
+use crate::ldk::probing::{self, ProbeStatus};
+use crate::ldk::rebalance::{self, RebalanceParams};
+use crate::ldk::scoring;
+use crate::ldk::tor;
 use crate::types::ChannelManager;
 use crate::types::HTLCStatus;
 use crate::types::MillisatAmount;
 use crate::types::NetworkGraph;
-use crate::types::OnionMessenger;
 use crate::types::PaymentInfo;
 use crate::types::PaymentInfoStorage;
 use crate::types::PeerManager;
+use crate::types::Router;
 use crate::utils::disk;
 use crate::utils::hex::hex_str;
 use crate::utils::hex::to_compressed_pubkey;
 use crate::utils::hex::to_vec;
+use crate::wallet::{multisig_descriptors, BitcoinWallet, ScriptType};
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::Hash;
 use bitcoin::network::constants::Network;
+use bitcoin::psbt::PartiallySignedTransaction;
 use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Address, OutPoint};
 use lightning::chain::keysinterface::EntropySource;
 use lightning::chain::keysinterface::KeysManager;
 use lightning::ln::channelmanager::PaymentId;
-use lightning::ln::channelmanager::RecipientOnionFields;
-use lightning::ln::channelmanager::Retry;
 use lightning::ln::msgs::NetAddress;
 use lightning::ln::{PaymentHash, PaymentPreimage};
-use lightning::onion_message::{CustomOnionMessageContents, Destination, OnionMessageContents};
+use lightning::onion_message::Destination;
 use lightning::routing::gossip::NodeId;
-use lightning::routing::router::{PaymentParameters, RouteParameters};
-use lightning::util::config::{ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig};
-use lightning::util::ser::{Writeable, Writer};
-use lightning_invoice::payment::pay_invoice;
 use lightning_invoice::{utils, Currency, Invoice};
 use std::env;
 use std::io;
 use std::io::Write;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::ops::Deref;
-use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use wallet_history::labels::LabelType;
+use wallet_history::HistoryQuery;
 
 pub(crate) struct LdkUserInfo {
     pub(crate) bitcoind_rpc_username: String,
@@ -50,38 +63,41 @@ pub(crate) struct LdkUserInfo {
     pub(crate) network: Network,
 }
 
-struct UserOnionMessageContents {
-    tlv_type: u64,
-    data: Vec<u8>,
-}
-
-impl CustomOnionMessageContents for UserOnionMessageContents {
-    fn tlv_type(&self) -> u64 {
-        self.tlv_type
-    }
-}
-
-impl Writeable for UserOnionMessageContents {
-    fn write<W: Writer>(&self, w: &mut W) -> Result<(), std::io::Error> {
-        w.write_all(&self.data)
-    }
-}
-
-pub(crate) async fn poll_for_user_input(
-    peer_manager: Arc<PeerManager>,
-    channel_manager: Arc<ChannelManager>,
-    keys_manager: Arc<KeysManager>,
-    network_graph: Arc<NetworkGraph>,
-    onion_messenger: Arc<OnionMessenger>,
-    inbound_payments: PaymentInfoStorage,
-    outbound_payments: PaymentInfoStorage,
-    ldk_data_dir: String,
-    network: Network,
-    logger: Arc<disk::FilesystemLogger>,
-    port: u16,
-    announced_listen_addr: &str,
-    node_name: &str
-) {
+// Not started by `start_node`, the HTTP server taking commands instead, but kept working.
+#[allow(dead_code)]
+pub(crate) async fn poll_for_user_input(node: NodeContext) {
+    let NodeContext {
+        peer_manager,
+        channel_manager,
+        chain_monitor,
+        keys_manager,
+        network_graph,
+        onion_messenger,
+        inbound_payments,
+        outbound_payments,
+        ldk_data_dir,
+        network,
+        logger,
+        port,
+        announcer,
+        node_name,
+        bdk_wallet,
+        scorer,
+        router,
+        probes,
+        payment_attempts,
+        onion_message_handler,
+        peer_message_handler,
+        peer_store,
+        channel_acceptor,
+        lsp,
+        fee_policies,
+        forwarding_history,
+        channel_history,
+        tor,
+        ..
+    } = node;
+    let channel_open_config = ChannelOpenConfig::load(&ldk_data_dir);
     println!(
         "LDK startup successful. Enter \"help\" to view available commands. Press Ctrl-D to quit."
     );
@@ -108,7 +124,7 @@ pub(crate) async fn poll_for_user_input(
                     let peer_pubkey_and_ip_addr = words.next();
                     let channel_value_sat = words.next();
                     if peer_pubkey_and_ip_addr.is_none() || channel_value_sat.is_none() {
-                        println!("ERROR: openchannel has 2 required arguments: `openchannel pubkey@host:port channel_amt_satoshis` [--public]");
+                        println!("ERROR: openchannel has 2 required arguments: `openchannel pubkey@host:port channel_amt_satoshis` [--public] [--utxos=txid:vout,..]");
                         continue;
                     }
                     let peer_pubkey_and_ip_addr = peer_pubkey_and_ip_addr.unwrap();
@@ -134,35 +150,73 @@ pub(crate) async fn poll_for_user_input(
                         continue;
                     };
 
-                    let announce_channel = match words.next() {
-                        Some("--public") | Some("--public=true") => true,
-                        Some("--public=false") => false,
-                        Some(_) => {
-                            println!("ERROR: invalid `--public` command format. Valid formats: `--public`, `--public=true` `--public=false`");
-                            continue;
+                    let mut announce_channel = false;
+                    let mut utxos = None;
+                    let mut parse_err = false;
+                    for arg in words.by_ref() {
+                        match arg {
+                            "--public" | "--public=true" => announce_channel = true,
+                            "--public=false" => announce_channel = false,
+                            _ if arg.starts_with("--utxos=") => {
+                                match parse_outpoints(arg.trim_start_matches("--utxos=")) {
+                                    Ok(list) => utxos = Some(list),
+                                    Err(e) => {
+                                        println!("ERROR: invalid outpoint: {}", e);
+                                        parse_err = true;
+                                    }
+                                }
+                            }
+                            _ => {
+                                println!("ERROR: invalid `--public` command format. Valid formats: `--public`, `--public=true` `--public=false`");
+                                parse_err = true;
+                            }
                         }
-                        None => false,
-                    };
+                    }
+                    if parse_err {
+                        continue;
+                    }
 
                     if open_channel(
                         pubkey,
                         chan_amt_sat.unwrap(),
                         announce_channel,
+                        utxos,
+                        &channel_open_config,
+                        &fee_policies,
                         channel_manager.clone(),
+                        &bdk_wallet,
                     )
                     .is_ok()
                     {
-                        let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
-                        let _ = disk::persist_channel_peer(
-                            Path::new(&peer_data_path),
-                            peer_pubkey_and_ip_addr,
-                        );
+                        if let Some((_, address)) = peer_pubkey_and_ip_addr.split_once('@') {
+                            peer_store.add(pubkey, address.to_string(), None);
+                        }
                     }
                 }
                 "sendpayment" => {
                     let invoice_str = words.next();
                     if invoice_str.is_none() {
-                        println!("ERROR: sendpayment requires an invoice: `sendpayment <invoice>`");
+                        println!("ERROR: sendpayment requires an invoice: `sendpayment <invoice> [amt_msat=<amt>] [<option>=<value> ..]`");
+                        continue;
+                    }
+                    let mut amt_msat = None;
+                    let mut options = PaymentOptions::default();
+                    let mut invalid = None;
+                    for arg in words.by_ref() {
+                        match arg.strip_prefix("amt_msat=") {
+                            Some(amt) => match amt.parse::<u64>() {
+                                Ok(amt) => amt_msat = Some(amt),
+                                Err(e) => invalid = Some(format!("invalid amt_msat: {}", e)),
+                            },
+                            None => {
+                                if let Err(e) = parse_payment_option(&mut options, arg) {
+                                    invalid = Some(e);
+                                }
+                            }
+                        }
+                    }
+                    if let Some(e) = invalid {
+                        println!("ERROR: {}", e);
                         continue;
                     }
 
@@ -174,7 +228,15 @@ pub(crate) async fn poll_for_user_input(
                         }
                     };
 
-                    send_payment(&*channel_manager, &invoice, outbound_payments.clone());
+                    send_payment(
+                        &channel_manager,
+                        &router,
+                        &network_graph,
+                        &invoice,
+                        amt_msat,
+                        &options,
+                        outbound_payments.clone(),
+                    );
                 }
                 "keysend" => {
                     let dest_pubkey = match words.next() {
@@ -204,10 +266,21 @@ pub(crate) async fn poll_for_user_input(
                             continue;
                         }
                     };
+                    let mut options = PaymentOptions::default();
+                    if let Some(e) = words
+                        .by_ref()
+                        .find_map(|arg| parse_payment_option(&mut options, arg).err())
+                    {
+                        println!("ERROR: {}", e);
+                        continue;
+                    }
                     keysend(
                         &*channel_manager,
+                        &router,
+                        &network_graph,
                         dest_pubkey,
                         amt_msat,
+                        &options,
                         &*keys_manager,
                         outbound_payments.clone(),
                     );
@@ -253,6 +326,31 @@ pub(crate) async fn poll_for_user_input(
                         println!("ERROR: connectpeer requires peer connection info: `connectpeer pubkey@host:port`");
                         continue;
                     }
+                    // Onion addresses don't resolve locally, and anything may have to go through tor.
+                    if tor.is_some() {
+                        let (pubkey, address) =
+                            match peer_pubkey_and_ip_addr.unwrap().split_once('@') {
+                                Some((pubkey, address)) => (to_compressed_pubkey(pubkey), address),
+                                None => (None, ""),
+                            };
+                        let pubkey = match pubkey {
+                            Some(pubkey) => pubkey,
+                            None => {
+                                println!("ERROR: incorrectly formatted peer info. Should be formatted as: `pubkey@host:port`");
+                                continue;
+                            }
+                        };
+                        match tor::connect(peer_manager.clone(), pubkey, address, tor.as_ref())
+                            .await
+                        {
+                            Ok(()) => {
+                                peer_store.add(pubkey, address.to_string(), None);
+                                println!("SUCCESS: connected to peer {}", pubkey);
+                            }
+                            Err(e) => println!("ERROR: {}", e),
+                        }
+                        continue;
+                    }
                     let (pubkey, peer_addr) =
                         match parse_peer_info(peer_pubkey_and_ip_addr.unwrap().to_string()) {
                             Ok(info) => info,
@@ -265,9 +363,70 @@ pub(crate) async fn poll_for_user_input(
                         .await
                         .is_ok()
                     {
+                        if let Some((_, address)) = peer_pubkey_and_ip_addr.unwrap().split_once('@')
+                        {
+                            peer_store.add(pubkey, address.to_string(), None);
+                        }
                         println!("SUCCESS: connected to peer {}", pubkey);
                     }
                 }
+                "addpeer" => {
+                    let peer_pubkey_and_ip_addr = words.next();
+                    if peer_pubkey_and_ip_addr.is_none() {
+                        println!("ERROR: addpeer requires peer connection info: `addpeer pubkey@host:port [--keep-connected]`");
+                        continue;
+                    }
+                    let keep_connected = match words.next() {
+                        Some("--keep-connected") => Some(true),
+                        Some(arg) => {
+                            println!("ERROR: invalid argument: {}", arg);
+                            continue;
+                        }
+                        None => None,
+                    };
+                    let peer_pubkey_and_ip_addr = peer_pubkey_and_ip_addr.unwrap();
+                    let (pubkey, address) = match peer_pubkey_and_ip_addr.split_once('@') {
+                        Some((pubkey, address)) => (to_compressed_pubkey(pubkey), address),
+                        None => (None, ""),
+                    };
+                    if pubkey.is_none() || !tor::is_dialable(address, tor.as_ref()) {
+                        println!("ERROR: incorrectly formatted peer info. Should be formatted as: `pubkey@host:port`, onion hosts needing tor");
+                        continue;
+                    }
+                    let pubkey = pubkey.unwrap();
+                    peer_store.add(pubkey, address.to_string(), keep_connected);
+                    println!("SUCCESS: added peer {}", pubkey);
+                }
+                "removepeer" => {
+                    let pubkey = match words.next().and_then(to_compressed_pubkey) {
+                        Some(pubkey) => pubkey,
+                        None => {
+                            println!("ERROR: removepeer requires a peer public key: `removepeer <peer_pubkey>`");
+                            continue;
+                        }
+                    };
+                    if peer_store.remove(&pubkey) {
+                        println!("SUCCESS: removed peer {}", pubkey);
+                    } else {
+                        println!("ERROR: peer {} is not in the peer store", pubkey);
+                    }
+                }
+                "listpeerstore" => {
+                    print!("[");
+                    for peer in peer_store.list() {
+                        println!();
+                        println!("\t{{");
+                        println!("\t\t pubkey: {},", peer.pubkey);
+                        println!("\t\t addresses: {:?},", peer.record.addresses);
+                        println!("\t\t keep_connected: {},", peer.record.keep_connected);
+                        if let Some(last_connected) = peer.record.last_connected {
+                            println!("\t\t last_connected: {},", last_connected);
+                        }
+                        println!("\t\t failures: {},", peer.record.failures);
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
                 "disconnectpeer" => {
                     let peer_pubkey = words.next();
                     if peer_pubkey.is_none() {
@@ -295,6 +454,406 @@ pub(crate) async fn poll_for_user_input(
                     }
                 }
                 "listchannels" => list_channels(&channel_manager, &network_graph),
+                "balances" => {
+                    match balances::total_funds(&channel_manager, &chain_monitor, &bdk_wallet) {
+                        Ok(funds) => print_total_funds(&funds),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "channelhistory" => {
+                    let channel_id = match words.next().map(fee_policy::parse_channel_id) {
+                        Some(Some(id)) => id,
+                        Some(None) => {
+                            println!("ERROR: couldn't parse channel_id");
+                            continue;
+                        }
+                        None => {
+                            println!("ERROR: channelhistory requires a channel id: `channelhistory <channel_id>`");
+                            continue;
+                        }
+                    };
+                    let record = match channel_history.get(&channel_id) {
+                        Some(record) => record,
+                        None => {
+                            println!("ERROR: no history of channel {}", hex_str(&channel_id));
+                            continue;
+                        }
+                    };
+                    println!("\t{{");
+                    println!("\t\t channel_id: {},", record.channel_id);
+                    println!("\t\t state: {:?},", record.state);
+                    for change in &record.history {
+                        println!("\t\t {:?}: {},", change.state, change.timestamp);
+                    }
+                    for funding in &record.fundings {
+                        println!("\t\t funding_txo: {},", funding.funding_txo);
+                    }
+                    if let Some(reason) = &record.close_reason {
+                        println!("\t\t close_reason: {},", reason);
+                    }
+                    if let Some(txid) = &record.closing_txid {
+                        println!("\t\t closing_txid: {},", txid);
+                    }
+                    if let Some(height) = record.close_confirmation_height {
+                        println!("\t\t close_confirmation_height: {},", height);
+                    }
+                    for balance in &record.claimable_balances {
+                        println!("\t\t claimable_balance: {:?},", balance);
+                    }
+                    if let Some(sats) = record.swept_sats {
+                        println!("\t\t swept_sats: {},", sats);
+                    }
+                    if let Some(height) = record.timelock_expiry_height {
+                        println!("\t\t timelock_expiry_height: {},", height);
+                    }
+                    println!("\t}},");
+                }
+                "listchannelrequests" => {
+                    print!("[");
+                    for request in channel_acceptor.pending() {
+                        println!();
+                        println!("\t{{");
+                        println!(
+                            "\t\t temporary_channel_id: {},",
+                            request.temporary_channel_id
+                        );
+                        println!("\t\t peer: {},", request.counterparty_node_id);
+                        println!("\t\t funding_satoshis: {},", request.funding_satoshis);
+                        println!("\t\t push_msat: {},", request.push_msat);
+                        println!("\t\t zero_conf: {},", request.zero_conf);
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
+                "rebalance" => {
+                    let usage = "`rebalance <source_channel_id> <target_channel_id> <amt_msat> <max_fee_msat>`";
+                    let (source, target) = match (words.next(), words.next()) {
+                        (Some(source), Some(target)) => (source, target),
+                        _ => {
+                            println!("ERROR: rebalance requires two channels: {}", usage);
+                            continue;
+                        }
+                    };
+                    let (source, target) = match (
+                        fee_policy::parse_channel_id(source),
+                        fee_policy::parse_channel_id(target),
+                    ) {
+                        (Some(source), Some(target)) => (source, target),
+                        _ => {
+                            println!("ERROR: couldn't parse channel_id");
+                            continue;
+                        }
+                    };
+                    let (amt_msat, max_fee_msat) = match (
+                        words.next().map(|amt| amt.parse::<u64>()),
+                        words.next().map(|fee| fee.parse::<u64>()),
+                    ) {
+                        (Some(Ok(amt_msat)), Some(Ok(max_fee_msat))) => (amt_msat, max_fee_msat),
+                        _ => {
+                            println!(
+                                "ERROR: rebalance requires an amount and a max fee: {}",
+                                usage
+                            );
+                            continue;
+                        }
+                    };
+                    match rebalance::rebalance(
+                        &channel_manager,
+                        &router,
+                        &inbound_payments,
+                        &outbound_payments,
+                        RebalanceParams {
+                            source_channel_id: source,
+                            target_channel_id: target,
+                            amount_msat: amt_msat,
+                            max_fee_msat,
+                        },
+                    ) {
+                        Ok(rebalance) => println!(
+                            "SUCCESS: sending {} msat to ourselves over {:?} for {} msat, payment hash {}",
+                            rebalance.amount_msat,
+                            rebalance.short_channel_ids,
+                            rebalance.fee_msat,
+                            rebalance.payment_hash
+                        ),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "listforwards" => {
+                    let since = match words.next().map(|secs| secs.parse::<u64>()) {
+                        Some(Ok(secs)) => now_secs().saturating_sub(secs),
+                        Some(Err(_)) => {
+                            println!("ERROR: couldn't parse window_secs");
+                            continue;
+                        }
+                        None => 0,
+                    };
+                    print!("[");
+                    for forward in forwarding_history.forwards(since, u64::MAX) {
+                        println!();
+                        println!("\t{{");
+                        println!("\t\t timestamp: {},", forward.timestamp);
+                        if let Some(channel_id) = forward.prev_channel_id {
+                            println!("\t\t prev_channel_id: {},", channel_id);
+                        }
+                        if let Some(channel_id) = forward.next_channel_id {
+                            println!("\t\t next_channel_id: {},", channel_id);
+                        }
+                        if let Some(amount_out_msat) = forward.amount_out_msat {
+                            println!("\t\t amount_out_msat: {},", amount_out_msat);
+                        }
+                        if let Some(fee_earned_msat) = forward.fee_earned_msat {
+                            println!("\t\t fee_earned_msat: {},", fee_earned_msat);
+                        }
+                        println!(
+                            "\t\t claim_from_onchain_tx: {},",
+                            forward.claim_from_onchain_tx
+                        );
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
+                "revenue" => {
+                    let group_by = match words.next() {
+                        Some("channels") => GroupBy::Channel,
+                        Some("peers") => GroupBy::Peer,
+                        _ => {
+                            println!("ERROR: revenue requires a grouping: `revenue <channels|peers> [window_secs]`");
+                            continue;
+                        }
+                    };
+                    let since = match words.next().map(|secs| secs.parse::<u64>()) {
+                        Some(Ok(secs)) => now_secs().saturating_sub(secs),
+                        Some(Err(_)) => {
+                            println!("ERROR: couldn't parse window_secs");
+                            continue;
+                        }
+                        None => 0,
+                    };
+                    let report = forwarding_history.revenue(group_by, since, u64::MAX);
+                    println!("\t{{");
+                    println!("\t\t forwards: {},", report.forwards);
+                    println!("\t\t failures: {},", report.failures);
+                    println!("\t\t fee_earned_msat: {},", report.fee_earned_msat);
+                    for summary in report.summaries {
+                        println!("\t\t {}: {{", summary.id);
+                        println!(
+                            "\t\t\t forwards_in: {}, forwards_out: {},",
+                            summary.forwards_in, summary.forwards_out
+                        );
+                        println!(
+                            "\t\t\t amount_in_msat: {}, amount_out_msat: {},",
+                            summary.amount_in_msat, summary.amount_out_msat
+                        );
+                        println!(
+                            "\t\t\t fee_earned_msat: {}, fee_earned_in_msat: {},",
+                            summary.fee_earned_msat, summary.fee_earned_in_msat
+                        );
+                        println!(
+                            "\t\t\t failed_in: {}, failed_out: {},",
+                            summary.failed_in, summary.failed_out
+                        );
+                        println!("\t\t }},");
+                    }
+                    println!("\t}},");
+                }
+                "feepolicy" => {
+                    let policies = fee_policies.policies();
+                    println!("\t{{");
+                    print_fee_policy("global", &policies.global);
+                    for (channel_id, policy) in &policies.channels {
+                        print_fee_policy(channel_id, policy);
+                    }
+                    println!("\t}},");
+                }
+                "setfeepolicy" => {
+                    let channel_id = match words.next() {
+                        Some("all") => None,
+                        Some(id) => match fee_policy::parse_channel_id(id) {
+                            Some(id) => Some(id),
+                            None => {
+                                println!("ERROR: couldn't parse channel_id");
+                                continue;
+                            }
+                        },
+                        None => {
+                            println!("ERROR: setfeepolicy requires a channel id or `all`: `setfeepolicy <channel_id|all> [<option>=<value> ..]`");
+                            continue;
+                        }
+                    };
+                    let mut policy = FeePolicy::default();
+                    if let Err(e) = words
+                        .by_ref()
+                        .try_for_each(|arg| parse_fee_policy_option(&mut policy, arg))
+                    {
+                        println!("ERROR: {}", e);
+                        continue;
+                    }
+                    if policy == FeePolicy::default() {
+                        println!("ERROR: setfeepolicy requires at least one option");
+                        continue;
+                    }
+                    match fee_policies.set(&channel_manager, channel_id, &policy) {
+                        Ok(()) => println!("SUCCESS: fee policy updated"),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "resetfeepolicy" => {
+                    let channel_id = match words.next().map(fee_policy::parse_channel_id) {
+                        Some(Some(id)) => id,
+                        Some(None) => {
+                            println!("ERROR: couldn't parse channel_id");
+                            continue;
+                        }
+                        None => {
+                            println!("ERROR: resetfeepolicy requires a channel id: `resetfeepolicy <channel_id>`");
+                            continue;
+                        }
+                    };
+                    match fee_policies.reset(&channel_manager, channel_id) {
+                        Ok(true) => println!("SUCCESS: channel follows the global fee policy"),
+                        Ok(false) => println!("ERROR: channel has no fee policy of its own"),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "lspregister" => {
+                    let lsp = match lsp.as_ref() {
+                        Some(lsp) => lsp,
+                        None => {
+                            println!("ERROR: LSP mode is off");
+                            continue;
+                        }
+                    };
+                    let client = match words.next().and_then(to_compressed_pubkey) {
+                        Some(client) => client,
+                        None => {
+                            println!("ERROR: lspregister requires a client public key: `lspregister <client_pubkey>`");
+                            continue;
+                        }
+                    };
+                    let payment_size_msat = match words.next().map(|amt| amt.parse::<u64>()) {
+                        Some(Ok(amt)) => Some(amt),
+                        Some(Err(_)) => {
+                            println!("ERROR: invalid payment size, expected millisatoshis");
+                            continue;
+                        }
+                        None => None,
+                    };
+                    let registration = lsp.register(&channel_manager, client, payment_size_msat);
+                    let hint = lsp.route_hint(channel_manager.get_our_node_id(), &registration);
+                    println!("\t{{");
+                    println!("\t\t client: {},", registration.client);
+                    println!("\t\t intercept_scid: {},", hint.short_channel_id);
+                    println!("\t\t route_hint_src_node_id: {},", hint.src_node_id);
+                    println!("\t\t route_hint_fee_base_msat: {},", hint.fee_base_msat);
+                    println!(
+                        "\t\t route_hint_fee_proportional_millionths: {},",
+                        hint.fee_proportional_millionths
+                    );
+                    println!(
+                        "\t\t route_hint_cltv_expiry_delta: {},",
+                        hint.cltv_expiry_delta
+                    );
+                    println!("\t}},");
+                }
+                "lspinvoice" => {
+                    // The amount, then the route hint our LSP gave us.
+                    let args: Vec<&str> = words.by_ref().collect();
+                    let parsed = match args.as_slice() {
+                        [amt, lsp, scid, base, ppm, cltv] => (|| {
+                            Some((
+                                amt.parse::<u64>().ok()?,
+                                RouteHintView {
+                                    src_node_id: to_compressed_pubkey(lsp)?.to_string(),
+                                    short_channel_id: scid.parse().ok()?,
+                                    fee_base_msat: base.parse().ok()?,
+                                    fee_proportional_millionths: ppm.parse().ok()?,
+                                    cltv_expiry_delta: cltv.parse().ok()?,
+                                },
+                            ))
+                        })(),
+                        _ => None,
+                    };
+                    let (amt_msat, route_hint) = match parsed {
+                        Some(parsed) => parsed,
+                        None => {
+                            println!("ERROR: lspinvoice requires an amount and the LSP's route hint: `lspinvoice <amt_msat> <lsp_pubkey> <intercept_scid> <fee_base_msat> <fee_proportional_millionths> <cltv_expiry_delta>`");
+                            continue;
+                        }
+                    };
+                    match lsp::client_invoice(
+                        &channel_manager,
+                        &keys_manager,
+                        network,
+                        &route_hint,
+                        amt_msat,
+                        String::new(),
+                        3600,
+                    ) {
+                        Ok(invoice) => {
+                            println!("SUCCESS: generated invoice: {}", invoice);
+                            inbound_payments.lock().unwrap().insert(
+                                PaymentHash(invoice.payment_hash().into_inner()),
+                                PaymentInfo {
+                                    preimage: None,
+                                    secret: Some(*invoice.payment_secret()),
+                                    status: HTLCStatus::Pending,
+                                    amt_msat: MillisatAmount(Some(amt_msat)),
+                                    internal: false,
+                                },
+                            );
+                        }
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "lspintercepts" => {
+                    let lsp = match lsp.as_ref() {
+                        Some(lsp) => lsp,
+                        None => {
+                            println!("ERROR: LSP mode is off");
+                            continue;
+                        }
+                    };
+                    print!("[");
+                    for intercept in lsp.intercepts() {
+                        println!();
+                        println!("\t{{");
+                        println!("\t\t payment_hash: {},", intercept.payment_hash);
+                        println!("\t\t intercept_scid: {},", intercept.intercept_scid);
+                        println!("\t\t client: {},", intercept.client);
+                        println!(
+                            "\t\t expected_outbound_amount_msat: {},",
+                            intercept.expected_outbound_amount_msat
+                        );
+                        println!("\t\t fee_msat: {},", intercept.fee_msat);
+                        println!("\t\t status: {:?},", intercept.status);
+                        if let Some(reason) = intercept.reason {
+                            println!("\t\t reason: {},", reason);
+                        }
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
+                "approvechannel" | "rejectchannel" => {
+                    let temporary_channel_id = match words.next() {
+                        Some(id) => id.to_lowercase(),
+                        None => {
+                            println!(
+                                "ERROR: {} requires a temporary channel id: `{} <temporary_channel_id>`",
+                                word, word
+                            );
+                            continue;
+                        }
+                    };
+                    let res = if word == "approvechannel" {
+                        channel_acceptor.approve(&channel_manager, &temporary_channel_id)
+                    } else {
+                        channel_acceptor.deny(&channel_manager, &temporary_channel_id)
+                    };
+                    if let Err(e) = res {
+                        println!("ERROR: {}", e);
+                    }
+                }
                 "listpayments" => {
                     list_payments(inbound_payments.clone(), outbound_payments.clone())
                 }
@@ -375,9 +934,16 @@ pub(crate) async fn poll_for_user_input(
                     &peer_manager,
                     &network,
                     &port,
+                    &announcer,
                     &node_name,
-                    &announced_listen_addr,
                 ),
+                "setannounceaddrs" => {
+                    let addresses: Vec<String> = words.map(|a| a.to_string()).collect();
+                    match announcer.set(&addresses) {
+                        Ok(()) => println!("SUCCESS: announcing {:?}", announcer.address_strs()),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
                 "listpeers" => list_peers(peer_manager.clone()),
                 "signmessage" => {
                     const MSG_STARTPOS: usize = "signmessage".len() + 1;
@@ -426,7 +992,7 @@ pub(crate) async fn poll_for_user_input(
                         continue;
                     }
                     let tlv_type = match words.next().map(|ty_str| ty_str.parse()) {
-                        Some(Ok(ty)) if ty >= 64 => ty,
+                        Some(Ok(ty)) => ty,
                         _ => {
                             println!("Need an integral message type above 64");
                             continue;
@@ -440,70 +1006,1000 @@ pub(crate) async fn poll_for_user_input(
                         }
                     };
                     let destination_pk = node_pks.pop().unwrap();
-                    match onion_messenger.send_onion_message(
+                    match onion_messages::send(
+                        &onion_messenger,
                         &node_pks,
                         Destination::Node(destination_pk),
-                        OnionMessageContents::Custom(UserOnionMessageContents { tlv_type, data }),
-                        None,
+                        tlv_type,
+                        data,
                     ) {
                         Ok(()) => println!("SUCCESS: forwarded onion message to first hop"),
-                        Err(e) => println!("ERROR: failed to send onion message: {:?}", e),
+                        Err(e) => println!("ERROR: {}", e),
                     }
                 }
-                "quit" | "exit" => break,
-                _ => println!("Unknown command. See `\"help\" for available commands."),
-            }
-        }
-    }
-}
-
-fn help() {
-    let package_version = env!("CARGO_PKG_VERSION");
-    let package_name = env!("CARGO_PKG_NAME");
-    println!("\nVERSION:");
-    println!("  {} v{}", package_name, package_version);
-    println!("\nUSAGE:");
-    println!("  Command [arguments]");
-    println!("\nCOMMANDS:");
-    println!("  help\tShows a list of commands.");
-    println!("  quit\tClose the application.");
-    println!("\n  Channels:");
-    println!("      openchannel pubkey@host:port <amt_satoshis> [--public]");
-    println!("      closechannel <channel_id> <peer_pubkey>");
-    println!("      forceclosechannel <channel_id> <peer_pubkey>");
-    println!("      listchannels");
-    println!("\n  Peers:");
-    println!("      connectpeer pubkey@host:port");
-    println!("      disconnectpeer <peer_pubkey>");
-    println!("      listpeers");
-    println!("\n  Payments:");
-    println!("      sendpayment <invoice>");
-    println!("      keysend <dest_pubkey> <amt_msats>");
-    println!("      listpayments");
-    println!("\n  Invoices:");
-    println!("      getinvoice <amt_msats> <expiry_secs>");
-    println!("\n  Other:");
-    println!("      signmessage <message>");
-    println!(
-        "      sendonionmessage <node_id_1,node_id_2,..,destination_node_id> <type> <hex_bytes>"
-    );
-    println!("      nodeinfo");
-}
-
-fn node_info(
-    channel_manager: &Arc<ChannelManager>,
-    peer_manager: &Arc<PeerManager>,
-    network: &Network,
-    port: &u16,
-    announced_listen_addr: &str,
-    node_name: &str,
-) {
-    println!("\t{{");
-    println!("\t\t node_pubkey: {}", channel_manager.get_our_node_id());
-    println!("\t\t network: {}", network);
+                "registeronionmessage" | "unregisteronionmessage" => {
+                    let tlv_type = match words.next().map(|ty_str| ty_str.parse::<u64>()) {
+                        Some(Ok(ty)) => ty,
+                        _ => {
+                            println!("ERROR: {} requires a message type: `{} <type>`", word, word);
+                            continue;
+                        }
+                    };
+                    let changed = if word == "registeronionmessage" {
+                        onion_message_handler.registry().register(tlv_type)
+                    } else {
+                        onion_message_handler.registry().unregister(tlv_type)
+                    };
+                    match changed {
+                        Ok(_) => println!(
+                            "SUCCESS: keeping onion messages of types {:?}",
+                            onion_message_handler.registry().types()
+                        ),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "registerpeermessage" | "unregisterpeermessage" => {
+                    let type_id = match words.next().map(|ty_str| ty_str.parse::<u16>()) {
+                        Some(Ok(ty)) => ty,
+                        _ => {
+                            println!("ERROR: {} requires a message type: `{} <type>`", word, word);
+                            continue;
+                        }
+                    };
+                    let changed = if word == "registerpeermessage" {
+                        peer_message_handler.registry().register(type_id)
+                    } else {
+                        peer_message_handler.registry().unregister(type_id)
+                    };
+                    match changed {
+                        Ok(_) => println!(
+                            "SUCCESS: keeping peer messages of types {:?}",
+                            peer_message_handler.registry().types()
+                        ),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "registerpeerfeature" | "unregisterpeerfeature" => {
+                    let bit = match words.next().map(|bit_str| bit_str.parse::<usize>()) {
+                        Some(Ok(bit)) => bit,
+                        _ => {
+                            println!("ERROR: {} requires a feature bit: `{} <bit>`", word, word);
+                            continue;
+                        }
+                    };
+                    let changed = if word == "registerpeerfeature" {
+                        peer_message_handler.feature_bits().register(bit)
+                    } else {
+                        peer_message_handler.feature_bits().unregister(bit)
+                    };
+                    match changed {
+                        Ok(_) => println!(
+                            "SUCCESS: advertising feature bits {:?} to peers that connect from now on",
+                            peer_message_handler.feature_bits().types()
+                        ),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "listpeermessages" => {
+                    let after = match words.next().map(|id| id.parse::<u64>()) {
+                        Some(Ok(id)) => id,
+                        Some(Err(_)) => {
+                            println!("ERROR: invalid message id: `listpeermessages [after_id]`");
+                            continue;
+                        }
+                        None => 0,
+                    };
+                    print!("[");
+                    for received in peer_message_handler.inbox().since(after) {
+                        println!();
+                        println!("\t{{");
+                        println!("\t\t id: {},", received.id);
+                        println!("\t\t peer: {},", received.message.peer);
+                        println!("\t\t type_id: {},", received.message.type_id);
+                        println!("\t\t data: {},", received.message.data);
+                        println!("\t\t received_at: {},", received.message.received_at);
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
+                "sendpeermessage" => {
+                    let peer = words.next().and_then(to_compressed_pubkey);
+                    let type_id = words.next().and_then(|ty| ty.parse::<u16>().ok());
+                    let data = words.next().and_then(to_vec);
+                    if peer.is_none() || type_id.is_none() || data.is_none() {
+                        println!("ERROR: sendpeermessage requires a peer, a message type and hex data: `sendpeermessage <peer_pubkey> <type> <hex_bytes>`");
+                        continue;
+                    }
+                    match peer_messages::send(
+                        &peer_manager,
+                        &peer_message_handler,
+                        peer.unwrap(),
+                        type_id.unwrap(),
+                        data.unwrap(),
+                    ) {
+                        Ok(()) => println!("SUCCESS: queued peer message"),
+                        Err(e) => println!("ERROR: {}", e),
+                    }
+                }
+                "listonionmessages" => {
+                    let after = match words.next().map(|id| id.parse::<u64>()) {
+                        Some(Ok(id)) => id,
+                        Some(Err(_)) => {
+                            println!("ERROR: invalid message id: `listonionmessages [after_id]`");
+                            continue;
+                        }
+                        None => 0,
+                    };
+                    print!("[");
+                    for received in onion_message_handler.inbox().since(after) {
+                        println!();
+                        println!("\t{{");
+                        println!("\t\t id: {},", received.id);
+                        println!("\t\t tlv_type: {},", received.message.tlv_type);
+                        println!("\t\t data: {},", received.message.data);
+                        println!("\t\t received_at: {},", received.message.received_at);
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
+                "createpsbt" => {
+                    let wallet_name = words.next();
+                    let address = words.next();
+                    let amt_sat = words.next();
+                    if wallet_name.is_none() || address.is_none() || amt_sat.is_none() {
+                        println!("ERROR: createpsbt has 3 required arguments: `createpsbt <wallet_name> <address> <amt_satoshis>` [sat_per_vb]");
+                        continue;
+                    }
+                    let address = match Address::from_str(address.unwrap()) {
+                        Ok(address) => address,
+                        Err(e) => {
+                            println!("ERROR: invalid address: {}", e);
+                            continue;
+                        }
+                    };
+                    let amt_sat: u64 = match amt_sat.unwrap().parse() {
+                        Ok(amt) => amt,
+                        Err(_) => {
+                            println!("ERROR: amount must be a number");
+                            continue;
+                        }
+                    };
+                    let mut fee_rate: Option<f32> = None;
+                    let mut utxos: Option<Vec<OutPoint>> = None;
+                    let mut parse_err = false;
+                    for arg in words.by_ref() {
+                        if let Some(list) = arg.strip_prefix("--utxos=") {
+                            match parse_outpoints(list) {
+                                Ok(list) => utxos = Some(list),
+                                Err(e) => {
+                                    println!("ERROR: invalid outpoint: {}", e);
+                                    parse_err = true;
+                                }
+                            }
+                        } else {
+                            match arg.parse() {
+                                Ok(rate) => fee_rate = Some(rate),
+                                Err(_) => {
+                                    println!("ERROR: fee rate must be a number");
+                                    parse_err = true;
+                                }
+                            }
+                        }
+                    }
+                    if parse_err {
+                        continue;
+                    }
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    if let Err(e) = wallet.sync_wallet() {
+                        println!("ERROR: failed to sync wallet: {}", e);
+                        continue;
+                    }
+                    match wallet.create_psbt(vec![(address, amt_sat)], fee_rate, utxos) {
+                        Ok(psbt) => println!("{}", psbt),
+                        Err(e) => println!("ERROR: failed to create psbt: {}", e),
+                    }
+                }
+                "signpsbt" | "finalizepsbt" | "broadcastpsbt" => {
+                    let wallet_name = words.next();
+                    let psbt_str = words.next();
+                    if wallet_name.is_none() || psbt_str.is_none() {
+                        println!(
+                            "ERROR: {} has 2 required arguments: `{} <wallet_name> <base64_psbt>`",
+                            word, word
+                        );
+                        continue;
+                    }
+                    let psbt = match PartiallySignedTransaction::from_str(psbt_str.unwrap()) {
+                        Ok(psbt) => psbt,
+                        Err(e) => {
+                            println!("ERROR: invalid psbt: {}", e);
+                            continue;
+                        }
+                    };
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    let res = match word {
+                        "signpsbt" => wallet.sign_psbt(psbt),
+                        "finalizepsbt" => wallet.finalize_psbt(psbt),
+                        _ => match wallet.broadcast_psbt(psbt) {
+                            Ok(txid) => {
+                                println!("SUCCESS: broadcast transaction {}", txid);
+                                continue;
+                            }
+                            Err(e) => Err(e),
+                        },
+                    };
+                    match res {
+                        Ok((psbt, finalized)) => {
+                            println!("{}", psbt);
+                            println!("finalized: {}", finalized);
+                        }
+                        Err(e) => println!("ERROR: {} failed: {}", word, e),
+                    }
+                }
+                "combinepsbt" => {
+                    let psbts_str = words.next();
+                    if psbts_str.is_none() {
+                        println!("ERROR: combinepsbt requires a list of psbts: `combinepsbt <psbt_1,psbt_2,..>`");
+                        continue;
+                    }
+                    let psbts: Result<Vec<_>, _> = psbts_str
+                        .unwrap()
+                        .split(",")
+                        .map(PartiallySignedTransaction::from_str)
+                        .collect();
+                    let psbts = match psbts {
+                        Ok(psbts) => psbts,
+                        Err(e) => {
+                            println!("ERROR: invalid psbt: {}", e);
+                            continue;
+                        }
+                    };
+                    match BitcoinWallet::combine_psbts(psbts) {
+                        Ok(psbt) => println!("{}", psbt),
+                        Err(e) => println!("ERROR: failed to combine psbts: {}", e),
+                    }
+                }
+                "importwallet" => {
+                    let key = words.next();
+                    if key.is_none() {
+                        println!("ERROR: importwallet requires an xpub or descriptor: `importwallet <xpub|descriptor> [change_descriptor]`");
+                        continue;
+                    }
+                    let key = key.unwrap();
+                    let wallet = if key.contains('(') {
+                        BitcoinWallet::load_with_descriptors(
+                            key.to_string(),
+                            words.next().map(|d| d.to_string()),
+                        )
+                    } else {
+                        BitcoinWallet::load_with_xpub(key)
+                    };
+                    match wallet {
+                        Ok(wallet) => {
+                            println!("SUCCESS: imported watch-only wallet {}", wallet.wallet_name)
+                        }
+                        Err(e) => println!("ERROR: failed to import wallet: {}", e),
+                    }
+                }
+                "restorewallet" => {
+                    let script_type = words.next().map(ScriptType::from_str);
+                    let passphrase = words.next();
+                    let mnemonic = words.clone().collect::<Vec<&str>>().join(" ");
+                    if script_type.is_none() || passphrase.is_none() || mnemonic.is_empty() {
+                        println!("ERROR: restorewallet has 3 required arguments: `restorewallet <bip49|bip84|bip86> <passphrase|-> <mnemonic>`");
+                        continue;
+                    }
+                    let script_type = match script_type.unwrap() {
+                        Ok(script_type) => script_type,
+                        Err(e) => {
+                            println!("ERROR: {}", e);
+                            continue;
+                        }
+                    };
+                    let passphrase = match passphrase.unwrap() {
+                        "-" => None,
+                        passphrase => Some(passphrase.to_string()),
+                    };
+                    match BitcoinWallet::restore_from_mnemonic(&mnemonic, passphrase, script_type) {
+                        Ok(wallet) => {
+                            println!("SUCCESS: restored wallet {}", wallet.wallet_name)
+                        }
+                        Err(e) => println!("ERROR: failed to restore wallet: {}", e),
+                    }
+                }
+                "createmultisig" => {
+                    let threshold = words.next().map(|t| t.parse::<usize>());
+                    let keys = words.next();
+                    if threshold.is_none() || keys.is_none() {
+                        println!("ERROR: createmultisig has 2 required arguments: `createmultisig <threshold> <xpub_1,xpub_2,..>`");
+                        continue;
+                    }
+                    let keys: Vec<String> =
+                        keys.unwrap().split(",").map(|k| k.to_string()).collect();
+                    let threshold = match threshold.unwrap() {
+                        Ok(threshold) if threshold > 0 && threshold <= keys.len() => threshold,
+                        _ => {
+                            println!("ERROR: threshold must be between 1 and the number of keys");
+                            continue;
+                        }
+                    };
+                    let (descriptor, change_descriptor) = multisig_descriptors(threshold, &keys);
+                    match BitcoinWallet::load_with_descriptors(descriptor, Some(change_descriptor))
+                    {
+                        Ok(wallet) => {
+                            println!("SUCCESS: created multisig wallet {}", wallet.wallet_name)
+                        }
+                        Err(e) => println!("ERROR: failed to create multisig wallet: {}", e),
+                    }
+                }
+                "exportdescriptors" => {
+                    let wallet_name = words.next();
+                    if wallet_name.is_none() {
+                        println!("ERROR: exportdescriptors requires a wallet name: `exportdescriptors <wallet_name>`");
+                        continue;
+                    }
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    match wallet.export_descriptors() {
+                        Ok((descriptor, change_descriptor)) => {
+                            println!("\t{{");
+                            println!("\t\t descriptor: {}", descriptor);
+                            if let Some(change_descriptor) = change_descriptor {
+                                println!("\t\t change_descriptor: {}", change_descriptor);
+                            }
+                            println!("\t}},");
+                        }
+                        Err(e) => println!("ERROR: failed to export descriptors: {}", e),
+                    }
+                }
+                "history" => {
+                    let wallet_name = words.next();
+                    if wallet_name.is_none() {
+                        println!("ERROR: history requires a wallet name: `history <wallet_name>` [--offset=<n>] [--limit=<n>] [--from=<unix_ts>] [--to=<unix_ts>]");
+                        continue;
+                    }
+                    let mut query = HistoryQuery::default();
+                    let mut parse_err = false;
+                    for arg in words.by_ref() {
+                        let (key, value) = match arg.split_once('=') {
+                            Some((key, value)) => (key, value),
+                            None => (arg, ""),
+                        };
+                        match key {
+                            "--offset" => query.offset = value.parse().ok(),
+                            "--limit" => query.limit = value.parse().ok(),
+                            "--from" => query.from = value.parse().ok(),
+                            "--to" => query.to = value.parse().ok(),
+                            _ => {
+                                println!("ERROR: unknown option {}", arg);
+                                parse_err = true;
+                            }
+                        }
+                    }
+                    if parse_err {
+                        continue;
+                    }
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    if let Err(e) = wallet.sync_wallet() {
+                        println!("ERROR: failed to sync wallet: {}", e);
+                        continue;
+                    }
+                    let records = disk::read_lightning_records(&ldk_data_dir);
+                    match wallet.history(&query, &records) {
+                        Ok(page) => {
+                            println!("total: {}", page.total);
+                            print!("[");
+                            for entry in page.entries {
+                                println!();
+                                println!("\t{{");
+                                println!("\t\t txid: {},", entry.txid);
+                                println!("\t\t direction: {:?},", entry.direction);
+                                println!("\t\t category: {:?},", entry.category);
+                                println!("\t\t amount_sat: {},", entry.amount);
+                                if let Some(fee) = entry.fee {
+                                    println!("\t\t fee_sat: {},", fee);
+                                }
+                                match (entry.confirmation_height, entry.confirmation_time) {
+                                    (Some(height), Some(time)) => {
+                                        println!("\t\t confirmation_height: {},", height);
+                                        println!("\t\t confirmation_time: {},", time);
+                                    }
+                                    _ => println!("\t\t confirmation_height: unconfirmed,"),
+                                }
+                                if let Some(label) = entry.label {
+                                    println!("\t\t label: {},", label);
+                                }
+                                println!("\t}},");
+                            }
+                            println!("]");
+                        }
+                        Err(e) => println!("ERROR: failed to list history: {}", e),
+                    }
+                }
+                "scorechannel" => {
+                    let scid = words.next().and_then(|scid| scid.parse::<u64>().ok());
+                    if scid.is_none() {
+                        println!("ERROR: scorechannel requires a short channel id: `scorechannel <short_channel_id>`");
+                        continue;
+                    }
+                    let scorer = scorer.lock().unwrap();
+                    match scoring::channel_score(&scorer, &network_graph, scid.unwrap()) {
+                        Some(score) => {
+                            print!("[");
+                            for direction in score.directions {
+                                println!();
+                                println!("\t{{");
+                                println!("\t\t towards: {},", direction.target);
+                                match direction.liquidity_range_msat {
+                                    Some((min, max)) => {
+                                        println!("\t\t liquidity_msat: {}..{},", min, max)
+                                    }
+                                    None => println!("\t\t liquidity_msat: unknown,"),
+                                }
+                                if let Some((min_buckets, max_buckets)) =
+                                    direction.historical_buckets
+                                {
+                                    println!("\t\t historical_min_buckets: {:?},", min_buckets);
+                                    println!("\t\t historical_max_buckets: {:?},", max_buckets);
+                                }
+                                println!("\t}},");
+                            }
+                            println!("]");
+                        }
+                        None => println!(
+                            "ERROR: channel {} is not in the network graph",
+                            scid.unwrap()
+                        ),
+                    }
+                }
+                "graphinfo" => {
+                    let stats = graph::stats(&network_graph);
+                    println!("\t{{");
+                    println!("\t\t num_nodes: {},", stats.num_nodes);
+                    println!("\t\t num_announced_nodes: {},", stats.num_announced_nodes);
+                    println!("\t\t num_channels: {},", stats.num_channels);
+                    println!("\t\t total_capacity_sat: {},", stats.total_capacity_sat);
+                    println!("\t}},");
+                }
+                "getnode" => {
+                    let query = words.collect::<Vec<_>>().join(" ");
+                    if query.is_empty() {
+                        println!(
+                            "ERROR: getnode requires a pubkey or alias: `getnode <pubkey|alias>`"
+                        );
+                        continue;
+                    }
+                    let nodes = graph::find_nodes(&network_graph, &query);
+                    if nodes.is_empty() {
+                        println!("ERROR: no node matching {} in the network graph", query);
+                        continue;
+                    }
+                    print!("[");
+                    for node in nodes {
+                        println!();
+                        println!("\t{{");
+                        println!("\t\t node_id: {},", node.node_id);
+                        if let Some(alias) = node.alias {
+                            println!("\t\t alias: {},", alias);
+                        }
+                        println!("\t\t addresses: {:?},", node.addresses);
+                        if let Some(last_update) = node.last_update {
+                            println!("\t\t last_update: {},", last_update);
+                        }
+                        println!("\t\t channels: {:?},", node.channels);
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
+                "getchannel" => {
+                    let scid = words.next().and_then(|scid| scid.parse::<u64>().ok());
+                    if scid.is_none() {
+                        println!("ERROR: getchannel requires a short channel id: `getchannel <short_channel_id>`");
+                        continue;
+                    }
+                    match graph::find_channel(&network_graph, scid.unwrap()) {
+                        Some(channel) => {
+                            println!("\t{{");
+                            println!("\t\t short_channel_id: {},", channel.short_channel_id);
+                            println!("\t\t node_one: {},", channel.node_one);
+                            println!("\t\t node_two: {},", channel.node_two);
+                            if let Some(capacity_sat) = channel.capacity_sat {
+                                println!("\t\t capacity_sat: {},", capacity_sat);
+                            }
+                            for (name, direction) in [
+                                ("one_to_two", channel.one_to_two),
+                                ("two_to_one", channel.two_to_one),
+                            ] {
+                                match direction {
+                                    Some(direction) => {
+                                        println!("\t\t {}: {{", name);
+                                        println!("\t\t\t enabled: {},", direction.enabled);
+                                        println!(
+                                            "\t\t\t cltv_expiry_delta: {},",
+                                            direction.cltv_expiry_delta
+                                        );
+                                        println!(
+                                            "\t\t\t htlc_msat: {}..{},",
+                                            direction.htlc_minimum_msat,
+                                            direction.htlc_maximum_msat
+                                        );
+                                        println!(
+                                            "\t\t\t fee_base_msat: {},",
+                                            direction.fee_base_msat
+                                        );
+                                        println!(
+                                            "\t\t\t fee_proportional_millionths: {},",
+                                            direction.fee_proportional_millionths
+                                        );
+                                        println!("\t\t }},");
+                                    }
+                                    None => println!("\t\t {}: unknown,", name),
+                                }
+                            }
+                            println!("\t}},");
+                        }
+                        None => println!(
+                            "ERROR: channel {} is not in the network graph",
+                            scid.unwrap()
+                        ),
+                    }
+                }
+                "queryroutes" => {
+                    let destination = words.next().and_then(to_compressed_pubkey);
+                    let amount_msat = words.next().and_then(|amt| amt.parse::<u64>().ok());
+                    if destination.is_none() || amount_msat.is_none() {
+                        println!("ERROR: queryroutes requires a destination pubkey and an amount: `queryroutes <pubkey> <amt_msat>`");
+                        continue;
+                    }
+                    match graph::query_routes(
+                        &router,
+                        &channel_manager,
+                        &network_graph,
+                        destination.unwrap(),
+                        amount_msat.unwrap(),
+                        graph::DEFAULT_FINAL_CLTV_EXPIRY_DELTA,
+                    ) {
+                        Ok(preview) => {
+                            println!("\t{{");
+                            println!("\t\t amount_msat: {},", preview.amount_msat);
+                            println!("\t\t total_fees_msat: {},", preview.total_fees_msat);
+                            println!("\t\t paths: [");
+                            for path in preview.paths {
+                                println!("\t\t\t {{");
+                                println!("\t\t\t\t amount_msat: {},", path.amount_msat);
+                                println!("\t\t\t\t fees_msat: {},", path.fees_msat);
+                                println!(
+                                    "\t\t\t\t total_cltv_expiry_delta: {},",
+                                    path.total_cltv_expiry_delta
+                                );
+                                for hop in path.hops {
+                                    println!(
+                                        "\t\t\t\t hop: {} ({}) via {}, fee_msat: {}, cltv_expiry_delta: {},",
+                                        hop.node_id,
+                                        hop.alias.unwrap_or_default(),
+                                        hop.short_channel_id,
+                                        hop.fee_msat,
+                                        hop.cltv_expiry_delta
+                                    );
+                                }
+                                println!("\t\t\t }},");
+                            }
+                            println!("\t\t ]");
+                            println!("\t}},");
+                        }
+                        Err(e) => println!("ERROR: no route found: {}", e),
+                    }
+                }
+                "probe" => {
+                    let target = words.next();
+                    let amount_msat = words.next().and_then(|amt| amt.parse::<u64>().ok());
+                    if target.is_none() {
+                        println!("ERROR: probe requires an invoice or a destination pubkey and an amount: `probe <invoice|pubkey> [amt_msat]`");
+                        continue;
+                    }
+                    let route_params = match to_compressed_pubkey(target.unwrap()) {
+                        Some(destination) => match amount_msat {
+                            Some(amount_msat) => {
+                                probing::destination_params(destination, amount_msat)
+                            }
+                            None => {
+                                println!("ERROR: probing a destination requires an amount: `probe <pubkey> <amt_msat>`");
+                                continue;
+                            }
+                        },
+                        None => match Invoice::from_str(target.unwrap()) {
+                            Ok(invoice) => match probing::invoice_params(&invoice, amount_msat) {
+                                Ok(route_params) => route_params,
+                                Err(e) => {
+                                    println!("ERROR: {}", e);
+                                    continue;
+                                }
+                            },
+                            Err(e) => {
+                                println!("ERROR: invalid invoice or pubkey: {:?}", e);
+                                continue;
+                            }
+                        },
+                    };
+                    match probing::probe(
+                        &router,
+                        &channel_manager,
+                        &probes,
+                        &route_params,
+                        Duration::from_secs(60),
+                    )
+                    .await
+                    {
+                        Ok(report) => {
+                            println!("\t{{");
+                            println!("\t\t status: {:?},", report.status);
+                            println!("\t\t amount_msat: {},", report.amount_msat);
+                            println!("\t\t total_fees_msat: {},", report.total_fees_msat);
+                            for path in report.paths {
+                                println!(
+                                    "\t\t path: {:?} {:?}, amount_msat: {}, fee_msat: {},",
+                                    path.short_channel_ids,
+                                    path.status,
+                                    path.amount_msat,
+                                    path.fee_msat
+                                );
+                                if path.status == ProbeStatus::Failed {
+                                    println!(
+                                        "\t\t\t failed at channel {} of node {},",
+                                        path.failed_short_channel_id
+                                            .map_or("unknown".to_string(), |scid| scid.to_string()),
+                                        path.failing_node.unwrap_or("ours".to_string())
+                                    );
+                                }
+                            }
+                            if let Some(e) = report.send_error {
+                                println!("\t\t remaining paths not sent: {},", e);
+                            }
+                            println!("\t}},");
+                        }
+                        Err(e) => println!("ERROR: failed to send probe: {}", e),
+                    }
+                }
+                "listattempts" => {
+                    let payment_hash = words.next().and_then(to_vec);
+                    if payment_hash.as_ref().is_none_or(|hash| hash.len() != 32) {
+                        println!("ERROR: listattempts requires a payment hash: `listattempts <payment_hash>`");
+                        continue;
+                    }
+                    let mut payment_id = [0; 32];
+                    payment_id.copy_from_slice(&payment_hash.unwrap());
+                    let attempts = payment_attempts.lock().unwrap();
+                    print!("[");
+                    for attempt in attempts.get(&PaymentId(payment_id)).into_iter().flatten() {
+                        println!();
+                        println!("\t{{");
+                        println!("\t\t status: {:?},", attempt.status);
+                        println!("\t\t amount_msat: {},", attempt.amount_msat);
+                        println!("\t\t fee_msat: {},", attempt.fee_msat);
+                        println!("\t\t short_channel_ids: {:?},", attempt.short_channel_ids);
+                        if let Some(scid) = attempt.failed_short_channel_id {
+                            println!("\t\t failed_short_channel_id: {},", scid);
+                            println!(
+                                "\t\t failing_node: {},",
+                                attempt.failing_node.as_deref().unwrap_or("ours")
+                            );
+                        }
+                        if attempt.payment_failed_permanently {
+                            println!("\t\t payment_failed_permanently: true,");
+                        }
+                        println!("\t\t timestamp: {},", attempt.timestamp);
+                        println!("\t}},");
+                    }
+                    println!("]");
+                }
+                "listutxos" => {
+                    let wallet_name = words.next();
+                    if wallet_name.is_none() {
+                        println!(
+                            "ERROR: listutxos requires a wallet name: `listutxos <wallet_name>`"
+                        );
+                        continue;
+                    }
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    if let Err(e) = wallet.sync_wallet() {
+                        println!("ERROR: failed to sync wallet: {}", e);
+                        continue;
+                    }
+                    match wallet.list_utxos() {
+                        Ok(utxos) => {
+                            print!("[");
+                            for utxo in utxos {
+                                println!();
+                                println!("\t{{");
+                                println!("\t\t outpoint: {},", utxo.outpoint);
+                                println!("\t\t value_sat: {},", utxo.value);
+                                println!("\t\t confirmations: {},", utxo.confirmations);
+                                println!("\t\t keychain: {:?},", utxo.keychain);
+                                if let Some(label) = utxo.label {
+                                    println!("\t\t label: {},", label);
+                                }
+                                println!("\t\t frozen: {},", utxo.frozen);
+                                println!("\t}},");
+                            }
+                            println!("]");
+                        }
+                        Err(e) => println!("ERROR: failed to list utxos: {}", e),
+                    }
+                }
+                "freezeutxo" | "unfreezeutxo" => {
+                    let wallet_name = words.next();
+                    let outpoint = words.next();
+                    if wallet_name.is_none() || outpoint.is_none() {
+                        println!(
+                            "ERROR: {} has 2 required arguments: `{} <wallet_name> <txid:vout>`",
+                            word, word
+                        );
+                        continue;
+                    }
+                    let outpoint = match OutPoint::from_str(outpoint.unwrap()) {
+                        Ok(outpoint) => outpoint,
+                        Err(e) => {
+                            println!("ERROR: invalid outpoint: {}", e);
+                            continue;
+                        }
+                    };
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    match wallet.set_frozen(&outpoint, word == "freezeutxo") {
+                        Ok(()) => println!("SUCCESS: {} {}", word, outpoint),
+                        Err(e) => println!("ERROR: {} failed: {}", word, e),
+                    }
+                }
+                "setlabel" => {
+                    let wallet_name = words.next();
+                    let label_type = words.next();
+                    let reference = words.next();
+                    let label = words.clone().collect::<Vec<&str>>().join(" ");
+                    if wallet_name.is_none()
+                        || label_type.is_none()
+                        || reference.is_none()
+                        || label.is_empty()
+                    {
+                        println!("ERROR: setlabel has 4 required arguments: `setlabel <wallet_name> <tx|addr|pubkey|input|output|xpub> <ref> <label>`");
+                        continue;
+                    }
+                    let label_type = match LabelType::from_str(label_type.unwrap()) {
+                        Ok(label_type) => label_type,
+                        Err(e) => {
+                            println!("ERROR: {}", e);
+                            continue;
+                        }
+                    };
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    match wallet.set_label(label_type, reference.unwrap(), label) {
+                        Ok(()) => println!("SUCCESS: labelled {}", reference.unwrap()),
+                        Err(e) => println!("ERROR: failed to set label: {}", e),
+                    }
+                }
+                "exportlabels" | "importlabels" => {
+                    let wallet_name = words.next();
+                    let file = words.next();
+                    if wallet_name.is_none() || file.is_none() {
+                        println!(
+                            "ERROR: {} has 2 required arguments: `{} <wallet_name> <file_path>`",
+                            word, word
+                        );
+                        continue;
+                    }
+                    let wallet = match load_wallet(wallet_name.unwrap()) {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    let res = if word == "exportlabels" {
+                        wallet.export_labels().and_then(|labels| {
+                            std::fs::write(file.unwrap(), labels)
+                                .map(|_| 0)
+                                .map_err(|e| bdk::Error::Generic(e.to_string()))
+                        })
+                    } else {
+                        std::fs::read_to_string(file.unwrap())
+                            .map_err(|e| bdk::Error::Generic(e.to_string()))
+                            .and_then(|labels| wallet.import_labels(&labels))
+                    };
+                    match res {
+                        Ok(count) if word == "importlabels" => {
+                            println!("SUCCESS: imported {} labels", count)
+                        }
+                        Ok(_) => println!("SUCCESS: exported labels to {}", file.unwrap()),
+                        Err(e) => println!("ERROR: {} failed: {}", word, e),
+                    }
+                }
+                "quit" | "exit" => break,
+                _ => println!("Unknown command. See `\"help\" for available commands."),
+            }
+        }
+    }
+}
+
+fn load_wallet(wallet_name: &str) -> Option<BitcoinWallet> {
+    match BitcoinWallet::load_by_wallet_name(wallet_name.to_string()) {
+        Ok(wallet) => Some(wallet),
+        Err(e) => {
+            println!("ERROR: failed to load wallet: {}", e);
+            None
+        }
+    }
+}
+
+fn print_total_funds(funds: &balances::TotalFunds) {
+    print!("[");
+    for channel in &funds.channels {
+        println!();
+        println!("\t{{");
+        println!("\t\t channel_id: {},", channel.channel_id);
+        if let Some(node_id) = &channel.counterparty_node_id {
+            println!("\t\t counterparty_pubkey: {},", node_id);
+        }
+        println!("\t\t open: {},", channel.open);
+        for balance in &channel.balances {
+            println!("\t\t {:?},", balance);
+        }
+        println!("\t\t total_sats: {},", channel.total_sats);
+        println!("\t}},");
+    }
+    println!("]");
+    let lightning = &funds.lightning;
+    println!(
+        "claimable_on_channel_close_sats: {}",
+        lightning.claimable_on_channel_close_sats
+    );
+    println!(
+        "claimable_awaiting_confirmations_sats: {}",
+        lightning.claimable_awaiting_confirmations_sats
+    );
+    println!(
+        "contentious_claimable_sats: {}",
+        lightning.contentious_claimable_sats
+    );
+    println!(
+        "maybe_timeout_claimable_htlc_sats: {}",
+        lightning.maybe_timeout_claimable_htlc_sats
+    );
+    println!(
+        "maybe_preimage_claimable_htlc_sats: {}",
+        lightning.maybe_preimage_claimable_htlc_sats
+    );
+    println!(
+        "counterparty_revoked_output_claimable_sats: {}",
+        lightning.counterparty_revoked_output_claimable_sats
+    );
+    println!("lightning_sats: {}", funds.lightning_sats);
+    println!("onchain_confirmed_sats: {}", funds.onchain.confirmed_sats);
+    println!(
+        "onchain_pending_sats: {}",
+        funds.onchain.trusted_pending_sats + funds.onchain.untrusted_pending_sats
+    );
+    println!("onchain_immature_sats: {}", funds.onchain.immature_sats);
+    println!("onchain_sats: {}", funds.onchain_sats);
+    println!("total_sats: {}", funds.total_sats);
+}
+
+fn help() {
+    let package_version = env!("CARGO_PKG_VERSION");
+    let package_name = env!("CARGO_PKG_NAME");
+    println!("\nVERSION:");
+    println!("  {} v{}", package_name, package_version);
+    println!("\nUSAGE:");
+    println!("  Command [arguments]");
+    println!("\nCOMMANDS:");
+    println!("  help\tShows a list of commands.");
+    println!("  quit\tClose the application.");
+    println!("\n  Channels:");
+    println!("      openchannel pubkey@host:port <amt_satoshis> [--public] [--utxos=txid:vout,..]");
+    println!("      closechannel <channel_id> <peer_pubkey>");
+    println!("      forceclosechannel <channel_id> <peer_pubkey>");
+    println!("      listchannels");
+    println!("      channelhistory <channel_id>");
+    println!("      balances");
+    println!("      listchannelrequests");
+    println!("      approvechannel <temporary_channel_id>");
+    println!("      rejectchannel <temporary_channel_id>");
+    println!("      feepolicy");
+    println!("      setfeepolicy <channel_id|all> [<option>=<value> ..]");
+    println!("        options: base_msat, ppm, cltv_delta, max_dust_msat");
+    println!("      resetfeepolicy <channel_id>");
+    println!("      rebalance <source_channel_id> <target_channel_id> <amt_msat> <max_fee_msat>");
+    println!("      lspregister <client_pubkey> [payment_size_msat]");
+    println!("      lspinvoice <amt_msat> <lsp_pubkey> <intercept_scid> <fee_base_msat> <fee_proportional_millionths> <cltv_expiry_delta>");
+    println!("      lspintercepts");
+    println!("\n  Peers:");
+    println!("      connectpeer pubkey@host:port");
+    println!("      disconnectpeer <peer_pubkey>");
+    println!("      listpeers");
+    println!("      addpeer pubkey@host:port [--keep-connected]");
+    println!("      removepeer <peer_pubkey>");
+    println!("      listpeerstore");
+    println!("      sendpeermessage <peer_pubkey> <type> <hex_bytes>");
+    println!("      registerpeermessage <type>");
+    println!("      unregisterpeermessage <type>");
+    println!("      registerpeerfeature <odd bit, 256 or more>");
+    println!("      unregisterpeerfeature <bit>");
+    println!("      listpeermessages [after_id]");
+    println!("\n  Payments:");
+    println!("      sendpayment <invoice> [amt_msat=<amt>] [<option>=<value> ..]");
+    println!("      keysend <dest_pubkey> <amt_msats> [<option>=<value> ..]");
+    println!("      listattempts <payment_hash>");
+    println!("        options: max_fee_msat, max_fee_ppm, max_paths, max_cltv, exclude_node,");
+    println!("                 exclude_channel, retry_attempts, retry_timeout_secs, final_cltv");
+    println!("      listpayments");
+    println!("\n  Invoices:");
+    println!("      getinvoice <amt_msats> <expiry_secs>");
+    println!("\n  On-chain:");
+    println!("      createpsbt <wallet_name> <address> <amt_satoshis> [sat_per_vb] [--utxos=txid:vout,..]");
+    println!("      signpsbt <wallet_name> <base64_psbt>");
+    println!("      combinepsbt <psbt_1,psbt_2,..>");
+    println!("      finalizepsbt <wallet_name> <base64_psbt>");
+    println!("      broadcastpsbt <wallet_name> <base64_psbt>");
+    println!("      importwallet <xpub|descriptor> [change_descriptor]");
+    println!("      restorewallet <bip49|bip84|bip86> <passphrase|-> <mnemonic>");
+    println!("      createmultisig <threshold> <xpub_1,xpub_2,..>");
+    println!("      exportdescriptors <wallet_name>");
+    println!("      history <wallet_name> [--offset=<n>] [--limit=<n>] [--from=<unix_ts>] [--to=<unix_ts>]");
+    println!("      listutxos <wallet_name>");
+    println!("      freezeutxo <wallet_name> <txid:vout>");
+    println!("      unfreezeutxo <wallet_name> <txid:vout>");
+    println!("      setlabel <wallet_name> <tx|addr|pubkey|input|output|xpub> <ref> <label>");
+    println!("      exportlabels <wallet_name> <file_path>");
+    println!("      importlabels <wallet_name> <file_path>");
+    println!("\n  Routing:");
+    println!("      scorechannel <short_channel_id>");
+    println!("      graphinfo");
+    println!("      getnode <pubkey|alias>");
+    println!("      getchannel <short_channel_id>");
+    println!("      queryroutes <pubkey> <amt_msat>");
+    println!("      probe <invoice|pubkey> [amt_msat]");
+    println!("      listforwards [window_secs]");
+    println!("      revenue <channels|peers> [window_secs]");
+    println!("\n  Other:");
+    println!("      signmessage <message>");
+    println!(
+        "      sendonionmessage <node_id_1,node_id_2,..,destination_node_id> <type> <hex_bytes>"
+    );
+    println!("      registeronionmessage <type>");
+    println!("      unregisteronionmessage <type>");
+    println!("      listonionmessages [after_id]");
+    println!("      nodeinfo");
+    println!("      setannounceaddrs [host[:port]] [host[:port]] ..");
+}
+
+fn node_info(
+    channel_manager: &Arc<ChannelManager>,
+    peer_manager: &Arc<PeerManager>,
+    network: &Network,
+    port: &u16,
+    announcer: &Announcer,
+    node_name: &str,
+) {
+    println!("\t{{");
+    println!("\t\t node_pubkey: {}", channel_manager.get_our_node_id());
+    println!("\t\t network: {}", network);
     println!("\t\t port: {}", port);
     println!("\t\t node_name: {}", node_name);
-    println!("\t\t announced_listen_addr: {}", announced_listen_addr);
+    println!("\t\t announce_addresses: {:?}", announcer.address_strs());
     let chans = channel_manager.list_channels();
     println!("\t\t num_channels: {}", chans.len());
     println!(
@@ -552,6 +2048,25 @@ fn list_channels(channel_manager: &Arc<ChannelManager>, network_graph: &Arc<Netw
             println!("\t\tshort_channel_id: {},", id);
         }
         println!("\t\tis_channel_ready: {},", chan_info.is_channel_ready);
+        if let Some(channel_type) = &chan_info.channel_type {
+            println!(
+                "\t\tchannel_type: {:?},",
+                channels::channel_type_names(channel_type)
+            );
+        }
+        println!("\t\tzero_conf: {},", channels::is_zero_conf(&chan_info));
+        if let Some(config) = &chan_info.config {
+            println!("\t\tfee_base_msat: {},", config.forwarding_fee_base_msat);
+            println!(
+                "\t\tfee_proportional_millionths: {},",
+                config.forwarding_fee_proportional_millionths
+            );
+            println!("\t\tcltv_expiry_delta: {},", config.cltv_expiry_delta);
+            println!(
+                "\t\tmax_dust_htlc_exposure_msat: {},",
+                config.max_dust_htlc_exposure_msat
+            );
+        }
         println!(
             "\t\tchannel_value_satoshis: {},",
             chan_info.channel_value_satoshis
@@ -592,6 +2107,9 @@ fn list_payments(inbound_payments: PaymentInfoStorage, outbound_payments: Paymen
                 HTLCStatus::Failed => "failed",
             }
         );
+        if payment_info.internal {
+            println!("\t\tinternal_transfer: true,");
+        }
 
         println!("\t}},");
     }
@@ -610,6 +2128,9 @@ fn list_payments(inbound_payments: PaymentInfoStorage, outbound_payments: Paymen
                 HTLCStatus::Failed => "failed",
             }
         );
+        if payment_info.internal {
+            println!("\t\tinternal_transfer: true,");
+        }
 
         println!("\t}},");
     }
@@ -641,26 +2162,35 @@ pub(crate) async fn do_connect_peer(
     match lightning_net_tokio::connect_outbound(Arc::clone(&peer_manager), pubkey, peer_addr).await
     {
         Some(connection_closed_future) => {
-            let mut connection_closed_future = Box::pin(connection_closed_future);
-            loop {
-                tokio::select! {
-                    _ = &mut connection_closed_future => return Err(()),
-                    _ = tokio::time::sleep(Duration::from_millis(10)) => {},
-                };
-                if peer_manager
-                    .get_peer_node_ids()
-                    .iter()
-                    .find(|(id, _)| *id == pubkey)
-                    .is_some()
-                {
-                    return Ok(());
-                }
-            }
+            await_connection(pubkey, peer_manager, connection_closed_future).await
         }
         None => Err(()),
     }
 }
 
+/// Waits for the handshake with `pubkey` to complete, or the connection to close first.
+pub(crate) async fn await_connection(
+    pubkey: PublicKey,
+    peer_manager: Arc<PeerManager>,
+    connection_closed_future: impl std::future::Future<Output = ()>,
+) -> Result<(), ()> {
+    let mut connection_closed_future = Box::pin(connection_closed_future);
+    loop {
+        tokio::select! {
+            _ = &mut connection_closed_future => return Err(()),
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {},
+        };
+        if peer_manager
+            .get_peer_node_ids()
+            .iter()
+            .find(|(id, _)| *id == pubkey)
+            .is_some()
+        {
+            return Ok(());
+        }
+    }
+}
+
 fn do_disconnect_peer(
     pubkey: bitcoin::secp256k1::PublicKey,
     peer_manager: Arc<PeerManager>,
@@ -689,22 +2219,27 @@ fn open_channel(
     peer_pubkey: PublicKey,
     channel_amt_sat: u64,
     announced_channel: bool,
+    utxos: Option<Vec<OutPoint>>,
+    channel_open_config: &ChannelOpenConfig,
+    fee_policies: &FeePolicyStore,
     channel_manager: Arc<ChannelManager>,
+    bdk_wallet: &BitcoinWallet,
 ) -> Result<(), ()> {
-    let config = UserConfig {
-        channel_handshake_limits: ChannelHandshakeLimits {
-            // lnd's max to_self_delay is 2016, so we want to be compatible.
-            their_to_self_delay: 2016,
-            ..Default::default()
-        },
-        channel_handshake_config: ChannelHandshakeConfig {
-            announced_channel,
-            ..Default::default()
-        },
-        ..Default::default()
-    };
+    let config = channel_open_config.user_config(announced_channel, fee_policies.default_config());
 
-    match channel_manager.create_channel(peer_pubkey, channel_amt_sat, 0, 0, Some(config)) {
+    // The funding transaction is built once the peer accepts, so remember which coins the user
+    // picked under the channel's `user_channel_id`.
+    let user_channel_id = u128::from_be_bytes(rand::random());
+    if let Some(utxos) = utxos {
+        bdk_wallet.reserve_channel_utxos(user_channel_id, utxos);
+    }
+    match channel_manager.create_channel(
+        peer_pubkey,
+        channel_amt_sat,
+        0,
+        user_channel_id,
+        Some(config),
+    ) {
         Ok(_) => {
             println!("EVENT: initiated channel with peer {}. ", peer_pubkey);
             return Ok(());
@@ -716,28 +2251,109 @@ fn open_channel(
     }
 }
 
+fn parse_outpoints(
+    list: &str,
+) -> Result<Vec<OutPoint>, bitcoin::blockdata::transaction::ParseOutPointError> {
+    list.split(",").map(OutPoint::from_str).collect()
+}
+
+/// Parses a `<option>=<value>` payment argument into `options`.
+fn parse_payment_option(options: &mut PaymentOptions, arg: &str) -> Result<(), String> {
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected <option>=<value>, got {}", arg))?;
+    let invalid = |e: std::num::ParseIntError| format!("invalid {}: {}", key, e);
+    match key {
+        "max_fee_msat" => options.max_fee_msat = Some(value.parse().map_err(invalid)?),
+        "max_fee_ppm" => options.max_fee_ppm = Some(value.parse().map_err(invalid)?),
+        "max_paths" => options.max_paths = Some(value.parse().map_err(invalid)?),
+        "max_cltv" => options.max_cltv_expiry_delta = Some(value.parse().map_err(invalid)?),
+        "exclude_node" => options.excluded_nodes.push(value.to_string()),
+        "exclude_channel" => options
+            .excluded_channels
+            .push(value.parse().map_err(invalid)?),
+        "retry_attempts" => options.retry = RetryPolicy::Attempts(value.parse().map_err(invalid)?),
+        "retry_timeout_secs" => {
+            options.retry = RetryPolicy::TimeoutSecs(value.parse().map_err(invalid)?)
+        }
+        "final_cltv" => options.final_cltv_expiry_delta = Some(value.parse().map_err(invalid)?),
+        _ => return Err(format!("unknown payment option {}", key)),
+    }
+    Ok(())
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn print_fee_policy(name: &str, policy: &FeePolicy) {
+    println!("\t\t{}: {{", name);
+    if let Some(fee_base_msat) = policy.fee_base_msat {
+        println!("\t\t\tfee_base_msat: {},", fee_base_msat);
+    }
+    if let Some(ppm) = policy.fee_proportional_millionths {
+        println!("\t\t\tfee_proportional_millionths: {},", ppm);
+    }
+    if let Some(cltv_expiry_delta) = policy.cltv_expiry_delta {
+        println!("\t\t\tcltv_expiry_delta: {},", cltv_expiry_delta);
+    }
+    if let Some(max_dust_msat) = policy.max_dust_htlc_exposure_msat {
+        println!("\t\t\tmax_dust_htlc_exposure_msat: {},", max_dust_msat);
+    }
+    println!("\t\t}},");
+}
+
+/// Parses a `<option>=<value>` fee policy argument into `policy`.
+fn parse_fee_policy_option(policy: &mut FeePolicy, arg: &str) -> Result<(), String> {
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected <option>=<value>, got {}", arg))?;
+    let invalid = |e: std::num::ParseIntError| format!("invalid {}: {}", key, e);
+    match key {
+        "base_msat" => policy.fee_base_msat = Some(value.parse().map_err(invalid)?),
+        "ppm" => policy.fee_proportional_millionths = Some(value.parse().map_err(invalid)?),
+        "cltv_delta" => policy.cltv_expiry_delta = Some(value.parse().map_err(invalid)?),
+        "max_dust_msat" => {
+            policy.max_dust_htlc_exposure_msat = Some(value.parse().map_err(invalid)?)
+        }
+        _ => return Err(format!("unknown fee policy option {}", key)),
+    }
+    Ok(())
+}
+
 fn send_payment(
     channel_manager: &ChannelManager,
+    router: &Router,
+    network_graph: &NetworkGraph,
     invoice: &Invoice,
+    amt_msat: Option<u64>,
+    options: &PaymentOptions,
     payment_storage: PaymentInfoStorage,
 ) {
-    let status = match pay_invoice(
-        invoice,
-        Retry::Timeout(Duration::from_secs(10)),
+    let amt_msat = invoice.amount_milli_satoshis().or(amt_msat);
+    let status = match payments::pay_invoice(
         channel_manager,
+        router,
+        network_graph,
+        invoice,
+        amt_msat,
+        options,
     ) {
         Ok(_payment_id) => {
             let payee_pubkey = invoice.recover_payee_pub_key();
-            let amt_msat = invoice.amount_milli_satoshis().unwrap();
             println!(
                 "EVENT: initiated sending {} msats to {}",
-                amt_msat, payee_pubkey
+                amt_msat.unwrap(),
+                payee_pubkey
             );
             print!("> ");
             HTLCStatus::Pending
         }
         Err(e) => {
-            println!("ERROR: failed to send payment: {:?}", e);
+            println!("ERROR: failed to send payment: {}", e);
             print!("> ");
             HTLCStatus::Failed
         }
@@ -752,33 +2368,35 @@ fn send_payment(
             preimage: None,
             secret: payment_secret,
             status,
-            amt_msat: MillisatAmount(invoice.amount_milli_satoshis()),
+            amt_msat: MillisatAmount(amt_msat),
+            internal: false,
         },
     );
 }
 
 fn keysend<E: EntropySource>(
     channel_manager: &ChannelManager,
+    router: &Router,
+    network_graph: &NetworkGraph,
     payee_pubkey: PublicKey,
     amt_msat: u64,
+    options: &PaymentOptions,
     entropy_source: &E,
     payment_storage: PaymentInfoStorage,
 ) {
     let payment_preimage = PaymentPreimage(entropy_source.get_secure_random_bytes());
     let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0[..]).into_inner());
 
-    let route_params = RouteParameters {
-        payment_params: PaymentParameters::for_keysend(payee_pubkey, 40),
-        final_value_msat: amt_msat,
-    };
-    let status = match channel_manager.send_spontaneous_payment_with_retry(
-        Some(payment_preimage),
-        RecipientOnionFields::spontaneous_empty(),
-        PaymentId(payment_hash.0),
-        route_params,
-        Retry::Timeout(Duration::from_secs(10)),
+    let status = match payments::keysend(
+        channel_manager,
+        router,
+        network_graph,
+        payee_pubkey,
+        amt_msat,
+        payment_preimage,
+        options,
     ) {
-        Ok(_payment_hash) => {
+        Ok(_payment_id) => {
             println!(
                 "EVENT: initiated sending {} msats to {}",
                 amt_msat, payee_pubkey
@@ -787,7 +2405,7 @@ fn keysend<E: EntropySource>(
             HTLCStatus::Pending
         }
         Err(e) => {
-            println!("ERROR: failed to send payment: {:?}", e);
+            println!("ERROR: failed to send payment: {}", e);
             print!("> ");
             HTLCStatus::Failed
         }
@@ -801,6 +2419,7 @@ fn keysend<E: EntropySource>(
             secret: None,
             status,
             amt_msat: MillisatAmount(Some(amt_msat)),
+            internal: false,
         },
     );
 }
@@ -849,6 +2468,7 @@ fn get_invoice(
             secret: Some(invoice.payment_secret().clone()),
             status: HTLCStatus::Pending,
             amt_msat: MillisatAmount(Some(amt_msat)),
+            internal: false,
         },
     );
 }
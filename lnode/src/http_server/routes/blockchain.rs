@@ -60,7 +60,10 @@ pub async fn lightning_peers_connect(
 #[get("/blockchain/info")]
 pub async fn blockchain_info(data: Data<Mutex<CoreLDK>>) -> actix_web::Result<impl Responder> {
     let data = data.lock().unwrap();
-    let bc_info = data.get_blockchain_info().await;
+    let bc_info = data
+        .get_blockchain_info()
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
     dbg!(&bc_info);
     Ok(web::Json(bc_info))
 }
@@ -0,0 +1,990 @@
+use crate::{
+    http_server::state::HttpServerState,
+    ldk::{
+        balances, channels,
+        fee_policy::{parse_channel_id, FeePolicy},
+        forwards::GroupBy,
+        graph,
+        lsp::{self, Lsp, Registration, RouteHintView},
+        onion_messages,
+        payments::{self, PaymentOptions},
+        peer_messages, probing,
+        rebalance::{self, RebalanceParams},
+        scoring,
+        tor,
+    },
+    types::{HTLCStatus, MillisatAmount, PaymentInfo},
+    utils::hex::{hex_str, to_compressed_pubkey, to_vec},
+};
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get, post,
+    web::{self, Data},
+    Responder,
+};
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::Hash;
+use lightning::chain::keysinterface::EntropySource;
+use lightning::ln::channelmanager::PaymentId;
+use lightning::ln::{PaymentHash, PaymentPreimage};
+use lightning::onion_message::Destination;
+use lightning_invoice::Invoice;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+pub struct QueryRoutesRequest {
+    pub destination: String,
+    pub amount_msat: u64,
+    pub final_cltv_expiry_delta: Option<u32>,
+}
+
+/// Either an invoice or a destination to probe, the amount being required unless the invoice
+/// has one.
+#[derive(Deserialize)]
+pub struct ProbeRequest {
+    pub invoice: Option<String>,
+    pub destination: Option<String>,
+    pub amount_msat: Option<u64>,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct PayInvoiceRequest {
+    pub invoice: String,
+    /// Required when the invoice has no amount.
+    pub amount_msat: Option<u64>,
+    #[serde(flatten)]
+    pub options: PaymentOptions,
+}
+
+#[derive(Deserialize)]
+pub struct KeysendRequest {
+    pub destination: String,
+    pub amount_msat: u64,
+    #[serde(flatten)]
+    pub options: PaymentOptions,
+}
+
+/// Sends to `destination` or, if set, `blinded_path` as made by
+/// `/lightning/onion_messages/blinded_path`, `data` being hex encoded.
+#[derive(Deserialize)]
+pub struct SendOnionMessageRequest {
+    #[serde(default)]
+    pub intermediate_nodes: Vec<String>,
+    pub destination: Option<String>,
+    pub blinded_path: Option<String>,
+    pub tlv_type: u64,
+    pub data: String,
+}
+
+/// Messages with an id above `after`, waiting up to `timeout_secs` for one if there are none.
+#[derive(Deserialize)]
+pub struct MessagesQuery {
+    #[serde(default)]
+    pub after: u64,
+    #[serde(default)]
+    pub timeout_secs: u64,
+}
+
+/// `data` being hex encoded.
+#[derive(Deserialize)]
+pub struct SendPeerMessageRequest {
+    pub peer: String,
+    pub type_id: u16,
+    pub data: String,
+}
+
+/// `address` being `host:port`.
+#[derive(Deserialize)]
+pub struct AddPeerRequest {
+    pub pubkey: String,
+    pub address: String,
+    pub keep_connected: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct LspRegisterRequest {
+    pub client: String,
+    /// The amount of the client's invoice, for taking the parts of its payment together. Without
+    /// it only single-part payments are accepted.
+    pub payment_size_msat: Option<u64>,
+}
+
+/// An invoice paid through our LSP, `route_hint` being the one it gave us.
+#[derive(Deserialize)]
+pub struct LspInvoiceRequest {
+    pub route_hint: RouteHintView,
+    pub amount_msat: u64,
+    pub description: Option<String>,
+    pub expiry_secs: Option<u32>,
+}
+
+/// `route_hint` goes in the client's invoices.
+#[derive(Serialize)]
+pub struct LspRegistrationResponse {
+    pub registration: Registration,
+    pub route_hint: RouteHintView,
+}
+
+/// The addresses to announce instead of the configured ones, as `host[:port]`.
+#[derive(Deserialize)]
+pub struct AnnounceAddressesRequest {
+    pub addresses: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BlindedPathQuery {
+    pub introduction_node: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RebalanceRequest {
+    pub source_channel_id: String,
+    pub target_channel_id: String,
+    pub amount_msat: u64,
+    pub max_fee_msat: u64,
+}
+
+/// A time window in unix seconds, the whole history by default.
+#[derive(Deserialize)]
+pub struct TimeWindowQuery {
+    #[serde(default)]
+    pub since: u64,
+    pub until: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct PaymentResponse {
+    pub payment_hash: String,
+}
+
+#[get("/lightning/scorer/{short_channel_id}")]
+pub async fn channel_score(
+    short_channel_id: web::Path<u64>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let short_channel_id = short_channel_id.into_inner();
+    let data = data.lock().unwrap();
+    let scorer = data.scorer.lock().unwrap();
+    match scoring::channel_score(&scorer, &data.network_graph, short_channel_id) {
+        Some(score) => Ok(web::Json(score)),
+        None => Err(ErrorNotFound(format!(
+            "Channel {} is not in the network graph",
+            short_channel_id
+        ))),
+    }
+}
+
+#[get("/lightning/graph")]
+pub async fn graph_stats(data: Data<Mutex<HttpServerState>>) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(graph::stats(&data.network_graph)))
+}
+
+#[get("/lightning/graph/nodes/{query}")]
+pub async fn graph_nodes(
+    query: web::Path<String>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(graph::find_nodes(
+        &data.network_graph,
+        &query.into_inner(),
+    )))
+}
+
+#[get("/lightning/graph/channels/{short_channel_id}")]
+pub async fn graph_channel(
+    short_channel_id: web::Path<u64>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let short_channel_id = short_channel_id.into_inner();
+    let data = data.lock().unwrap();
+    match graph::find_channel(&data.network_graph, short_channel_id) {
+        Some(channel) => Ok(web::Json(channel)),
+        None => Err(ErrorNotFound(format!(
+            "Channel {} is not in the network graph",
+            short_channel_id
+        ))),
+    }
+}
+
+#[get("/lightning/graph/routes")]
+pub async fn query_routes(
+    query: web::Query<QueryRoutesRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let query = query.into_inner();
+    let destination = to_compressed_pubkey(&query.destination)
+        .ok_or_else(|| ErrorBadRequest("Invalid destination pubkey"))?;
+    let data = data.lock().unwrap();
+    let preview = graph::query_routes(
+        &data.router,
+        &data.channel_manager,
+        &data.network_graph,
+        destination,
+        query.amount_msat,
+        query
+            .final_cltv_expiry_delta
+            .unwrap_or(graph::DEFAULT_FINAL_CLTV_EXPIRY_DELTA),
+    )
+    .map_err(ErrorNotFound)?;
+    Ok(web::Json(preview))
+}
+
+#[post("/lightning/probe")]
+pub async fn probe(
+    req: web::Json<ProbeRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let req = req.into_inner();
+    let route_params = match (req.invoice, req.destination, req.amount_msat) {
+        (Some(invoice), None, amount_msat) => {
+            let invoice = Invoice::from_str(&invoice).map_err(ErrorBadRequest)?;
+            probing::invoice_params(&invoice, amount_msat).map_err(ErrorBadRequest)?
+        }
+        (None, Some(destination), Some(amount_msat)) => {
+            let destination = to_compressed_pubkey(&destination)
+                .ok_or_else(|| ErrorBadRequest("Invalid destination pubkey"))?;
+            probing::destination_params(destination, amount_msat)
+        }
+        _ => {
+            return Err(ErrorBadRequest(
+                "Either an invoice or a destination and amount_msat is required",
+            ))
+        }
+    };
+    // Don't hold the state while waiting on the probes.
+    let (router, channel_manager, probes) = {
+        let data = data.lock().unwrap();
+        (
+            data.router.clone(),
+            data.channel_manager.clone(),
+            data.probes.clone(),
+        )
+    };
+    let report = probing::probe(
+        &router,
+        &channel_manager,
+        &probes,
+        &route_params,
+        Duration::from_secs(req.timeout_secs.unwrap_or(60)),
+    )
+    .await
+    .map_err(ErrorNotFound)?;
+    Ok(web::Json(report))
+}
+
+#[post("/lightning/payments/invoice")]
+pub async fn pay_invoice(
+    req: web::Json<PayInvoiceRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let req = req.into_inner();
+    let invoice = Invoice::from_str(&req.invoice).map_err(ErrorBadRequest)?;
+    let data = data.lock().unwrap();
+    payments::pay_invoice(
+        &data.channel_manager,
+        &data.router,
+        &data.network_graph,
+        &invoice,
+        req.amount_msat,
+        &req.options,
+    )
+    .map_err(ErrorBadRequest)?;
+    let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+    data.outbound_payments.lock().unwrap().insert(
+        payment_hash,
+        PaymentInfo {
+            preimage: None,
+            secret: Some(*invoice.payment_secret()),
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(invoice.amount_milli_satoshis().or(req.amount_msat)),
+            internal: false,
+        },
+    );
+    Ok(web::Json(PaymentResponse {
+        payment_hash: hex_str(&payment_hash.0),
+    }))
+}
+
+#[post("/lightning/payments/keysend")]
+pub async fn keysend(
+    req: web::Json<KeysendRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let req = req.into_inner();
+    let destination = to_compressed_pubkey(&req.destination)
+        .ok_or_else(|| ErrorBadRequest("Invalid destination pubkey"))?;
+    let data = data.lock().unwrap();
+    let payment_preimage = PaymentPreimage(data.keys_manager.get_secure_random_bytes());
+    payments::keysend(
+        &data.channel_manager,
+        &data.router,
+        &data.network_graph,
+        destination,
+        req.amount_msat,
+        payment_preimage,
+        &req.options,
+    )
+    .map_err(ErrorBadRequest)?;
+    let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+    data.outbound_payments.lock().unwrap().insert(
+        payment_hash,
+        PaymentInfo {
+            preimage: None,
+            secret: None,
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(Some(req.amount_msat)),
+            internal: false,
+        },
+    );
+    Ok(web::Json(PaymentResponse {
+        payment_hash: hex_str(&payment_hash.0),
+    }))
+}
+
+#[get("/lightning/payments/{payment_hash}/attempts")]
+pub async fn payment_attempts(
+    payment_hash: web::Path<String>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let payment_hash = to_vec(&payment_hash.into_inner())
+        .filter(|hash| hash.len() == 32)
+        .ok_or_else(|| ErrorBadRequest("Invalid payment hash"))?;
+    let mut payment_id = [0; 32];
+    payment_id.copy_from_slice(&payment_hash);
+    let data = data.lock().unwrap();
+    let attempts = data
+        .payment_attempts
+        .lock()
+        .unwrap()
+        .get(&PaymentId(payment_id))
+        .cloned()
+        .unwrap_or_default();
+    Ok(web::Json(attempts))
+}
+
+#[post("/lightning/onion_messages/types/{tlv_type}")]
+pub async fn register_onion_message_type(
+    tlv_type: web::Path<u64>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    data.onion_message_handler
+        .registry()
+        .register(tlv_type.into_inner())
+        .map_err(ErrorBadRequest)?;
+    Ok(web::Json(data.onion_message_handler.registry().types()))
+}
+
+#[delete("/lightning/onion_messages/types/{tlv_type}")]
+pub async fn unregister_onion_message_type(
+    tlv_type: web::Path<u64>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let tlv_type = tlv_type.into_inner();
+    let data = data.lock().unwrap();
+    if !data
+        .onion_message_handler
+        .registry()
+        .unregister(tlv_type)
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound(format!(
+            "Onion message type {} is not registered",
+            tlv_type
+        )));
+    }
+    Ok(web::Json(data.onion_message_handler.registry().types()))
+}
+
+#[get("/lightning/onion_messages/types")]
+pub async fn onion_message_types(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.onion_message_handler.registry().types()))
+}
+
+#[get("/lightning/onion_messages")]
+pub async fn list_onion_messages(
+    query: web::Query<MessagesQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    // Don't hold the state while waiting for messages.
+    let handler = data.lock().unwrap().onion_message_handler.clone();
+    let messages = handler
+        .inbox()
+        .wait_since(
+            query.after,
+            Duration::from_secs(query.timeout_secs.min(300)),
+        )
+        .await;
+    Ok(web::Json(messages))
+}
+
+#[post("/lightning/onion_messages")]
+pub async fn send_onion_message(
+    body: web::Json<SendOnionMessageRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let body = body.into_inner();
+    let intermediate_nodes = body
+        .intermediate_nodes
+        .iter()
+        .map(|node| to_compressed_pubkey(node))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| ErrorBadRequest("Invalid intermediate node"))?;
+    let destination = match (body.blinded_path, body.destination) {
+        (Some(path), _) => Destination::BlindedPath(
+            onion_messages::decode_blinded_path(&path).map_err(ErrorBadRequest)?,
+        ),
+        (None, Some(node)) => Destination::Node(
+            to_compressed_pubkey(&node).ok_or_else(|| ErrorBadRequest("Invalid destination"))?,
+        ),
+        (None, None) => return Err(ErrorBadRequest("A destination or blinded path is required")),
+    };
+    let message = to_vec(&body.data).ok_or_else(|| ErrorBadRequest("Data must be hex"))?;
+    let data = data.lock().unwrap();
+    onion_messages::send(
+        &data.onion_messenger,
+        &intermediate_nodes,
+        destination,
+        body.tlv_type,
+        message,
+    )
+    .map_err(ErrorBadRequest)?;
+    Ok(web::Json("OK"))
+}
+
+#[get("/lightning/onion_messages/blinded_path")]
+pub async fn onion_message_blinded_path(
+    query: web::Query<BlindedPathQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    let introduction_node = match &query.introduction_node {
+        Some(node) => to_compressed_pubkey(node)
+            .ok_or_else(|| ErrorBadRequest("Invalid introduction node"))?,
+        None => data
+            .list_peers()
+            .into_iter()
+            .next()
+            .ok_or_else(|| ErrorBadRequest("A blinded path requires a connected peer"))?,
+    };
+    let path = onion_messages::blinded_path_to_us(
+        &data.keys_manager,
+        introduction_node,
+        data.channel_manager.get_our_node_id(),
+    )
+    .map_err(ErrorInternalServerError)?;
+    Ok(web::Json(path))
+}
+
+#[post("/lightning/peer_messages/types/{type_id}")]
+pub async fn register_peer_message_type(
+    type_id: web::Path<u16>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    data.peer_message_handler
+        .registry()
+        .register(type_id.into_inner())
+        .map_err(ErrorBadRequest)?;
+    Ok(web::Json(data.peer_message_handler.registry().types()))
+}
+
+#[delete("/lightning/peer_messages/types/{type_id}")]
+pub async fn unregister_peer_message_type(
+    type_id: web::Path<u16>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let type_id = type_id.into_inner();
+    let data = data.lock().unwrap();
+    if !data
+        .peer_message_handler
+        .registry()
+        .unregister(type_id)
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound(format!(
+            "Peer message type {} is not registered",
+            type_id
+        )));
+    }
+    Ok(web::Json(data.peer_message_handler.registry().types()))
+}
+
+#[get("/lightning/peer_messages/types")]
+pub async fn peer_message_types(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.peer_message_handler.registry().types()))
+}
+
+#[post("/lightning/peer_messages/features/{bit}")]
+pub async fn register_peer_feature(
+    bit: web::Path<usize>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    data.peer_message_handler
+        .feature_bits()
+        .register(bit.into_inner())
+        .map_err(ErrorBadRequest)?;
+    Ok(web::Json(data.peer_message_handler.feature_bits().types()))
+}
+
+#[delete("/lightning/peer_messages/features/{bit}")]
+pub async fn unregister_peer_feature(
+    bit: web::Path<usize>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let bit = bit.into_inner();
+    let data = data.lock().unwrap();
+    if !data
+        .peer_message_handler
+        .feature_bits()
+        .unregister(bit)
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound(format!(
+            "Feature bit {} is not registered",
+            bit
+        )));
+    }
+    Ok(web::Json(data.peer_message_handler.feature_bits().types()))
+}
+
+#[get("/lightning/peer_messages/features")]
+pub async fn peer_features(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.peer_message_handler.feature_bits().types()))
+}
+
+#[get("/lightning/peer_messages")]
+pub async fn list_peer_messages(
+    query: web::Query<MessagesQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    // Don't hold the state while waiting for messages.
+    let handler = data.lock().unwrap().peer_message_handler.clone();
+    let messages = handler
+        .inbox()
+        .wait_since(
+            query.after,
+            Duration::from_secs(query.timeout_secs.min(300)),
+        )
+        .await;
+    Ok(web::Json(messages))
+}
+
+#[post("/lightning/peer_messages")]
+pub async fn send_peer_message(
+    body: web::Json<SendPeerMessageRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let body = body.into_inner();
+    let peer = to_compressed_pubkey(&body.peer).ok_or_else(|| ErrorBadRequest("Invalid peer"))?;
+    let message = to_vec(&body.data).ok_or_else(|| ErrorBadRequest("Data must be hex"))?;
+    let data = data.lock().unwrap();
+    peer_messages::send(
+        &data.peer_manager,
+        &data.peer_message_handler,
+        peer,
+        body.type_id,
+        message,
+    )
+    .map_err(ErrorBadRequest)?;
+    Ok(web::Json("OK"))
+}
+
+#[get("/lightning/peer_store")]
+pub async fn list_peer_store(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.peer_store.list()))
+}
+
+#[post("/lightning/peer_store")]
+pub async fn add_peer(
+    body: web::Json<AddPeerRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let body = body.into_inner();
+    let pubkey =
+        to_compressed_pubkey(&body.pubkey).ok_or_else(|| ErrorBadRequest("Invalid pubkey"))?;
+    let data = data.lock().unwrap();
+    if !tor::is_dialable(&body.address, data.tor.as_ref()) {
+        return Err(ErrorBadRequest(
+            "Invalid address, expected host:port, and tor for onion addresses",
+        ));
+    }
+    data.peer_store
+        .add(pubkey, body.address, body.keep_connected);
+    Ok(web::Json(data.peer_store.list()))
+}
+
+#[delete("/lightning/peer_store/{pubkey}")]
+pub async fn remove_peer(
+    pubkey: web::Path<String>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let pubkey = to_compressed_pubkey(&pubkey.into_inner())
+        .ok_or_else(|| ErrorBadRequest("Invalid pubkey"))?;
+    let data = data.lock().unwrap();
+    if !data.peer_store.remove(&pubkey) {
+        return Err(ErrorNotFound(format!(
+            "Peer {} is not in the peer store",
+            pubkey
+        )));
+    }
+    Ok(web::Json(data.peer_store.list()))
+}
+
+#[get("/lightning/announce_addresses")]
+pub async fn announce_addresses(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.announcer.address_strs()))
+}
+
+#[post("/lightning/announce_addresses")]
+pub async fn set_announce_addresses(
+    body: web::Json<AnnounceAddressesRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    data.announcer
+        .set(&body.into_inner().addresses)
+        .map_err(ErrorBadRequest)?;
+    Ok(web::Json(data.announcer.address_strs()))
+}
+
+#[get("/lightning/channel_requests")]
+pub async fn channel_requests(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.channel_acceptor.pending()))
+}
+
+#[get("/lightning/channel_requests/decisions")]
+pub async fn channel_decisions(
+    query: web::Query<MessagesQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    // Don't hold the state while waiting for decisions.
+    let channel_acceptor = data.lock().unwrap().channel_acceptor.clone();
+    let decisions = channel_acceptor
+        .decisions()
+        .wait_since(
+            query.after,
+            Duration::from_secs(query.timeout_secs.min(300)),
+        )
+        .await;
+    Ok(web::Json(decisions))
+}
+
+fn check_channel_request(
+    data: &HttpServerState,
+    temporary_channel_id: &str,
+) -> actix_web::Result<()> {
+    if !data
+        .channel_acceptor
+        .pending()
+        .iter()
+        .any(|request| request.temporary_channel_id == temporary_channel_id)
+    {
+        return Err(ErrorNotFound(format!(
+            "No pending channel request {}",
+            temporary_channel_id
+        )));
+    }
+    Ok(())
+}
+
+#[post("/lightning/channel_requests/{temporary_channel_id}")]
+pub async fn approve_channel_request(
+    temporary_channel_id: web::Path<String>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let temporary_channel_id = temporary_channel_id.into_inner().to_lowercase();
+    let data = data.lock().unwrap();
+    check_channel_request(&data, &temporary_channel_id)?;
+    data.channel_acceptor
+        .approve(&data.channel_manager, &temporary_channel_id)
+        .map_err(ErrorInternalServerError)?;
+    Ok(web::Json("OK"))
+}
+
+#[delete("/lightning/channel_requests/{temporary_channel_id}")]
+pub async fn reject_channel_request(
+    temporary_channel_id: web::Path<String>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let temporary_channel_id = temporary_channel_id.into_inner().to_lowercase();
+    let data = data.lock().unwrap();
+    check_channel_request(&data, &temporary_channel_id)?;
+    data.channel_acceptor
+        .deny(&data.channel_manager, &temporary_channel_id)
+        .map_err(ErrorInternalServerError)?;
+    Ok(web::Json("OK"))
+}
+
+#[get("/lightning/channels")]
+pub async fn list_channels(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(channels::list(&data.channel_manager)))
+}
+
+fn lsp(data: &HttpServerState) -> actix_web::Result<&Lsp> {
+    data.lsp
+        .as_deref()
+        .ok_or_else(|| ErrorBadRequest("LSP mode is off"))
+}
+
+#[post("/lightning/lsp/registrations")]
+pub async fn lsp_register(
+    body: web::Json<LspRegisterRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let client =
+        to_compressed_pubkey(&body.client).ok_or_else(|| ErrorBadRequest("Invalid client"))?;
+    let data = data.lock().unwrap();
+    let lsp = lsp(&data)?;
+    let registration = lsp.register(&data.channel_manager, client, body.payment_size_msat);
+    let route_hint = lsp.route_hint(data.channel_manager.get_our_node_id(), &registration);
+    Ok(web::Json(LspRegistrationResponse {
+        registration,
+        route_hint,
+    }))
+}
+
+#[post("/lightning/lsp/invoice")]
+pub async fn lsp_invoice(
+    body: web::Json<LspInvoiceRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let body = body.into_inner();
+    let data = data.lock().unwrap();
+    let invoice = lsp::client_invoice(
+        &data.channel_manager,
+        &data.keys_manager,
+        data.network,
+        &body.route_hint,
+        body.amount_msat,
+        body.description.unwrap_or_default(),
+        body.expiry_secs.unwrap_or(3600),
+    )
+    .map_err(ErrorBadRequest)?;
+    data.inbound_payments.lock().unwrap().insert(
+        PaymentHash(invoice.payment_hash().into_inner()),
+        PaymentInfo {
+            preimage: None,
+            secret: Some(*invoice.payment_secret()),
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(Some(body.amount_msat)),
+            internal: false,
+        },
+    );
+    Ok(web::Json(invoice.to_string()))
+}
+
+#[delete("/lightning/lsp/registrations/{intercept_scid}")]
+pub async fn lsp_unregister(
+    intercept_scid: web::Path<u64>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let intercept_scid = intercept_scid.into_inner();
+    let data = data.lock().unwrap();
+    if !lsp(&data)?.unregister(intercept_scid) {
+        return Err(ErrorNotFound(format!(
+            "No registration for intercept SCID {}",
+            intercept_scid
+        )));
+    }
+    Ok(web::Json("OK"))
+}
+
+#[get("/lightning/lsp/registrations")]
+pub async fn lsp_registrations(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(lsp(&data)?.registrations()))
+}
+
+#[get("/lightning/lsp/intercepts")]
+pub async fn lsp_intercepts(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(lsp(&data)?.intercepts()))
+}
+
+#[get("/lightning/fee_policy")]
+pub async fn fee_policy(data: Data<Mutex<HttpServerState>>) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.fee_policies.policies()))
+}
+
+#[post("/lightning/fee_policy")]
+pub async fn set_global_fee_policy(
+    body: web::Json<FeePolicy>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    data.fee_policies
+        .set(&data.channel_manager, None, &body)
+        .map_err(ErrorBadRequest)?;
+    Ok(web::Json(data.fee_policies.policies()))
+}
+
+#[post("/lightning/fee_policy/{channel_id}")]
+pub async fn set_channel_fee_policy(
+    channel_id: web::Path<String>,
+    body: web::Json<FeePolicy>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let channel_id =
+        parse_channel_id(&channel_id).ok_or_else(|| ErrorBadRequest("Invalid channel id"))?;
+    let data = data.lock().unwrap();
+    data.fee_policies
+        .set(&data.channel_manager, Some(channel_id), &body)
+        .map_err(ErrorBadRequest)?;
+    Ok(web::Json(data.fee_policies.policies()))
+}
+
+#[delete("/lightning/fee_policy/{channel_id}")]
+pub async fn reset_channel_fee_policy(
+    channel_id: web::Path<String>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let channel_id =
+        parse_channel_id(&channel_id).ok_or_else(|| ErrorBadRequest("Invalid channel id"))?;
+    let data = data.lock().unwrap();
+    match data.fee_policies.reset(&data.channel_manager, channel_id) {
+        Ok(true) => Ok(web::Json(data.fee_policies.policies())),
+        Ok(false) => Err(ErrorNotFound(format!(
+            "Channel {} has no fee policy of its own",
+            hex_str(&channel_id)
+        ))),
+        Err(e) => Err(ErrorInternalServerError(e)),
+    }
+}
+
+#[get("/lightning/forwards")]
+pub async fn list_forwards(
+    query: web::Query<TimeWindowQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(
+        data.forwarding_history
+            .forwards(query.since, query.until.unwrap_or(u64::MAX)),
+    ))
+}
+
+#[get("/lightning/forwards/failures")]
+pub async fn list_forward_failures(
+    query: web::Query<TimeWindowQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(
+        data.forwarding_history
+            .failures(query.since, query.until.unwrap_or(u64::MAX)),
+    ))
+}
+
+#[get("/lightning/forwards/revenue/channels")]
+pub async fn channel_revenue(
+    query: web::Query<TimeWindowQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.forwarding_history.revenue(
+        GroupBy::Channel,
+        query.since,
+        query.until.unwrap_or(u64::MAX),
+    )))
+}
+
+#[get("/lightning/forwards/revenue/peers")]
+pub async fn peer_revenue(
+    query: web::Query<TimeWindowQuery>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    Ok(web::Json(data.forwarding_history.revenue(
+        GroupBy::Peer,
+        query.since,
+        query.until.unwrap_or(u64::MAX),
+    )))
+}
+
+#[post("/lightning/rebalance")]
+pub async fn rebalance_channels(
+    req: web::Json<RebalanceRequest>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let source = parse_channel_id(&req.source_channel_id)
+        .ok_or_else(|| ErrorBadRequest("Invalid source channel id"))?;
+    let target = parse_channel_id(&req.target_channel_id)
+        .ok_or_else(|| ErrorBadRequest("Invalid target channel id"))?;
+    let data = data.lock().unwrap();
+    let rebalance = rebalance::rebalance(
+        &data.channel_manager,
+        &data.router,
+        &data.inbound_payments,
+        &data.outbound_payments,
+        RebalanceParams {
+            source_channel_id: source,
+            target_channel_id: target,
+            amount_msat: req.amount_msat,
+            max_fee_msat: req.max_fee_msat,
+        },
+    )
+    .map_err(ErrorBadRequest)?;
+    Ok(web::Json(rebalance))
+}
+
+#[get("/lightning/channels/{channel_id}/history")]
+pub async fn channel_history(
+    channel_id: web::Path<String>,
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let channel_id =
+        parse_channel_id(&channel_id).ok_or_else(|| ErrorBadRequest("Invalid channel id"))?;
+    let data = data.lock().unwrap();
+    match data.channel_history.get(&channel_id) {
+        Some(record) => Ok(web::Json(record)),
+        None => Err(ErrorNotFound(format!(
+            "No history of channel {}",
+            hex_str(&channel_id)
+        ))),
+    }
+}
+
+#[get("/lightning/balances")]
+pub async fn list_balances(
+    data: Data<Mutex<HttpServerState>>,
+) -> actix_web::Result<impl Responder> {
+    let data = data.lock().unwrap();
+    let funds = balances::total_funds(&data.channel_manager, &data.chain_monitor, &data.bdk_wallet)
+        .map_err(ErrorInternalServerError)?;
+    Ok(web::Json(funds))
+}
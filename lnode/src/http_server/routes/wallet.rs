@@ -1,4 +1,9 @@
-use crate::{blockchain::BlockchainHandler, wallet::BitcoinWallet};
+use crate::{
+    blockchain::BlockchainHandler,
+    http_server::state::HttpServerState,
+    utils::disk,
+    wallet::{multisig_descriptors, BitcoinWallet, ScriptType},
+};
 use actix_web::{
     get, post,
     web::{self, Data},
@@ -11,11 +16,14 @@ use bdk::{
     },
     miniscript::Segwitv0,
 };
-use bitcoin::Address;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, OutPoint};
+use serde::{Deserialize, Serialize};
 use std::{
     str::FromStr,
     sync::{Arc, Mutex},
 };
+use wallet_history::{labels::LabelType, HistoryQuery, LightningRecords};
 
 #[get("/wallet/list")]
 pub async fn wallet_list(
@@ -28,14 +36,14 @@ pub async fn wallet_list(
 
 #[get("/wallet/{wallet_name}/info")]
 pub async fn my_wallet_info(wallet_name: web::Path<String>) -> actix_web::Result<impl Responder> {
-    let wallet = BitcoinWallet::load_by_wallet_name(wallet_name.into_inner());
+    let wallet = load_wallet(wallet_name.into_inner())?;
     let info = wallet.wallet_info().unwrap();
     Ok(web::Json(info))
 }
 
 #[get("/wallet/{wallet_name}/address")]
 pub async fn generate_address(wallet_name: web::Path<String>) -> actix_web::Result<impl Responder> {
-    let wallet = BitcoinWallet::load_by_wallet_name(wallet_name.into_inner());
+    let wallet = load_wallet(wallet_name.into_inner())?;
     let info = wallet.generate_address().unwrap();
     Ok(web::Json(info.address))
 }
@@ -46,16 +54,364 @@ pub async fn send_coins(
     rec_address: web::Json<String>,
     amount: web::Json<u64>,
 ) -> actix_web::Result<impl Responder> {
-    let wallet = BitcoinWallet::load_by_wallet_name(wallet_name.into_inner());
+    let wallet = load_wallet(wallet_name.into_inner())?;
     let _info = wallet.send_tx(
         Address::from_str(&rec_address.into_inner()).unwrap(),
         amount.into_inner(),
+        None,
     );
     Ok(web::Json(""))
 }
+#[derive(Deserialize)]
+pub struct PsbtRecipient {
+    pub address: String,
+    pub amount: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CreatePsbtRequest {
+    pub recipients: Vec<PsbtRecipient>,
+    pub fee_rate: Option<f32>,
+    /// Spend only these coins, given as `txid:vout`.
+    pub utxos: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct PsbtRequest {
+    pub psbt: String,
+}
+
+#[derive(Deserialize)]
+pub struct CombinePsbtRequest {
+    pub psbts: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PsbtResponse {
+    pub psbt: String,
+    pub finalized: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ImportWalletRequest {
+    pub xpub: Option<String>,
+    pub descriptor: Option<String>,
+    pub change_descriptor: Option<String>,
+    pub multisig: Option<MultisigRequest>,
+}
+
+#[derive(Deserialize)]
+pub struct MultisigRequest {
+    pub threshold: usize,
+    pub keys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RestoreWalletRequest {
+    pub mnemonic: String,
+    pub passphrase: Option<String>,
+    pub script_type: Option<ScriptType>,
+}
+
+#[derive(Serialize)]
+pub struct DescriptorsResponse {
+    pub descriptor: String,
+    pub change_descriptor: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OutPointRequest {
+    pub outpoint: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetLabelRequest {
+    #[serde(rename = "type")]
+    pub label_type: LabelType,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub label: String,
+}
+
+#[derive(Deserialize)]
+pub struct MnemonicQuery {
+    pub words: Option<usize>,
+}
+
+fn load_wallet(wallet_name: String) -> actix_web::Result<BitcoinWallet> {
+    BitcoinWallet::load_by_wallet_name(wallet_name)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+}
+
+fn parse_psbt(psbt: &str) -> actix_web::Result<PartiallySignedTransaction> {
+    PartiallySignedTransaction::from_str(psbt)
+        .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))
+}
+
+fn parse_outpoint(outpoint: &str) -> actix_web::Result<OutPoint> {
+    OutPoint::from_str(outpoint).map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))
+}
+
+#[post("/wallet/{wallet_name}/psbt/create")]
+pub async fn create_psbt(
+    wallet_name: web::Path<String>,
+    info: web::Json<CreatePsbtRequest>,
+) -> actix_web::Result<impl Responder> {
+    let info = info.into_inner();
+    let mut recipients = vec![];
+    for recipient in info.recipients {
+        let address = Address::from_str(&recipient.address)
+            .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))?;
+        recipients.push((address, recipient.amount));
+    }
+    let utxos = match info.utxos {
+        Some(utxos) => Some(
+            utxos
+                .iter()
+                .map(|o| parse_outpoint(o))
+                .collect::<actix_web::Result<Vec<OutPoint>>>()?,
+        ),
+        None => None,
+    };
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    wallet
+        .sync_wallet()
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    match wallet.create_psbt(recipients, info.fee_rate, utxos) {
+        Ok(psbt) => Ok(web::Json(PsbtResponse {
+            psbt: psbt.to_string(),
+            finalized: false,
+        })),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[post("/wallet/{wallet_name}/psbt/sign")]
+pub async fn sign_psbt(
+    wallet_name: web::Path<String>,
+    info: web::Json<PsbtRequest>,
+) -> actix_web::Result<impl Responder> {
+    let psbt = parse_psbt(&info.psbt)?;
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.sign_psbt(psbt) {
+        Ok((psbt, finalized)) => Ok(web::Json(PsbtResponse {
+            psbt: psbt.to_string(),
+            finalized,
+        })),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[post("/wallet/psbt/combine")]
+pub async fn combine_psbt(
+    info: web::Json<CombinePsbtRequest>,
+) -> actix_web::Result<impl Responder> {
+    let mut psbts = vec![];
+    for psbt in info.psbts.iter() {
+        psbts.push(parse_psbt(psbt)?);
+    }
+    match BitcoinWallet::combine_psbts(psbts) {
+        Ok(psbt) => Ok(web::Json(PsbtResponse {
+            psbt: psbt.to_string(),
+            finalized: false,
+        })),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[post("/wallet/{wallet_name}/psbt/finalize")]
+pub async fn finalize_psbt(
+    wallet_name: web::Path<String>,
+    info: web::Json<PsbtRequest>,
+) -> actix_web::Result<impl Responder> {
+    let psbt = parse_psbt(&info.psbt)?;
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.finalize_psbt(psbt) {
+        Ok((psbt, finalized)) => Ok(web::Json(PsbtResponse {
+            psbt: psbt.to_string(),
+            finalized,
+        })),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[post("/wallet/{wallet_name}/psbt/broadcast")]
+pub async fn broadcast_psbt(
+    wallet_name: web::Path<String>,
+    info: web::Json<PsbtRequest>,
+) -> actix_web::Result<impl Responder> {
+    let psbt = parse_psbt(&info.psbt)?;
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.broadcast_psbt(psbt) {
+        Ok(txid) => Ok(web::Json(txid.to_string())),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[post("/wallet/import")]
+pub async fn import_wallet(
+    info: web::Json<ImportWalletRequest>,
+) -> actix_web::Result<impl Responder> {
+    let info = info.into_inner();
+    let wallet = match (info.xpub, info.descriptor, info.multisig) {
+        (Some(xpub), None, None) => BitcoinWallet::load_with_xpub(&xpub),
+        (None, Some(descriptor), None) => {
+            BitcoinWallet::load_with_descriptors(descriptor, info.change_descriptor)
+        }
+        (None, None, Some(multisig)) => {
+            if multisig.threshold == 0 || multisig.threshold > multisig.keys.len() {
+                return Err(actix_web::error::ErrorBadRequest(
+                    "Threshold must be between 1 and the number of keys",
+                ));
+            }
+            let (descriptor, change_descriptor) =
+                multisig_descriptors(multisig.threshold, &multisig.keys);
+            BitcoinWallet::load_with_descriptors(descriptor, Some(change_descriptor))
+        }
+        _ => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "Exactly one of xpub, descriptor or multisig is required",
+            ))
+        }
+    };
+    match wallet {
+        Ok(wallet) => Ok(web::Json(wallet.wallet_name)),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[post("/wallet/restore")]
+pub async fn restore_wallet(
+    info: web::Json<RestoreWalletRequest>,
+) -> actix_web::Result<impl Responder> {
+    let info = info.into_inner();
+    match BitcoinWallet::restore_from_mnemonic(
+        &info.mnemonic,
+        info.passphrase,
+        info.script_type.unwrap_or(ScriptType::Bip84),
+    ) {
+        Ok(wallet) => Ok(web::Json(wallet.wallet_name)),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+#[get("/wallet/{wallet_name}/descriptors")]
+pub async fn export_descriptors(
+    wallet_name: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.export_descriptors() {
+        Ok((descriptor, change_descriptor)) => Ok(web::Json(DescriptorsResponse {
+            descriptor,
+            change_descriptor,
+        })),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+    }
+}
+
 #[get("/mmc")]
-pub async fn new_mmc() -> actix_web::Result<impl Responder> {
+pub async fn new_mmc(query: web::Query<MnemonicQuery>) -> actix_web::Result<impl Responder> {
+    let word_count = match query.words {
+        None | Some(12) => WordCount::Words12,
+        Some(24) => WordCount::Words24,
+        Some(_) => return Err(actix_web::error::ErrorBadRequest("words must be 12 or 24")),
+    };
     let mmc: GeneratedKey<Mnemonic, Segwitv0> =
-        Mnemonic::generate((WordCount::Words12, Language::English)).unwrap();
+        Mnemonic::generate((word_count, Language::English)).unwrap();
     Ok(web::Json(mmc.word_iter().collect::<Vec<&str>>().join(" ")))
 }
+
+#[get("/wallet/{wallet_name}/utxos")]
+pub async fn list_utxos(wallet_name: web::Path<String>) -> actix_web::Result<impl Responder> {
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    wallet
+        .sync_wallet()
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    match wallet.list_utxos() {
+        Ok(utxos) => Ok(web::Json(utxos)),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+    }
+}
+
+#[post("/wallet/{wallet_name}/utxos/freeze")]
+pub async fn freeze_utxo(
+    wallet_name: web::Path<String>,
+    info: web::Json<OutPointRequest>,
+) -> actix_web::Result<impl Responder> {
+    let outpoint = parse_outpoint(&info.outpoint)?;
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.set_frozen(&outpoint, true) {
+        Ok(()) => Ok(web::Json(outpoint.to_string())),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+    }
+}
+
+#[post("/wallet/{wallet_name}/utxos/unfreeze")]
+pub async fn unfreeze_utxo(
+    wallet_name: web::Path<String>,
+    info: web::Json<OutPointRequest>,
+) -> actix_web::Result<impl Responder> {
+    let outpoint = parse_outpoint(&info.outpoint)?;
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.set_frozen(&outpoint, false) {
+        Ok(()) => Ok(web::Json(outpoint.to_string())),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+    }
+}
+
+#[post("/wallet/{wallet_name}/labels")]
+pub async fn set_label(
+    wallet_name: web::Path<String>,
+    info: web::Json<SetLabelRequest>,
+) -> actix_web::Result<impl Responder> {
+    let info = info.into_inner();
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.set_label(info.label_type, &info.reference, info.label) {
+        Ok(()) => Ok(web::Json(info.reference)),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+    }
+}
+
+/// Exports the wallet labels as BIP329 JSON lines.
+#[get("/wallet/{wallet_name}/labels/export")]
+pub async fn export_labels(wallet_name: web::Path<String>) -> actix_web::Result<impl Responder> {
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.export_labels() {
+        Ok(labels) => Ok(labels),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+    }
+}
+
+/// Imports BIP329 JSON lines sent as the request body.
+#[post("/wallet/{wallet_name}/labels/import")]
+pub async fn import_labels(
+    wallet_name: web::Path<String>,
+    body: String,
+) -> actix_web::Result<impl Responder> {
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    match wallet.import_labels(&body) {
+        Ok(count) => Ok(web::Json(count)),
+        Err(e) => Err(actix_web::error::ErrorBadRequest(e.to_string())),
+    }
+}
+
+/// Channel transactions are only told apart when the node is running next to the wallet.
+#[get("/wallet/{wallet_name}/history")]
+pub async fn history(
+    node: Option<Data<Mutex<HttpServerState>>>,
+    wallet_name: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+) -> actix_web::Result<impl Responder> {
+    let wallet = load_wallet(wallet_name.into_inner())?;
+    wallet
+        .sync_wallet()
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    let records = match node {
+        Some(node) => disk::read_lightning_records(&node.lock().unwrap().ldk_data_dir),
+        None => LightningRecords::default(),
+    };
+    match wallet.history(&query, &records) {
+        Ok(page) => Ok(web::Json(page)),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e.to_string())),
+    }
+}
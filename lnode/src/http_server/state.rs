@@ -1,12 +1,22 @@
 use crate::{
     cli::{connect_peer_if_necessary, parse_peer_info},
-    types::{ChannelManager, NetworkGraph, OnionMessenger, PaymentInfoStorage, PeerManager},
+    ldk::{
+        announce::Announcer, channel_acceptance::ChannelAcceptor, channel_history::ChannelHistory,
+        fee_policy::FeePolicyStore, forwards::ForwardingHistory, lsp::Lsp,
+        onion_messages::OnionMessageHandler, peer_messages::PeerMessageHandler,
+        peer_store::PeerStore, tor::TorConfig,
+    },
+    types::{
+        ChainMonitor, ChannelManager, NetworkGraph, OnionMessenger, PaymentAttemptStorage,
+        PaymentInfoStorage, PeerManager, ProbeStorage, Router, Scorer,
+    },
     utils::disk,
+    wallet::BitcoinWallet,
 };
 use bitcoin::Network;
 use lightning::chain::keysinterface::KeysManager;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 #[derive(Serialize, Deserialize)]
 pub struct PeerInfo {
@@ -18,8 +28,24 @@ pub struct PeerInfo {
 pub struct HttpServerState {
     pub peer_manager: Arc<PeerManager>,
     pub channel_manager: Arc<ChannelManager>,
+    pub chain_monitor: Arc<ChainMonitor>,
     pub keys_manager: Arc<KeysManager>,
     pub network_graph: Arc<NetworkGraph>,
+    pub scorer: Arc<Mutex<Scorer>>,
+    pub router: Arc<Router>,
+    pub probes: ProbeStorage,
+    pub payment_attempts: PaymentAttemptStorage,
+    pub onion_message_handler: Arc<OnionMessageHandler>,
+    pub peer_message_handler: Arc<PeerMessageHandler>,
+    pub peer_store: Arc<PeerStore>,
+    pub channel_acceptor: Arc<ChannelAcceptor>,
+    /// Set in LSP mode.
+    pub lsp: Option<Arc<Lsp>>,
+    pub fee_policies: Arc<FeePolicyStore>,
+    pub forwarding_history: Arc<ForwardingHistory>,
+    pub channel_history: Arc<ChannelHistory>,
+    pub bdk_wallet: Arc<BitcoinWallet>,
+    pub tor: Option<TorConfig>,
     pub onion_messenger: Arc<OnionMessenger>,
     pub inbound_payments: PaymentInfoStorage,
     pub outbound_payments: PaymentInfoStorage,
@@ -27,7 +53,7 @@ pub struct HttpServerState {
     pub network: Network,
     pub logger: Arc<disk::FilesystemLogger>,
     pub port: u16,
-    pub announced_listen_addr: String,
+    pub announcer: Arc<Announcer>,
     pub node_name: String,
 }
 
@@ -37,7 +63,8 @@ pub struct NodeInfo {
     pub network: bitcoin::network::constants::Network,
     pub port: u16,
     pub node_name: String,
-    pub announced_listen_addr: String,
+    /// `host:port`, onion addresses in their `.onion` form.
+    pub announce_addresses: Vec<String>,
     pub num_usable_channels: usize,
     pub num_channels: usize,
     pub local_balance_msat: u64,
@@ -51,7 +78,7 @@ impl HttpServerState {
             network: self.network,
             port: self.port,
             node_name: self.node_name.to_string(),
-            announced_listen_addr: self.announced_listen_addr.to_string(),
+            announce_addresses: self.announcer.address_strs(),
             num_usable_channels: self
                 .channel_manager
                 .list_channels()
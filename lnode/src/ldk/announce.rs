@@ -0,0 +1,201 @@
+//! The addresses we announce our node at, kept in `{ldk_data_dir}/announce.json`, and the loop
+//! broadcasting our node announcement, which goes out again right away when they change.
+
+use crate::types::{ChannelManager, PeerManager};
+use crate::utils::hex::{net_addr_str, parse_net_address, str_to_u8};
+use lightning::ln::msgs::NetAddress;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+const CONFIG_FILE: &str = "announce.json";
+
+/// LDK panics when asked to announce more addresses than this.
+pub const MAX_ADDRESSES: usize = 100;
+
+/// The configured addresses, as `host[:port]` with our listening port as the default.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct AnnounceConfig {
+    #[serde(default)]
+    addresses: Vec<String>,
+}
+
+pub struct Announcer {
+    addresses: Mutex<Vec<NetAddress>>,
+    /// Our onion address when we publish a hidden service, announced with the configured ones.
+    hidden_service: Mutex<Option<NetAddress>>,
+    changed: Notify,
+    port: u16,
+    ldk_data_dir: String,
+}
+
+impl Announcer {
+    /// Reads the configured addresses, failing if any is invalid. None are announced without a
+    /// config.
+    pub fn load(ldk_data_dir: &str, port: u16) -> Result<Self, String> {
+        let config = match fs::read_to_string(format!("{}/{}", ldk_data_dir, CONFIG_FILE)) {
+            Ok(config) => serde_json::from_str(&config)
+                .map_err(|e| format!("invalid announce config: {}", e))?,
+            Err(_) => AnnounceConfig::default(),
+        };
+        let addresses = parse_addresses(&config.addresses, port)
+            .map_err(|e| format!("invalid announce config: {}", e))?;
+        Ok(Self {
+            addresses: Mutex::new(addresses),
+            hidden_service: Mutex::new(None),
+            changed: Notify::new(),
+            port,
+            ldk_data_dir: ldk_data_dir.to_string(),
+        })
+    }
+
+    /// Everything we announce, the hidden service last.
+    pub fn addresses(&self) -> Vec<NetAddress> {
+        let mut addresses = self.addresses.lock().unwrap().clone();
+        if let Some(onion) = self.hidden_service.lock().unwrap().clone() {
+            if !addresses.contains(&onion) {
+                addresses.push(onion);
+            }
+        }
+        addresses
+    }
+
+    /// [`Announcer::addresses`] as `host:port` strings.
+    pub fn address_strs(&self) -> Vec<String> {
+        self.addresses().iter().map(net_addr_str).collect()
+    }
+
+    /// Replaces the configured addresses and rebroadcasts our announcement with them.
+    pub fn set(&self, addresses: &[String]) -> Result<(), String> {
+        let parsed = parse_addresses(addresses, self.port)?;
+        let config = serde_json::to_string_pretty(&AnnounceConfig {
+            addresses: parsed.iter().map(net_addr_str).collect(),
+        })
+        .map_err(|e| e.to_string())?;
+        fs::write(format!("{}/{}", self.ldk_data_dir, CONFIG_FILE), config)
+            .map_err(|e| e.to_string())?;
+        *self.addresses.lock().unwrap() = parsed;
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    pub fn set_hidden_service(&self, onion: NetAddress) {
+        *self.hidden_service.lock().unwrap() = Some(onion);
+        self.changed.notify_one();
+    }
+}
+
+/// Parses `addresses`, dropping duplicates and leaving room for a hidden service.
+fn parse_addresses(addresses: &[String], port: u16) -> Result<Vec<NetAddress>, String> {
+    let mut parsed = Vec::new();
+    for address in addresses {
+        let address = parse_net_address(address.trim(), port)?;
+        if !parsed.contains(&address) {
+            parsed.push(address);
+        }
+    }
+    if parsed.len() >= MAX_ADDRESSES {
+        return Err(format!(
+            "at most {} addresses can be announced",
+            MAX_ADDRESSES - 1
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Broadcasts our node announcement once an hour and whenever the addresses change. This is
+/// only required (or possible) if we have some public channels.
+pub async fn broadcast(
+    announcer: Arc<Announcer>,
+    channel_manager: Arc<ChannelManager>,
+    peer_manager: Arc<PeerManager>,
+    node_name: String,
+) {
+    // First wait a minute until we have some peers and maybe have opened a channel.
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    // Then, update our announcement once an hour to keep it fresh but avoid unnecessary churn
+    // in the global gossip network.
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = announcer.changed.notified() => {}
+        }
+        // Don't bother trying to announce if we don't have any public channls, though our
+        // peers should drop such an announcement anyway. Note that announcement may not
+        // propagate until we have a channel with 6+ confirmations.
+        if channel_manager
+            .list_channels()
+            .iter()
+            .any(|chan| chan.is_public)
+        {
+            peer_manager.broadcast_node_announcement(
+                [0; 3],
+                str_to_u8(&node_name),
+                announcer.addresses(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hex::onion_v3_checksum;
+    use crate::utils::test_utils::TempDataDir;
+
+    #[test]
+    fn addresses_are_validated_and_kept() {
+        let dir = TempDataDir::new("announce");
+        let announcer = Announcer::load(dir.path(), 9735).unwrap();
+        assert!(announcer.addresses().is_empty());
+        let addresses = [
+            "203.0.113.7",
+            "[2001:db8::1]:9736",
+            "2001:db8::2",
+            "node.example.com:9737",
+            "203.0.113.7:9735",
+        ];
+        announcer
+            .set(&addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(
+            announcer.address_strs(),
+            vec![
+                "203.0.113.7:9735",
+                "[2001:db8::1]:9736",
+                "[2001:db8::2]:9735",
+                "node.example.com:9737",
+            ]
+        );
+        for invalid in &[
+            "1.2.3",
+            "localhost",
+            "node.example.com:port",
+            "bad_host.com",
+        ] {
+            assert!(announcer.set(&[invalid.to_string()]).is_err());
+        }
+
+        let onion = NetAddress::OnionV3 {
+            ed25519_pubkey: [7; 32],
+            checksum: onion_v3_checksum(&[7; 32]),
+            version: 3,
+            port: 9735,
+        };
+        announcer.set_hidden_service(onion.clone());
+        let restarted = Announcer::load(dir.path(), 9735).unwrap();
+        assert_eq!(restarted.address_strs().len(), 4);
+        restarted.set_hidden_service(onion.clone());
+        assert_eq!(restarted.addresses().last(), Some(&onion));
+
+        // The onion address parses back from its string form.
+        restarted.set(&[net_addr_str(&onion)]).unwrap();
+        assert_eq!(restarted.addresses(), vec![onion]);
+
+        dir.write(CONFIG_FILE, r#"{"addresses": ["x"]}"#);
+        assert!(Announcer::load(dir.path(), 9735).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Everything we own, on and off chain.
+//!
+//! Lightning funds come from the channel monitors, which know about open channels as well as
+//! closing and closed ones still waiting for confirmations, HTLC resolutions or timelocks, and
+//! report them as LDK's claimable balances. LDK 0.0.115 never archives monitors, so those of
+//! channels with nothing left to claim stay around with no balances: we leave these fully
+//! resolved channels out of the view. Amounts are what we expect to get, before the fees of
+//! claiming them on chain.
+
+use crate::ldk::channel_history::ClaimableBalance;
+use crate::types::{ChainMonitor, ChannelManager};
+use crate::utils::hex::hex_str;
+use crate::wallet::BitcoinWallet;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelBalances {
+    pub channel_id: String,
+    pub counterparty_node_id: Option<String>,
+    /// Whether the channel manager still lists the channel, i.e. it hasn't been closed.
+    pub open: bool,
+    pub balances: Vec<ClaimableBalance>,
+    pub total_sats: u64,
+}
+
+impl ChannelBalances {
+    /// Closed with nothing left to claim on chain.
+    pub fn is_fully_resolved(&self) -> bool {
+        !self.open && self.balances.is_empty()
+    }
+}
+
+/// Lightning balances summed over all channels, one field per balance type.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BalanceTotals {
+    pub claimable_on_channel_close_sats: u64,
+    pub claimable_awaiting_confirmations_sats: u64,
+    pub contentious_claimable_sats: u64,
+    pub maybe_timeout_claimable_htlc_sats: u64,
+    /// HTLCs we can't claim without a preimage we don't have, so most likely not ours.
+    pub maybe_preimage_claimable_htlc_sats: u64,
+    pub counterparty_revoked_output_claimable_sats: u64,
+}
+
+impl BalanceTotals {
+    fn add(&mut self, balance: &ClaimableBalance) {
+        let amount_sats = balance.amount_sats();
+        let total = match balance {
+            ClaimableBalance::ClaimableOnChannelClose { .. } => {
+                &mut self.claimable_on_channel_close_sats
+            }
+            ClaimableBalance::ClaimableAwaitingConfirmations { .. } => {
+                &mut self.claimable_awaiting_confirmations_sats
+            }
+            ClaimableBalance::ContentiousClaimable { .. } => &mut self.contentious_claimable_sats,
+            ClaimableBalance::MaybeTimeoutClaimableHtlc { .. } => {
+                &mut self.maybe_timeout_claimable_htlc_sats
+            }
+            ClaimableBalance::MaybePreimageClaimableHtlc { .. } => {
+                &mut self.maybe_preimage_claimable_htlc_sats
+            }
+            ClaimableBalance::CounterpartyRevokedOutputClaimable { .. } => {
+                &mut self.counterparty_revoked_output_claimable_sats
+            }
+        };
+        *total += amount_sats;
+    }
+
+    /// What we count as ours, leaving out the HTLCs waiting on a preimage.
+    pub fn lightning_sats(&self) -> u64 {
+        self.claimable_on_channel_close_sats
+            + self.claimable_awaiting_confirmations_sats
+            + self.contentious_claimable_sats
+            + self.maybe_timeout_claimable_htlc_sats
+            + self.counterparty_revoked_output_claimable_sats
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OnchainBalance {
+    pub confirmed_sats: u64,
+    /// Unconfirmed outputs of our own transactions.
+    pub trusted_pending_sats: u64,
+    pub untrusted_pending_sats: u64,
+    pub immature_sats: u64,
+}
+
+impl OnchainBalance {
+    pub fn total_sats(&self) -> u64 {
+        self.confirmed_sats
+            + self.trusted_pending_sats
+            + self.untrusted_pending_sats
+            + self.immature_sats
+    }
+}
+
+impl From<bdk::Balance> for OnchainBalance {
+    fn from(balance: bdk::Balance) -> Self {
+        OnchainBalance {
+            confirmed_sats: balance.confirmed,
+            trusted_pending_sats: balance.trusted_pending,
+            untrusted_pending_sats: balance.untrusted_pending,
+            immature_sats: balance.immature,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TotalFunds {
+    pub channels: Vec<ChannelBalances>,
+    pub lightning: BalanceTotals,
+    pub lightning_sats: u64,
+    pub onchain: OnchainBalance,
+    pub onchain_sats: u64,
+    /// Lightning and on-chain funds together.
+    pub total_sats: u64,
+}
+
+impl TotalFunds {
+    fn new(channels: Vec<ChannelBalances>, onchain: OnchainBalance) -> Self {
+        let mut lightning = BalanceTotals::default();
+        for balance in channels.iter().flat_map(|channel| channel.balances.iter()) {
+            lightning.add(balance);
+        }
+        let lightning_sats = lightning.lightning_sats();
+        let onchain_sats = onchain.total_sats();
+        TotalFunds {
+            channels,
+            lightning,
+            lightning_sats,
+            onchain,
+            onchain_sats,
+            total_sats: lightning_sats + onchain_sats,
+        }
+    }
+}
+
+/// The claimable balances of every channel we have a monitor for, but the fully resolved ones.
+pub fn channel_balances(
+    channel_manager: &ChannelManager,
+    chain_monitor: &ChainMonitor,
+) -> Vec<ChannelBalances> {
+    let open = channel_manager.list_channels();
+    let mut channels = vec![];
+    for funding_txo in chain_monitor.list_monitors() {
+        let monitor = match chain_monitor.get_monitor(funding_txo) {
+            Ok(monitor) => monitor,
+            Err(()) => continue,
+        };
+        let balances: Vec<ClaimableBalance> = monitor
+            .get_claimable_balances()
+            .iter()
+            .map(ClaimableBalance::from)
+            .collect();
+        let channel_id = funding_txo.to_channel_id();
+        channels.push(ChannelBalances {
+            channel_id: hex_str(&channel_id),
+            counterparty_node_id: monitor
+                .get_counterparty_node_id()
+                .map(|node_id| node_id.to_string()),
+            open: open.iter().any(|chan| chan.channel_id == channel_id),
+            total_sats: balances.iter().map(ClaimableBalance::amount_sats).sum(),
+            balances,
+        });
+    }
+    channels.retain(|channel| !channel.is_fully_resolved());
+    channels.sort_by_key(|channel| Reverse(channel.total_sats));
+    channels
+}
+
+/// Our channel balances together with the on-chain wallet's.
+pub fn total_funds(
+    channel_manager: &ChannelManager,
+    chain_monitor: &ChainMonitor,
+    wallet: &BitcoinWallet,
+) -> Result<TotalFunds, String> {
+    let onchain = wallet
+        .balance()
+        .map_err(|e| format!("failed to get the wallet balance: {:?}", e))?;
+    Ok(TotalFunds::new(
+        channel_balances(channel_manager, chain_monitor),
+        onchain.into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(balances: Vec<ClaimableBalance>) -> ChannelBalances {
+        ChannelBalances {
+            channel_id: String::new(),
+            counterparty_node_id: None,
+            open: false,
+            total_sats: balances.iter().map(ClaimableBalance::amount_sats).sum(),
+            balances,
+        }
+    }
+
+    #[test]
+    fn totals_add_up_by_type() {
+        let funds = TotalFunds::new(
+            vec![
+                channel(vec![
+                    ClaimableBalance::ClaimableOnChannelClose {
+                        amount_sats: 50_000,
+                    },
+                    ClaimableBalance::MaybePreimageClaimableHtlc {
+                        amount_sats: 1_000,
+                        expiry_height: 800_100,
+                    },
+                ]),
+                channel(vec![
+                    ClaimableBalance::ClaimableAwaitingConfirmations {
+                        amount_sats: 20_000,
+                        confirmation_height: 800_006,
+                    },
+                    ClaimableBalance::MaybeTimeoutClaimableHtlc {
+                        amount_sats: 2_000,
+                        claimable_height: 800_040,
+                    },
+                    ClaimableBalance::ClaimableAwaitingConfirmations {
+                        amount_sats: 5_000,
+                        confirmation_height: 800_144,
+                    },
+                ]),
+            ],
+            OnchainBalance {
+                confirmed_sats: 100_000,
+                trusted_pending_sats: 3_000,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            funds.lightning,
+            BalanceTotals {
+                claimable_on_channel_close_sats: 50_000,
+                claimable_awaiting_confirmations_sats: 25_000,
+                maybe_timeout_claimable_htlc_sats: 2_000,
+                maybe_preimage_claimable_htlc_sats: 1_000,
+                ..Default::default()
+            }
+        );
+        assert_eq!(funds.lightning_sats, 77_000);
+        assert_eq!(funds.onchain_sats, 103_000);
+        assert_eq!(funds.total_sats, 180_000);
+    }
+
+    #[test]
+    fn only_closed_channels_without_balances_are_resolved() {
+        assert!(channel(vec![]).is_fully_resolved());
+        assert!(!ChannelBalances {
+            open: true,
+            ..channel(vec![])
+        }
+        .is_fully_resolved());
+        assert!(
+            !channel(vec![ClaimableBalance::ClaimableAwaitingConfirmations {
+                amount_sats: 20_000,
+                confirmation_height: 800_006,
+            }])
+            .is_fully_resolved()
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! Inbound channel acceptance: every open channel request goes through the policy in
+//! `{ldk_data_dir}/channel_acceptance.json`, which accepts it, rejects it or holds it for an
+//! operator to approve. Decisions are kept in an inbox for readers.
+
+use crate::ldk::channel_history::ChannelHistory;
+use crate::types::ChannelManager;
+use crate::utils::hex::{hex_str, to_compressed_pubkey, to_vec};
+use crate::utils::inbox::Inbox;
+use crate::utils::json_file;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::features::ChannelTypeFeatures;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const CONFIG_FILE: &str = "channel_acceptance.json";
+
+/// Decisions kept for readers.
+const DECISIONS_CAPACITY: usize = 1000;
+
+/// The acceptance policy, as configured. Without a config every request is accepted, except
+/// zero-conf ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcceptancePolicyConfig {
+    #[serde(default)]
+    pub min_funding_sats: Option<u64>,
+    #[serde(default)]
+    pub max_funding_sats: Option<u64>,
+    /// Only these peers may open channels to us, unless empty.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Channels a peer may have opening with us at once, held requests included.
+    #[serde(default)]
+    pub max_pending_per_peer: Option<usize>,
+    /// Confirmations of the funding transaction we wait for on inbound channels.
+    #[serde(default)]
+    pub min_confirmations: Option<u32>,
+    /// Peers we trust not to double spend a channel's funding, such as our LSP. Their channels
+    /// are accepted as zero-conf, usable before the funding confirms, and only theirs are when
+    /// they ask for it.
+    #[serde(default)]
+    pub zero_conf_peers: Vec<String>,
+    /// Hold the requests passing the policy for an operator to approve.
+    #[serde(default)]
+    pub manual_approval: bool,
+}
+
+/// [`AcceptancePolicyConfig`] with the peers parsed.
+#[derive(Debug, Default)]
+struct AcceptancePolicy {
+    min_funding_sats: Option<u64>,
+    max_funding_sats: Option<u64>,
+    allow: HashSet<PublicKey>,
+    deny: HashSet<PublicKey>,
+    max_pending_per_peer: Option<usize>,
+    min_confirmations: Option<u32>,
+    zero_conf_peers: HashSet<PublicKey>,
+    manual_approval: bool,
+}
+
+impl AcceptancePolicy {
+    fn from_config(config: AcceptancePolicyConfig) -> Result<Self, String> {
+        let peers = |peers: &[String]| {
+            peers
+                .iter()
+                .map(|peer| {
+                    to_compressed_pubkey(peer).ok_or_else(|| format!("invalid peer {}", peer))
+                })
+                .collect::<Result<HashSet<_>, _>>()
+        };
+        Ok(Self {
+            min_funding_sats: config.min_funding_sats,
+            max_funding_sats: config.max_funding_sats,
+            allow: peers(&config.allow)?,
+            deny: peers(&config.deny)?,
+            max_pending_per_peer: config.max_pending_per_peer,
+            min_confirmations: config.min_confirmations,
+            zero_conf_peers: peers(&config.zero_conf_peers)?,
+            manual_approval: config.manual_approval,
+        })
+    }
+
+    /// What to do with `request`, `pending` being the peer's channels not yet ready.
+    fn evaluate(&self, peer: &PublicKey, request: &ChannelRequest, pending: usize) -> Verdict {
+        if self.deny.contains(peer) {
+            return Verdict::Reject("peer is denied".to_string());
+        }
+        if !self.allow.is_empty() && !self.allow.contains(peer) {
+            return Verdict::Reject("peer is not allowed".to_string());
+        }
+        if let Some(min) = self.min_funding_sats {
+            if request.funding_satoshis < min {
+                return Verdict::Reject(format!("channel is below {} sats", min));
+            }
+        }
+        if let Some(max) = self.max_funding_sats {
+            if request.funding_satoshis > max {
+                return Verdict::Reject(format!("channel is above {} sats", max));
+            }
+        }
+        if request.zero_conf && !self.zero_conf_peers.contains(peer) {
+            return Verdict::Reject("zero-conf channels are only for trusted peers".to_string());
+        }
+        if let Some(max) = self.max_pending_per_peer {
+            if pending >= max {
+                return Verdict::Reject(format!("peer has {} channels pending", pending));
+            }
+        }
+        if self.manual_approval {
+            return Verdict::Hold;
+        }
+        Verdict::Accept
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Accept,
+    Reject(String),
+    Hold,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelRequest {
+    pub temporary_channel_id: String,
+    pub counterparty_node_id: String,
+    pub funding_satoshis: u64,
+    pub push_msat: u64,
+    /// Whether the peer wants to use the channel before its funding confirms.
+    pub zero_conf: bool,
+    /// Seconds since the Unix epoch.
+    pub received_at: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionKind {
+    Accepted,
+    Rejected,
+    Held,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelDecision {
+    #[serde(flatten)]
+    pub request: ChannelRequest,
+    pub decision: DecisionKind,
+    /// Why the request was rejected, or who decided.
+    pub reason: String,
+}
+
+pub struct ChannelAcceptor {
+    policy: AcceptancePolicy,
+    /// Held requests by temporary channel id.
+    pending: Mutex<HashMap<[u8; 32], (PublicKey, ChannelRequest)>>,
+    decisions: Inbox<ChannelDecision>,
+    /// Where the channels we accept are recorded.
+    channel_history: Arc<ChannelHistory>,
+}
+
+impl ChannelAcceptor {
+    /// Reads the policy, holding every request for approval if it's invalid.
+    pub fn load(ldk_data_dir: &str, channel_history: Arc<ChannelHistory>) -> Self {
+        let policy = if Path::new(&format!("{}/{}", ldk_data_dir, CONFIG_FILE)).exists() {
+            json_file::load_checked(
+                ldk_data_dir,
+                CONFIG_FILE,
+                "channel acceptance config",
+                "holding every request for approval",
+                AcceptancePolicy::from_config,
+            )
+            .unwrap_or(AcceptancePolicy {
+                manual_approval: true,
+                ..Default::default()
+            })
+        } else {
+            AcceptancePolicy::default()
+        };
+        Self {
+            policy,
+            pending: Mutex::new(HashMap::new()),
+            decisions: Inbox::new(DECISIONS_CAPACITY),
+            channel_history,
+        }
+    }
+
+    pub fn min_confirmations(&self) -> Option<u32> {
+        self.policy.min_confirmations
+    }
+
+    pub fn decisions(&self) -> &Inbox<ChannelDecision> {
+        &self.decisions
+    }
+
+    /// The requests held for approval, oldest first.
+    pub fn pending(&self) -> Vec<ChannelRequest> {
+        let mut pending: Vec<ChannelRequest> = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(_, request)| request.clone())
+            .collect();
+        pending.sort_by_key(|request| request.received_at);
+        pending
+    }
+
+    /// Decides on an `Event::OpenChannelRequest`.
+    pub fn handle_request(
+        &self,
+        channel_manager: &ChannelManager,
+        temporary_channel_id: [u8; 32],
+        counterparty_node_id: PublicKey,
+        funding_satoshis: u64,
+        push_msat: u64,
+        channel_type: &ChannelTypeFeatures,
+    ) {
+        let request = ChannelRequest {
+            temporary_channel_id: hex_str(&temporary_channel_id),
+            counterparty_node_id: counterparty_node_id.to_string(),
+            funding_satoshis,
+            push_msat,
+            zero_conf: channel_type.requires_zero_conf(),
+            received_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        let opening = channel_manager
+            .list_channels()
+            .iter()
+            .filter(|chan| chan.counterparty.node_id == counterparty_node_id)
+            .filter(|chan| !chan.is_channel_ready)
+            .count()
+            + pending
+                .values()
+                .filter(|(peer, _)| *peer == counterparty_node_id)
+                .count();
+        match self
+            .policy
+            .evaluate(&counterparty_node_id, &request, opening)
+        {
+            Verdict::Accept => {
+                drop(pending);
+                let _ = self.accept(channel_manager, counterparty_node_id, request, "policy");
+            }
+            Verdict::Reject(reason) => {
+                drop(pending);
+                self.reject(channel_manager, counterparty_node_id, request, reason);
+            }
+            Verdict::Hold => {
+                pending.insert(
+                    temporary_channel_id,
+                    (counterparty_node_id, request.clone()),
+                );
+                drop(pending);
+                self.decide(request, DecisionKind::Held, "awaiting approval".to_string());
+            }
+        }
+    }
+
+    /// Accepts the held request for `temporary_channel_id`.
+    pub fn approve(
+        &self,
+        channel_manager: &ChannelManager,
+        temporary_channel_id: &str,
+    ) -> Result<(), String> {
+        let (peer, request) = self.take_pending(temporary_channel_id)?;
+        self.accept(channel_manager, peer, request, "operator")
+    }
+
+    /// Rejects the held request for `temporary_channel_id`.
+    pub fn deny(
+        &self,
+        channel_manager: &ChannelManager,
+        temporary_channel_id: &str,
+    ) -> Result<(), String> {
+        let (peer, request) = self.take_pending(temporary_channel_id)?;
+        self.reject(
+            channel_manager,
+            peer,
+            request,
+            "rejected by operator".to_string(),
+        );
+        Ok(())
+    }
+
+    /// Drops the requests held from `peer`, which LDK forgets as it disconnects.
+    pub fn peer_disconnected(&self, peer: &PublicKey) {
+        let mut dropped = Vec::new();
+        self.pending.lock().unwrap().retain(|_, (from, request)| {
+            if from == peer {
+                dropped.push(request.clone());
+            }
+            from != peer
+        });
+        for request in dropped {
+            self.decide(
+                request,
+                DecisionKind::Rejected,
+                "peer disconnected before approval".to_string(),
+            );
+        }
+    }
+
+    fn take_pending(
+        &self,
+        temporary_channel_id: &str,
+    ) -> Result<(PublicKey, ChannelRequest), String> {
+        let id = to_vec(temporary_channel_id)
+            .filter(|id| id.len() == 32)
+            .ok_or_else(|| "invalid temporary channel id".to_string())?;
+        let mut key = [0; 32];
+        key.copy_from_slice(&id);
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(&key)
+            .ok_or_else(|| format!("no pending channel request {}", temporary_channel_id))
+    }
+
+    /// Accepts `request`, recording the channel's opening once it is.
+    fn accept(
+        &self,
+        channel_manager: &ChannelManager,
+        peer: PublicKey,
+        request: ChannelRequest,
+        decided_by: &str,
+    ) -> Result<(), String> {
+        let temporary_channel_id = channel_id(&request);
+        let user_channel_id = u128::from_be_bytes(rand::random());
+        // Zero-conf requests only get here from trusted peers.
+        let zero_conf = request.zero_conf || self.policy.zero_conf_peers.contains(&peer);
+        let res = if zero_conf {
+            channel_manager.accept_inbound_channel_from_trusted_peer_0conf(
+                &temporary_channel_id,
+                &peer,
+                user_channel_id,
+            )
+        } else {
+            channel_manager.accept_inbound_channel(&temporary_channel_id, &peer, user_channel_id)
+        };
+        match res {
+            Ok(()) => {
+                self.channel_history
+                    .opening(temporary_channel_id, false, request.funding_satoshis);
+                self.decide(
+                    request,
+                    DecisionKind::Accepted,
+                    format!(
+                        "accepted{} by {}",
+                        if zero_conf { " as zero-conf" } else { "" },
+                        decided_by
+                    ),
+                );
+                Ok(())
+            }
+            Err(e) => {
+                let reason = format!("failed to accept: {:?}", e);
+                self.decide(request, DecisionKind::Rejected, reason.clone());
+                Err(reason)
+            }
+        }
+    }
+
+    fn reject(
+        &self,
+        channel_manager: &ChannelManager,
+        peer: PublicKey,
+        request: ChannelRequest,
+        reason: String,
+    ) {
+        // The peer may be gone already, which rejects the request too.
+        let _ = channel_manager.force_close_without_broadcasting_txn(&channel_id(&request), &peer);
+        self.decide(request, DecisionKind::Rejected, reason);
+    }
+
+    fn decide(&self, request: ChannelRequest, decision: DecisionKind, reason: String) {
+        println!(
+            "\nEVENT: channel request {} from {} for {} sats {:?}: {}",
+            request.temporary_channel_id,
+            request.counterparty_node_id,
+            request.funding_satoshis,
+            decision,
+            reason
+        );
+        print!("> ");
+        std::io::stdout().flush().unwrap();
+        self.decisions.push(ChannelDecision {
+            request,
+            decision,
+            reason,
+        });
+    }
+}
+
+fn channel_id(request: &ChannelRequest) -> [u8; 32] {
+    let mut id = [0; 32];
+    id.copy_from_slice(&to_vec(&request.temporary_channel_id).unwrap());
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+    #[test]
+    fn policy_decides_requests() {
+        let trusted = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+        let denied = "0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c";
+        let policy = AcceptancePolicy::from_config(AcceptancePolicyConfig {
+            min_funding_sats: Some(20_000),
+            max_funding_sats: Some(1_000_000),
+            deny: vec![denied.to_string()],
+            max_pending_per_peer: Some(2),
+            zero_conf_peers: vec![trusted.to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let request = |funding_satoshis, zero_conf| ChannelRequest {
+            temporary_channel_id: hex_str(&[1; 32]),
+            counterparty_node_id: String::new(),
+            funding_satoshis,
+            push_msat: 0,
+            zero_conf,
+            received_at: 0,
+        };
+        let trusted = to_compressed_pubkey(trusted).unwrap();
+        let denied = to_compressed_pubkey(denied).unwrap();
+
+        assert_eq!(
+            policy.evaluate(&trusted, &request(100_000, true), 0),
+            Verdict::Accept
+        );
+        assert_eq!(
+            policy.evaluate(&denied, &request(100_000, false), 0),
+            Verdict::Reject("peer is denied".to_string())
+        );
+        assert!(matches!(
+            policy.evaluate(&trusted, &request(10_000, false), 0),
+            Verdict::Reject(_)
+        ));
+        assert!(matches!(
+            policy.evaluate(&trusted, &request(2_000_000, false), 0),
+            Verdict::Reject(_)
+        ));
+        assert!(matches!(
+            policy.evaluate(&trusted, &request(100_000, false), 2),
+            Verdict::Reject(_)
+        ));
+        let other = PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[3; 32]).unwrap(),
+        );
+        assert_eq!(
+            policy.evaluate(&other, &request(100_000, true), 0),
+            Verdict::Reject("zero-conf channels are only for trusted peers".to_string())
+        );
+        assert_eq!(
+            policy.evaluate(&other, &request(100_000, false), 0),
+            Verdict::Accept
+        );
+
+        let allow_only = AcceptancePolicy::from_config(AcceptancePolicyConfig {
+            allow: vec![trusted.to_string()],
+            manual_approval: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            allow_only.evaluate(&trusted, &request(100_000, false), 0),
+            Verdict::Hold
+        );
+        assert_eq!(
+            allow_only.evaluate(&denied, &request(100_000, false), 0),
+            Verdict::Reject("peer is not allowed".to_string())
+        );
+        assert!(AcceptancePolicy::from_config(AcceptancePolicyConfig {
+            deny: vec!["nope".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn held_requests_are_dropped_when_their_peer_disconnects() {
+        let acceptor = ChannelAcceptor::load(
+            "/nonexistent",
+            Arc::new(ChannelHistory::load("/nonexistent")),
+        );
+        let peer = |byte| {
+            PublicKey::from_secret_key(
+                &Secp256k1::new(),
+                &SecretKey::from_slice(&[byte; 32]).unwrap(),
+            )
+        };
+        for (id, from) in &[(1u8, peer(1)), (2, peer(2)), (3, peer(1))] {
+            let request = ChannelRequest {
+                temporary_channel_id: hex_str(&[*id; 32]),
+                counterparty_node_id: from.to_string(),
+                funding_satoshis: 100_000,
+                push_msat: 0,
+                zero_conf: false,
+                received_at: *id as u64,
+            };
+            acceptor
+                .pending
+                .lock()
+                .unwrap()
+                .insert([*id; 32], (*from, request));
+        }
+
+        acceptor.peer_disconnected(&peer(1));
+        let pending = acceptor.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].counterparty_node_id, peer(2).to_string());
+        let decisions = acceptor.decisions().since(0);
+        assert_eq!(decisions.len(), 2);
+        assert!(decisions
+            .iter()
+            .all(|decided| decided.message.decision == DecisionKind::Rejected
+                && decided.message.request.counterparty_node_id == peer(1).to_string()));
+    }
+}
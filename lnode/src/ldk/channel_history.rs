@@ -0,0 +1,711 @@
+//! The lifecycle of our channels, kept in `{ldk_data_dir}/channel_history.json` from their
+//! creation through funding, ready and close to the sweep of their last on-chain output.
+//!
+//! Events move channels up to their close. After that, [`track`] follows their monitors: the
+//! close confirms once the funding output's spend does, the on-chain balances still to claim
+//! come from [`ChannelMonitor::get_claimable_balances`], and the channel is swept when none
+//! remain. What it swept adds up from the `Event::SpendableOutputs` its close gave us.
+//!
+//! A channel keeps every funding output it had, so a spliced channel can move to a new one. LDK
+//! 0.0.115 doesn't splice, so there's only ever one for now.
+//!
+//! [`ChannelMonitor::get_claimable_balances`]: lightning::chain::channelmonitor::ChannelMonitor::get_claimable_balances
+
+use crate::ldk::core::CoreLDK;
+use crate::types::{ChainMonitor, ChannelManager};
+use crate::utils::hex::hex_str;
+use crate::utils::json_file;
+use bitcoin::OutPoint;
+use lightning::chain::channelmonitor::Balance;
+use lightning::chain::keysinterface::SpendableOutputDescriptor;
+use lightning::chain::transaction::OutPoint as FundingOutPoint;
+use lightning::events::ClosureReason;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const HISTORY_FILE: &str = "channel_history.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelState {
+    Created,
+    /// The funding transaction was signed and broadcast.
+    Funded,
+    Ready,
+    /// Closed off-chain, its closing transaction not confirmed yet.
+    Closing,
+    CloseConfirmed,
+    /// Nothing of the channel is left to claim on chain.
+    Swept,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateChange {
+    pub state: ChannelState,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Funding {
+    /// `txid:vout`
+    pub funding_txo: String,
+    pub timestamp: u64,
+}
+
+/// A [`Balance`] of a channel, the amounts in satoshis.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClaimableBalance {
+    /// Ours if the channel closed now, less the fees of claiming it.
+    ClaimableOnChannelClose { amount_sats: u64 },
+    /// Ours once the transaction paying it reaches `confirmation_height`.
+    ClaimableAwaitingConfirmations {
+        amount_sats: u64,
+        confirmation_height: u32,
+    },
+    /// An HTLC we know the preimage of and can claim until `timeout_height`.
+    ContentiousClaimable {
+        amount_sats: u64,
+        timeout_height: u32,
+    },
+    /// An HTLC we sent, ours at `claimable_height` unless the counterparty claims it first.
+    MaybeTimeoutClaimableHtlc {
+        amount_sats: u64,
+        claimable_height: u32,
+    },
+    /// An HTLC we received, ours if we learn its preimage before `expiry_height`.
+    MaybePreimageClaimableHtlc {
+        amount_sats: u64,
+        expiry_height: u32,
+    },
+    /// An output of a revoked commitment the counterparty broadcast.
+    CounterpartyRevokedOutputClaimable { amount_sats: u64 },
+}
+
+impl ClaimableBalance {
+    pub fn amount_sats(&self) -> u64 {
+        match *self {
+            ClaimableBalance::ClaimableOnChannelClose { amount_sats }
+            | ClaimableBalance::ClaimableAwaitingConfirmations { amount_sats, .. }
+            | ClaimableBalance::ContentiousClaimable { amount_sats, .. }
+            | ClaimableBalance::MaybeTimeoutClaimableHtlc { amount_sats, .. }
+            | ClaimableBalance::MaybePreimageClaimableHtlc { amount_sats, .. }
+            | ClaimableBalance::CounterpartyRevokedOutputClaimable { amount_sats } => amount_sats,
+        }
+    }
+
+    /// The height its timelock expires at, if it has one.
+    pub fn height(&self) -> Option<u32> {
+        match *self {
+            ClaimableBalance::ClaimableAwaitingConfirmations {
+                confirmation_height,
+                ..
+            } => Some(confirmation_height),
+            ClaimableBalance::ContentiousClaimable { timeout_height, .. } => Some(timeout_height),
+            ClaimableBalance::MaybeTimeoutClaimableHtlc {
+                claimable_height, ..
+            } => Some(claimable_height),
+            ClaimableBalance::MaybePreimageClaimableHtlc { expiry_height, .. } => {
+                Some(expiry_height)
+            }
+            ClaimableBalance::ClaimableOnChannelClose { .. }
+            | ClaimableBalance::CounterpartyRevokedOutputClaimable { .. } => None,
+        }
+    }
+}
+
+impl From<&Balance> for ClaimableBalance {
+    fn from(balance: &Balance) -> Self {
+        match *balance {
+            Balance::ClaimableOnChannelClose {
+                claimable_amount_satoshis,
+            } => ClaimableBalance::ClaimableOnChannelClose {
+                amount_sats: claimable_amount_satoshis,
+            },
+            Balance::ClaimableAwaitingConfirmations {
+                claimable_amount_satoshis,
+                confirmation_height,
+            } => ClaimableBalance::ClaimableAwaitingConfirmations {
+                amount_sats: claimable_amount_satoshis,
+                confirmation_height,
+            },
+            Balance::ContentiousClaimable {
+                claimable_amount_satoshis,
+                timeout_height,
+            } => ClaimableBalance::ContentiousClaimable {
+                amount_sats: claimable_amount_satoshis,
+                timeout_height,
+            },
+            Balance::MaybeTimeoutClaimableHTLC {
+                claimable_amount_satoshis,
+                claimable_height,
+            } => ClaimableBalance::MaybeTimeoutClaimableHtlc {
+                amount_sats: claimable_amount_satoshis,
+                claimable_height,
+            },
+            Balance::MaybePreimageClaimableHTLC {
+                claimable_amount_satoshis,
+                expiry_height,
+            } => ClaimableBalance::MaybePreimageClaimableHtlc {
+                amount_sats: claimable_amount_satoshis,
+                expiry_height,
+            },
+            Balance::CounterpartyRevokedOutputClaimable {
+                claimable_amount_satoshis,
+            } => ClaimableBalance::CounterpartyRevokedOutputClaimable {
+                amount_sats: claimable_amount_satoshis,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelRecord {
+    pub channel_id: String,
+    pub counterparty_node_id: String,
+    #[serde(default)]
+    pub user_channel_id: Option<u128>,
+    pub is_outbound: Option<bool>,
+    pub channel_value_satoshis: Option<u64>,
+    pub state: ChannelState,
+    pub history: Vec<StateChange>,
+    /// Every funding output the channel had, the current one last.
+    pub fundings: Vec<Funding>,
+    pub close_reason: Option<String>,
+    pub closing_txid: Option<String>,
+    pub close_confirmation_height: Option<u32>,
+    /// What's left to claim on chain after the close.
+    pub claimable_balances: Vec<ClaimableBalance>,
+    /// What the outputs the close gave us to sweep were worth, before sweep fees.
+    #[serde(default)]
+    pub swept_sats: Option<u64>,
+    /// When the last timelock on our balances expires.
+    pub timelock_expiry_height: Option<u32>,
+}
+
+impl ChannelRecord {
+    fn new(channel_id: &[u8; 32], counterparty_node_id: String) -> Self {
+        Self {
+            channel_id: hex_str(channel_id),
+            counterparty_node_id,
+            user_channel_id: None,
+            is_outbound: None,
+            channel_value_satoshis: None,
+            state: ChannelState::Created,
+            history: Vec::new(),
+            fundings: Vec::new(),
+            close_reason: None,
+            closing_txid: None,
+            close_confirmation_height: None,
+            claimable_balances: Vec::new(),
+            swept_sats: None,
+            timelock_expiry_height: None,
+        }
+    }
+
+    /// Moves to `state` unless the channel is past it already.
+    fn advance(&mut self, state: ChannelState, timestamp: u64) {
+        if self.history.is_empty() || state > self.state {
+            self.state = state;
+            self.history.push(StateChange { state, timestamp });
+        }
+    }
+
+    fn funding_txo(&self) -> Option<OutPoint> {
+        self.fundings
+            .last()
+            .and_then(|funding| OutPoint::from_str(&funding.funding_txo).ok())
+    }
+
+    fn set_balances(&mut self, balances: Vec<ClaimableBalance>) {
+        if let Some(height) = balances.iter().filter_map(ClaimableBalance::height).max() {
+            self.timelock_expiry_height = Some(height);
+        }
+        self.claimable_balances = balances;
+    }
+}
+
+/// Channels being opened, by temporary channel id, until they get their real one.
+struct Opening {
+    is_outbound: bool,
+    channel_value_satoshis: u64,
+    timestamp: u64,
+}
+
+pub struct ChannelHistory {
+    records: Mutex<BTreeMap<String, ChannelRecord>>,
+    opening: Mutex<HashMap<[u8; 32], Opening>>,
+    ldk_data_dir: String,
+}
+
+impl ChannelHistory {
+    pub fn load(ldk_data_dir: &str) -> Self {
+        let records = json_file::load(
+            ldk_data_dir,
+            HISTORY_FILE,
+            "channel history",
+            "starting without",
+        )
+        .unwrap_or_default();
+        Self {
+            records: Mutex::new(records),
+            opening: Mutex::new(HashMap::new()),
+            ldk_data_dir: ldk_data_dir.to_string(),
+        }
+    }
+
+    fn save(&self, records: &BTreeMap<String, ChannelRecord>) {
+        let res = serde_json::to_string_pretty(records)
+            .map_err(|e| e.to_string())
+            .and_then(|records| {
+                fs::write(format!("{}/{}", self.ldk_data_dir, HISTORY_FILE), records)
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = res {
+            println!("ERROR: failed to persist channel history: {}", e);
+        }
+    }
+
+    fn update(
+        &self,
+        channel_id: &[u8; 32],
+        counterparty_node_id: impl FnOnce() -> String,
+        update: impl FnOnce(&mut ChannelRecord),
+    ) {
+        let mut records = self.records.lock().unwrap();
+        let record = records
+            .entry(hex_str(channel_id))
+            .or_insert_with(|| ChannelRecord::new(channel_id, counterparty_node_id()));
+        update(record);
+        self.save(&records);
+    }
+
+    pub fn get(&self, channel_id: &[u8; 32]) -> Option<ChannelRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .get(&hex_str(channel_id))
+            .cloned()
+    }
+
+    pub fn records(&self) -> Vec<ChannelRecord> {
+        self.records.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Adds the channels opened before we kept their history.
+    pub fn sync(&self, channel_manager: &ChannelManager) {
+        let now = now();
+        for chan in channel_manager.list_channels() {
+            if self.get(&chan.channel_id).is_some() {
+                continue;
+            }
+            self.update(
+                &chan.channel_id,
+                || chan.counterparty.node_id.to_string(),
+                |record| {
+                    record.user_channel_id = Some(chan.user_channel_id);
+                    record.is_outbound = Some(chan.is_outbound);
+                    record.channel_value_satoshis = Some(chan.channel_value_satoshis);
+                    record.advance(ChannelState::Created, now);
+                    if let Some(funding_txo) = chan.funding_txo {
+                        record.fundings.push(Funding {
+                            funding_txo: funding_txo.into_bitcoin_outpoint().to_string(),
+                            timestamp: now,
+                        });
+                        record.advance(ChannelState::Funded, now);
+                    }
+                    if chan.is_channel_ready {
+                        record.advance(ChannelState::Ready, now);
+                    }
+                },
+            );
+        }
+    }
+
+    /// A channel we open or have accepted, under its temporary channel id.
+    pub fn opening(
+        &self,
+        temporary_channel_id: [u8; 32],
+        is_outbound: bool,
+        channel_value_satoshis: u64,
+    ) {
+        self.opening.lock().unwrap().insert(
+            temporary_channel_id,
+            Opening {
+                is_outbound,
+                channel_value_satoshis,
+                timestamp: now(),
+            },
+        );
+    }
+
+    pub fn funded(
+        &self,
+        channel_manager: &ChannelManager,
+        channel_id: [u8; 32],
+        former_temporary_channel_id: Option<[u8; 32]>,
+        user_channel_id: u128,
+        counterparty_node_id: String,
+        funding_txo: OutPoint,
+    ) {
+        let opening =
+            former_temporary_channel_id.and_then(|id| self.opening.lock().unwrap().remove(&id));
+        let chan = channel_manager
+            .list_channels()
+            .into_iter()
+            .find(|chan| chan.channel_id == channel_id);
+        let now = now();
+        self.update(
+            &channel_id,
+            || counterparty_node_id,
+            |record| {
+                record.user_channel_id = Some(user_channel_id);
+                record.is_outbound = opening
+                    .as_ref()
+                    .map(|opening| opening.is_outbound)
+                    .or_else(|| chan.as_ref().map(|chan| chan.is_outbound));
+                record.channel_value_satoshis = opening
+                    .as_ref()
+                    .map(|opening| opening.channel_value_satoshis)
+                    .or_else(|| chan.as_ref().map(|chan| chan.channel_value_satoshis));
+                record.advance(
+                    ChannelState::Created,
+                    opening.as_ref().map_or(now, |opening| opening.timestamp),
+                );
+                let funding_txo = funding_txo.to_string();
+                if record.fundings.last().map(|f| &f.funding_txo) != Some(&funding_txo) {
+                    record.fundings.push(Funding {
+                        funding_txo,
+                        timestamp: now,
+                    });
+                }
+                record.advance(ChannelState::Funded, now);
+            },
+        );
+    }
+
+    pub fn ready(&self, channel_id: [u8; 32], counterparty_node_id: String) {
+        self.update(
+            &channel_id,
+            || counterparty_node_id,
+            |record| record.advance(ChannelState::Ready, now()),
+        );
+    }
+
+    pub fn closed(&self, channel_id: [u8; 32], reason: &ClosureReason) {
+        // Channels that never got their real id have nothing to follow on chain, and requests
+        // we rejected were never recorded.
+        self.opening.lock().unwrap().remove(&channel_id);
+        let mut records = self.records.lock().unwrap();
+        if let Some(record) = records.get_mut(&hex_str(&channel_id)) {
+            record.close_reason = Some(reason.to_string());
+            record.advance(ChannelState::Closing, now());
+            self.save(&records);
+        }
+    }
+
+    /// Adds the outputs of an `Event::SpendableOutputs` to what their channels swept. Outputs
+    /// from our commitment and HTLC transactions carry the channel's keys id, which
+    /// `KeysManager` ends with the `user_channel_id`, and those paid to our shutdown script
+    /// come from the closing transaction.
+    pub fn spendable_outputs(&self, outputs: &[SpendableOutputDescriptor]) {
+        let mut records = self.records.lock().unwrap();
+        let mut changed = false;
+        for output in outputs {
+            let (user_channel_id, txid, value) = match output {
+                SpendableOutputDescriptor::StaticOutput { outpoint, output } => {
+                    (None, outpoint.txid, output.value)
+                }
+                SpendableOutputDescriptor::DelayedPaymentOutput(descriptor) => (
+                    Some(user_channel_id(&descriptor.channel_keys_id)),
+                    descriptor.outpoint.txid,
+                    descriptor.output.value,
+                ),
+                SpendableOutputDescriptor::StaticPaymentOutput(descriptor) => (
+                    Some(user_channel_id(&descriptor.channel_keys_id)),
+                    descriptor.outpoint.txid,
+                    descriptor.output.value,
+                ),
+            };
+            let txid = txid.to_string();
+            let record = records.values_mut().find(|record| match user_channel_id {
+                Some(user_channel_id) => record.user_channel_id == Some(user_channel_id),
+                None => record.closing_txid.as_ref() == Some(&txid),
+            });
+            if let Some(record) = record {
+                record.swept_sats = Some(record.swept_sats.unwrap_or(0) + value);
+                changed = true;
+            }
+        }
+        if changed {
+            self.save(&records);
+        }
+    }
+
+    /// Follows the closed channels on chain, see the module docs.
+    async fn track_closes(&self, chain_monitor: &ChainMonitor, core: &CoreLDK) {
+        let monitors = chain_monitor.list_monitors();
+        let closing: Vec<_> = self
+            .records()
+            .into_iter()
+            .filter(|record| {
+                record.state >= ChannelState::Closing && record.state < ChannelState::Swept
+            })
+            .collect();
+        for record in closing {
+            let funding_txo = match record.funding_txo().or_else(|| {
+                monitors
+                    .iter()
+                    .find(|txo| hex_str(&txo.to_channel_id()) == record.channel_id)
+                    .map(|txo| txo.into_bitcoin_outpoint())
+            }) {
+                Some(funding_txo) => funding_txo,
+                None => continue,
+            };
+            let monitor_txo = FundingOutPoint {
+                txid: funding_txo.txid,
+                index: funding_txo.vout as u16,
+            };
+            let (balances, funding_script, candidates) =
+                match chain_monitor.get_monitor(monitor_txo) {
+                    Ok(monitor) => (
+                        monitor
+                            .get_claimable_balances()
+                            .iter()
+                            .map(ClaimableBalance::from)
+                            .collect::<Vec<_>>(),
+                        monitor.get_funding_txo().1,
+                        monitor.get_relevant_txids(),
+                    ),
+                    Err(()) => continue,
+                };
+            let spend = if record.closing_txid.is_none() {
+                match core
+                    .confirmed_spend(funding_txo, funding_script, candidates)
+                    .await
+                {
+                    Ok(spend) => spend,
+                    Err(e) => {
+                        println!(
+                            "ERROR: failed to look up the close of channel {}: {}",
+                            record.channel_id, e
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let mut records = self.records.lock().unwrap();
+            let record = match records.get_mut(&record.channel_id) {
+                Some(record) => record,
+                None => continue,
+            };
+            let now = now();
+            if record.fundings.is_empty() {
+                record.fundings.push(Funding {
+                    funding_txo: funding_txo.to_string(),
+                    timestamp: now,
+                });
+            }
+            if let Some((txid, height)) = spend {
+                record.closing_txid = Some(txid.to_string());
+                record.close_confirmation_height = Some(height);
+                record.advance(ChannelState::CloseConfirmed, now);
+            }
+            let swept = balances.is_empty() && record.state == ChannelState::CloseConfirmed;
+            record.set_balances(balances);
+            if swept {
+                record.advance(ChannelState::Swept, now);
+            }
+            self.save(&records);
+        }
+    }
+}
+
+/// The `user_channel_id` in the last 16 bytes of a channel keys id from `KeysManager`.
+fn user_channel_id(channel_keys_id: &[u8; 32]) -> u128 {
+    let mut id = [0; 16];
+    id.copy_from_slice(&channel_keys_id[16..]);
+    u128::from_be_bytes(id)
+}
+
+/// Follows the closed channels on chain once a minute.
+pub async fn track(
+    history: Arc<ChannelHistory>,
+    chain_monitor: Arc<ChainMonitor>,
+    core: Arc<CoreLDK>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        history.track_closes(&chain_monitor, &core).await;
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Script, TxOut, Txid};
+    use lightning::chain::keysinterface::StaticPaymentOutputDescriptor;
+
+    const CHANNEL_ID: [u8; 32] = [1; 32];
+
+    /// A channel that got to ready and was then closed cooperatively.
+    fn history(dir: &TempDataDir) -> ChannelHistory {
+        let history = ChannelHistory::load(dir.path());
+        history.update(
+            &CHANNEL_ID,
+            || "peer".to_string(),
+            |record| {
+                record.advance(ChannelState::Created, 100);
+                record.advance(ChannelState::Funded, 110);
+                record.advance(ChannelState::Ready, 120);
+            },
+        );
+        history.closed(CHANNEL_ID, &ClosureReason::CooperativeClosure);
+        history
+    }
+
+    fn states(record: &ChannelRecord) -> Vec<ChannelState> {
+        record.history.iter().map(|change| change.state).collect()
+    }
+
+    #[test]
+    fn lifecycle_is_kept_in_order() {
+        let dir = TempDataDir::new("channel-history-order");
+        let record = history(&dir).get(&CHANNEL_ID).unwrap();
+        assert_eq!(record.counterparty_node_id, "peer");
+        assert_eq!(
+            states(&record),
+            vec![
+                ChannelState::Created,
+                ChannelState::Funded,
+                ChannelState::Ready,
+                ChannelState::Closing
+            ]
+        );
+        assert!(record.close_reason.unwrap().contains("cooperatively"));
+    }
+
+    #[test]
+    fn late_events_dont_move_channels_back() {
+        let dir = TempDataDir::new("channel-history-late");
+        let history = history(&dir);
+        history.ready(CHANNEL_ID, String::new());
+        history.update(&CHANNEL_ID, String::new, |record| {
+            record.advance(ChannelState::Funded, 130)
+        });
+        let record = history.get(&CHANNEL_ID).unwrap();
+        assert_eq!(record.state, ChannelState::Closing);
+        assert_eq!(record.history.len(), 4);
+    }
+
+    #[test]
+    fn history_is_reloaded() {
+        let dir = TempDataDir::new("channel-history-reload");
+        let record = history(&dir).get(&CHANNEL_ID);
+        assert!(record.is_some());
+        assert_eq!(ChannelHistory::load(dir.path()).get(&CHANNEL_ID), record);
+    }
+
+    #[test]
+    fn claimable_balances_set_the_timelock_expiry() {
+        let dir = TempDataDir::new("channel-history-balances");
+        let history = history(&dir);
+        history.update(&CHANNEL_ID, String::new, |record| {
+            record.set_balances(vec![
+                ClaimableBalance::ClaimableAwaitingConfirmations {
+                    amount_sats: 50_000,
+                    confirmation_height: 800_144,
+                },
+                ClaimableBalance::MaybeTimeoutClaimableHtlc {
+                    amount_sats: 1_000,
+                    claimable_height: 800_040,
+                },
+            ]);
+            record.set_balances(vec![ClaimableBalance::ClaimableAwaitingConfirmations {
+                amount_sats: 50_000,
+                confirmation_height: 800_144,
+            }]);
+        });
+        let record = history.get(&CHANNEL_ID).unwrap();
+        assert_eq!(record.timelock_expiry_height, Some(800_144));
+        assert_eq!(record.claimable_balances.len(), 1);
+    }
+
+    #[test]
+    fn unknown_channels_have_no_record() {
+        let dir = TempDataDir::new("channel-history-unknown");
+        let history = history(&dir);
+        assert!(history.get(&[2; 32]).is_none());
+        // Closing a channel that never got its real id doesn't record it either.
+        history.opening([3; 32], true, 100_000);
+        history.closed([3; 32], &ClosureReason::CooperativeClosure);
+        assert!(history.get(&[3; 32]).is_none());
+        // Nor closing one we rejected.
+        history.closed([4; 32], &ClosureReason::CooperativeClosure);
+        assert!(history.get(&[4; 32]).is_none());
+        assert_eq!(history.records().len(), 1);
+    }
+
+    #[test]
+    fn spendable_outputs_add_up_to_what_was_swept() {
+        let dir = TempDataDir::new("channel-history-swept");
+        let history = history(&dir);
+        let closing_txid =
+            Txid::from_str("f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd")
+                .unwrap();
+        history.update(&CHANNEL_ID, String::new, |record| {
+            record.user_channel_id = Some(7);
+            record.closing_txid = Some(closing_txid.to_string());
+        });
+        let output = |value| TxOut {
+            value,
+            script_pubkey: Script::new(),
+        };
+        let mut channel_keys_id = [9; 32];
+        channel_keys_id[16..].copy_from_slice(&7u128.to_be_bytes());
+        history.spendable_outputs(&[
+            SpendableOutputDescriptor::StaticOutput {
+                outpoint: FundingOutPoint {
+                    txid: closing_txid,
+                    index: 0,
+                },
+                output: output(40_000),
+            },
+            SpendableOutputDescriptor::StaticPaymentOutput(StaticPaymentOutputDescriptor {
+                outpoint: FundingOutPoint {
+                    txid: Txid::all_zeros(),
+                    index: 1,
+                },
+                output: output(2_000),
+                channel_keys_id,
+                channel_value_satoshis: 100_000,
+            }),
+            // Some other channel's.
+            SpendableOutputDescriptor::StaticOutput {
+                outpoint: FundingOutPoint {
+                    txid: Txid::all_zeros(),
+                    index: 2,
+                },
+                output: output(1_000),
+            },
+        ]);
+        assert_eq!(history.get(&CHANNEL_ID).unwrap().swept_sats, Some(42_000));
+    }
+}
@@ -0,0 +1,199 @@
+//! Our channels as listed to users, and the settings for the ones we open, read from
+//! `{ldk_data_dir}/channels.json`.
+//!
+//! Zero-conf channels can be used before their funding transaction confirms. We accept them
+//! inbound from the trusted peers of the acceptance policy, and use the ones we open as soon as
+//! the peer lets us when `zero_conf` is set here. LDK 0.0.115 can't ask for `option_zeroconf`
+//! on the channels we open, so outbound it's up to the peer sending a `minimum_depth` of 0.
+
+use crate::ldk::fee_policy::FeePolicy;
+use crate::types::ChannelManager;
+use crate::utils::hex::hex_str;
+use crate::utils::json_file;
+use lightning::ln::channelmanager::ChannelDetails;
+use lightning::ln::features::ChannelTypeFeatures;
+use lightning::util::config::{
+    ChannelConfig, ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig,
+};
+use serde::{Deserialize, Serialize};
+
+const CONFIG_FILE: &str = "channels.json";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelOpenConfig {
+    /// Use the channels we open before their funding confirms, if the peer allows it.
+    #[serde(default)]
+    pub zero_conf: bool,
+}
+
+impl ChannelOpenConfig {
+    /// Reads `{ldk_data_dir}/channels.json`, the defaults applying when it doesn't exist.
+    pub fn load(ldk_data_dir: &str) -> Self {
+        json_file::load(
+            ldk_data_dir,
+            CONFIG_FILE,
+            "channels config",
+            "using the defaults",
+        )
+        .unwrap_or_default()
+    }
+
+    /// The config for a channel we open, starting with `channel_config` as its fee policy.
+    pub fn user_config(
+        &self,
+        announced_channel: bool,
+        channel_config: ChannelConfig,
+    ) -> UserConfig {
+        UserConfig {
+            channel_handshake_limits: ChannelHandshakeLimits {
+                // lnd's max to_self_delay is 2016, so we want to be compatible.
+                their_to_self_delay: 2016,
+                trust_own_funding_0conf: self.zero_conf,
+                ..Default::default()
+            },
+            channel_handshake_config: ChannelHandshakeConfig {
+                announced_channel,
+                ..Default::default()
+            },
+            channel_config,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelView {
+    pub channel_id: String,
+    pub counterparty_node_id: String,
+    pub funding_txid: Option<String>,
+    pub short_channel_id: Option<u64>,
+    /// What the peer calls the channel before it confirms, or instead of its real id.
+    pub inbound_scid_alias: Option<u64>,
+    pub channel_value_satoshis: u64,
+    pub balance_msat: u64,
+    pub outbound_capacity_msat: u64,
+    pub inbound_capacity_msat: u64,
+    pub is_outbound: bool,
+    pub is_channel_ready: bool,
+    pub is_usable: bool,
+    pub is_public: bool,
+    pub confirmations_required: Option<u32>,
+    pub confirmations: Option<u32>,
+    /// The negotiated channel type's features.
+    pub channel_type: Vec<String>,
+    /// Usable before the funding confirms.
+    pub zero_conf: bool,
+    /// The forwarding policy in effect.
+    pub fee_policy: Option<FeePolicy>,
+}
+
+impl From<&ChannelDetails> for ChannelView {
+    fn from(chan: &ChannelDetails) -> Self {
+        Self {
+            channel_id: hex_str(&chan.channel_id),
+            counterparty_node_id: chan.counterparty.node_id.to_string(),
+            funding_txid: chan.funding_txo.map(|txo| txo.txid.to_string()),
+            short_channel_id: chan.short_channel_id,
+            inbound_scid_alias: chan.inbound_scid_alias,
+            channel_value_satoshis: chan.channel_value_satoshis,
+            balance_msat: chan.balance_msat,
+            outbound_capacity_msat: chan.outbound_capacity_msat,
+            inbound_capacity_msat: chan.inbound_capacity_msat,
+            is_outbound: chan.is_outbound,
+            is_channel_ready: chan.is_channel_ready,
+            is_usable: chan.is_usable,
+            is_public: chan.is_public,
+            confirmations_required: chan.confirmations_required,
+            confirmations: chan.confirmations,
+            channel_type: chan
+                .channel_type
+                .as_ref()
+                .map(channel_type_names)
+                .unwrap_or_default(),
+            zero_conf: is_zero_conf(chan),
+            fee_policy: chan.config.as_ref().map(FeePolicy::from),
+        }
+    }
+}
+
+/// The features of a channel type, by their BOLT names.
+pub fn channel_type_names(channel_type: &ChannelTypeFeatures) -> Vec<String> {
+    let mut names = Vec::new();
+    if channel_type.requires_static_remote_key() {
+        names.push("static_remotekey".to_string());
+    }
+    if channel_type.requires_anchors_zero_fee_htlc_tx() {
+        names.push("anchors_zero_fee_htlc_tx".to_string());
+    }
+    if channel_type.requires_scid_privacy() {
+        names.push("scid_alias".to_string());
+    }
+    if channel_type.requires_zero_conf() {
+        names.push("zeroconf".to_string());
+    }
+    names
+}
+
+/// Whether `chan` was opened to be used before its funding confirms, whether the channel type
+/// says so or one side just didn't wait for confirmations.
+pub fn is_zero_conf(chan: &ChannelDetails) -> bool {
+    chan.confirmations_required == Some(0)
+        || chan
+            .channel_type
+            .as_ref()
+            .is_some_and(|channel_type| channel_type.requires_zero_conf())
+}
+
+pub fn list(channel_manager: &ChannelManager) -> Vec<ChannelView> {
+    channel_manager
+        .list_channels()
+        .iter()
+        .map(ChannelView::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+
+    #[test]
+    fn channel_types_are_named() {
+        let mut channel_type = ChannelTypeFeatures::empty();
+        channel_type.set_static_remote_key_required();
+        assert_eq!(channel_type_names(&channel_type), vec!["static_remotekey"]);
+        channel_type.set_zero_conf_required();
+        channel_type.set_scid_privacy_required();
+        assert_eq!(
+            channel_type_names(&channel_type),
+            vec!["static_remotekey", "scid_alias", "zeroconf"]
+        );
+    }
+
+    #[test]
+    fn open_config_defaults_without_a_file() {
+        let dir = TempDataDir::new("channels-default");
+        let config = ChannelOpenConfig::load(dir.path());
+        assert!(!config.zero_conf);
+        assert!(
+            !config
+                .user_config(true, ChannelConfig::default())
+                .channel_handshake_limits
+                .trust_own_funding_0conf
+        );
+    }
+
+    #[test]
+    fn open_config_is_applied() {
+        let dir = TempDataDir::new("channels-config");
+        dir.write(CONFIG_FILE, r#"{"zero_conf": true}"#);
+        let channel_config = ChannelConfig {
+            forwarding_fee_base_msat: 0,
+            ..Default::default()
+        };
+        let user_config = ChannelOpenConfig::load(dir.path()).user_config(false, channel_config);
+        assert!(user_config.channel_handshake_limits.trust_own_funding_0conf);
+        assert_eq!(user_config.channel_config, channel_config);
+        assert!(!user_config.channel_handshake_config.announced_channel);
+    }
+}
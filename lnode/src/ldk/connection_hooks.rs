@@ -0,0 +1,92 @@
+//! What we do as peers connect and disconnect, through the onion messenger's slot in the peer manager: LDK
+//! 0.0.115 tells no other handler we can plug in about connections, or lets it add features.
+
+use crate::ldk::channel_acceptance::ChannelAcceptor;
+use crate::ldk::peer_messages::PeerMessageHandler;
+use crate::ldk::peer_store::PeerStore;
+use crate::types::OnionMessenger;
+use bitcoin::secp256k1::PublicKey;
+use lightning::events::OnionMessageProvider;
+use lightning::ln::features::{InitFeatures, NodeFeatures};
+use lightning::ln::msgs::{Init, OnionMessage, OnionMessageHandler};
+use lightning::util::ser::Writeable;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// The flags of `features`, which LDK only hands out in their wire encoding: a length followed by
+/// the flags, big endian.
+pub(crate) fn le_flags<F: Writeable>(features: &F) -> Vec<u8> {
+    let mut flags = features.encode().split_off(2);
+    flags.reverse();
+    flags
+}
+
+/// The onion messenger as the peer manager sees it. It adds our custom feature bits to the
+/// features the messenger provides, records every connection, inbound or outbound, in the
+/// peer store and drops the channel requests held from peers as they disconnect.
+pub struct ConnectionHooks {
+    onion_messenger: Arc<OnionMessenger>,
+    peer_messages: Arc<PeerMessageHandler>,
+    peer_store: Arc<PeerStore>,
+    channel_acceptor: Arc<ChannelAcceptor>,
+}
+
+impl ConnectionHooks {
+    pub fn new(
+        onion_messenger: Arc<OnionMessenger>,
+        peer_messages: Arc<PeerMessageHandler>,
+        peer_store: Arc<PeerStore>,
+        channel_acceptor: Arc<ChannelAcceptor>,
+    ) -> Self {
+        Self {
+            onion_messenger,
+            peer_messages,
+            peer_store,
+            channel_acceptor,
+        }
+    }
+}
+
+impl OnionMessageProvider for ConnectionHooks {
+    fn next_onion_message_for_peer(&self, peer_node_id: PublicKey) -> Option<OnionMessage> {
+        self.onion_messenger
+            .next_onion_message_for_peer(peer_node_id)
+    }
+}
+
+impl OnionMessageHandler for ConnectionHooks {
+    fn handle_onion_message(&self, peer_node_id: &PublicKey, msg: &OnionMessage) {
+        self.onion_messenger.handle_onion_message(peer_node_id, msg)
+    }
+
+    fn peer_connected(
+        &self,
+        their_node_id: &PublicKey,
+        init: &Init,
+        inbound: bool,
+    ) -> Result<(), ()> {
+        self.onion_messenger
+            .peer_connected(their_node_id, init, inbound)?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.peer_store.connected(their_node_id, now);
+        Ok(())
+    }
+
+    fn peer_disconnected(&self, their_node_id: &PublicKey) {
+        self.onion_messenger.peer_disconnected(their_node_id);
+        self.channel_acceptor.peer_disconnected(their_node_id);
+    }
+
+    fn provided_node_features(&self) -> NodeFeatures {
+        let provided = self.onion_messenger.provided_node_features();
+        NodeFeatures::from_le_bytes(self.peer_messages.with_feature_bits(&provided))
+    }
+
+    fn provided_init_features(&self, their_node_id: &PublicKey) -> InitFeatures {
+        let provided = self.onion_messenger.provided_init_features(their_node_id);
+        InitFeatures::from_le_bytes(self.peer_messages.with_feature_bits(&provided))
+    }
+}
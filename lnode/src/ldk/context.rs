@@ -0,0 +1,59 @@
+//! The node's services, built once at startup and shared by the event handler and the CLI.
+
+use crate::ldk::announce::Announcer;
+use crate::ldk::channel_acceptance::ChannelAcceptor;
+use crate::ldk::channel_history::ChannelHistory;
+use crate::ldk::core::CoreLDK;
+use crate::ldk::fee_policy::FeePolicyStore;
+use crate::ldk::forwards::ForwardingHistory;
+use crate::ldk::lsp::Lsp;
+use crate::ldk::onion_messages::OnionMessageHandler;
+use crate::ldk::peer_messages::PeerMessageHandler;
+use crate::ldk::peer_store::PeerStore;
+use crate::ldk::tor::TorConfig;
+use crate::types::{
+    ChainMonitor, ChannelManager, NetworkGraph, OnionMessenger, PaymentAttemptStorage,
+    PaymentInfoStorage, PeerManager, ProbeStorage, Router, Scorer,
+};
+use crate::utils::disk::FilesystemLogger;
+use crate::wallet::BitcoinWallet;
+use bitcoin::network::constants::Network;
+use lightning::chain::keysinterface::KeysManager;
+use lightning_persister::FilesystemPersister;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct NodeContext {
+    pub channel_manager: Arc<ChannelManager>,
+    pub chain_monitor: Arc<ChainMonitor>,
+    pub peer_manager: Arc<PeerManager>,
+    /// The chain backend, which also broadcasts and estimates fees.
+    pub core: Arc<CoreLDK>,
+    pub keys_manager: Arc<KeysManager>,
+    pub network_graph: Arc<NetworkGraph>,
+    pub scorer: Arc<Mutex<Scorer>>,
+    pub router: Arc<Router>,
+    pub onion_messenger: Arc<OnionMessenger>,
+    pub persister: Arc<FilesystemPersister>,
+    pub logger: Arc<FilesystemLogger>,
+    pub inbound_payments: PaymentInfoStorage,
+    pub outbound_payments: PaymentInfoStorage,
+    pub probes: ProbeStorage,
+    pub payment_attempts: PaymentAttemptStorage,
+    pub onion_message_handler: Arc<OnionMessageHandler>,
+    pub peer_message_handler: Arc<PeerMessageHandler>,
+    pub peer_store: Arc<PeerStore>,
+    pub channel_acceptor: Arc<ChannelAcceptor>,
+    /// Set in LSP mode.
+    pub lsp: Option<Arc<Lsp>>,
+    pub fee_policies: Arc<FeePolicyStore>,
+    pub forwarding_history: Arc<ForwardingHistory>,
+    pub channel_history: Arc<ChannelHistory>,
+    pub bdk_wallet: Arc<BitcoinWallet>,
+    pub announcer: Arc<Announcer>,
+    pub tor: Option<TorConfig>,
+    pub ldk_data_dir: String,
+    pub network: Network,
+    pub port: u16,
+    pub node_name: String,
+}
@@ -1,15 +1,22 @@
-use crate::utils::convert::{BlockchainInfo, FeeResponse, FundedTx, NewAddress, RawTx, SignedTx};
+use crate::chain_source::sync::TxSync;
+use crate::chain_source::{self, ChainBackendConfig};
+use crate::utils::convert::{
+    BlockHashResponse, BlockchainInfo, FeeResponse, FundedTx, NewAddress, RawTx, SignedTx,
+    TxOutResponse,
+};
+use crate::utils::hex::to_vec;
 use base64;
 use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::consensus::encode;
 use bitcoin::hash_types::Txid;
-use bitcoin::{Address, BlockHash};
+use bitcoin::{Address, Block, BlockHash, OutPoint, Script, TxOut};
 use lightning::chain::chaininterface::BroadcasterInterface;
 use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
-use lightning::routing::utxo::{UtxoLookup, UtxoResult};
 use lightning_block_sync::http::HttpEndpoint;
 use lightning_block_sync::rpc::RpcClient;
-use lightning_block_sync::{AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSource};
+use lightning_block_sync::{
+    AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSource, BlockSourceError,
+};
 use serde_json;
 use std::collections::HashMap;
 use std::str::FromStr;
@@ -18,67 +25,151 @@ use std::sync::Arc;
 use std::time::Duration;
 use tokio::runtime::Handle;
 
+/// bitcoind's JSON-RPC interface, which also serves LDK blocks.
 #[derive(Clone)]
-pub struct CoreLDK {
-    bitcoind_rpc_client: Arc<RpcClient>,
+struct Bitcoind {
+    client: Arc<RpcClient>,
     host: String,
     port: u16,
     rpc_user: String,
     rpc_password: String,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Bitcoind(Bitcoind),
+    /// An Esplora or Electrum server, followed through `TxSync` instead of blocks.
+    TxSync(Arc<TxSync>),
+}
+
+#[derive(Clone)]
+pub struct CoreLDK {
+    backend: Backend,
     handle: tokio::runtime::Handle,
     fees: Arc<HashMap<Target, AtomicU32>>,
 }
 
 impl CoreLDK {
-    pub async fn new() -> std::io::Result<Self> {
+    pub async fn new(backend: &ChainBackendConfig) -> std::io::Result<Self> {
         let handle = tokio::runtime::Handle::current();
-        let host = "127.0.0.1".to_string();
-        let rpc_user: String = "admin".to_string();
-        let port = 18443;
-        let rpc_password: String = "password".to_string();
-        // let http_endpoint = HttpEndpoint::for_host(host.clone()).with_port(port);
-        // let rpc_credentials = base64::encode(format!("{}:{}", rpc_user, rpc_password));
-        // let bitcoind_rpc_client = RpcClient::new(&rpc_credentials, http_endpoint)?;
-        // let _dummy = bitcoind_rpc_client
-        //     .call_method::<BlockchainInfo>("getblockchaininfo", &vec![])
-        //     .await
-        //     .map_err(|_| {
-        //         std::io::Error::new(std::io::ErrorKind::PermissionDenied,
-				// "Failed to make initial call to bitcoind - please check your RPC user/password and access settings")
-        //     })?;
-        // dbg!(&_dummy.latest_height);
-        let http_endpoint = HttpEndpoint::for_host(host.clone()).with_port(port);
-        let rpc_credentials =
-            base64::encode(format!("{}:{}", rpc_user.clone(), rpc_password.clone()));
-        let bitcoind_rpc_client = RpcClient::new(&rpc_credentials, http_endpoint)?;
-        let _dummy = bitcoind_rpc_client
-            .call_method::<BlockchainInfo>("getblockchaininfo", &vec![])
-            .await
-            .map_err(|_| {
-                std::io::Error::new(std::io::ErrorKind::PermissionDenied,
+        let backend = match backend {
+            ChainBackendConfig::Rpc {
+                url,
+                username,
+                password,
+            } => {
+                let (host, port) = match url.rsplit_once(':') {
+                    Some((host, port)) => (host.to_string(), port.parse().ok()),
+                    None => (url.clone(), None),
+                };
+                let port = port.ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid bitcoind RPC url {}, expected host:port", url),
+                    )
+                })?;
+                let http_endpoint = HttpEndpoint::for_host(host.clone()).with_port(port);
+                let rpc_credentials = base64::encode(format!("{}:{}", username, password));
+                let client = RpcClient::new(&rpc_credentials, http_endpoint)?;
+                let _dummy = client
+                    .call_method::<BlockchainInfo>("getblockchaininfo", &[])
+                    .await
+                    .map_err(|_| {
+                        std::io::Error::new(std::io::ErrorKind::PermissionDenied,
 				"Failed to make initial call to bitcoind - please check your RPC user/password and access settings")
-            })?;
+                    })?;
+                Backend::Bitcoind(Bitcoind {
+                    client: Arc::new(client),
+                    host,
+                    port,
+                    rpc_user: username.clone(),
+                    rpc_password: password.clone(),
+                })
+            }
+            backend => {
+                let tx_sync = Arc::new(TxSync::new(chain_source::chain_client(backend)?));
+                let client = tx_sync.clone();
+                tokio::task::spawn_blocking(move || client.client().tip())
+                    .await
+                    .unwrap()
+                    .map_err(|e| {
+                        std::io::Error::new(
+                            e.kind(),
+                            format!("Failed to make initial call to the chain backend: {}", e),
+                        )
+                    })?;
+                Backend::TxSync(tx_sync)
+            }
+        };
         let mut fees: HashMap<Target, AtomicU32> = HashMap::new();
         fees.insert(Target::Background, AtomicU32::new(MIN_FEERATE));
         fees.insert(Target::Normal, AtomicU32::new(2000));
         fees.insert(Target::HighPriority, AtomicU32::new(5000));
         let client = Self {
-            bitcoind_rpc_client: Arc::new(bitcoind_rpc_client),
-            host,
-            port,
-            rpc_user,
-            rpc_password,
+            backend,
             fees: Arc::new(fees),
             handle: handle.clone(),
         };
-        CoreLDK::poll_for_fee_estimates(
-            client.fees.clone(),
-            client.bitcoind_rpc_client.clone(),
-            handle,
-        );
+        match &client.backend {
+            Backend::TxSync(tx_sync) => {
+                CoreLDK::poll_for_indexer_fee_estimates(client.fees.clone(), tx_sync.clone())
+            }
+            Backend::Bitcoind(bitcoind) => CoreLDK::poll_for_fee_estimates(
+                client.fees.clone(),
+                bitcoind.client.clone(),
+                handle,
+            ),
+        }
         Ok(client)
     }
 
+    /// The Esplora or Electrum sync LDK is fed through, `None` when following bitcoind.
+    pub fn tx_sync(&self) -> Option<Arc<TxSync>> {
+        match &self.backend {
+            Backend::TxSync(tx_sync) => Some(tx_sync.clone()),
+            Backend::Bitcoind(_) => None,
+        }
+    }
+
+    /// bitcoind, for the calls only it can answer.
+    fn bitcoind(&self) -> std::io::Result<&Bitcoind> {
+        match &self.backend {
+            Backend::Bitcoind(bitcoind) => Ok(bitcoind),
+            Backend::TxSync(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "This call needs the bitcoind chain backend",
+            )),
+        }
+    }
+
+    fn poll_for_indexer_fee_estimates(fees: Arc<HashMap<Target, AtomicU32>>, tx_sync: Arc<TxSync>) {
+        std::thread::spawn(move || loop {
+            // Indexers quote sat/vB, LDK wants sat/kW.
+            let estimate =
+                |target_blocks, default| match tx_sync.client().estimate_fee(target_blocks) {
+                    Ok(Some(sat_per_vb)) => std::cmp::max((sat_per_vb * 250.0) as u32, MIN_FEERATE),
+                    Ok(None) => default,
+                    Err(e) => {
+                        println!("ERROR: failed to fetch fee estimate: {}", e);
+                        default
+                    }
+                };
+            let background_estimate = estimate(144, MIN_FEERATE);
+            let normal_estimate = estimate(18, 2000);
+            let high_prio_estimate = estimate(6, 5000);
+            fees.get(&Target::Background)
+                .unwrap()
+                .store(background_estimate, Ordering::Release);
+            fees.get(&Target::Normal)
+                .unwrap()
+                .store(normal_estimate, Ordering::Release);
+            fees.get(&Target::HighPriority)
+                .unwrap()
+                .store(high_prio_estimate, Ordering::Release);
+            std::thread::sleep(Duration::from_secs(60));
+        });
+    }
+
     fn poll_for_fee_estimates(
         fees: Arc<HashMap<Target, AtomicU32>>,
         rpc_client: Arc<RpcClient>,
@@ -150,27 +241,31 @@ impl CoreLDK {
     }
 
     pub fn get_new_rpc_client(&self) -> std::io::Result<RpcClient> {
-        let http_endpoint = HttpEndpoint::for_host(self.host.clone()).with_port(self.port);
+        let bitcoind = self.bitcoind()?;
+        let http_endpoint = HttpEndpoint::for_host(bitcoind.host.clone()).with_port(bitcoind.port);
         let rpc_credentials = base64::encode(format!(
             "{}:{}",
-            self.rpc_user.clone(),
-            self.rpc_password.clone()
+            bitcoind.rpc_user.clone(),
+            bitcoind.rpc_password.clone()
         ));
         RpcClient::new(&rpc_credentials, http_endpoint)
     }
 
-    pub async fn create_raw_transaction(&self, outputs: Vec<HashMap<String, f64>>) -> RawTx {
+    pub async fn create_raw_transaction(
+        &self,
+        outputs: Vec<HashMap<String, f64>>,
+    ) -> std::io::Result<RawTx> {
         let outputs_json = serde_json::json!(outputs);
-        self.bitcoind_rpc_client
+        self.bitcoind()?
+            .client
             .call_method::<RawTx>(
                 "createrawtransaction",
                 &vec![serde_json::json!([]), outputs_json],
             )
             .await
-            .unwrap()
     }
 
-    pub async fn fund_raw_transaction(&self, raw_tx: RawTx) -> FundedTx {
+    pub async fn fund_raw_transaction(&self, raw_tx: RawTx) -> std::io::Result<FundedTx> {
         let raw_tx_json = serde_json::json!(raw_tx.0);
         let options = serde_json::json!({
             // LDK gives us feerates in satoshis per KW but Bitcoin Core here expects fees
@@ -184,51 +279,179 @@ impl CoreLDK {
             // change address or to a new channel output negotiated with the same node.
             "replaceable": false,
         });
-        self.bitcoind_rpc_client
+        self.bitcoind()?
+            .client
             .call_method("fundrawtransaction", &[raw_tx_json, options])
             .await
-            .unwrap()
     }
 
-    pub async fn send_raw_transaction(&self, raw_tx: RawTx) {
+    pub async fn send_raw_transaction(&self, raw_tx: RawTx) -> std::io::Result<Txid> {
         let raw_tx_json = serde_json::json!(raw_tx.0);
-        self.bitcoind_rpc_client
+        self.bitcoind()?
+            .client
             .call_method::<Txid>("sendrawtransaction", &[raw_tx_json])
             .await
-            .unwrap();
     }
 
-    pub async fn sign_raw_transaction_with_wallet(&self, tx_hex: String) -> SignedTx {
+    pub async fn sign_raw_transaction_with_wallet(
+        &self,
+        tx_hex: String,
+    ) -> std::io::Result<SignedTx> {
         let tx_hex_json = serde_json::json!(tx_hex);
-        self.bitcoind_rpc_client
+        self.bitcoind()?
+            .client
             .call_method("signrawtransactionwithwallet", &vec![tx_hex_json])
             .await
-            .unwrap()
     }
 
-    pub async fn get_new_address(&self) -> Address {
+    pub async fn get_new_address(&self) -> std::io::Result<Address> {
         let addr_args = vec![serde_json::json!("LDK output address")];
         let addr = self
-            .bitcoind_rpc_client
+            .bitcoind()?
+            .client
             .call_method::<NewAddress>("getnewaddress", &addr_args)
-            .await
-            .unwrap();
-        Address::from_str(addr.0.as_str()).unwrap()
+            .await?;
+        Address::from_str(addr.0.as_str())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// The unspent funding output `short_channel_id` points to, `None` if it doesn't exist or
+    /// was spent.
+    pub async fn funding_output(&self, short_channel_id: u64) -> std::io::Result<Option<TxOut>> {
+        let height = (short_channel_id >> 40) as u32;
+        let tx_index = ((short_channel_id >> 16) & 0xff_ffff) as usize;
+        let vout = (short_channel_id & 0xffff) as u32;
+        let bitcoind = match &self.backend {
+            Backend::Bitcoind(bitcoind) => bitcoind,
+            Backend::TxSync(tx_sync) => {
+                let tx_sync = tx_sync.clone();
+                return tokio::task::spawn_blocking(move || {
+                    tx_sync.client().unspent_output(height, tx_index, vout)
+                })
+                .await
+                .map_err(std::io::Error::other)?;
+            }
+        };
+        let block_hash = bitcoind
+            .client
+            .call_method::<BlockHashResponse>("getblockhash", &[serde_json::json!(height)])
+            .await?
+            .0;
+        let block = bitcoind
+            .client
+            .call_method::<Block>(
+                "getblock",
+                &[
+                    serde_json::json!(block_hash.to_string()),
+                    serde_json::json!(0),
+                ],
+            )
+            .await?;
+        let txid = match block.txdata.get(tx_index) {
+            Some(tx) => tx.txid(),
+            None => return Ok(None),
+        };
+        let tx_out = bitcoind
+            .client
+            .call_method::<TxOutResponse>(
+                "gettxout",
+                &[serde_json::json!(txid.to_string()), serde_json::json!(vout)],
+            )
+            .await?;
+        Ok(tx_out.0)
+    }
+
+    /// The confirmed transaction spending `outpoint`, which pays to `script_pubkey`, and the
+    /// height it confirmed at. Without an indexing backend, only the confirmed `candidates` are
+    /// looked at, bitcoind not indexing spends.
+    pub async fn confirmed_spend(
+        &self,
+        outpoint: OutPoint,
+        script_pubkey: Script,
+        candidates: Vec<(Txid, Option<BlockHash>)>,
+    ) -> std::io::Result<Option<(Txid, u32)>> {
+        let bitcoind = match &self.backend {
+            Backend::Bitcoind(bitcoind) => bitcoind,
+            Backend::TxSync(tx_sync) => {
+                let tx_sync = tx_sync.clone();
+                return tokio::task::spawn_blocking(move || {
+                    let client = tx_sync.client();
+                    match client.output_spender(&outpoint, &script_pubkey)? {
+                        Some(txid) => {
+                            Ok(client.tx_status(&txid)?.map(|(height, _)| (txid, height)))
+                        }
+                        None => Ok(None),
+                    }
+                })
+                .await
+                .map_err(std::io::Error::other)?;
+            }
+        };
+        for (txid, block_hash) in candidates {
+            let block_hash = match block_hash {
+                Some(block_hash) => block_hash,
+                None => continue,
+            };
+            let raw_tx = bitcoind
+                .client
+                .call_method::<RawTx>(
+                    "getrawtransaction",
+                    &[
+                        serde_json::json!(txid.to_string()),
+                        serde_json::json!(false),
+                        serde_json::json!(block_hash.to_string()),
+                    ],
+                )
+                .await?;
+            let raw_tx = to_vec(&raw_tx.0).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid transaction hex")
+            })?;
+            let tx: Transaction = encode::deserialize(&raw_tx)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            if tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == outpoint)
+            {
+                let header = bitcoind
+                    .client
+                    .get_header(&block_hash, None)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.into_inner()))?;
+                return Ok(Some((txid, header.height)));
+            }
+        }
+        Ok(None)
     }
 
-    pub async fn get_blockchain_info(&self) -> BlockchainInfo {
-        let res = self.bitcoind_rpc_client
+    pub async fn get_blockchain_info(&self) -> std::io::Result<BlockchainInfo> {
+        let res = self
+            .bitcoind()?
+            .client
             .call_method::<BlockchainInfo>("getblockchaininfo", &vec![])
-            .await
-            .unwrap();
+            .await?;
         dbg!(&res);
-        res
+        Ok(res)
     }
 }
 
 impl BroadcasterInterface for CoreLDK {
     fn broadcast_transaction(&self, tx: &Transaction) {
-        let bitcoind_rpc_client = self.bitcoind_rpc_client.clone();
+        let bitcoind_rpc_client = match &self.backend {
+            Backend::Bitcoind(bitcoind) => bitcoind.client.clone(),
+            Backend::TxSync(tx_sync) => {
+                let tx_sync = tx_sync.clone();
+                let tx = tx.clone();
+                // Indexers word their rejections differently, so there is no telling an already
+                // confirmed transaction apart from a bad one here. Log instead of panicking.
+                self.handle.spawn_blocking(move || {
+                    if let Err(e) = tx_sync.client().broadcast(&tx) {
+                        println!("ERROR: failed to broadcast {}: {}", tx.txid(), e);
+                    }
+                });
+                return;
+            }
+        };
         let tx_serialized = serde_json::json!(encode::serialize_hex(tx));
         // let x =
         self.handle.spawn(async move {
@@ -295,6 +518,17 @@ impl FeeEstimator for CoreLDK {
     }
 }
 
+impl CoreLDK {
+    fn block_source(&self) -> Result<&RpcClient, BlockSourceError> {
+        match &self.backend {
+            Backend::Bitcoind(bitcoind) => Ok(&bitcoind.client),
+            Backend::TxSync(_) => Err(BlockSourceError::persistent(
+                "blocks are only served by the bitcoind backend",
+            )),
+        }
+    }
+}
+
 impl BlockSource for CoreLDK {
     fn get_header<'a>(
         &'a self,
@@ -302,7 +536,7 @@ impl BlockSource for CoreLDK {
         height_hint: Option<u32>,
     ) -> AsyncBlockSourceResult<'a, BlockHeaderData> {
         Box::pin(async move {
-            self.bitcoind_rpc_client
+            self.block_source()?
                 .get_header(header_hash, height_hint)
                 .await
         })
@@ -312,27 +546,23 @@ impl BlockSource for CoreLDK {
         &'a self,
         header_hash: &'a BlockHash,
     ) -> AsyncBlockSourceResult<'a, BlockData> {
-        Box::pin(async move { self.bitcoind_rpc_client.get_block(header_hash).await })
+        Box::pin(async move { self.block_source()?.get_block(header_hash).await })
     }
 
     fn get_best_block<'a>(&'a self) -> AsyncBlockSourceResult<(BlockHash, Option<u32>)> {
-        Box::pin(async move { self.bitcoind_rpc_client.get_best_block().await })
-    }
-}
-
-impl UtxoLookup for CoreLDK {
-    fn get_utxo(&self, _genesis_hash: &BlockHash, _short_channel_id: u64) -> UtxoResult {
-        // P2PGossipSync takes None for a UtxoLookup, so this will never be called.
-        todo!();
+        Box::pin(async move { self.block_source()?.get_best_block().await })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::chain_source::ChainBackendConfig;
 
     #[tokio::test]
     async fn test_rpc_connection() {
-        let bitcoinrpc = super::CoreLDK::new().await.unwrap();
-        assert_eq!(bitcoinrpc.port, 18443);
+        let bitcoinrpc = super::CoreLDK::new(&ChainBackendConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(bitcoinrpc.bitcoind().unwrap().port, 18443);
     }
 }
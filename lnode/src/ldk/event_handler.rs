@@ -1,84 +1,91 @@
-use bitcoin::consensus::encode;
 use lightning::{events::{ Event, PaymentFailureReason, PaymentPurpose }, chain::keysinterface::{SpendableOutputDescriptor, EntropySource}, util::persist::KVStorePersister};
-use lightning_persister::FilesystemPersister;
 use std::{
-    collections::HashMap,
     io::{self, Write},
     sync::Arc,
     time::Duration,
 };
 
-use bitcoin::{secp256k1::Secp256k1, Network, Transaction};
-use bitcoin_bech32::WitnessProgram;
-use lightning::{
-    chain::{
-        chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator},
-        keysinterface::KeysManager,
-    },
-    routing::gossip::NodeId,
-};
+use lightning::routing::gossip::NodeId;
 use rand::{thread_rng, Rng};
 use std::collections::hash_map::Entry;
 
 use crate::{
-    types::{
-        ChannelManager, HTLCStatus, MillisatAmount, NetworkGraph, PaymentInfo, PaymentInfoStorage,
-    },
-    utils::hex::{hex_str, to_vec}, wallet::BitcoinWallet,
+    types::{HTLCStatus, MillisatAmount, PaymentInfo},
+    utils::hex::hex_str,
 };
 
-use super::core::CoreLDK;
+use super::context::NodeContext;
+use super::lsp::InterceptedHtlc;
+use super::payments;
+use super::probing;
 
 
 pub(crate) const PENDING_SPENDABLE_OUTPUT_DIR: &'static str = "pending_spendable_outputs";
 
-pub async fn handle_ldk_events(
-    channel_manager: &Arc<ChannelManager>,
-    bitcoind_client: &CoreLDK,
-    network_graph: &NetworkGraph,
-    keys_manager: &KeysManager,
-    inbound_payments: &PaymentInfoStorage,
-    outbound_payments: &PaymentInfoStorage,
-    persister: &Arc<FilesystemPersister>,
-    network: Network,
-    event: Event,
-) {
+pub async fn handle_ldk_events(node: &NodeContext, event: Event) {
+    let NodeContext {
+        channel_manager,
+        network_graph,
+        keys_manager,
+        inbound_payments,
+        outbound_payments,
+        probes,
+        payment_attempts,
+        router,
+        persister,
+        channel_acceptor,
+        lsp,
+        fee_policies,
+        forwarding_history,
+        channel_history,
+        bdk_wallet,
+        ..
+    } = node;
     match event {
         Event::FundingGenerationReady {
             temporary_channel_id,
             counterparty_node_id,
             channel_value_satoshis,
             output_script,
-            ..
+            user_channel_id,
         } => {
-            // Construct the raw transaction with one output, that is paid the amount of the
-            // channel.
-            let addr = WitnessProgram::from_scriptpubkey(
-                &output_script[..],
-                match network {
-                    Network::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
-                    Network::Testnet => bitcoin_bech32::constants::Network::Testnet,
-                    Network::Regtest => bitcoin_bech32::constants::Network::Regtest,
-                    Network::Signet => bitcoin_bech32::constants::Network::Signet,
-                },
-            )
-            .expect("Lightning funding tx should always be to a SegWit output")
-            .to_address();
-            let mut outputs = vec![HashMap::with_capacity(1)];
-            outputs[0].insert(addr, channel_value_satoshis as f64 / 100_000_000.0);
-            let raw_tx = bitcoind_client.create_raw_transaction(outputs).await;
-
-            // Have your wallet put the inputs into the transaction such that the output is
-            // satisfied.
-            let funded_tx = bitcoind_client.fund_raw_transaction(raw_tx).await;
-
-            // Sign the final funding transaction and broadcast it.
-            let signed_tx = bitcoind_client
-                .sign_raw_transaction_with_wallet(funded_tx.hex)
-                .await;
-            assert_eq!(signed_tx.complete, true);
-            let final_tx: Transaction =
-                encode::deserialize(&to_vec(&signed_tx.hex).unwrap()).unwrap();
+            channel_history.opening(temporary_channel_id, true, channel_value_satoshis);
+            if let Some(lsp) = lsp {
+                if let Err(e) =
+                    lsp.check_funding(channel_manager, &temporary_channel_id, user_channel_id)
+                {
+                    println!("\nERROR: not funding channel: {}", e);
+                    print!("> ");
+                    io::stdout().flush().unwrap();
+                    let _ = channel_manager.force_close_without_broadcasting_txn(
+                        &temporary_channel_id,
+                        &counterparty_node_id,
+                    );
+                    return;
+                }
+            }
+            // Fund the channel from the on-chain wallet, which skips frozen coins and spends
+            // the coins picked for this channel when it was opened. Funding syncs the wallet
+            // first, so it runs off the async runtime.
+            let wallet = Arc::clone(bdk_wallet);
+            let funding = tokio::task::spawn_blocking(move || {
+                wallet.fund_channel(output_script, channel_value_satoshis, user_channel_id)
+            })
+            .await
+            .unwrap_or_else(|e| Err(bdk::Error::Generic(e.to_string())));
+            let final_tx = match funding {
+                Ok(tx) => tx,
+                Err(e) => {
+                    println!("\nERROR: failed to fund channel: {}", e);
+                    print!("> ");
+                    io::stdout().flush().unwrap();
+                    let _ = channel_manager.force_close_without_broadcasting_txn(
+                        &temporary_channel_id,
+                        &counterparty_node_id,
+                    );
+                    return;
+                }
+            };
             // Give the funding transaction back to LDK for opening the channel.
             if channel_manager
                 .funding_transaction_generated(
@@ -154,16 +161,20 @@ pub async fn handle_ldk_events(
                         secret: payment_secret,
                         status: HTLCStatus::Succeeded,
                         amt_msat: MillisatAmount(Some(amount_msat)),
+                        internal: false,
                     });
                 }
             }
         }
         Event::PaymentSent {
+            payment_id,
             payment_preimage,
             payment_hash,
             fee_paid_msat,
-            ..
         } => {
+            if let Some(payment_id) = payment_id {
+                router.payment_resolved(payment_id);
+            }
             let mut payments = outbound_payments.lock().unwrap();
             for (hash, payment) in payments.iter_mut() {
                 if *hash == payment_hash {
@@ -186,18 +197,60 @@ pub async fn handle_ldk_events(
                 }
             }
         }
-        Event::OpenChannelRequest { .. } => {
-            // Unreachable, we don't set manually_accept_inbound_channels
+        Event::OpenChannelRequest {
+            temporary_channel_id,
+            counterparty_node_id,
+            funding_satoshis,
+            push_msat,
+            channel_type,
+        } => {
+            channel_acceptor.handle_request(
+                channel_manager,
+                temporary_channel_id,
+                counterparty_node_id,
+                funding_satoshis,
+                push_msat,
+                &channel_type,
+            )
         }
-        Event::PaymentPathSuccessful { .. } => {}
-        Event::PaymentPathFailed { .. } => {}
-        Event::ProbeSuccessful { .. } => {}
-        Event::ProbeFailed { .. } => {}
+        Event::PaymentPathSuccessful {
+            payment_id, path, ..
+        } => payments::record_attempt(payment_attempts, payment_id, &path, None, false, true),
+        Event::PaymentPathFailed {
+            payment_id,
+            payment_failed_permanently,
+            path,
+            short_channel_id,
+            ..
+        } => {
+            // Payments sent before LDK tracked payment ids have nothing to record under.
+            if let Some(payment_id) = payment_id {
+                router.path_failed(payment_id, &path);
+                payments::record_attempt(
+                    payment_attempts,
+                    payment_id,
+                    &path,
+                    short_channel_id,
+                    payment_failed_permanently,
+                    false,
+                );
+            }
+        }
+        Event::ProbeSuccessful {
+            payment_id, path, ..
+        } => probing::probe_resolved(probes, payment_id, &path, None, true),
+        Event::ProbeFailed {
+            payment_id,
+            path,
+            short_channel_id,
+            ..
+        } => probing::probe_resolved(probes, payment_id, &path, short_channel_id, false),
         Event::PaymentFailed {
+            payment_id,
             payment_hash,
             reason,
-            ..
         } => {
+            router.payment_resolved(payment_id);
             print!(
                 "\nEVENT: Failed to send payment to payment hash {:?}: {:?}",
                 hex_str(&payment_hash.0),
@@ -215,6 +268,12 @@ pub async fn handle_ldk_events(
                 let payment = payments.get_mut(&payment_hash).unwrap();
                 payment.status = HTLCStatus::Failed;
             }
+            // A failed rebalance won't be received either.
+            if let Some(payment) = inbound_payments.lock().unwrap().get_mut(&payment_hash) {
+                if payment.internal {
+                    payment.status = HTLCStatus::Failed;
+                }
+            }
         }
         Event::PaymentForwarded {
             prev_channel_id,
@@ -223,6 +282,14 @@ pub async fn handle_ldk_events(
             claim_from_onchain_tx,
             outbound_amount_forwarded_msat,
         } => {
+            forwarding_history.forwarded(
+                channel_manager,
+                prev_channel_id,
+                next_channel_id,
+                fee_earned_msat,
+                claim_from_onchain_tx,
+                outbound_amount_forwarded_msat,
+            );
             let read_only_network_graph = network_graph.read_only();
             let nodes = read_only_network_graph.nodes();
             let channels = channel_manager.list_channels();
@@ -284,7 +351,12 @@ pub async fn handle_ldk_events(
             print!("> ");
             io::stdout().flush().unwrap();
         }
-        Event::HTLCHandlingFailed { .. } => {}
+        Event::HTLCHandlingFailed {
+            prev_channel_id,
+            failed_next_destination,
+        } => {
+            forwarding_history.failed(channel_manager, prev_channel_id, &failed_next_destination);
+        }
         Event::PendingHTLCsForwardable { time_forwardable } => {
             let forwarding_channel_manager = channel_manager.clone();
             let min = time_forwardable.as_millis() as u64;
@@ -304,6 +376,7 @@ pub async fn handle_ldk_events(
             // to spend them regularly (possibly duplicatively/RBF'ing them). These can just be
             // treated as normal funds where possible - they are only spendable by us and there is
             // no rush to claim them.
+            channel_history.spendable_outputs(&outputs);
             for output in outputs {
                 let key = hex_str(&keys_manager.get_secure_random_bytes());
                 // Note that if the type here changes our read code needs to change as well.
@@ -319,8 +392,18 @@ pub async fn handle_ldk_events(
         Event::ChannelPending {
             channel_id,
             counterparty_node_id,
-            ..
+            former_temporary_channel_id,
+            funding_txo,
+            user_channel_id,
         } => {
+            channel_history.funded(
+                channel_manager,
+                channel_id,
+                former_temporary_channel_id,
+                user_channel_id,
+                counterparty_node_id.to_string(),
+                funding_txo,
+            );
             println!(
                 "\nEVENT: Channel {} with peer {} is pending awaiting funding lock-in!",
                 hex_str(&channel_id),
@@ -331,7 +414,7 @@ pub async fn handle_ldk_events(
         }
         Event::ChannelReady {
             ref channel_id,
-            user_channel_id: _,
+            user_channel_id,
             ref counterparty_node_id,
             channel_type: _,
         } => {
@@ -342,11 +425,18 @@ pub async fn handle_ldk_events(
             );
             print!("> ");
             io::stdout().flush().unwrap();
+            channel_history.ready(*channel_id, counterparty_node_id.to_string());
+            if let Err(e) = fee_policies.apply(channel_manager, Some(*channel_id)) {
+                println!("ERROR: {}", e);
+            }
+            if let Some(lsp) = lsp {
+                lsp.channel_ready(channel_manager, channel_id, user_channel_id, *counterparty_node_id);
+            }
         }
         Event::ChannelClosed {
             channel_id,
             reason,
-            user_channel_id: _,
+            user_channel_id,
         } => {
             println!(
                 "\nEVENT: Channel {} closed due to: {:?}",
@@ -355,11 +445,37 @@ pub async fn handle_ldk_events(
             );
             print!("> ");
             io::stdout().flush().unwrap();
+            channel_history.closed(channel_id, &reason);
+            if let Some(lsp) = lsp {
+                lsp.channel_closed(channel_manager, user_channel_id);
+            }
         }
         Event::DiscardFunding { .. } => {
             // A "real" node should probably "lock" the UTXOs spent in funding transactions until
             // the funding transaction either confirms, or this event is generated.
         }
-        Event::HTLCIntercepted { .. } => {}
+        Event::HTLCIntercepted {
+            intercept_id,
+            requested_next_hop_scid,
+            payment_hash,
+            inbound_amount_msat,
+            expected_outbound_amount_msat,
+        } => match lsp {
+            Some(lsp) => lsp.handle_intercept(
+                channel_manager,
+                fee_policies,
+                InterceptedHtlc {
+                    id: intercept_id,
+                    requested_next_hop_scid,
+                    payment_hash,
+                    inbound_amount_msat,
+                    expected_outbound_amount_msat,
+                },
+            ),
+            // Unreachable, we only set accept_intercept_htlcs in LSP mode
+            None => {
+                let _ = channel_manager.fail_intercepted_htlc(intercept_id);
+            }
+        },
     }
 }
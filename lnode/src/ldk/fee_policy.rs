@@ -0,0 +1,353 @@
+//! Forwarding fee and CLTV policy, kept in `{ldk_data_dir}/fee_policy.json`: a global policy
+//! and per-channel overrides on top of LDK's defaults, applied to our channels at startup, when
+//! they become ready and when the policy changes.
+
+use crate::types::ChannelManager;
+use crate::utils::hex::{hex_str, to_vec};
+use crate::utils::json_file;
+use lightning::ln::channelmanager::MIN_CLTV_EXPIRY_DELTA;
+use lightning::util::config::ChannelConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+
+const POLICY_FILE: &str = "fee_policy.json";
+
+/// The parts of a channel's config we manage, the ones set overriding those below them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeePolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_base_msat: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_proportional_millionths: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cltv_expiry_delta: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_dust_htlc_exposure_msat: Option<u64>,
+}
+
+impl FeePolicy {
+    fn check(&self) -> Result<(), String> {
+        match self.cltv_expiry_delta {
+            Some(delta) if delta < MIN_CLTV_EXPIRY_DELTA => Err(format!(
+                "CLTV expiry delta must be at least {}",
+                MIN_CLTV_EXPIRY_DELTA
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the fields set in `other`.
+    fn merge(&mut self, other: &FeePolicy) {
+        self.fee_base_msat = other.fee_base_msat.or(self.fee_base_msat);
+        self.fee_proportional_millionths = other
+            .fee_proportional_millionths
+            .or(self.fee_proportional_millionths);
+        self.cltv_expiry_delta = other.cltv_expiry_delta.or(self.cltv_expiry_delta);
+        self.max_dust_htlc_exposure_msat = other
+            .max_dust_htlc_exposure_msat
+            .or(self.max_dust_htlc_exposure_msat);
+    }
+
+    fn apply_to(&self, config: &mut ChannelConfig) {
+        if let Some(fee_base_msat) = self.fee_base_msat {
+            config.forwarding_fee_base_msat = fee_base_msat;
+        }
+        if let Some(fee_proportional_millionths) = self.fee_proportional_millionths {
+            config.forwarding_fee_proportional_millionths = fee_proportional_millionths;
+        }
+        if let Some(cltv_expiry_delta) = self.cltv_expiry_delta {
+            config.cltv_expiry_delta = cltv_expiry_delta;
+        }
+        if let Some(max_dust_htlc_exposure_msat) = self.max_dust_htlc_exposure_msat {
+            config.max_dust_htlc_exposure_msat = max_dust_htlc_exposure_msat;
+        }
+    }
+}
+
+/// A channel's config as a policy, every field set.
+impl From<&ChannelConfig> for FeePolicy {
+    fn from(config: &ChannelConfig) -> Self {
+        Self {
+            fee_base_msat: Some(config.forwarding_fee_base_msat),
+            fee_proportional_millionths: Some(config.forwarding_fee_proportional_millionths),
+            cltv_expiry_delta: Some(config.cltv_expiry_delta),
+            max_dust_htlc_exposure_msat: Some(config.max_dust_htlc_exposure_msat),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeePolicies {
+    #[serde(default)]
+    pub global: FeePolicy,
+    /// Overrides by hex channel id.
+    #[serde(default)]
+    pub channels: BTreeMap<String, FeePolicy>,
+}
+
+impl FeePolicies {
+    /// The config `channel_id` should have, the fields we don't manage kept from `current`.
+    fn channel_config(&self, channel_id: &[u8; 32], current: &ChannelConfig) -> ChannelConfig {
+        let mut config = *current;
+        // Fields no policy sets anymore go back to LDK's defaults.
+        FeePolicy::from(&ChannelConfig::default()).apply_to(&mut config);
+        self.global.apply_to(&mut config);
+        if let Some(policy) = self.channels.get(&hex_str(channel_id)) {
+            policy.apply_to(&mut config);
+        }
+        config
+    }
+}
+
+pub struct FeePolicyStore {
+    policies: Mutex<FeePolicies>,
+    ldk_data_dir: String,
+}
+
+impl FeePolicyStore {
+    pub fn load(ldk_data_dir: &str) -> Self {
+        let policies = json_file::load_checked(
+            ldk_data_dir,
+            POLICY_FILE,
+            "fee policy",
+            "using LDK's defaults",
+            |policies: FeePolicies| {
+                policies.global.check()?;
+                for policy in policies.channels.values() {
+                    policy.check()?;
+                }
+                Ok(policies)
+            },
+        )
+        .unwrap_or_default();
+        Self {
+            policies: Mutex::new(policies),
+            ldk_data_dir: ldk_data_dir.to_string(),
+        }
+    }
+
+    pub fn policies(&self) -> FeePolicies {
+        self.policies.lock().unwrap().clone()
+    }
+
+    /// The config the channels we open start with, see `ChannelOpenConfig::user_config`, and
+    /// inbound ones.
+    pub fn default_config(&self) -> ChannelConfig {
+        let mut config = ChannelConfig::default();
+        self.policies.lock().unwrap().global.apply_to(&mut config);
+        config
+    }
+
+    fn save(&self, policies: &FeePolicies) -> Result<(), String> {
+        let policies = serde_json::to_string_pretty(policies).map_err(|e| e.to_string())?;
+        fs::write(format!("{}/{}", self.ldk_data_dir, POLICY_FILE), policies)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Sets the fields of `policy` on `channel_id`, or globally without one, and applies them.
+    /// The policy is kept even if applying it fails, channels catching up with it as they
+    /// become ready and at startup.
+    pub fn set(
+        &self,
+        channel_manager: &ChannelManager,
+        channel_id: Option<[u8; 32]>,
+        policy: &FeePolicy,
+    ) -> Result<(), String> {
+        policy.check()?;
+        if let Some(channel_id) = channel_id {
+            if !channel_manager
+                .list_channels()
+                .iter()
+                .any(|chan| chan.channel_id == channel_id)
+            {
+                return Err(format!("unknown channel {}", hex_str(&channel_id)));
+            }
+        }
+        let mut policies = self.policies.lock().unwrap();
+        let mut updated = policies.clone();
+        match channel_id {
+            Some(channel_id) => updated
+                .channels
+                .entry(hex_str(&channel_id))
+                .or_default()
+                .merge(policy),
+            None => updated.global.merge(policy),
+        }
+        self.save(&updated)?;
+        *policies = updated;
+        apply(channel_manager, &policies, channel_id)
+    }
+
+    /// Drops the override of `channel_id`, returning false if it had none.
+    pub fn reset(
+        &self,
+        channel_manager: &ChannelManager,
+        channel_id: [u8; 32],
+    ) -> Result<bool, String> {
+        let mut policies = self.policies.lock().unwrap();
+        let mut updated = policies.clone();
+        if updated.channels.remove(&hex_str(&channel_id)).is_none() {
+            return Ok(false);
+        }
+        self.save(&updated)?;
+        *policies = updated;
+        // The channel may be gone already.
+        let _ = apply(channel_manager, &policies, Some(channel_id));
+        Ok(true)
+    }
+
+    /// Applies the policy to `channel_id`, or to every channel without one.
+    pub fn apply(
+        &self,
+        channel_manager: &ChannelManager,
+        channel_id: Option<[u8; 32]>,
+    ) -> Result<(), String> {
+        apply(channel_manager, &self.policies.lock().unwrap(), channel_id)
+    }
+}
+
+fn apply(
+    channel_manager: &ChannelManager,
+    policies: &FeePolicies,
+    channel_id: Option<[u8; 32]>,
+) -> Result<(), String> {
+    for chan in channel_manager.list_channels() {
+        if channel_id.is_some_and(|channel_id| channel_id != chan.channel_id) {
+            continue;
+        }
+        let config = policies.channel_config(&chan.channel_id, &chan.config.unwrap_or_default());
+        if chan.config == Some(config) {
+            continue;
+        }
+        channel_manager
+            .update_channel_config(&chan.counterparty.node_id, &[chan.channel_id], &config)
+            .map_err(|e| {
+                format!(
+                    "failed to update channel {}: {:?}",
+                    hex_str(&chan.channel_id),
+                    e
+                )
+            })?;
+    }
+    Ok(())
+}
+
+/// Parses a hex channel id.
+pub fn parse_channel_id(channel_id: &str) -> Option<[u8; 32]> {
+    let bytes = to_vec(channel_id).filter(|bytes| bytes.len() == 32)?;
+    let mut id = [0; 32];
+    id.copy_from_slice(&bytes);
+    Some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+
+    fn policies() -> FeePolicies {
+        FeePolicies {
+            global: FeePolicy {
+                fee_proportional_millionths: Some(100),
+                cltv_expiry_delta: Some(80),
+                ..Default::default()
+            },
+            channels: BTreeMap::from([(
+                hex_str(&[1; 32]),
+                FeePolicy {
+                    fee_base_msat: Some(0),
+                    ..Default::default()
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn policies_are_loaded() {
+        let dir = TempDataDir::new("fee-policy-load");
+        dir.write(
+            POLICY_FILE,
+            &format!(
+                r#"{{"global": {{"fee_proportional_millionths": 100, "cltv_expiry_delta": 80}},
+                    "channels": {{"{}": {{"fee_base_msat": 0}}}}}}"#,
+                hex_str(&[1; 32])
+            ),
+        );
+        let store = FeePolicyStore::load(dir.path());
+        assert_eq!(store.policies(), policies());
+        let default_config = store.default_config();
+        assert_eq!(default_config.forwarding_fee_proportional_millionths, 100);
+        assert_eq!(default_config.forwarding_fee_base_msat, 1000);
+        assert_eq!(default_config.cltv_expiry_delta, 80);
+    }
+
+    #[test]
+    fn invalid_policies_are_dropped() {
+        let dir = TempDataDir::new("fee-policy-invalid");
+        dir.write(POLICY_FILE, r#"{"global": {"cltv_expiry_delta": 10}}"#);
+        assert_eq!(
+            FeePolicyStore::load(dir.path()).policies(),
+            FeePolicies::default()
+        );
+    }
+
+    #[test]
+    fn channel_overrides_layer_on_the_global_policy() {
+        let policies = policies();
+        let current = ChannelConfig::default();
+        let config = policies.channel_config(&[1; 32], &current);
+        assert_eq!(config.forwarding_fee_base_msat, 0);
+        assert_eq!(config.forwarding_fee_proportional_millionths, 100);
+        assert_eq!(config.cltv_expiry_delta, 80);
+        let config = policies.channel_config(&[2; 32], &current);
+        assert_eq!(config.forwarding_fee_base_msat, 1000);
+        assert_eq!(config.forwarding_fee_proportional_millionths, 100);
+    }
+
+    #[test]
+    fn unmanaged_fields_are_kept() {
+        let current = ChannelConfig {
+            forwarding_fee_base_msat: 5,
+            force_close_avoidance_max_fee_satoshis: 2000,
+            ..Default::default()
+        };
+        let config = FeePolicies::default().channel_config(&[1; 32], &current);
+        assert_eq!(config.force_close_avoidance_max_fee_satoshis, 2000);
+        // A policy no longer set doesn't stick.
+        assert_eq!(config.forwarding_fee_base_msat, 1000);
+    }
+
+    #[test]
+    fn merged_policies_keep_the_fields_left_unset() {
+        let mut global = policies().global;
+        global.merge(&FeePolicy {
+            cltv_expiry_delta: Some(144),
+            ..Default::default()
+        });
+        assert_eq!(global.fee_proportional_millionths, Some(100));
+        assert_eq!(global.cltv_expiry_delta, Some(144));
+        assert_eq!(
+            FeePolicy::from(&ChannelConfig::default()).max_dust_htlc_exposure_msat,
+            Some(5_000_000)
+        );
+    }
+
+    #[test]
+    fn cltv_expiry_deltas_are_checked() {
+        let policy = |delta| FeePolicy {
+            cltv_expiry_delta: Some(delta),
+            ..Default::default()
+        };
+        assert!(policy(10).check().is_err());
+        assert!(policy(MIN_CLTV_EXPIRY_DELTA).check().is_ok());
+    }
+
+    #[test]
+    fn channel_ids_are_32_bytes_of_hex() {
+        assert_eq!(parse_channel_id(&hex_str(&[1; 32])), Some([1; 32]));
+        assert_eq!(parse_channel_id("01"), None);
+        assert_eq!(parse_channel_id("zz"), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! Forwarding history: every HTLC we forwarded and every forward we failed, appended to
+//! `{ldk_data_dir}/forwards.jsonl` and `{ldk_data_dir}/forward_failures.jsonl`, and the routing
+//! revenue they add up to per channel or per peer over a time window.
+//!
+//! Peers are resolved when the event comes in, so forwards settled on chain after their channel
+//! closed have none.
+
+use crate::types::ChannelManager;
+use crate::utils::hex::hex_str;
+use lightning::events::HTLCDestination;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const FORWARDS_FILE: &str = "forwards.jsonl";
+const FAILURES_FILE: &str = "forward_failures.jsonl";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Forward {
+    pub timestamp: u64,
+    pub prev_channel_id: Option<String>,
+    pub prev_peer: Option<String>,
+    pub next_channel_id: Option<String>,
+    pub next_peer: Option<String>,
+    /// The fee included, when LDK knows both.
+    pub amount_in_msat: Option<u64>,
+    pub amount_out_msat: Option<u64>,
+    pub fee_earned_msat: Option<u64>,
+    /// Settled with the downstream claim on chain rather than an update_fulfill_htlc.
+    pub claim_from_onchain_tx: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    /// The next channel couldn't take the HTLC, e.g. for lack of liquidity.
+    NextHopChannel,
+    UnknownNextHop,
+    InvalidForward,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForwardFailure {
+    pub timestamp: u64,
+    pub prev_channel_id: String,
+    pub prev_peer: Option<String>,
+    pub next_channel_id: Option<String>,
+    pub next_peer: Option<String>,
+    /// The channel the sender asked for when we don't know it.
+    pub requested_scid: Option<u64>,
+    pub reason: FailureReason,
+}
+
+/// What a channel or peer routed, on either side of our forwards.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevenueSummary {
+    pub id: String,
+    pub forwards_in: u64,
+    pub forwards_out: u64,
+    pub amount_in_msat: u64,
+    pub amount_out_msat: u64,
+    /// Fees of the forwards leaving through it, which is where LDK charges them.
+    pub fee_earned_msat: u64,
+    /// Fees of the forwards coming in through it.
+    pub fee_earned_in_msat: u64,
+    pub failed_in: u64,
+    pub failed_out: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevenueReport {
+    pub since: u64,
+    pub until: u64,
+    pub forwards: u64,
+    pub failures: u64,
+    pub fee_earned_msat: u64,
+    pub summaries: Vec<RevenueSummary>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    Channel,
+    Peer,
+}
+
+pub struct ForwardingHistory {
+    forwards: Mutex<Vec<Forward>>,
+    failures: Mutex<Vec<ForwardFailure>>,
+    ldk_data_dir: String,
+}
+
+impl ForwardingHistory {
+    pub fn load(ldk_data_dir: &str) -> Self {
+        Self {
+            forwards: Mutex::new(read_lines(ldk_data_dir, FORWARDS_FILE)),
+            failures: Mutex::new(read_lines(ldk_data_dir, FAILURES_FILE)),
+            ldk_data_dir: ldk_data_dir.to_string(),
+        }
+    }
+
+    pub fn forwarded(
+        &self,
+        channel_manager: &ChannelManager,
+        prev_channel_id: Option<[u8; 32]>,
+        next_channel_id: Option<[u8; 32]>,
+        fee_earned_msat: Option<u64>,
+        claim_from_onchain_tx: bool,
+        outbound_amount_forwarded_msat: Option<u64>,
+    ) {
+        let forward = Forward {
+            timestamp: now(),
+            prev_channel_id: prev_channel_id.map(|id| hex_str(&id)),
+            prev_peer: prev_channel_id.and_then(|id| peer_of(channel_manager, &id)),
+            next_channel_id: next_channel_id.map(|id| hex_str(&id)),
+            next_peer: next_channel_id.and_then(|id| peer_of(channel_manager, &id)),
+            amount_in_msat: outbound_amount_forwarded_msat
+                .zip(fee_earned_msat)
+                .map(|(amount, fee)| amount + fee),
+            amount_out_msat: outbound_amount_forwarded_msat,
+            fee_earned_msat,
+            claim_from_onchain_tx,
+        };
+        self.record_forward(forward);
+    }
+
+    fn record_forward(&self, forward: Forward) {
+        append_line(&self.ldk_data_dir, FORWARDS_FILE, &forward);
+        self.forwards.lock().unwrap().push(forward);
+    }
+
+    /// Records a failed forward, ignoring the payments to us LDK reports the same way.
+    pub fn failed(
+        &self,
+        channel_manager: &ChannelManager,
+        prev_channel_id: [u8; 32],
+        failed_next_destination: &HTLCDestination,
+    ) {
+        let (next_channel_id, next_peer, requested_scid, reason) = match failed_next_destination {
+            HTLCDestination::NextHopChannel {
+                node_id,
+                channel_id,
+            } => (
+                Some(hex_str(channel_id)),
+                node_id
+                    .map(|node_id| node_id.to_string())
+                    .or_else(|| peer_of(channel_manager, channel_id)),
+                None,
+                FailureReason::NextHopChannel,
+            ),
+            HTLCDestination::UnknownNextHop {
+                requested_forward_scid,
+            } => (
+                None,
+                None,
+                Some(*requested_forward_scid),
+                FailureReason::UnknownNextHop,
+            ),
+            HTLCDestination::InvalidForward {
+                requested_forward_scid,
+            } => (
+                None,
+                None,
+                Some(*requested_forward_scid),
+                FailureReason::InvalidForward,
+            ),
+            HTLCDestination::FailedPayment { .. } => return,
+        };
+        let failure = ForwardFailure {
+            timestamp: now(),
+            prev_channel_id: hex_str(&prev_channel_id),
+            prev_peer: peer_of(channel_manager, &prev_channel_id),
+            next_channel_id,
+            next_peer,
+            requested_scid,
+            reason,
+        };
+        self.record_failure(failure);
+    }
+
+    fn record_failure(&self, failure: ForwardFailure) {
+        append_line(&self.ldk_data_dir, FAILURES_FILE, &failure);
+        self.failures.lock().unwrap().push(failure);
+    }
+
+    /// The forwards between `since` and `until`, in unix seconds, oldest first.
+    pub fn forwards(&self, since: u64, until: u64) -> Vec<Forward> {
+        self.forwards
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|forward| (since..=until).contains(&forward.timestamp))
+            .cloned()
+            .collect()
+    }
+
+    pub fn failures(&self, since: u64, until: u64) -> Vec<ForwardFailure> {
+        self.failures
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|failure| (since..=until).contains(&failure.timestamp))
+            .cloned()
+            .collect()
+    }
+
+    /// What we routed between `since` and `until`, highest earning first.
+    pub fn revenue(&self, group_by: GroupBy, since: u64, until: u64) -> RevenueReport {
+        let forwards = self.forwards(since, until);
+        let failures = self.failures(since, until);
+        let key = |channel_id: &Option<String>, peer: &Option<String>| match group_by {
+            GroupBy::Channel => channel_id.clone(),
+            GroupBy::Peer => peer.clone(),
+        };
+        let mut summaries = BTreeMap::new();
+
+        for forward in &forwards {
+            let fee = forward.fee_earned_msat.unwrap_or(0);
+            if let Some(id) = key(&forward.prev_channel_id, &forward.prev_peer) {
+                let summary = summary_for(&mut summaries, id);
+                summary.forwards_in += 1;
+                summary.amount_in_msat += forward.amount_in_msat.unwrap_or(0);
+                summary.fee_earned_in_msat += fee;
+            }
+            if let Some(id) = key(&forward.next_channel_id, &forward.next_peer) {
+                let summary = summary_for(&mut summaries, id);
+                summary.forwards_out += 1;
+                summary.amount_out_msat += forward.amount_out_msat.unwrap_or(0);
+                summary.fee_earned_msat += fee;
+            }
+        }
+        for failure in &failures {
+            if let Some(id) = key(&Some(failure.prev_channel_id.clone()), &failure.prev_peer) {
+                summary_for(&mut summaries, id).failed_in += 1;
+            }
+            if let Some(id) = key(&failure.next_channel_id, &failure.next_peer) {
+                summary_for(&mut summaries, id).failed_out += 1;
+            }
+        }
+
+        let mut summaries: Vec<_> = summaries.into_values().collect();
+        summaries.sort_by_key(|summary| Reverse(summary.fee_earned_msat));
+        RevenueReport {
+            since,
+            until,
+            forwards: forwards.len() as u64,
+            failures: failures.len() as u64,
+            fee_earned_msat: forwards
+                .iter()
+                .filter_map(|forward| forward.fee_earned_msat)
+                .sum(),
+            summaries,
+        }
+    }
+}
+
+fn summary_for(
+    summaries: &mut BTreeMap<String, RevenueSummary>,
+    id: String,
+) -> &mut RevenueSummary {
+    summaries
+        .entry(id.clone())
+        .or_insert_with(|| RevenueSummary {
+            id,
+            ..Default::default()
+        })
+}
+
+fn peer_of(channel_manager: &ChannelManager, channel_id: &[u8; 32]) -> Option<String> {
+    channel_manager
+        .list_channels()
+        .into_iter()
+        .find(|chan| chan.channel_id == *channel_id)
+        .map(|chan| chan.counterparty.node_id.to_string())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn read_lines<T: DeserializeOwned>(ldk_data_dir: &str, file: &str) -> Vec<T> {
+    let lines = match fs::read_to_string(format!("{}/{}", ldk_data_dir, file)) {
+        Ok(lines) => lines,
+        Err(_) => return Vec::new(),
+    };
+    lines
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                println!("ERROR: skipping invalid line of {}: {}", file, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn append_line<T: Serialize>(ldk_data_dir: &str, file: &str, record: &T) {
+    let res = serde_json::to_string(record)
+        .map_err(|e| e.to_string())
+        .and_then(|line| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(format!("{}/{}", ldk_data_dir, file))
+                .and_then(|mut f| writeln!(f, "{}", line))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = res {
+        println!("ERROR: failed to persist {}: {}", file, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+
+    fn forward(timestamp: u64, prev: u8, next: u8, amount_out_msat: u64, fee: u64) -> Forward {
+        Forward {
+            timestamp,
+            prev_channel_id: Some(hex_str(&[prev; 32])),
+            prev_peer: Some(format!("peer{}", prev)),
+            next_channel_id: Some(hex_str(&[next; 32])),
+            next_peer: Some(format!("peer{}", next)),
+            amount_in_msat: Some(amount_out_msat + fee),
+            amount_out_msat: Some(amount_out_msat),
+            fee_earned_msat: Some(fee),
+            claim_from_onchain_tx: false,
+        }
+    }
+
+    fn failure(timestamp: u64, prev: u8, next: u8) -> ForwardFailure {
+        ForwardFailure {
+            timestamp,
+            prev_channel_id: hex_str(&[prev; 32]),
+            prev_peer: Some(format!("peer{}", prev)),
+            next_channel_id: Some(hex_str(&[next; 32])),
+            next_peer: Some(format!("peer{}", next)),
+            requested_scid: None,
+            reason: FailureReason::NextHopChannel,
+        }
+    }
+
+    /// Three forwards out of channel 1 and 2, and a failure from 1 to 2.
+    fn history(dir: &TempDataDir) -> ForwardingHistory {
+        let history = ForwardingHistory::load(dir.path());
+        history.record_forward(forward(100, 1, 2, 1_000_000, 1_000));
+        history.record_forward(forward(200, 1, 3, 2_000_000, 3_000));
+        history.record_forward(forward(300, 2, 3, 500_000, 500));
+        history.record_failure(failure(250, 1, 2));
+        history
+    }
+
+    #[test]
+    fn history_is_reloaded() {
+        let dir = TempDataDir::new("forwards-reload");
+        history(&dir);
+
+        let history = ForwardingHistory::load(dir.path());
+        assert_eq!(history.forwards(0, u64::MAX).len(), 3);
+        assert_eq!(history.failures(0, u64::MAX), vec![failure(250, 1, 2)]);
+    }
+
+    #[test]
+    fn history_is_filtered_by_time() {
+        let dir = TempDataDir::new("forwards-time");
+        let history = history(&dir);
+
+        assert_eq!(
+            history.forwards(150, 250),
+            vec![forward(200, 1, 3, 2_000_000, 3_000)]
+        );
+        assert!(history.failures(0, 200).is_empty());
+    }
+
+    #[test]
+    fn revenue_adds_up_by_channel() {
+        let dir = TempDataDir::new("forwards-channel");
+        let report = history(&dir).revenue(GroupBy::Channel, 0, u64::MAX);
+
+        assert_eq!(report.forwards, 3);
+        assert_eq!(report.failures, 1);
+        assert_eq!(report.fee_earned_msat, 4_500);
+        let ids: Vec<_> = report.summaries.iter().map(|s| s.id.clone()).collect();
+        assert_eq!(
+            ids,
+            vec![hex_str(&[3; 32]), hex_str(&[2; 32]), hex_str(&[1; 32])]
+        );
+        assert_eq!(report.summaries[0].fee_earned_msat, 3_500);
+        assert_eq!(report.summaries[0].amount_out_msat, 2_500_000);
+        assert_eq!(report.summaries[1].failed_out, 1);
+        assert_eq!(report.summaries[2].forwards_in, 2);
+        assert_eq!(report.summaries[2].fee_earned_in_msat, 4_000);
+        assert_eq!(report.summaries[2].failed_in, 1);
+    }
+
+    #[test]
+    fn revenue_adds_up_by_peer() {
+        let dir = TempDataDir::new("forwards-peer");
+        let report = history(&dir).revenue(GroupBy::Peer, 0, 150);
+
+        assert_eq!(report.fee_earned_msat, 1_000);
+        assert_eq!(report.failures, 0);
+        assert_eq!(report.summaries[0].id, "peer2");
+        assert_eq!(report.summaries[0].fee_earned_msat, 1_000);
+    }
+
+    #[test]
+    fn corrupt_lines_are_skipped() {
+        let dir = TempDataDir::new("forwards-corrupt");
+        let line = serde_json::to_string(&forward(100, 1, 2, 1_000, 1)).unwrap();
+        dir.write(
+            FORWARDS_FILE,
+            &format!("{}\nnot json\n\n{{\"timestamp\":1}}\n", line),
+        );
+        dir.write(FAILURES_FILE, "garbage");
+
+        let history = ForwardingHistory::load(dir.path());
+        assert_eq!(
+            history.forwards(0, u64::MAX),
+            vec![forward(100, 1, 2, 1_000, 1)]
+        );
+        assert!(history.failures(0, u64::MAX).is_empty());
+    }
+}
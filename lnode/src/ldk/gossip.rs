@@ -0,0 +1,221 @@
+//! Checks that announced channels are funded by a real, unspent output before they enter the
+//! network graph.
+
+use crate::ldk::core::CoreLDK;
+use crate::types::{NetworkGraph, P2PGossipSync};
+use bitcoin::{BlockHash, TxOut};
+use lightning::routing::utxo::{UtxoFuture, UtxoLookup, UtxoLookupError, UtxoResult};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// Pending checks hold up gossip processing, so give up on slow lookups quickly.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_CACHED_OUTPUTS: usize = 10_000;
+/// Funding outputs get spent when channels close, so cached ones are looked up again after this
+/// long rather than vouching for a closed channel forever.
+const CACHED_OUTPUT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Funding outputs found unspent, evicting the least recently used one when full and forgetting
+/// each one `CACHED_OUTPUT_TTL` after it was checked.
+struct OutputCache {
+    capacity: usize,
+    ttl: Duration,
+    /// Output, when it was found unspent and its position in `recency`.
+    outputs: HashMap<u64, (TxOut, Instant, u64)>,
+    /// Short channel ids from least to most recently used.
+    recency: BTreeMap<u64, u64>,
+    next_use: u64,
+}
+
+impl OutputCache {
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        OutputCache {
+            capacity,
+            ttl,
+            outputs: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    fn get(&mut self, short_channel_id: u64, now: Instant) -> Option<TxOut> {
+        let (output, checked_at, last_use) = self.outputs.get_mut(&short_channel_id)?;
+        let last_use = *last_use;
+        if now.duration_since(*checked_at) >= self.ttl {
+            self.outputs.remove(&short_channel_id);
+            self.recency.remove(&last_use);
+            return None;
+        }
+        let output = output.clone();
+        self.touch(short_channel_id, last_use);
+        Some(output)
+    }
+
+    fn insert(&mut self, short_channel_id: u64, output: TxOut, now: Instant) {
+        if let Some((_, _, last_use)) = self.outputs.remove(&short_channel_id) {
+            self.recency.remove(&last_use);
+        }
+        while self.outputs.len() >= self.capacity {
+            let (&oldest, &evicted) = match self.recency.iter().next() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.recency.remove(&oldest);
+            self.outputs.remove(&evicted);
+        }
+        self.outputs
+            .insert(short_channel_id, (output, now, self.next_use));
+        self.recency.insert(self.next_use, short_channel_id);
+        self.next_use += 1;
+    }
+
+    fn touch(&mut self, short_channel_id: u64, last_use: u64) {
+        self.recency.remove(&last_use);
+        self.recency.insert(self.next_use, short_channel_id);
+        if let Some(entry) = self.outputs.get_mut(&short_channel_id) {
+            entry.2 = self.next_use;
+        }
+        self.next_use += 1;
+    }
+}
+
+pub struct GossipVerifier {
+    core_ldk: Arc<CoreLDK>,
+    network_graph: Arc<NetworkGraph>,
+    /// The gossip sync owning this verifier, which forwards the announcements it validates.
+    gossip_sync: Weak<P2PGossipSync>,
+    genesis_hash: BlockHash,
+    cache: Arc<Mutex<OutputCache>>,
+    handle: Handle,
+}
+
+impl GossipVerifier {
+    pub fn new(
+        core_ldk: Arc<CoreLDK>,
+        network_graph: Arc<NetworkGraph>,
+        gossip_sync: Weak<P2PGossipSync>,
+        genesis_hash: BlockHash,
+    ) -> Self {
+        GossipVerifier {
+            core_ldk,
+            network_graph,
+            gossip_sync,
+            genesis_hash,
+            cache: Arc::new(Mutex::new(OutputCache::new(
+                MAX_CACHED_OUTPUTS,
+                CACHED_OUTPUT_TTL,
+            ))),
+            handle: Handle::current(),
+        }
+    }
+}
+
+impl UtxoLookup for GossipVerifier {
+    fn get_utxo(&self, genesis_hash: &BlockHash, short_channel_id: u64) -> UtxoResult {
+        if *genesis_hash != self.genesis_hash {
+            return UtxoResult::Sync(Err(UtxoLookupError::UnknownChain));
+        }
+        if let Some(output) = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(short_channel_id, Instant::now())
+        {
+            return UtxoResult::Sync(Ok(output));
+        }
+
+        let future = UtxoFuture::new();
+        let pending = future.clone();
+        let core_ldk = Arc::clone(&self.core_ldk);
+        let network_graph = Arc::clone(&self.network_graph);
+        let gossip_sync = Weak::clone(&self.gossip_sync);
+        let cache = Arc::clone(&self.cache);
+        self.handle.spawn(async move {
+            let lookup =
+                tokio::time::timeout(LOOKUP_TIMEOUT, core_ldk.funding_output(short_channel_id));
+            let result = match lookup.await {
+                Ok(Ok(Some(output))) => {
+                    cache
+                        .lock()
+                        .unwrap()
+                        .insert(short_channel_id, output.clone(), Instant::now());
+                    Ok(output)
+                }
+                // Missing and spent outputs aren't cached, the funding transaction may only
+                // just have confirmed.
+                Ok(Ok(None)) => Err(UtxoLookupError::UnknownTx),
+                Ok(Err(e)) => {
+                    println!(
+                        "ERROR: failed to look up the funding output of {}: {}",
+                        short_channel_id, e
+                    );
+                    Err(UtxoLookupError::UnknownTx)
+                }
+                Err(_) => Err(UtxoLookupError::UnknownTx),
+            };
+            // The background processor's regular `process_events` sends the validated gossip on
+            // to our peers from here.
+            match gossip_sync.upgrade() {
+                Some(gossip_sync) => pending.resolve(&network_graph, gossip_sync, result),
+                None => pending.resolve_without_forwarding(&network_graph, result),
+            }
+        });
+        UtxoResult::Async(future)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Script;
+
+    fn output(value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: Script::new(),
+        }
+    }
+
+    #[test]
+    fn cache_evicts_the_least_recently_used_output() {
+        let now = Instant::now();
+        let mut cache = OutputCache::new(2, CACHED_OUTPUT_TTL);
+        cache.insert(1, output(1), now);
+        cache.insert(2, output(2), now);
+        assert_eq!(cache.get(1, now), Some(output(1)));
+        cache.insert(3, output(3), now);
+        assert_eq!(cache.get(2, now), None);
+        assert_eq!(cache.get(1, now), Some(output(1)));
+        assert_eq!(cache.get(3, now), Some(output(3)));
+    }
+
+    #[test]
+    fn cached_outputs_expire() {
+        let now = Instant::now();
+        let mut cache = OutputCache::new(2, Duration::from_secs(60));
+        cache.insert(1, output(1), now);
+        assert_eq!(cache.get(1, now + Duration::from_secs(59)), Some(output(1)));
+        assert_eq!(cache.get(1, now + Duration::from_secs(60)), None);
+        // Expired entries free their slot.
+        cache.insert(2, output(2), now);
+        cache.insert(3, output(3), now);
+        assert_eq!(cache.get(2, now), Some(output(2)));
+        assert_eq!(cache.get(3, now), Some(output(3)));
+    }
+
+    #[test]
+    fn reinserting_refreshes_an_output() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(30);
+        let mut cache = OutputCache::new(2, Duration::from_secs(60));
+        cache.insert(1, output(1), now);
+        cache.insert(1, output(10), later);
+        assert_eq!(
+            cache.get(1, now + Duration::from_secs(80)),
+            Some(output(10))
+        );
+        assert_eq!(cache.outputs.len(), cache.recency.len());
+    }
+}
@@ -0,0 +1,367 @@
+//! Read-only views of the network graph and route previews, for inspecting what the router
+//! sees without paying anything.
+
+use crate::types::{ChannelManager, NetworkGraph, Router};
+use crate::utils::hex;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::channelmanager::ChannelDetails;
+use lightning::routing::gossip::{ChannelInfo, ChannelUpdateInfo, NodeId, ReadOnlyNetworkGraph};
+use lightning::routing::router::{InFlightHtlcs, PaymentParameters, RouteParameters, Router as _};
+use serde::{Deserialize, Serialize};
+
+/// CLTV delta asked of the last hop when previewing a route, the same as our keysends.
+pub const DEFAULT_FINAL_CLTV_EXPIRY_DELTA: u32 = 40;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GraphStats {
+    pub num_nodes: usize,
+    pub num_announced_nodes: usize,
+    pub num_channels: usize,
+    pub total_capacity_sat: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NodeView {
+    pub node_id: String,
+    /// `None` until the node's announcement has been seen.
+    pub alias: Option<String>,
+    pub addresses: Vec<String>,
+    pub last_update: Option<u32>,
+    pub channels: Vec<u64>,
+}
+
+/// The forwarding policy one side of a channel announced.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectionView {
+    pub enabled: bool,
+    pub cltv_expiry_delta: u16,
+    pub htlc_minimum_msat: u64,
+    pub htlc_maximum_msat: u64,
+    pub fee_base_msat: u32,
+    pub fee_proportional_millionths: u32,
+    pub last_update: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelView {
+    pub short_channel_id: u64,
+    pub node_one: String,
+    pub node_two: String,
+    pub capacity_sat: Option<u64>,
+    pub one_to_two: Option<DirectionView>,
+    pub two_to_one: Option<DirectionView>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HopView {
+    pub node_id: String,
+    pub alias: Option<String>,
+    pub short_channel_id: u64,
+    /// Fee charged by this hop, the amount delivered for the last one.
+    pub fee_msat: u64,
+    /// CLTV delta this hop requires, the final CLTV delta padded with a random shadow offset
+    /// for the last one.
+    pub cltv_expiry_delta: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PathView {
+    pub hops: Vec<HopView>,
+    pub amount_msat: u64,
+    pub fees_msat: u64,
+    pub total_cltv_expiry_delta: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoutePreview {
+    pub amount_msat: u64,
+    pub total_fees_msat: u64,
+    pub paths: Vec<PathView>,
+}
+
+pub fn stats(network_graph: &NetworkGraph) -> GraphStats {
+    let graph = network_graph.read_only();
+    GraphStats {
+        num_nodes: graph.nodes().len(),
+        num_announced_nodes: graph
+            .nodes()
+            .unordered_iter()
+            .filter(|(_, node)| node.announcement_info.is_some())
+            .count(),
+        num_channels: graph.channels().len(),
+        total_capacity_sat: graph
+            .channels()
+            .unordered_iter()
+            .filter_map(|(_, channel)| channel.capacity_sats)
+            .sum(),
+    }
+}
+
+fn alias(graph: &ReadOnlyNetworkGraph, node_id: &NodeId) -> Option<String> {
+    graph
+        .node(node_id)?
+        .announcement_info
+        .as_ref()
+        .map(|info| info.alias.to_string())
+}
+
+fn node_view(graph: &ReadOnlyNetworkGraph, node_id: &NodeId) -> Option<NodeView> {
+    let node = graph.node(node_id)?;
+    let info = node.announcement_info.as_ref();
+    Some(NodeView {
+        node_id: node_id.to_string(),
+        alias: info.map(|info| info.alias.to_string()),
+        addresses: info
+            .map(|info| info.addresses().iter().map(hex::net_addr_str).collect())
+            .unwrap_or_default(),
+        last_update: info.map(|info| info.last_update),
+        channels: node.channels.clone(),
+    })
+}
+
+/// Nodes matching `query`: the node with that pubkey, or else every node whose alias contains
+/// it, ignoring case.
+pub fn find_nodes(network_graph: &NetworkGraph, query: &str) -> Vec<NodeView> {
+    let graph = network_graph.read_only();
+    if let Some(pubkey) = hex::to_compressed_pubkey(query) {
+        return node_view(&graph, &NodeId::from_pubkey(&pubkey))
+            .into_iter()
+            .collect();
+    }
+    let query = query.to_lowercase();
+    graph
+        .nodes()
+        .unordered_iter()
+        .filter(|(_, node)| match &node.announcement_info {
+            Some(info) => info.alias.to_string().to_lowercase().contains(&query),
+            None => false,
+        })
+        .filter_map(|(node_id, _)| node_view(&graph, node_id))
+        .collect()
+}
+
+fn direction_view(update: &ChannelUpdateInfo) -> DirectionView {
+    DirectionView {
+        enabled: update.enabled,
+        cltv_expiry_delta: update.cltv_expiry_delta,
+        htlc_minimum_msat: update.htlc_minimum_msat,
+        htlc_maximum_msat: update.htlc_maximum_msat,
+        fee_base_msat: update.fees.base_msat,
+        fee_proportional_millionths: update.fees.proportional_millionths,
+        last_update: update.last_update,
+    }
+}
+
+fn channel_view(short_channel_id: u64, channel: &ChannelInfo) -> ChannelView {
+    ChannelView {
+        short_channel_id,
+        node_one: channel.node_one.to_string(),
+        node_two: channel.node_two.to_string(),
+        capacity_sat: channel.capacity_sats,
+        one_to_two: channel.one_to_two.as_ref().map(direction_view),
+        two_to_one: channel.two_to_one.as_ref().map(direction_view),
+    }
+}
+
+pub fn find_channel(network_graph: &NetworkGraph, short_channel_id: u64) -> Option<ChannelView> {
+    let graph = network_graph.read_only();
+    graph
+        .channel(short_channel_id)
+        .map(|channel| channel_view(short_channel_id, channel))
+}
+
+/// Runs the router from `payer` for `route_params`, as a payment would, but only reports the
+/// route it found. `first_hops`, when given, are the only channels the route may start with.
+pub fn preview_route(
+    router: &Router,
+    network_graph: &NetworkGraph,
+    payer: &PublicKey,
+    first_hops: Option<&[ChannelDetails]>,
+    inflight_htlcs: &InFlightHtlcs,
+    route_params: &RouteParameters,
+) -> Result<RoutePreview, String> {
+    let first_hops = first_hops.map(|hops| hops.iter().collect::<Vec<_>>());
+    let route = router
+        .find_route(
+            payer,
+            route_params,
+            first_hops.as_ref().map(|hops| &hops[..]),
+            inflight_htlcs,
+        )
+        .map_err(|e| e.err)?;
+
+    let graph = network_graph.read_only();
+    let paths = route
+        .paths
+        .iter()
+        .map(|path| PathView {
+            hops: path
+                .hops
+                .iter()
+                .map(|hop| HopView {
+                    node_id: hop.pubkey.to_string(),
+                    alias: alias(&graph, &NodeId::from_pubkey(&hop.pubkey)),
+                    short_channel_id: hop.short_channel_id,
+                    fee_msat: hop.fee_msat,
+                    cltv_expiry_delta: hop.cltv_expiry_delta,
+                })
+                .collect(),
+            amount_msat: path.final_value_msat(),
+            fees_msat: path.fee_msat(),
+            total_cltv_expiry_delta: path.hops.iter().map(|hop| hop.cltv_expiry_delta).sum(),
+        })
+        .collect();
+    Ok(RoutePreview {
+        amount_msat: route.get_total_amount(),
+        total_fees_msat: route.get_total_fees(),
+        paths,
+    })
+}
+
+/// [`preview_route`] from our node, over the channels we can currently use and accounting for
+/// the HTLCs already in flight on them.
+pub fn query_routes(
+    router: &Router,
+    channel_manager: &ChannelManager,
+    network_graph: &NetworkGraph,
+    destination: PublicKey,
+    amount_msat: u64,
+    final_cltv_expiry_delta: u32,
+) -> Result<RoutePreview, String> {
+    preview_route(
+        router,
+        network_graph,
+        &channel_manager.get_our_node_id(),
+        Some(&channel_manager.list_usable_channels()),
+        &channel_manager.compute_inflight_htlcs(),
+        &RouteParameters {
+            payment_params: PaymentParameters::from_node_id(destination, final_cltv_expiry_delta),
+            final_value_msat: amount_msat,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Scorer;
+    use crate::utils::disk::FilesystemLogger;
+    use crate::utils::test_utils::TempDataDir;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::Network;
+    use lightning::ln::features::ChannelFeatures;
+    use lightning::ln::msgs::UnsignedChannelUpdate;
+    use lightning::routing::scoring::ProbabilisticScorer;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    fn pubkey(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[byte; 32]).unwrap(),
+        )
+    }
+
+    fn route_params(destination: PublicKey, amount_msat: u64) -> RouteParameters {
+        RouteParameters {
+            payment_params: PaymentParameters::from_node_id(
+                destination,
+                DEFAULT_FINAL_CLTV_EXPIRY_DELTA,
+            ),
+            final_value_msat: amount_msat,
+        }
+    }
+
+    fn add_channel(graph: &NetworkGraph, short_channel_id: u64, one: u8, two: u8) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        graph
+            .add_channel_from_partial_announcement(
+                short_channel_id,
+                now,
+                ChannelFeatures::empty(),
+                pubkey(one),
+                pubkey(two),
+            )
+            .unwrap();
+        for flags in 0..2 {
+            graph
+                .update_channel_unsigned(&UnsignedChannelUpdate {
+                    chain_hash: genesis_block(Network::Regtest).header.block_hash(),
+                    short_channel_id,
+                    timestamp: now as u32,
+                    flags,
+                    cltv_expiry_delta: 40,
+                    htlc_minimum_msat: 0,
+                    htlc_maximum_msat: 100_000_000,
+                    fee_base_msat: 1000,
+                    fee_proportional_millionths: 0,
+                    excess_data: Vec::new(),
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn graph_views_and_route_preview() {
+        let dir = TempDataDir::new("graph");
+        let logger = Arc::new(FilesystemLogger::new(dir.path().to_string()));
+        let graph = Arc::new(NetworkGraph::new(Network::Regtest, logger.clone()));
+        add_channel(&graph, 42, 1, 2);
+        add_channel(&graph, 43, 2, 3);
+
+        let stats = stats(&graph);
+        assert_eq!(stats.num_nodes, 3);
+        assert_eq!(stats.num_announced_nodes, 0);
+        assert_eq!(stats.num_channels, 2);
+
+        let nodes = find_nodes(&graph, &pubkey(2).to_string());
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].channels.len(), 2);
+        assert!(nodes[0].alias.is_none());
+        assert!(find_nodes(&graph, "unknown alias").is_empty());
+
+        let channel = find_channel(&graph, 42).unwrap();
+        assert_eq!(channel.one_to_two.unwrap().fee_base_msat, 1000);
+        assert!(find_channel(&graph, 44).is_none());
+
+        let scorer: Scorer =
+            ProbabilisticScorer::new(Default::default(), graph.clone(), logger.clone());
+        let router = Router::new(graph.clone(), logger, [0; 32], Arc::new(Mutex::new(scorer)));
+        let preview = preview_route(
+            &router,
+            &graph,
+            &pubkey(1),
+            None,
+            &InFlightHtlcs::new(),
+            &route_params(pubkey(3), 10_000),
+        )
+        .unwrap();
+        assert_eq!(preview.amount_msat, 10_000);
+        assert_eq!(preview.total_fees_msat, 1000);
+        assert_eq!(preview.paths.len(), 1);
+        let hops = &preview.paths[0].hops;
+        assert_eq!(
+            hops.iter()
+                .map(|hop| hop.short_channel_id)
+                .collect::<Vec<_>>(),
+            vec![42, 43]
+        );
+        assert_eq!(hops[0].cltv_expiry_delta, 40);
+        // The router pads the final CLTV delta with a random shadow offset.
+        assert!(hops[1].cltv_expiry_delta >= DEFAULT_FINAL_CLTV_EXPIRY_DELTA);
+
+        assert!(preview_route(
+            &router,
+            &graph,
+            &pubkey(1),
+            None,
+            &InFlightHtlcs::new(),
+            &route_params(pubkey(4), 10_000),
+        )
+        .is_err());
+    }
+}
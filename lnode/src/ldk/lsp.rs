@@ -0,0 +1,764 @@
+//! LSP mode, on when `{ldk_data_dir}/lsp.json` exists: just-in-time channels for clients
+//! without inbound liquidity.
+//!
+//! A client registers for an intercept SCID, a fake short channel id, and puts it in the route
+//! hint of its invoices along with our fee, which senders pay as for any other hop. We can't
+//! issue the invoices ourselves as the client holds the preimages: [`client_invoice`] is how a
+//! client node issues them.
+//!
+//! The parts of a payment for an intercept SCID are taken together: once they add up to the
+//! amount the client registered, or as they come without one, we open a channel to the client
+//! sized for them and forward every part as it was sent when the channel is ready, or fail them
+//! back if that takes too long. The timeout only leaves room for channels the client accepts as
+//! zero-conf, so we don't fund those it would have us wait confirmations for. Intercepted
+//! payments are kept in `{ldk_data_dir}/lsp_intercepts.json`.
+
+use crate::ldk::channels::{self, ChannelOpenConfig};
+use crate::ldk::fee_policy::FeePolicyStore;
+use crate::types::ChannelManager;
+use crate::utils::hex::{hex_str, to_vec};
+use crate::utils::json_file;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::Network;
+use lightning::chain::keysinterface::KeysManager;
+use lightning::ln::channelmanager::{InterceptId, MIN_FINAL_CLTV_EXPIRY_DELTA};
+use lightning::ln::PaymentHash;
+use lightning::routing::gossip::RoutingFees;
+use lightning::routing::router::{RouteHint, RouteHintHop};
+use lightning_invoice::{Currency, Invoice, InvoiceBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const CONFIG_FILE: &str = "lsp.json";
+const STATE_FILE: &str = "lsp_intercepts.json";
+
+fn default_min_channel_size_sats() -> u64 {
+    100_000
+}
+
+fn default_intercept_timeout_secs() -> u64 {
+    120
+}
+
+fn default_cltv_expiry_delta() -> u16 {
+    144
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LspConfig {
+    #[serde(default)]
+    pub fee_base_msat: u32,
+    #[serde(default)]
+    pub fee_proportional_millionths: u32,
+    /// The smallest channel we open, larger ones fitting twice the payment that opens them.
+    #[serde(default = "default_min_channel_size_sats")]
+    pub min_channel_size_sats: u64,
+    /// How long a payment waits for its channel before we fail it back.
+    #[serde(default = "default_intercept_timeout_secs")]
+    pub intercept_timeout_secs: u64,
+    /// The CLTV delta of the route hints we hand out.
+    #[serde(default = "default_cltv_expiry_delta")]
+    pub cltv_expiry_delta: u16,
+}
+
+impl LspConfig {
+    /// Our fee for forwarding `amount_msat` to a client.
+    pub fn fee_msat(&self, amount_msat: u64) -> u64 {
+        self.fee_base_msat as u64
+            + (amount_msat as u128 * self.fee_proportional_millionths as u128 / 1_000_000) as u64
+    }
+
+    fn channel_size_sats(&self, amount_msat: u64) -> u64 {
+        self.min_channel_size_sats.max(amount_msat / 1000 * 2)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registration {
+    pub intercept_scid: u64,
+    pub client: String,
+    /// The amount of the client's invoice, when it told us: the parts of a payment are held
+    /// until they add up to it. Without it a payment must come in one part, its channel being
+    /// sized from the first part that arrives.
+    #[serde(default)]
+    pub payment_size_msat: Option<u64>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+/// The route hint a client puts in its invoices for payments to reach it through us.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteHintView {
+    pub src_node_id: String,
+    pub short_channel_id: u64,
+    pub fee_base_msat: u32,
+    pub fee_proportional_millionths: u32,
+    pub cltv_expiry_delta: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterceptStatus {
+    AwaitingChannel,
+    Forwarded,
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Intercept {
+    pub intercept_id: String,
+    pub intercept_scid: u64,
+    pub client: String,
+    pub payment_hash: String,
+    pub inbound_amount_msat: u64,
+    pub expected_outbound_amount_msat: u64,
+    /// What the part pays us, the difference of its amounts.
+    pub fee_msat: u64,
+    /// Seconds since the Unix epoch.
+    pub received_at: u64,
+    /// The `user_channel_id` of the channel opened for it.
+    pub user_channel_id: Option<u128>,
+    pub status: InterceptStatus,
+    /// Why it failed.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// The fields of an `Event::HTLCIntercepted`.
+pub struct InterceptedHtlc {
+    pub id: InterceptId,
+    pub requested_next_hop_scid: u64,
+    pub payment_hash: PaymentHash,
+    pub inbound_amount_msat: u64,
+    pub expected_outbound_amount_msat: u64,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct LspState {
+    #[serde(default)]
+    registrations: Vec<Registration>,
+    #[serde(default)]
+    intercepts: Vec<Intercept>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn intercept_id(intercept: &Intercept) -> InterceptId {
+    let mut id = [0; 32];
+    id.copy_from_slice(&to_vec(&intercept.intercept_id).unwrap());
+    InterceptId(id)
+}
+
+pub struct Lsp {
+    config: LspConfig,
+    state: Mutex<LspState>,
+    ldk_data_dir: String,
+}
+
+impl Lsp {
+    /// Reads `{ldk_data_dir}/lsp.json` and the intercepts kept, LSP mode being off when the
+    /// config doesn't exist.
+    pub fn load(ldk_data_dir: &str) -> Option<Self> {
+        let config = json_file::load(ldk_data_dir, CONFIG_FILE, "LSP config", "LSP mode is off")?;
+        let state = json_file::load(
+            ldk_data_dir,
+            STATE_FILE,
+            "LSP intercepts",
+            "starting without",
+        )
+        .unwrap_or_default();
+        Some(Self {
+            config,
+            state: Mutex::new(state),
+            ldk_data_dir: ldk_data_dir.to_string(),
+        })
+    }
+
+    pub fn config(&self) -> &LspConfig {
+        &self.config
+    }
+
+    fn save(&self, state: &LspState) {
+        let res = serde_json::to_string_pretty(state)
+            .map_err(|e| e.to_string())
+            .and_then(|state| {
+                fs::write(format!("{}/{}", self.ldk_data_dir, STATE_FILE), state)
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = res {
+            println!("ERROR: failed to persist LSP intercepts: {}", e);
+        }
+    }
+
+    /// Gives `client` a new intercept SCID for its invoices, of `payment_size_msat` if given.
+    pub fn register(
+        &self,
+        channel_manager: &ChannelManager,
+        client: PublicKey,
+        payment_size_msat: Option<u64>,
+    ) -> Registration {
+        let registration = Registration {
+            intercept_scid: channel_manager.get_intercept_scid(),
+            client: client.to_string(),
+            payment_size_msat,
+            created_at: now(),
+        };
+        let mut state = self.state.lock().unwrap();
+        state.registrations.push(registration.clone());
+        self.save(&state);
+        registration
+    }
+
+    /// Forgets the registration of `intercept_scid`, returning false if there was none.
+    pub fn unregister(&self, intercept_scid: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let len = state.registrations.len();
+        state
+            .registrations
+            .retain(|registration| registration.intercept_scid != intercept_scid);
+        let removed = state.registrations.len() != len;
+        if removed {
+            self.save(&state);
+        }
+        removed
+    }
+
+    pub fn route_hint(&self, our_node_id: PublicKey, registration: &Registration) -> RouteHintView {
+        RouteHintView {
+            src_node_id: our_node_id.to_string(),
+            short_channel_id: registration.intercept_scid,
+            fee_base_msat: self.config.fee_base_msat,
+            fee_proportional_millionths: self.config.fee_proportional_millionths,
+            cltv_expiry_delta: self.config.cltv_expiry_delta,
+        }
+    }
+
+    pub fn registrations(&self) -> Vec<Registration> {
+        self.state.lock().unwrap().registrations.clone()
+    }
+
+    /// The intercepted payments, most recent last.
+    pub fn intercepts(&self) -> Vec<Intercept> {
+        self.state.lock().unwrap().intercepts.clone()
+    }
+
+    /// Handles an `Event::HTLCIntercepted`: holds it with the other parts of its payment until
+    /// they're all in, then forwards them if the client has a channel they fit, or opens one.
+    pub fn handle_intercept(
+        &self,
+        channel_manager: &ChannelManager,
+        fee_policies: &FeePolicyStore,
+        htlc: InterceptedHtlc,
+    ) {
+        let InterceptedHtlc {
+            id,
+            requested_next_hop_scid,
+            payment_hash,
+            inbound_amount_msat,
+            expected_outbound_amount_msat,
+        } = htlc;
+        let mut state = self.state.lock().unwrap();
+        let registration = state
+            .registrations
+            .iter()
+            .find(|registration| registration.intercept_scid == requested_next_hop_scid)
+            .cloned();
+        let client = registration
+            .as_ref()
+            .and_then(|registration| registration.client.parse::<PublicKey>().ok());
+        let mut intercept = Intercept {
+            intercept_id: hex_str(&id.0),
+            intercept_scid: requested_next_hop_scid,
+            client: client.map(|client| client.to_string()).unwrap_or_default(),
+            payment_hash: hex_str(&payment_hash.0),
+            inbound_amount_msat,
+            expected_outbound_amount_msat,
+            fee_msat: inbound_amount_msat.saturating_sub(expected_outbound_amount_msat),
+            received_at: now(),
+            user_channel_id: None,
+            status: InterceptStatus::AwaitingChannel,
+            reason: None,
+        };
+        let (client, registration) = match (client, registration) {
+            (Some(client), Some(registration)) => (client, registration),
+            _ => {
+                fail(
+                    channel_manager,
+                    &mut intercept,
+                    "unknown intercept SCID".to_string(),
+                );
+                state.intercepts.push(intercept);
+                self.save(&state);
+                return;
+            }
+        };
+        let payment_hash = intercept.payment_hash.clone();
+        state.intercepts.push(intercept);
+        self.handle_payment(
+            channel_manager,
+            fee_policies,
+            &mut state,
+            client,
+            &payment_hash,
+            registration.payment_size_msat,
+        );
+        self.save(&state);
+    }
+
+    /// Moves the parts of `payment_hash` waiting for a channel along, if they're all in.
+    fn handle_payment(
+        &self,
+        channel_manager: &ChannelManager,
+        fee_policies: &FeePolicyStore,
+        state: &mut LspState,
+        client: PublicKey,
+        payment_hash: &str,
+        payment_size_msat: Option<u64>,
+    ) {
+        let mut parts: Vec<&mut Intercept> = state
+            .intercepts
+            .iter_mut()
+            .filter(|intercept| {
+                intercept.payment_hash == payment_hash
+                    && intercept.status == InterceptStatus::AwaitingChannel
+            })
+            .collect();
+        let outbound_msat = match self.parts_ready(&parts, payment_size_msat) {
+            Ok(Some(outbound_msat)) => outbound_msat,
+            Ok(None) => return,
+            Err(reason) => {
+                for part in parts {
+                    fail(channel_manager, part, reason.clone());
+                }
+                return;
+            }
+        };
+
+        // The parts that came before may have opened a channel already.
+        if let Some(user_channel_id) = parts.iter().find_map(|part| part.user_channel_id) {
+            for part in parts.iter_mut() {
+                part.user_channel_id = Some(user_channel_id);
+            }
+        } else if let Some(channel) = channel_manager.list_channels().into_iter().find(|chan| {
+            chan.counterparty.node_id == client
+                && chan.is_usable
+                && chan.next_outbound_htlc_limit_msat >= outbound_msat
+        }) {
+            for part in parts {
+                forward(channel_manager, part, &channel.channel_id, client);
+            }
+        } else {
+            let user_channel_id = u128::from_be_bytes(rand::random());
+            let channel_size_sats = self.config.channel_size_sats(outbound_msat);
+            match channel_manager.create_channel(
+                client,
+                channel_size_sats,
+                0,
+                user_channel_id,
+                Some(
+                    ChannelOpenConfig { zero_conf: true }
+                        .user_config(false, fee_policies.default_config()),
+                ),
+            ) {
+                Ok(_) => {
+                    println!(
+                        "\nEVENT: opening a {} sat channel to {} for intercepted payment {}",
+                        channel_size_sats, client, payment_hash
+                    );
+                    print!("> ");
+                    std::io::stdout().flush().unwrap();
+                    for part in parts {
+                        part.user_channel_id = Some(user_channel_id);
+                    }
+                }
+                Err(e) => {
+                    for part in parts {
+                        fail(
+                            channel_manager,
+                            part,
+                            format!("failed to open a channel: {:?}", e),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// What the parts of a payment forward in total once they add up to `payment_size_msat`,
+    /// none before, or why they should be failed. Our fee is charged once for all of them.
+    /// Without a payment size there's no telling how big the payment is, so only single-part
+    /// payments are taken.
+    fn parts_ready(
+        &self,
+        parts: &[&mut Intercept],
+        payment_size_msat: Option<u64>,
+    ) -> Result<Option<u64>, String> {
+        if payment_size_msat.is_none() && parts.len() > 1 {
+            return Err("multi-part payments need a registered payment size".to_string());
+        }
+        let (inbound_msat, outbound_msat) =
+            parts.iter().fold((0, 0), |(inbound, outbound), part| {
+                (
+                    inbound + part.inbound_amount_msat,
+                    outbound + part.expected_outbound_amount_msat,
+                )
+            });
+        if outbound_msat < payment_size_msat.unwrap_or(0) {
+            return Ok(None);
+        }
+        let fee_msat = self.config.fee_msat(outbound_msat);
+        if inbound_msat < outbound_msat + fee_msat {
+            return Err(format!("payment pays less than our {} msat fee", fee_msat));
+        }
+        Ok(Some(outbound_msat))
+    }
+
+    /// Checks, as the channel `user_channel_id` is about to be funded, that a channel opened for
+    /// intercepted payments is zero-conf. If it isn't its payments are failed, and it shouldn't
+    /// be funded: they would time out before it confirmed.
+    pub fn check_funding(
+        &self,
+        channel_manager: &ChannelManager,
+        temporary_channel_id: &[u8; 32],
+        user_channel_id: u128,
+    ) -> Result<(), String> {
+        let opened_for_intercepts = self
+            .state
+            .lock()
+            .unwrap()
+            .intercepts
+            .iter()
+            .any(|intercept| {
+                intercept.user_channel_id == Some(user_channel_id)
+                    && intercept.status == InterceptStatus::AwaitingChannel
+            });
+        if !opened_for_intercepts {
+            return Ok(());
+        }
+        let zero_conf = channel_manager
+            .list_channels()
+            .iter()
+            .find(|chan| chan.channel_id == *temporary_channel_id)
+            .is_some_and(channels::is_zero_conf);
+        if zero_conf {
+            return Ok(());
+        }
+        let reason = "the client didn't accept the channel as zero-conf";
+        self.fail_awaiting(channel_manager, reason, |intercept| {
+            intercept.user_channel_id == Some(user_channel_id)
+        });
+        Err(reason.to_string())
+    }
+
+    /// Forwards the payments waiting for the channel `user_channel_id`, now ready.
+    pub fn channel_ready(
+        &self,
+        channel_manager: &ChannelManager,
+        channel_id: &[u8; 32],
+        user_channel_id: u128,
+        counterparty_node_id: PublicKey,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let mut forwarded = false;
+        for intercept in state.intercepts.iter_mut().filter(|intercept| {
+            intercept.user_channel_id == Some(user_channel_id)
+                && intercept.status == InterceptStatus::AwaitingChannel
+        }) {
+            forward(channel_manager, intercept, channel_id, counterparty_node_id);
+            forwarded = true;
+        }
+        if forwarded {
+            self.save(&state);
+        }
+    }
+
+    /// Fails the payments waiting for the channel `user_channel_id`, closed before it was ready.
+    pub fn channel_closed(&self, channel_manager: &ChannelManager, user_channel_id: u128) {
+        self.fail_awaiting(
+            channel_manager,
+            "channel closed before it was ready",
+            |intercept| intercept.user_channel_id == Some(user_channel_id),
+        );
+    }
+
+    /// Fails the payments that have been waiting for their channel since before `at` less the
+    /// timeout.
+    pub fn expire(&self, channel_manager: &ChannelManager, at: u64) {
+        let timeout = self.config.intercept_timeout_secs;
+        self.fail_awaiting(
+            channel_manager,
+            "timed out waiting for the channel",
+            |intercept| intercept.received_at + timeout <= at,
+        );
+    }
+
+    fn fail_awaiting<F: Fn(&Intercept) -> bool>(
+        &self,
+        channel_manager: &ChannelManager,
+        reason: &str,
+        filter: F,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let mut failed = false;
+        for intercept in state.intercepts.iter_mut().filter(|intercept| {
+            intercept.status == InterceptStatus::AwaitingChannel && filter(intercept)
+        }) {
+            fail(channel_manager, intercept, reason.to_string());
+            failed = true;
+        }
+        if failed {
+            self.save(&state);
+        }
+    }
+}
+
+fn forward(
+    channel_manager: &ChannelManager,
+    intercept: &mut Intercept,
+    channel_id: &[u8; 32],
+    client: PublicKey,
+) {
+    let amount_msat = intercept.expected_outbound_amount_msat;
+    match channel_manager.forward_intercepted_htlc(
+        intercept_id(intercept),
+        channel_id,
+        client,
+        amount_msat,
+    ) {
+        Ok(()) => {
+            intercept.status = InterceptStatus::Forwarded;
+            println!(
+                "\nEVENT: forwarded intercepted payment {} to {}: {} msat, earning {} msat",
+                intercept.payment_hash, client, amount_msat, intercept.fee_msat
+            );
+            print!("> ");
+            std::io::stdout().flush().unwrap();
+        }
+        Err(e) => fail(
+            channel_manager,
+            intercept,
+            format!("failed to forward: {:?}", e),
+        ),
+    }
+}
+
+fn fail(channel_manager: &ChannelManager, intercept: &mut Intercept, reason: String) {
+    // LDK fails it itself if it's no longer pending, e.g. too close to expiry.
+    let _ = channel_manager.fail_intercepted_htlc(intercept_id(intercept));
+    println!(
+        "\nEVENT: failed intercepted payment {}: {}",
+        intercept.payment_hash, reason
+    );
+    print!("> ");
+    std::io::stdout().flush().unwrap();
+    intercept.status = InterceptStatus::Failed;
+    intercept.reason = Some(reason);
+}
+
+/// An invoice of ours for `amount_msat` that is paid through an LSP, `route_hint` being the one
+/// it gave us when we registered with it. We have to accept its channel as zero-conf, see
+/// `zero_conf_peers` in the channel acceptance policy.
+pub fn client_invoice(
+    channel_manager: &ChannelManager,
+    keys_manager: &KeysManager,
+    network: Network,
+    route_hint: &RouteHintView,
+    amount_msat: u64,
+    description: String,
+    expiry_secs: u32,
+) -> Result<Invoice, String> {
+    let src_node_id = route_hint
+        .src_node_id
+        .parse::<PublicKey>()
+        .map_err(|_| "invalid route hint node id".to_string())?;
+    let (payment_hash, payment_secret) = channel_manager
+        .create_inbound_payment(Some(amount_msat), expiry_secs, None)
+        .map_err(|()| "invalid amount".to_string())?;
+    let hint = RouteHint(vec![RouteHintHop {
+        src_node_id,
+        short_channel_id: route_hint.short_channel_id,
+        fees: RoutingFees {
+            base_msat: route_hint.fee_base_msat,
+            proportional_millionths: route_hint.fee_proportional_millionths,
+        },
+        cltv_expiry_delta: route_hint.cltv_expiry_delta,
+        htlc_minimum_msat: None,
+        htlc_maximum_msat: None,
+    }]);
+    let node_secret = keys_manager.get_node_secret_key();
+    InvoiceBuilder::new(Currency::from(network))
+        .description(description)
+        .current_timestamp()
+        .payee_pub_key(channel_manager.get_our_node_id())
+        .payment_hash(sha256::Hash::from_inner(payment_hash.0))
+        .payment_secret(payment_secret)
+        .basic_mpp()
+        .min_final_cltv_expiry_delta(MIN_FINAL_CLTV_EXPIRY_DELTA.into())
+        .expiry_time(Duration::from_secs(expiry_secs.into()))
+        .amount_milli_satoshis(amount_msat)
+        .private_route(hint)
+        .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &node_secret))
+        .map_err(|e| format!("failed to create invoice: {}", e))
+}
+
+/// Fails the intercepted payments that waited too long for their channel.
+pub async fn periodic_expiry(lsp: Arc<Lsp>, channel_manager: Arc<ChannelManager>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10));
+    loop {
+        interval.tick().await;
+        lsp.expire(&channel_manager, now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+
+    const CONFIG: &str = r#"{"fee_base_msat": 1000, "fee_proportional_millionths": 10000}"#;
+
+    fn lsp(dir: &TempDataDir) -> Lsp {
+        dir.write(CONFIG_FILE, CONFIG);
+        Lsp::load(dir.path()).unwrap()
+    }
+
+    fn part(inbound_amount_msat: u64, expected_outbound_amount_msat: u64) -> Intercept {
+        Intercept {
+            intercept_id: hex_str(&[9; 32]),
+            intercept_scid: 42,
+            client: String::new(),
+            payment_hash: hex_str(&[1; 32]),
+            inbound_amount_msat,
+            expected_outbound_amount_msat,
+            fee_msat: inbound_amount_msat - expected_outbound_amount_msat,
+            received_at: 100,
+            user_channel_id: None,
+            status: InterceptStatus::AwaitingChannel,
+            reason: None,
+        }
+    }
+
+    fn registration(payment_size_msat: Option<u64>) -> Registration {
+        Registration {
+            intercept_scid: 42,
+            client: String::new(),
+            payment_size_msat,
+            created_at: 100,
+        }
+    }
+
+    #[test]
+    fn lsp_mode_is_off_without_a_config() {
+        let dir = TempDataDir::new("lsp-off");
+        assert!(Lsp::load(dir.path()).is_none());
+    }
+
+    #[test]
+    fn config_sets_fees_and_channel_sizes() {
+        let dir = TempDataDir::new("lsp-config");
+        let lsp = lsp(&dir);
+        assert_eq!(lsp.config().fee_msat(1_000_000), 11_000);
+        assert_eq!(lsp.config().channel_size_sats(1_000_000), 100_000);
+        assert_eq!(lsp.config().channel_size_sats(100_000_000), 200_000);
+        assert_eq!(lsp.config().intercept_timeout_secs, 120);
+    }
+
+    #[test]
+    fn intercepts_are_reloaded() {
+        let dir = TempDataDir::new("lsp-intercepts");
+        let lsp = lsp(&dir);
+        let intercept = Intercept {
+            user_channel_id: Some(u128::MAX),
+            ..part(1_011_000, 1_000_000)
+        };
+        {
+            let mut state = lsp.state.lock().unwrap();
+            state.registrations.push(registration(None));
+            state.intercepts.push(intercept.clone());
+            lsp.save(&state);
+        }
+        assert_eq!(intercept_id(&intercept), InterceptId([9; 32]));
+
+        let restarted = Lsp::load(dir.path()).unwrap();
+        assert_eq!(restarted.intercepts(), vec![intercept]);
+        assert_eq!(restarted.registrations(), vec![registration(None)]);
+    }
+
+    #[test]
+    fn only_known_registrations_are_unregistered() {
+        let dir = TempDataDir::new("lsp-unregister");
+        let lsp = lsp(&dir);
+        {
+            let mut state = lsp.state.lock().unwrap();
+            state.registrations.push(registration(None));
+            lsp.save(&state);
+        }
+        assert!(!lsp.unregister(43));
+        assert!(lsp.unregister(42));
+        assert!(!lsp.unregister(42));
+        assert!(Lsp::load(dir.path()).unwrap().registrations().is_empty());
+    }
+
+    #[test]
+    fn route_hints_carry_our_fees() {
+        let dir = TempDataDir::new("lsp-route-hint");
+        let our_node_id = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619"
+            .parse()
+            .unwrap();
+        let hint = lsp(&dir).route_hint(our_node_id, &registration(Some(2_000_000)));
+        assert_eq!(hint.short_channel_id, 42);
+        assert_eq!(hint.fee_base_msat, 1000);
+        assert_eq!(hint.fee_proportional_millionths, 10_000);
+        assert_eq!(hint.cltv_expiry_delta, 144);
+    }
+
+    #[test]
+    fn parts_are_held_until_they_add_up() {
+        let dir = TempDataDir::new("lsp-parts-held");
+        let lsp = lsp(&dir);
+        let mut first = part(1_011_000, 1_000_000);
+        assert_eq!(lsp.parts_ready(&[&mut first], Some(2_000_000)), Ok(None));
+    }
+
+    #[test]
+    fn parts_pay_the_fee_once() {
+        let dir = TempDataDir::new("lsp-parts-fee");
+        let lsp = lsp(&dir);
+        let mut first = part(1_011_000, 1_000_000);
+        // The second part pays no base fee of its own, the first paid it for both.
+        let mut second = part(1_010_000, 1_000_000);
+        assert_eq!(
+            lsp.parts_ready(&[&mut first, &mut second], Some(2_000_000)),
+            Ok(Some(2_000_000))
+        );
+    }
+
+    #[test]
+    fn parts_without_a_payment_size_pay_their_own_fee() {
+        let dir = TempDataDir::new("lsp-parts-alone");
+        let lsp = lsp(&dir);
+        let mut first = part(1_011_000, 1_000_000);
+        let mut second = part(1_010_000, 1_000_000);
+        assert_eq!(lsp.parts_ready(&[&mut first], None), Ok(Some(1_000_000)));
+        assert!(lsp.parts_ready(&[&mut second], None).is_err());
+    }
+
+    #[test]
+    fn multi_part_payments_need_a_payment_size() {
+        let dir = TempDataDir::new("lsp-parts-unsized");
+        let lsp = lsp(&dir);
+        let mut first = part(1_011_000, 1_000_000);
+        let mut second = part(1_011_000, 1_000_000);
+        assert!(lsp.parts_ready(&[&mut first, &mut second], None).is_err());
+    }
+}
@@ -1,4 +1,25 @@
+pub mod announce;
+pub mod balances;
+pub mod channel_acceptance;
+pub mod channel_history;
+pub mod channels;
+pub mod connection_hooks;
+pub mod context;
 pub mod core;
 pub mod event_handler;
+pub mod fee_policy;
+pub mod forwards;
+pub mod gossip;
+pub mod graph;
 pub mod keys_manager;
+pub mod lsp;
+pub mod onion_messages;
+pub mod payments;
+pub mod peer_messages;
+pub mod peer_store;
 pub mod persister;
+pub mod probing;
+pub mod rebalance;
+pub mod rgs;
+pub mod scoring;
+pub mod tor;
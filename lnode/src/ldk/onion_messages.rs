@@ -0,0 +1,194 @@
+//! Custom onion messages: the TLV types an app registers are kept in an inbox it reads from.
+
+use crate::types::OnionMessenger;
+use crate::utils::hex::{hex_str, to_vec};
+use crate::utils::inbox::Inbox;
+use crate::utils::registry::TypeRegistry;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use lightning::blinded_path::BlindedPath;
+use lightning::chain::keysinterface::KeysManager;
+use lightning::io;
+use lightning::ln::msgs::DecodeError;
+use lightning::onion_message::{
+    CustomOnionMessageContents, CustomOnionMessageHandler, Destination, OnionMessageContents,
+};
+use lightning::util::ser::{Readable, Writeable, Writer};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::time::SystemTime;
+
+const CONFIG_FILE: &str = "onion_messages.json";
+
+/// Received messages kept for readers.
+const INBOX_CAPACITY: usize = 1000;
+
+/// Onion message TLV types below this are reserved for the protocol itself.
+pub const MIN_CUSTOM_TLV_TYPE: u64 = 64;
+
+/// A custom onion message, its data being written as is.
+pub struct RawOnionMessage {
+    pub tlv_type: u64,
+    pub data: Vec<u8>,
+}
+
+impl CustomOnionMessageContents for RawOnionMessage {
+    fn tlv_type(&self) -> u64 {
+        self.tlv_type
+    }
+}
+
+impl Writeable for RawOnionMessage {
+    fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+        w.write_all(&self.data)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceivedOnionMessage {
+    pub tlv_type: u64,
+    /// Hex encoded.
+    pub data: String,
+    /// Seconds since the Unix epoch.
+    pub received_at: u64,
+}
+
+fn check_tlv_type(tlv_type: u64) -> Result<(), String> {
+    if tlv_type < MIN_CUSTOM_TLV_TYPE {
+        return Err(format!(
+            "onion message types below {} are reserved",
+            MIN_CUSTOM_TLV_TYPE
+        ));
+    }
+    Ok(())
+}
+
+/// The onion messenger's handler for everything but the messages LDK handles itself.
+pub struct OnionMessageHandler {
+    /// Registered types, kept in `{ldk_data_dir}/onion_messages.json`.
+    registry: TypeRegistry<u64>,
+    inbox: Inbox<ReceivedOnionMessage>,
+}
+
+impl OnionMessageHandler {
+    pub fn new(ldk_data_dir: String) -> Self {
+        Self {
+            registry: TypeRegistry::load(&ldk_data_dir, CONFIG_FILE, check_tlv_type),
+            inbox: Inbox::new(INBOX_CAPACITY),
+        }
+    }
+
+    /// The types whose messages are kept in the inbox.
+    pub fn registry(&self) -> &TypeRegistry<u64> {
+        &self.registry
+    }
+
+    pub fn inbox(&self) -> &Inbox<ReceivedOnionMessage> {
+        &self.inbox
+    }
+}
+
+impl CustomOnionMessageHandler for OnionMessageHandler {
+    type CustomMessage = RawOnionMessage;
+
+    fn handle_custom_message(&self, message: RawOnionMessage) {
+        let received_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let id = self.inbox.push(ReceivedOnionMessage {
+            tlv_type: message.tlv_type,
+            data: hex_str(&message.data),
+            received_at,
+        });
+        println!(
+            "\nEVENT: received onion message {} of type {} ({} bytes)",
+            id,
+            message.tlv_type,
+            message.data.len()
+        );
+        print!("> ");
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn read_custom_message<R: io::Read>(
+        &self,
+        message_type: u64,
+        buffer: &mut R,
+    ) -> Result<Option<RawOnionMessage>, DecodeError> {
+        if !self.registry.contains(message_type) {
+            return Ok(None);
+        }
+        let mut data = Vec::new();
+        buffer.read_to_end(&mut data)?;
+        Ok(Some(RawOnionMessage {
+            tlv_type: message_type,
+            data,
+        }))
+    }
+}
+
+/// Decodes a hex encoded blinded path, as made by [`blinded_path_to_us`].
+pub fn decode_blinded_path(encoded: &str) -> Result<BlindedPath, String> {
+    let bytes = to_vec(encoded).ok_or_else(|| "blinded path isn't hex".to_string())?;
+    BlindedPath::read(&mut &bytes[..]).map_err(|e| format!("invalid blinded path: {:?}", e))
+}
+
+/// A hex encoded blinded path through `introduction_node`, one of our peers, for other nodes
+/// to send us onion messages without learning who we are.
+pub fn blinded_path_to_us(
+    keys_manager: &KeysManager,
+    introduction_node: PublicKey,
+    node_id: PublicKey,
+) -> Result<String, String> {
+    let path = BlindedPath::new_for_message(
+        &[introduction_node, node_id],
+        keys_manager,
+        &Secp256k1::new(),
+    )
+    .map_err(|_| "failed to build a blinded path".to_string())?;
+    Ok(hex_str(&path.encode()))
+}
+
+/// Sends a custom onion message to `destination` through `intermediate_nodes`, the first of
+/// which, or the destination itself if there are none, must be a peer.
+pub fn send(
+    onion_messenger: &OnionMessenger,
+    intermediate_nodes: &[PublicKey],
+    destination: Destination,
+    tlv_type: u64,
+    data: Vec<u8>,
+) -> Result<(), String> {
+    check_tlv_type(tlv_type)?;
+    onion_messenger
+        .send_onion_message(
+            intermediate_nodes,
+            destination,
+            OnionMessageContents::Custom(RawOnionMessage { tlv_type, data }),
+            None,
+        )
+        .map_err(|e| format!("failed to send onion message: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+
+    #[test]
+    fn only_registered_types_are_kept() {
+        let dir = TempDataDir::new("onion-messages");
+        let handler = OnionMessageHandler::new(dir.path().to_string());
+        assert!(handler.registry().register(10).is_err());
+        assert_eq!(handler.registry().register(1001), Ok(true));
+
+        let read = |ty: u64| handler.read_custom_message(ty, &mut &[1u8, 2, 3][..]);
+        assert!(read(1003).unwrap().is_none());
+        let message = read(1001).unwrap().unwrap();
+        handler.handle_custom_message(message);
+        let received = handler.inbox().since(0);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].message.tlv_type, 1001);
+        assert_eq!(received[0].message.data, "010203");
+        assert!(handler.inbox().since(received[0].id).is_empty());
+    }
+}
@@ -0,0 +1,463 @@
+//! Outgoing payments with per-payment limits on fees, paths, CLTV and the nodes or channels to
+//! avoid, and a record of every path they were attempted over.
+
+use crate::ldk::probing::{self, failing_node};
+use crate::types::{ChannelManager, NetworkGraph, PaymentAttemptStorage, Router, Scorer};
+use crate::utils::disk::FilesystemLogger;
+use crate::utils::hex::to_compressed_pubkey;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::channelmanager::{
+    ChannelDetails, PaymentId, RecipientOnionFields, Retry, RetryableSendFailure,
+};
+use lightning::ln::msgs::{ErrorAction, LightningError};
+use lightning::ln::{PaymentHash, PaymentPreimage};
+use lightning::routing::gossip::NodeId;
+use lightning::routing::router::{
+    DefaultRouter, InFlightHtlcs, Path, PaymentParameters, Route, RouteParameters,
+    Router as RouterTrait,
+};
+use lightning_invoice::Invoice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Final CLTV delta of keysends unless the payment asks for another one.
+pub const DEFAULT_KEYSEND_FINAL_CLTV_EXPIRY_DELTA: u32 = 40;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryPolicy {
+    /// Retry failed paths up to this many times.
+    Attempts(usize),
+    /// Retry failed paths until this many seconds after the payment was sent.
+    TimeoutSecs(u64),
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::TimeoutSecs(10)
+    }
+}
+
+impl RetryPolicy {
+    pub fn retry(&self) -> Retry {
+        match self {
+            RetryPolicy::Attempts(attempts) => Retry::Attempts(*attempts),
+            RetryPolicy::TimeoutSecs(secs) => Retry::Timeout(Duration::from_secs(*secs)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaymentOptions {
+    /// Most the payment may spend on fees, over all its paths and retries.
+    pub max_fee_msat: Option<u64>,
+    /// Most the payment may spend on fees, in millionths of the amount.
+    pub max_fee_ppm: Option<u64>,
+    pub max_paths: Option<u8>,
+    pub max_cltv_expiry_delta: Option<u32>,
+    /// Nodes not to route through, by pubkey.
+    pub excluded_nodes: Vec<String>,
+    pub excluded_channels: Vec<u64>,
+    pub retry: RetryPolicy,
+    /// Keysends only, invoices set their own.
+    pub final_cltv_expiry_delta: Option<u32>,
+}
+
+impl PaymentOptions {
+    /// The fee limit for paying `amount_msat`, the lower of the absolute and proportional ones.
+    pub fn max_fee_for(&self, amount_msat: u64) -> Option<u64> {
+        let proportional = self
+            .max_fee_ppm
+            .map(|ppm| (amount_msat as u128 * ppm as u128 / 1_000_000) as u64);
+        match (self.max_fee_msat, proportional) {
+            (Some(absolute), Some(proportional)) => Some(absolute.min(proportional)),
+            (absolute, proportional) => absolute.or(proportional),
+        }
+    }
+
+    /// Restricts `payment_params` to the paths, CLTV and channels these options allow. Excluded
+    /// nodes are avoided through every channel the graph knows them by.
+    pub fn apply(
+        &self,
+        mut payment_params: PaymentParameters,
+        network_graph: &NetworkGraph,
+    ) -> Result<PaymentParameters, String> {
+        if let Some(max_paths) = self.max_paths {
+            payment_params = payment_params.with_max_path_count(max_paths);
+        }
+        if let Some(max_cltv_expiry_delta) = self.max_cltv_expiry_delta {
+            payment_params = payment_params.with_max_total_cltv_expiry_delta(max_cltv_expiry_delta);
+        }
+        let mut excluded_channels = self.excluded_channels.clone();
+        let graph = network_graph.read_only();
+        for node in self.excluded_nodes.iter() {
+            let pubkey = to_compressed_pubkey(node)
+                .ok_or_else(|| format!("Invalid excluded node pubkey {}", node))?;
+            if let Some(node) = graph.node(&NodeId::from_pubkey(&pubkey)) {
+                excluded_channels.extend(node.channels.iter());
+            }
+        }
+        // The router avoids channels that already failed the payment, which is all exclusion is.
+        payment_params
+            .previously_failed_channels
+            .extend(excluded_channels);
+        Ok(payment_params)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttemptStatus {
+    Succeeded,
+    Failed,
+}
+
+/// One path a payment was sent over.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaymentAttempt {
+    pub status: AttemptStatus,
+    pub amount_msat: u64,
+    pub fee_msat: u64,
+    pub short_channel_ids: Vec<u64>,
+    pub failed_short_channel_id: Option<u64>,
+    /// The node that couldn't forward over the failed channel, `None` if that was us.
+    pub failing_node: Option<String>,
+    /// Whether the failure ruled out the payment rather than only this path.
+    pub payment_failed_permanently: bool,
+    pub timestamp: u64,
+}
+
+/// Records a path of a payment succeeding or failing.
+pub fn record_attempt(
+    attempts: &PaymentAttemptStorage,
+    payment_id: PaymentId,
+    path: &Path,
+    failed_short_channel_id: Option<u64>,
+    payment_failed_permanently: bool,
+    succeeded: bool,
+) {
+    let attempt = PaymentAttempt {
+        status: if succeeded {
+            AttemptStatus::Succeeded
+        } else {
+            AttemptStatus::Failed
+        },
+        amount_msat: path.final_value_msat(),
+        fee_msat: path.fee_msat(),
+        short_channel_ids: path.hops.iter().map(|hop| hop.short_channel_id).collect(),
+        failed_short_channel_id,
+        failing_node: failed_short_channel_id
+            .and_then(|scid| failing_node(path, scid))
+            .map(|node| node.to_string()),
+        payment_failed_permanently,
+        timestamp: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    };
+    attempts
+        .lock()
+        .unwrap()
+        .entry(payment_id)
+        .or_default()
+        .push(attempt);
+}
+
+struct FeeBudget {
+    max_fee_msat: u64,
+    /// Fees of the paths in flight or already claimed.
+    committed_msat: u64,
+}
+
+/// The [`DefaultRouter`], refusing routes that would take a payment over its fee limit. Limits
+/// are tracked per payment so retries only spend what the failed paths gave back.
+pub struct FeeLimitedRouter {
+    router: DefaultRouter<Arc<NetworkGraph>, Arc<FilesystemLogger>, Arc<Mutex<Scorer>>>,
+    fee_budgets: Mutex<HashMap<PaymentId, FeeBudget>>,
+}
+
+impl FeeLimitedRouter {
+    pub fn new(
+        network_graph: Arc<NetworkGraph>,
+        logger: Arc<FilesystemLogger>,
+        random_seed_bytes: [u8; 32],
+        scorer: Arc<Mutex<Scorer>>,
+    ) -> Self {
+        FeeLimitedRouter {
+            router: DefaultRouter::new(network_graph, logger, random_seed_bytes, scorer),
+            fee_budgets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_fee_limit(&self, payment_id: PaymentId, max_fee_msat: u64) {
+        self.fee_budgets.lock().unwrap().insert(
+            payment_id,
+            FeeBudget {
+                max_fee_msat,
+                committed_msat: 0,
+            },
+        );
+    }
+
+    /// Gives the fees of a failed path back to the payment's budget.
+    pub fn path_failed(&self, payment_id: PaymentId, path: &Path) {
+        if let Some(budget) = self.fee_budgets.lock().unwrap().get_mut(&payment_id) {
+            budget.committed_msat = budget.committed_msat.saturating_sub(path.fee_msat());
+        }
+    }
+
+    pub fn payment_resolved(&self, payment_id: PaymentId) {
+        self.fee_budgets.lock().unwrap().remove(&payment_id);
+    }
+}
+
+impl RouterTrait for FeeLimitedRouter {
+    fn find_route(
+        &self,
+        payer: &PublicKey,
+        route_params: &RouteParameters,
+        first_hops: Option<&[&ChannelDetails]>,
+        inflight_htlcs: &InFlightHtlcs,
+    ) -> Result<Route, LightningError> {
+        self.router
+            .find_route(payer, route_params, first_hops, inflight_htlcs)
+    }
+
+    fn find_route_with_id(
+        &self,
+        payer: &PublicKey,
+        route_params: &RouteParameters,
+        first_hops: Option<&[&ChannelDetails]>,
+        inflight_htlcs: &InFlightHtlcs,
+        _payment_hash: PaymentHash,
+        payment_id: PaymentId,
+    ) -> Result<Route, LightningError> {
+        let route = self.find_route(payer, route_params, first_hops, inflight_htlcs)?;
+        if let Some(budget) = self.fee_budgets.lock().unwrap().get_mut(&payment_id) {
+            let fees_msat = route.get_total_fees();
+            if budget.committed_msat + fees_msat > budget.max_fee_msat {
+                return Err(LightningError {
+                    err: format!(
+                        "Route fees of {} msat exceed the remaining fee budget of {} msat",
+                        fees_msat,
+                        budget.max_fee_msat - budget.committed_msat
+                    ),
+                    action: ErrorAction::IgnoreError,
+                });
+            }
+            budget.committed_msat += fees_msat;
+        }
+        Ok(route)
+    }
+}
+
+/// Sends a payment through `send`, holding it to its fee limit for as long as it's pending.
+fn send_with_fee_limit(
+    router: &Router,
+    payment_hash: PaymentHash,
+    amount_msat: u64,
+    options: &PaymentOptions,
+    send: impl FnOnce(PaymentId) -> Result<(), RetryableSendFailure>,
+) -> Result<PaymentId, String> {
+    let payment_id = PaymentId(payment_hash.0);
+    if let Some(max_fee_msat) = options.max_fee_for(amount_msat) {
+        router.set_fee_limit(payment_id, max_fee_msat);
+    }
+    send(payment_id).map_err(|e| {
+        router.payment_resolved(payment_id);
+        format!("{:?}", e)
+    })?;
+    Ok(payment_id)
+}
+
+/// Pays `invoice`, `amount_msat` being required when the invoice has no amount.
+pub fn pay_invoice(
+    channel_manager: &ChannelManager,
+    router: &Router,
+    network_graph: &NetworkGraph,
+    invoice: &Invoice,
+    amount_msat: Option<u64>,
+    options: &PaymentOptions,
+) -> Result<PaymentId, String> {
+    let mut route_params = probing::invoice_params(invoice, amount_msat)?;
+    route_params.payment_params = options.apply(route_params.payment_params, network_graph)?;
+    let recipient_onion = RecipientOnionFields {
+        payment_secret: Some(*invoice.payment_secret()),
+        payment_metadata: invoice.payment_metadata().cloned(),
+    };
+    let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+    send_with_fee_limit(
+        router,
+        payment_hash,
+        route_params.final_value_msat,
+        options,
+        |payment_id| {
+            channel_manager.send_payment(
+                payment_hash,
+                recipient_onion,
+                payment_id,
+                route_params,
+                options.retry.retry(),
+            )
+        },
+    )
+}
+
+/// Sends `amount_msat` to `payee` with `payment_preimage`, which the payee learns from the onion.
+pub fn keysend(
+    channel_manager: &ChannelManager,
+    router: &Router,
+    network_graph: &NetworkGraph,
+    payee: PublicKey,
+    amount_msat: u64,
+    payment_preimage: PaymentPreimage,
+    options: &PaymentOptions,
+) -> Result<PaymentId, String> {
+    let payment_params = PaymentParameters::for_keysend(
+        payee,
+        options
+            .final_cltv_expiry_delta
+            .unwrap_or(DEFAULT_KEYSEND_FINAL_CLTV_EXPIRY_DELTA),
+    );
+    let route_params = RouteParameters {
+        payment_params: options.apply(payment_params, network_graph)?,
+        final_value_msat: amount_msat,
+    };
+    let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+    send_with_fee_limit(router, payment_hash, amount_msat, options, |payment_id| {
+        channel_manager
+            .send_spontaneous_payment_with_retry(
+                Some(payment_preimage),
+                RecipientOnionFields::spontaneous_empty(),
+                payment_id,
+                route_params,
+                options.retry.retry(),
+            )
+            .map(|_| ())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::Network;
+    use lightning::ln::features::ChannelFeatures;
+    use lightning::ln::msgs::UnsignedChannelUpdate;
+    use lightning::routing::scoring::ProbabilisticScorer;
+
+    fn pubkey(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[byte; 32]).unwrap(),
+        )
+    }
+
+    fn add_channel(graph: &NetworkGraph, short_channel_id: u64, one: u8, two: u8) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        graph
+            .add_channel_from_partial_announcement(
+                short_channel_id,
+                now,
+                ChannelFeatures::empty(),
+                pubkey(one),
+                pubkey(two),
+            )
+            .unwrap();
+        for flags in 0..2 {
+            graph
+                .update_channel_unsigned(&UnsignedChannelUpdate {
+                    chain_hash: genesis_block(Network::Regtest).header.block_hash(),
+                    short_channel_id,
+                    timestamp: now as u32,
+                    flags,
+                    cltv_expiry_delta: 40,
+                    htlc_minimum_msat: 0,
+                    htlc_maximum_msat: 100_000_000,
+                    fee_base_msat: 1000,
+                    fee_proportional_millionths: 0,
+                    excess_data: Vec::new(),
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn options_and_fee_budget() {
+        let dir = TempDataDir::new("payments");
+        let logger = Arc::new(FilesystemLogger::new(dir.path().to_string()));
+        let graph = Arc::new(NetworkGraph::new(Network::Regtest, logger.clone()));
+        add_channel(&graph, 42, 1, 2);
+        add_channel(&graph, 43, 2, 3);
+
+        let options: PaymentOptions = serde_json::from_str(&format!(
+            r#"{{"max_fee_msat": 1500, "max_fee_ppm": 100000, "excluded_nodes": ["{}"],
+                "excluded_channels": [7], "retry": {{"attempts": 3}}}}"#,
+            pubkey(2)
+        ))
+        .unwrap();
+        assert_eq!(options.max_fee_for(10_000), Some(1000));
+        assert_eq!(options.max_fee_for(100_000), Some(1500));
+        assert_eq!(options.retry.retry(), Retry::Attempts(3));
+        let payment_params = options
+            .apply(PaymentParameters::from_node_id(pubkey(3), 40), &graph)
+            .unwrap();
+        let mut excluded = payment_params.previously_failed_channels.clone();
+        excluded.sort();
+        assert_eq!(excluded, vec![7, 42, 43]);
+
+        let scorer: Scorer =
+            ProbabilisticScorer::new(Default::default(), graph.clone(), logger.clone());
+        let router = Router::new(graph.clone(), logger, [0; 32], Arc::new(Mutex::new(scorer)));
+        let route_params = RouteParameters {
+            payment_params: PaymentParameters::from_node_id(pubkey(3), 40),
+            final_value_msat: 10_000,
+        };
+        let find_route = |payment_id| {
+            router.find_route_with_id(
+                &pubkey(1),
+                &route_params,
+                None,
+                &InFlightHtlcs::new(),
+                PaymentHash(payment_id),
+                PaymentId(payment_id),
+            )
+        };
+        // Payments without a limit route as usual.
+        assert!(find_route([0; 32]).is_ok());
+
+        router.set_fee_limit(PaymentId([1; 32]), 500);
+        assert!(find_route([1; 32]).is_err());
+
+        router.set_fee_limit(PaymentId([2; 32]), 1000);
+        let route = find_route([2; 32]).unwrap();
+        assert_eq!(route.get_total_fees(), 1000);
+        assert!(find_route([2; 32]).is_err());
+        router.path_failed(PaymentId([2; 32]), &route.paths[0]);
+        assert!(find_route([2; 32]).is_ok());
+
+        let attempts: PaymentAttemptStorage = Arc::new(Mutex::new(HashMap::new()));
+        record_attempt(
+            &attempts,
+            PaymentId([2; 32]),
+            &route.paths[0],
+            Some(43),
+            false,
+            false,
+        );
+        let attempt = attempts.lock().unwrap()[&PaymentId([2; 32])][0].clone();
+        assert_eq!(attempt.status, AttemptStatus::Failed);
+        assert_eq!(attempt.short_channel_ids, vec![42, 43]);
+        assert_eq!(attempt.failing_node, Some(pubkey(2).to_string()));
+    }
+}
@@ -0,0 +1,264 @@
+//! Custom peer messages (BOLT 1 odd types above 32767) for app-level protocols between our own
+//! nodes: registered types are kept in an inbox it reads from, and messages are queued for the
+//! peer manager to send. Apps also register the feature bits that tell peers which protocols we
+//! speak.
+//!
+//! LDK 0.0.115's `CustomMessageHandler` has no say in the features we send, so the registered
+//! bits are added to the onion messenger's by
+//! [`ConnectionHooks`](crate::ldk::connection_hooks::ConnectionHooks).
+
+use crate::ldk::connection_hooks::le_flags;
+use crate::types::PeerManager;
+use crate::utils::hex::hex_str;
+use crate::utils::inbox::Inbox;
+use crate::utils::registry::TypeRegistry;
+use bitcoin::secp256k1::PublicKey;
+use lightning::io;
+use lightning::ln::msgs::{DecodeError, LightningError};
+use lightning::ln::peer_handler::CustomMessageHandler;
+use lightning::ln::wire::{CustomMessageReader, Type};
+use lightning::util::ser::{Writeable, Writer};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const CONFIG_FILE: &str = "peer_messages.json";
+const FEATURES_FILE: &str = "peer_features.json";
+
+/// Received messages kept for readers.
+const INBOX_CAPACITY: usize = 1000;
+
+/// BOLT 1 leaves the message types from here up to custom protocols.
+pub const MIN_CUSTOM_TYPE: u16 = 32768;
+
+/// A custom peer message, its data being written as is.
+#[derive(Debug)]
+pub struct PeerMessage {
+    pub type_id: u16,
+    pub data: Vec<u8>,
+}
+
+impl Writeable for PeerMessage {
+    fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+        w.write_all(&self.data)
+    }
+}
+
+impl Type for PeerMessage {
+    fn type_id(&self) -> u16 {
+        self.type_id
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceivedPeerMessage {
+    pub peer: String,
+    pub type_id: u16,
+    /// Hex encoded.
+    pub data: String,
+    /// Seconds since the Unix epoch.
+    pub received_at: u64,
+}
+
+/// Only odd types are allowed: peers that don't know them ignore them instead of
+/// disconnecting.
+fn check_type_id(type_id: u16) -> Result<(), String> {
+    if type_id < MIN_CUSTOM_TYPE || type_id & 1 == 0 {
+        return Err(format!(
+            "custom peer message types must be odd and at least {}",
+            MIN_CUSTOM_TYPE
+        ));
+    }
+    Ok(())
+}
+
+/// Feature bits below this are left to BOLT 9.
+pub const MIN_CUSTOM_FEATURE_BIT: usize = 256;
+
+/// Only optional, odd, bits are allowed so that peers that don't know them still talk to us.
+fn check_feature_bit(bit: usize) -> Result<(), String> {
+    if bit < MIN_CUSTOM_FEATURE_BIT || bit & 1 == 0 {
+        return Err(format!(
+            "custom feature bits must be odd and at least {}",
+            MIN_CUSTOM_FEATURE_BIT
+        ));
+    }
+    Ok(())
+}
+
+pub struct PeerMessageHandler {
+    /// Registered types, kept in `{ldk_data_dir}/peer_messages.json`.
+    registry: TypeRegistry<u16>,
+    /// Advertised feature bits, kept in `{ldk_data_dir}/peer_features.json`.
+    feature_bits: TypeRegistry<usize>,
+    pending: Mutex<Vec<(PublicKey, PeerMessage)>>,
+    inbox: Inbox<ReceivedPeerMessage>,
+}
+
+impl PeerMessageHandler {
+    pub fn new(ldk_data_dir: String) -> Self {
+        Self {
+            registry: TypeRegistry::load(&ldk_data_dir, CONFIG_FILE, check_type_id),
+            feature_bits: TypeRegistry::load(&ldk_data_dir, FEATURES_FILE, check_feature_bit),
+            pending: Mutex::new(Vec::new()),
+            inbox: Inbox::new(INBOX_CAPACITY),
+        }
+    }
+
+    /// The types whose messages are kept in the inbox.
+    pub fn registry(&self) -> &TypeRegistry<u16> {
+        &self.registry
+    }
+
+    /// The feature bits we advertise. Peers connected before a change only see it once they
+    /// reconnect, and the network once we announce our node again.
+    pub fn feature_bits(&self) -> &TypeRegistry<usize> {
+        &self.feature_bits
+    }
+
+    /// `features` with the registered bits set, little endian as `Features::from_le_bytes` takes
+    /// them.
+    pub(crate) fn with_feature_bits<F: Writeable>(&self, features: &F) -> Vec<u8> {
+        let mut flags = le_flags(features);
+        for bit in self.feature_bits.types() {
+            if flags.len() <= bit / 8 {
+                flags.resize(bit / 8 + 1, 0);
+            }
+            flags[bit / 8] |= 1 << (bit % 8);
+        }
+        flags
+    }
+
+    pub fn inbox(&self) -> &Inbox<ReceivedPeerMessage> {
+        &self.inbox
+    }
+}
+
+impl CustomMessageReader for PeerMessageHandler {
+    type CustomMessage = PeerMessage;
+
+    fn read<R: io::Read>(
+        &self,
+        message_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<PeerMessage>, DecodeError> {
+        if !self.registry.contains(message_type) {
+            return Ok(None);
+        }
+        let mut data = Vec::new();
+        buffer.read_to_end(&mut data)?;
+        Ok(Some(PeerMessage {
+            type_id: message_type,
+            data,
+        }))
+    }
+}
+
+impl CustomMessageHandler for PeerMessageHandler {
+    fn handle_custom_message(
+        &self,
+        msg: PeerMessage,
+        sender_node_id: &PublicKey,
+    ) -> Result<(), LightningError> {
+        let received_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let id = self.inbox.push(ReceivedPeerMessage {
+            peer: sender_node_id.to_string(),
+            type_id: msg.type_id,
+            data: hex_str(&msg.data),
+            received_at,
+        });
+        println!(
+            "\nEVENT: received peer message {} of type {} from {} ({} bytes)",
+            id,
+            msg.type_id,
+            sender_node_id,
+            msg.data.len()
+        );
+        print!("> ");
+        std::io::stdout().flush().unwrap();
+        Ok(())
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, PeerMessage)> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+/// Sends a custom message to `peer`, which must be connected.
+pub fn send(
+    peer_manager: &PeerManager,
+    handler: &PeerMessageHandler,
+    peer: PublicKey,
+    type_id: u16,
+    data: Vec<u8>,
+) -> Result<(), String> {
+    check_type_id(type_id)?;
+    if !peer_manager
+        .get_peer_node_ids()
+        .iter()
+        .any(|(pubkey, _)| *pubkey == peer)
+    {
+        return Err(format!("not connected to {}", peer));
+    }
+    handler
+        .pending
+        .lock()
+        .unwrap()
+        .push((peer, PeerMessage { type_id, data }));
+    peer_manager.process_events();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hex::to_compressed_pubkey;
+    use crate::utils::test_utils::TempDataDir;
+    use lightning::ln::features::InitFeatures;
+
+    #[test]
+    fn only_registered_types_are_kept() {
+        let dir = TempDataDir::new("peer-messages-inbox");
+        let handler = PeerMessageHandler::new(dir.path().to_string());
+        let peer = to_compressed_pubkey(
+            "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619",
+        )
+        .unwrap();
+        assert!(handler.registry().register(32770).is_err());
+        assert!(handler.registry().register(101).is_err());
+        assert_eq!(handler.registry().register(32769), Ok(true));
+
+        assert!(handler.read(32771, &mut &[1u8][..]).unwrap().is_none());
+        let message = handler.read(32769, &mut &[1u8, 2][..]).unwrap().unwrap();
+        assert_eq!(message.type_id(), 32769);
+        handler.handle_custom_message(message, &peer).unwrap();
+        let received = handler.inbox().since(0);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].message.peer, peer.to_string());
+        assert_eq!(received[0].message.data, "0102");
+    }
+
+    #[test]
+    fn registered_feature_bits_are_added_to_the_features() {
+        let dir = TempDataDir::new("peer-messages-features");
+        let handler = PeerMessageHandler::new(dir.path().to_string());
+        assert!(handler.feature_bits().register(258).is_err());
+        assert!(handler.feature_bits().register(51).is_err());
+        assert_eq!(handler.feature_bits().register(259), Ok(true));
+
+        let mut provided = InitFeatures::empty();
+        provided.set_static_remote_key_optional();
+        let features = InitFeatures::from_le_bytes(handler.with_feature_bits(&provided));
+        assert!(features.supports_static_remote_key());
+        assert_eq!(le_flags(&features).len(), 33);
+        assert_eq!(le_flags(&features)[32], 0b1000);
+        assert!(!features.requires_unknown_bits());
+
+        assert_eq!(handler.feature_bits().unregister(259), Ok(true));
+        assert_eq!(handler.with_feature_bits(&provided), le_flags(&provided));
+    }
+}
@@ -0,0 +1,376 @@
+//! The peers we know how to reach, kept in `{ldk_data_dir}/peers.json`, and the reconnect loop
+//! that keeps us connected to channel peers and the ones marked keep-connected, backing off
+//! exponentially from those that keep failing.
+
+use crate::ldk::tor::{self, TorConfig};
+use crate::types::{ChannelManager, PeerManager};
+use crate::utils::{disk, json_file};
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const PEERS_FILE: &str = "peers.json";
+
+/// The peers file written before there was a peer store, one `pubkey@host:port` per line.
+const LEGACY_PEERS_FILE: &str = "channel_peer_data";
+
+/// Reconnect backoff doubles from a second up to this.
+const MAX_BACKOFF_SECS: u64 = 60 * 60;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    /// `host:port`, most recently added first.
+    pub addresses: Vec<String>,
+    /// Reconnect even without a channel.
+    #[serde(default)]
+    pub keep_connected: bool,
+    /// Seconds since the Unix epoch.
+    #[serde(default)]
+    pub last_connected: Option<u64>,
+    /// Reconnect attempts that failed since we were last connected.
+    #[serde(default)]
+    pub failures: u32,
+    /// Seconds since the Unix epoch before which we won't reconnect.
+    #[serde(default)]
+    pub next_attempt: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerView {
+    pub pubkey: String,
+    #[serde(flatten)]
+    pub record: PeerRecord,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub struct PeerStore {
+    peers: Mutex<HashMap<PublicKey, PeerRecord>>,
+    ldk_data_dir: String,
+}
+
+impl PeerStore {
+    /// Loads the store, taking in the legacy peers file the first time.
+    pub fn load(ldk_data_dir: &str) -> Self {
+        let mut peers = HashMap::new();
+        if Path::new(&format!("{}/{}", ldk_data_dir, PEERS_FILE)).exists() {
+            let stored: HashMap<String, PeerRecord> = json_file::load(
+                ldk_data_dir,
+                PEERS_FILE,
+                "peer store",
+                "starting without peers",
+            )
+            .unwrap_or_default();
+            for (pubkey, record) in stored {
+                match pubkey.parse() {
+                    Ok(pubkey) => {
+                        peers.insert(pubkey, record);
+                    }
+                    Err(_) => println!("ERROR: invalid peer {} in peer store", pubkey),
+                }
+            }
+        } else {
+            let legacy_path = format!("{}/{}", ldk_data_dir, LEGACY_PEERS_FILE);
+            if let Ok(legacy) = disk::read_channel_peer_data(Path::new(&legacy_path)) {
+                for (pubkey, addr) in legacy {
+                    peers.insert(
+                        pubkey,
+                        PeerRecord {
+                            addresses: vec![addr.to_string()],
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+        }
+        let store = Self {
+            peers: Mutex::new(peers),
+            ldk_data_dir: ldk_data_dir.to_string(),
+        };
+        store.save(&store.peers.lock().unwrap());
+        store
+    }
+
+    fn save(&self, peers: &HashMap<PublicKey, PeerRecord>) {
+        let stored: HashMap<String, &PeerRecord> = peers
+            .iter()
+            .map(|(pubkey, record)| (pubkey.to_string(), record))
+            .collect();
+        let res = serde_json::to_string_pretty(&stored)
+            .map_err(|e| e.to_string())
+            .and_then(|stored| {
+                fs::write(format!("{}/{}", self.ldk_data_dir, PEERS_FILE), stored)
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = res {
+            println!("ERROR: failed to persist peer store: {}", e);
+        }
+    }
+
+    /// Records `address` for `pubkey`, updating whether to keep connected if given.
+    pub fn add(&self, pubkey: PublicKey, address: String, keep_connected: Option<bool>) {
+        let mut peers = self.peers.lock().unwrap();
+        let record = peers.entry(pubkey).or_default();
+        record.addresses.retain(|a| *a != address);
+        record.addresses.insert(0, address);
+        if let Some(keep_connected) = keep_connected {
+            record.keep_connected = keep_connected;
+        }
+        // A new address is worth trying right away.
+        record.failures = 0;
+        record.next_attempt = 0;
+        self.save(&peers);
+    }
+
+    /// Forgets `pubkey`, returning false if it wasn't known.
+    pub fn remove(&self, pubkey: &PublicKey) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        let removed = peers.remove(pubkey).is_some();
+        if removed {
+            self.save(&peers);
+        }
+        removed
+    }
+
+    pub fn list(&self) -> Vec<PeerView> {
+        let peers = self.peers.lock().unwrap();
+        let mut views: Vec<PeerView> = peers
+            .iter()
+            .map(|(pubkey, record)| PeerView {
+                pubkey: pubkey.to_string(),
+                record: record.clone(),
+            })
+            .collect();
+        views.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+        views
+    }
+
+    /// Records that we connected to `pubkey` at `at`, however the connection came about.
+    pub(crate) fn connected(&self, pubkey: &PublicKey, at: u64) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(record) = peers.get_mut(pubkey) {
+            record.last_connected = Some(at);
+            record.failures = 0;
+            record.next_attempt = 0;
+            self.save(&peers);
+        }
+    }
+
+    fn failed(&self, pubkey: &PublicKey, at: u64) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(record) = peers.get_mut(pubkey) {
+            let backoff = 1u64
+                .checked_shl(record.failures)
+                .unwrap_or(MAX_BACKOFF_SECS)
+                .min(MAX_BACKOFF_SECS);
+            record.failures = record.failures.saturating_add(1);
+            record.next_attempt = at + backoff;
+            self.save(&peers);
+        }
+    }
+
+    /// The peers to reconnect to at `at`, with their addresses.
+    fn due(
+        &self,
+        at: u64,
+        channel_peers: &HashSet<PublicKey>,
+        connected: &HashSet<PublicKey>,
+    ) -> Vec<(PublicKey, Vec<String>)> {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .iter()
+            .filter(|(pubkey, record)| {
+                (record.keep_connected || channel_peers.contains(*pubkey))
+                    && !connected.contains(*pubkey)
+                    && record.next_attempt <= at
+            })
+            .map(|(pubkey, record)| (*pubkey, record.addresses.clone()))
+            .collect()
+    }
+}
+
+/// Dials the peers that are due every second until `stop` is set, through Tor if configured.
+/// Peers are dialed concurrently, and one still being dialed isn't dialed again. Successful
+/// connections are recorded as they complete, by the peer manager.
+pub async fn reconnect(
+    peer_store: Arc<PeerStore>,
+    channel_manager: Arc<ChannelManager>,
+    peer_manager: Arc<PeerManager>,
+    tor: Option<TorConfig>,
+    stop: Arc<AtomicBool>,
+) {
+    let dialing = Arc::new(Mutex::new(HashSet::new()));
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if stop.load(Ordering::Acquire) {
+            return;
+        }
+        let channel_peers = channel_manager
+            .list_channels()
+            .iter()
+            .map(|chan| chan.counterparty.node_id)
+            .collect();
+        let connected = peer_manager
+            .get_peer_node_ids()
+            .into_iter()
+            .map(|(pubkey, _)| pubkey)
+            .collect();
+        for (pubkey, addresses) in peer_store.due(now(), &channel_peers, &connected) {
+            if !dialing.lock().unwrap().insert(pubkey) {
+                continue;
+            }
+            let peer_store = Arc::clone(&peer_store);
+            let peer_manager = Arc::clone(&peer_manager);
+            let tor = tor.clone();
+            let dialing = Arc::clone(&dialing);
+            tokio::spawn(async move {
+                let mut reached = false;
+                for address in addresses {
+                    if tor::connect(Arc::clone(&peer_manager), pubkey, &address, tor.as_ref())
+                        .await
+                        .is_ok()
+                    {
+                        reached = true;
+                        break;
+                    }
+                }
+                if !reached {
+                    peer_store.failed(&pubkey, now());
+                }
+                dialing.lock().unwrap().remove(&pubkey);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hex::to_compressed_pubkey;
+    use crate::utils::test_utils::TempDataDir;
+
+    fn channel_peer() -> PublicKey {
+        to_compressed_pubkey("02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619")
+            .unwrap()
+    }
+
+    fn other_peer() -> PublicKey {
+        to_compressed_pubkey("0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c")
+            .unwrap()
+    }
+
+    fn peers(pubkeys: &[PublicKey]) -> HashSet<PublicKey> {
+        pubkeys.iter().cloned().collect()
+    }
+
+    #[test]
+    fn legacy_peers_are_taken_in() {
+        let dir = TempDataDir::new("peer-store-legacy");
+        dir.write(
+            LEGACY_PEERS_FILE,
+            &format!(
+                "{}@127.0.0.1:9735\n{}@127.0.0.1:9736\n",
+                channel_peer(),
+                channel_peer()
+            ),
+        );
+        let store = PeerStore::load(dir.path());
+        assert_eq!(store.list()[0].record.addresses, vec!["127.0.0.1:9736"]);
+        // Once in the store, the legacy file isn't read again.
+        dir.write(LEGACY_PEERS_FILE, "");
+        assert_eq!(PeerStore::load(dir.path()).list().len(), 1);
+    }
+
+    #[test]
+    fn only_channel_and_kept_peers_are_dialed() {
+        let dir = TempDataDir::new("peer-store-dialed");
+        let store = PeerStore::load(dir.path());
+        store.add(channel_peer(), "127.0.0.1:9735".to_string(), None);
+        store.add(other_peer(), "127.0.0.1:9737".to_string(), None);
+        let channel_peers = peers(&[channel_peer()]);
+        assert_eq!(store.due(100, &channel_peers, &peers(&[])).len(), 1);
+        store.add(other_peer(), "127.0.0.1:9738".to_string(), Some(true));
+        assert_eq!(store.due(100, &channel_peers, &peers(&[])).len(), 2);
+        // Nor are peers we're connected to already.
+        assert_eq!(
+            store
+                .due(100, &channel_peers, &peers(&[other_peer()]))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn failed_dials_back_off_until_connected() {
+        let dir = TempDataDir::new("peer-store-backoff");
+        let store = PeerStore::load(dir.path());
+        store.add(channel_peer(), "127.0.0.1:9735".to_string(), None);
+        let channel_peers = peers(&[channel_peer()]);
+        store.failed(&channel_peer(), 100);
+        store.failed(&channel_peer(), 101);
+        store.failed(&channel_peer(), 103);
+        assert!(store.due(106, &channel_peers, &peers(&[])).is_empty());
+        assert_eq!(store.due(107, &channel_peers, &peers(&[])).len(), 1);
+        store.failed(&channel_peer(), 107);
+        store.connected(&channel_peer(), 108);
+        assert_eq!(store.due(108, &channel_peers, &peers(&[])).len(), 1);
+    }
+
+    #[test]
+    fn peers_are_reloaded() {
+        let dir = TempDataDir::new("peer-store-reload");
+        let store = PeerStore::load(dir.path());
+        store.add(other_peer(), "127.0.0.1:9737".to_string(), None);
+        store.add(other_peer(), "127.0.0.1:9738".to_string(), Some(true));
+        store.connected(&other_peer(), 100);
+
+        let reloaded = PeerStore::load(dir.path()).list();
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded[0].pubkey, other_peer().to_string());
+        assert_eq!(
+            reloaded[0].record.addresses,
+            vec!["127.0.0.1:9738", "127.0.0.1:9737"]
+        );
+        assert!(reloaded[0].record.keep_connected);
+        assert_eq!(reloaded[0].record.last_connected, Some(100));
+    }
+
+    #[test]
+    fn unknown_peers_are_left_alone() {
+        let dir = TempDataDir::new("peer-store-unknown");
+        let store = PeerStore::load(dir.path());
+        store.add(other_peer(), "127.0.0.1:9737".to_string(), None);
+        store.connected(&channel_peer(), 100);
+        store.failed(&channel_peer(), 100);
+        assert!(!store.remove(&channel_peer()));
+        assert!(store.remove(&other_peer()));
+        assert!(!store.remove(&other_peer()));
+        assert!(PeerStore::load(dir.path()).list().is_empty());
+    }
+
+    #[test]
+    fn invalid_pubkeys_are_skipped() {
+        let dir = TempDataDir::new("peer-store-invalid");
+        dir.write(
+            PEERS_FILE,
+            &format!(
+                r#"{{"{}": {{"addresses": ["127.0.0.1:9735"]}}, "nope": {{"addresses": []}}}}"#,
+                other_peer()
+            ),
+        );
+        let peers = PeerStore::load(dir.path()).list();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].pubkey, other_peer().to_string());
+    }
+}
@@ -0,0 +1,457 @@
+//! Payment probing: sends HTLCs that can't be claimed along the routes a payment would take,
+//! so fees and failing hops are known up front and the scorer learns channel liquidity before
+//! real payments depend on it.
+
+use crate::types::{ChannelManager, NetworkGraph, ProbeStorage, Router};
+use crate::utils::hex::to_compressed_pubkey;
+use crate::utils::json_file;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::channelmanager::PaymentId;
+use lightning::routing::gossip::NodeId;
+use lightning::routing::router::{Path, PaymentParameters, RouteParameters, Router as _};
+use lightning_invoice::Invoice;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CONFIG_FILE: &str = "probing.json";
+
+/// Final CLTV delta asked of destinations probed by pubkey, the same as our keysends.
+const FINAL_CLTV_EXPIRY_DELTA: u32 = 40;
+
+fn default_interval_secs() -> u64 {
+    10 * 60
+}
+
+fn default_max_probes_per_interval() -> usize {
+    10
+}
+
+fn default_top_nodes_amount_msat() -> u64 {
+    10_000_000
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+/// One probe, sent along a single path.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProbeInfo {
+    pub status: ProbeStatus,
+    pub amount_msat: u64,
+    pub fee_msat: u64,
+    pub short_channel_ids: Vec<u64>,
+    pub failed_short_channel_id: Option<u64>,
+    /// The node that couldn't forward over the failed channel, `None` if that was us.
+    pub failing_node: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProbeReport {
+    /// Failed if any path failed or couldn't be sent, pending if some didn't resolve in time.
+    pub status: ProbeStatus,
+    pub amount_msat: u64,
+    pub total_fees_msat: u64,
+    pub paths: Vec<ProbeInfo>,
+    /// Why the paths after the ones in `paths` couldn't be sent.
+    pub send_error: Option<String>,
+}
+
+impl ProbeReport {
+    fn new(amount_msat: u64, paths: Vec<ProbeInfo>, send_error: Option<String>) -> Self {
+        let status =
+            if send_error.is_some() || paths.iter().any(|p| p.status == ProbeStatus::Failed) {
+                ProbeStatus::Failed
+            } else if paths.iter().any(|p| p.status == ProbeStatus::Pending) {
+                ProbeStatus::Pending
+            } else {
+                ProbeStatus::Succeeded
+            };
+        ProbeReport {
+            status,
+            amount_msat,
+            total_fees_msat: paths.iter().map(|p| p.fee_msat).sum(),
+            paths,
+            send_error,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeTarget {
+    pub destination: String,
+    pub amount_msat: u64,
+}
+
+/// Background probing, read from `{ldk_data_dir}/probing.json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbingConfig {
+    #[serde(default)]
+    pub targets: Vec<ProbeTarget>,
+    /// Also probe the nodes with the most channels in the graph, this many of them.
+    #[serde(default)]
+    pub top_nodes: usize,
+    #[serde(default = "default_top_nodes_amount_msat")]
+    pub top_nodes_amount_msat: u64,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Probes sent per interval, each path of a multi-path route counting as one.
+    #[serde(default = "default_max_probes_per_interval")]
+    pub max_probes_per_interval: usize,
+    /// Total amount probes may lock up per interval, unlimited when unset.
+    #[serde(default)]
+    pub budget_msat_per_interval: Option<u64>,
+}
+
+impl ProbingConfig {
+    /// Reads `{ldk_data_dir}/probing.json`, background probing being off when it doesn't exist.
+    pub fn load(ldk_data_dir: &str) -> Option<Self> {
+        json_file::load_checked(
+            ldk_data_dir,
+            CONFIG_FILE,
+            "probing config",
+            "background probing is off",
+            Self::check,
+        )
+    }
+
+    fn check(self) -> Result<Self, String> {
+        if self.interval_secs == 0 {
+            return Err("interval_secs must be at least 1".to_string());
+        }
+        Ok(self)
+    }
+}
+
+/// Route parameters for probing `destination` with `amount_msat`.
+pub fn destination_params(destination: PublicKey, amount_msat: u64) -> RouteParameters {
+    RouteParameters {
+        payment_params: PaymentParameters::from_node_id(destination, FINAL_CLTV_EXPIRY_DELTA),
+        final_value_msat: amount_msat,
+    }
+}
+
+/// Route parameters for paying `invoice`, `amount_msat` being required when the invoice has no
+/// amount.
+pub fn invoice_params(
+    invoice: &Invoice,
+    amount_msat: Option<u64>,
+) -> Result<RouteParameters, String> {
+    let amount_msat = match (invoice.amount_milli_satoshis(), amount_msat) {
+        (Some(amount_msat), _) | (None, Some(amount_msat)) => amount_msat,
+        (None, None) => return Err("Invoice has no amount, one is required".to_string()),
+    };
+    let expiry_time = invoice.duration_since_epoch() + invoice.expiry_time();
+    let mut payment_params = PaymentParameters::from_node_id(
+        invoice.recover_payee_pub_key(),
+        invoice.min_final_cltv_expiry_delta() as u32,
+    )
+    .with_expiry_time(expiry_time.as_secs())
+    .with_route_hints(invoice.route_hints());
+    if let Some(features) = invoice.features() {
+        payment_params = payment_params.with_features(features.clone());
+    }
+    Ok(RouteParameters {
+        payment_params,
+        final_value_msat: amount_msat,
+    })
+}
+
+/// The node that was meant to forward over `short_channel_id` on `path`, `None` if we were.
+pub fn failing_node(path: &Path, short_channel_id: u64) -> Option<PublicKey> {
+    let position = path
+        .hops
+        .iter()
+        .position(|hop| hop.short_channel_id == short_channel_id)?;
+    position
+        .checked_sub(1)
+        .map(|previous| path.hops[previous].pubkey)
+}
+
+/// Sends a probe along each path the router picks for `route_params`, recording each one in
+/// `probes` as soon as it's out. Stops at the first path that can't be sent, returning the probes
+/// sent before it together with the error.
+pub fn send_probes(
+    router: &Router,
+    channel_manager: &ChannelManager,
+    probes: Option<&ProbeStorage>,
+    route_params: &RouteParameters,
+) -> Result<(Vec<PaymentId>, Option<String>), String> {
+    let first_hops = channel_manager.list_usable_channels();
+    let route = router
+        .find_route(
+            &channel_manager.get_our_node_id(),
+            route_params,
+            Some(&first_hops.iter().collect::<Vec<_>>()),
+            &channel_manager.compute_inflight_htlcs(),
+        )
+        .map_err(|e| e.err)?;
+    let mut sent = Vec::new();
+    for path in route.paths {
+        let payment_id = match channel_manager.send_probe(path.clone()) {
+            Ok((_, payment_id)) => payment_id,
+            Err(e) if sent.is_empty() => return Err(format!("{:?}", e)),
+            Err(e) => return Ok((sent, Some(format!("{:?}", e)))),
+        };
+        if let Some(probes) = probes {
+            probes.lock().unwrap().insert(
+                payment_id,
+                ProbeInfo {
+                    status: ProbeStatus::Pending,
+                    amount_msat: path.final_value_msat(),
+                    fee_msat: path.fee_msat(),
+                    short_channel_ids: path.hops.iter().map(|hop| hop.short_channel_id).collect(),
+                    failed_short_channel_id: None,
+                    failing_node: None,
+                },
+            );
+        }
+        sent.push(payment_id);
+    }
+    Ok((sent, None))
+}
+
+/// Probes the route to `route_params`' destination, waiting up to `timeout` for the probes to
+/// come back. The probes are forgotten once reported, results arriving after the timeout are
+/// dropped.
+pub async fn probe(
+    router: &Router,
+    channel_manager: &ChannelManager,
+    probes: &ProbeStorage,
+    route_params: &RouteParameters,
+    timeout: Duration,
+) -> Result<ProbeReport, String> {
+    let (sent, send_error) = send_probes(router, channel_manager, Some(probes), route_params)?;
+    let started = Instant::now();
+    loop {
+        let pending = {
+            let probes = probes.lock().unwrap();
+            sent.iter()
+                .any(|payment_id| probes[payment_id].status == ProbeStatus::Pending)
+        };
+        if !pending || started.elapsed() >= timeout {
+            let paths = {
+                let mut probes = probes.lock().unwrap();
+                sent.iter()
+                    .filter_map(|payment_id| probes.remove(payment_id))
+                    .collect()
+            };
+            return Ok(ProbeReport::new(
+                route_params.final_value_msat,
+                paths,
+                send_error,
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Records a probe coming back, ignoring the ones nobody waits on.
+pub fn probe_resolved(
+    probes: &ProbeStorage,
+    payment_id: PaymentId,
+    path: &Path,
+    failed_short_channel_id: Option<u64>,
+    succeeded: bool,
+) {
+    let mut probes = probes.lock().unwrap();
+    if let Some(probe) = probes.get_mut(&payment_id) {
+        if succeeded {
+            probe.status = ProbeStatus::Succeeded;
+        } else {
+            probe.status = ProbeStatus::Failed;
+            probe.failed_short_channel_id = failed_short_channel_id;
+            probe.failing_node = failed_short_channel_id
+                .and_then(|scid| failing_node(path, scid))
+                .map(|node| node.to_string());
+        }
+    }
+}
+
+fn targets(
+    config: &ProbingConfig,
+    network_graph: &NetworkGraph,
+    us: &NodeId,
+) -> Vec<(PublicKey, u64)> {
+    let mut targets = config
+        .targets
+        .iter()
+        .filter_map(|target| match to_compressed_pubkey(&target.destination) {
+            Some(pubkey) => Some((pubkey, target.amount_msat)),
+            None => {
+                println!("ERROR: invalid probe target {}", target.destination);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if config.top_nodes > 0 {
+        let graph = network_graph.read_only();
+        let mut nodes = graph
+            .nodes()
+            .unordered_iter()
+            .filter(|(node_id, _)| *node_id != us)
+            .map(|(node_id, node)| (node_id, node.channels.len()))
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|&(_, channels)| Reverse(channels));
+        targets.extend(
+            nodes
+                .into_iter()
+                .take(config.top_nodes)
+                .filter_map(|(node_id, _)| node_id.as_pubkey().ok())
+                .map(|pubkey| (pubkey, config.top_nodes_amount_msat)),
+        );
+    }
+    targets
+}
+
+/// Probes `config`'s targets every `config.interval_secs`, picking up each interval where the
+/// last one ran out of budget.
+pub async fn periodic_probe(
+    config: ProbingConfig,
+    router: Arc<Router>,
+    channel_manager: Arc<ChannelManager>,
+    network_graph: Arc<NetworkGraph>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+    interval.tick().await;
+    let mut next_target = 0;
+    loop {
+        interval.tick().await;
+        let us = NodeId::from_pubkey(&channel_manager.get_our_node_id());
+        let targets = targets(&config, &network_graph, &us);
+        let mut sent_probes = 0;
+        let mut sent_msat = 0;
+        for _ in 0..targets.len() {
+            let (destination, amount_msat) = targets[next_target % targets.len()];
+            if sent_probes >= config.max_probes_per_interval
+                || config
+                    .budget_msat_per_interval
+                    .is_some_and(|budget| sent_msat + amount_msat > budget)
+            {
+                break;
+            }
+            next_target = (next_target + 1) % targets.len();
+            // Unreachable targets are expected, the scorer learns from the failed probes anyway.
+            if let Ok((sent, _)) = send_probes(
+                &router,
+                &channel_manager,
+                None,
+                &destination_params(destination, amount_msat),
+            ) {
+                sent_probes += sent.len();
+                sent_msat += amount_msat;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use lightning::ln::features::{ChannelFeatures, NodeFeatures};
+    use lightning::routing::router::RouteHop;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    fn pubkey(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[byte; 32]).unwrap(),
+        )
+    }
+
+    fn hop(node: u8, short_channel_id: u64) -> RouteHop {
+        RouteHop {
+            pubkey: pubkey(node),
+            node_features: NodeFeatures::empty(),
+            short_channel_id,
+            channel_features: ChannelFeatures::empty(),
+            fee_msat: 1000,
+            cltv_expiry_delta: 40,
+        }
+    }
+
+    fn pending(path: &Path) -> ProbeInfo {
+        ProbeInfo {
+            status: ProbeStatus::Pending,
+            amount_msat: path.final_value_msat(),
+            fee_msat: path.fee_msat(),
+            short_channel_ids: path.hops.iter().map(|hop| hop.short_channel_id).collect(),
+            failed_short_channel_id: None,
+            failing_node: None,
+        }
+    }
+
+    #[test]
+    fn failing_node_is_the_hop_before_the_failed_channel() {
+        let path = Path {
+            hops: vec![hop(2, 42), hop(3, 43), hop(4, 44)],
+            blinded_tail: None,
+        };
+        assert_eq!(failing_node(&path, 42), None);
+        assert_eq!(failing_node(&path, 44), Some(pubkey(3)));
+        assert_eq!(failing_node(&path, 45), None);
+    }
+
+    #[test]
+    fn resolved_probes_record_the_failing_hop() {
+        let path = Path {
+            hops: vec![hop(2, 42), hop(3, 43), hop(4, 44)],
+            blinded_tail: None,
+        };
+        let probes: ProbeStorage = Arc::new(Mutex::new(HashMap::new()));
+        let payment_id = PaymentId([1; 32]);
+        probes.lock().unwrap().insert(payment_id, pending(&path));
+        probe_resolved(&probes, PaymentId([2; 32]), &path, Some(43), false);
+        assert_eq!(probes.lock().unwrap().len(), 1);
+        assert_eq!(
+            probes.lock().unwrap()[&payment_id].status,
+            ProbeStatus::Pending
+        );
+        probe_resolved(&probes, payment_id, &path, Some(43), false);
+        let probe = probes.lock().unwrap()[&payment_id].clone();
+        assert_eq!(probe.status, ProbeStatus::Failed);
+        assert_eq!(probe.failed_short_channel_id, Some(43));
+        assert_eq!(probe.failing_node, Some(pubkey(2).to_string()));
+    }
+
+    #[test]
+    fn reports_fail_when_a_path_fails_or_wasnt_sent() {
+        let path = Path {
+            hops: vec![hop(2, 42)],
+            blinded_tail: None,
+        };
+        let mut succeeded = pending(&path);
+        succeeded.status = ProbeStatus::Succeeded;
+        let report = ProbeReport::new(1000, vec![succeeded.clone(), pending(&path)], None);
+        assert_eq!(report.status, ProbeStatus::Pending);
+        assert_eq!(report.total_fees_msat, 0);
+        let report = ProbeReport::new(1000, vec![succeeded.clone()], None);
+        assert_eq!(report.status, ProbeStatus::Succeeded);
+        let report = ProbeReport::new(1000, vec![succeeded], Some("no channel".to_string()));
+        assert_eq!(report.status, ProbeStatus::Failed);
+    }
+
+    #[test]
+    fn config_defaults_and_rejects_a_zero_interval() {
+        let config: ProbingConfig = serde_json::from_str(&format!(
+            r#"{{"targets": [{{"destination": "{}", "amount_msat": 5000}}], "budget_msat_per_interval": 1000000}}"#,
+            pubkey(4)
+        ))
+        .unwrap();
+        let config = config.check().unwrap();
+        assert_eq!(config.interval_secs, default_interval_secs());
+        assert_eq!(config.max_probes_per_interval, 10);
+        assert_eq!(config.top_nodes, 0);
+        assert_eq!(config.budget_msat_per_interval, Some(1_000_000));
+        let config: ProbingConfig = serde_json::from_str(r#"{"interval_secs": 0}"#).unwrap();
+        assert!(config.check().is_err());
+    }
+}
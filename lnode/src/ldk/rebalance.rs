@@ -0,0 +1,329 @@
+//! Circular rebalancing: paying ourselves over a route that leaves through one of our
+//! channels and comes back through another, moving liquidity from the first to the second.
+//!
+//! LDK's router won't find routes to ourselves, so we route to the target channel's peer with
+//! the source channel as the only first hop and add the hop back to us over the target channel,
+//! paying the peer the fees it told us it charges on it. Rebalances are recorded on both sides of
+//! the payment stores as internal transfers.
+
+use crate::types::{
+    ChannelManager, HTLCStatus, MillisatAmount, PaymentInfo, PaymentInfoStorage, Router,
+};
+use crate::utils::hex::hex_str;
+use lightning::ln::channelmanager::{
+    ChannelDetails, CounterpartyForwardingInfo, PaymentId, RecipientOnionFields,
+    MIN_FINAL_CLTV_EXPIRY_DELTA,
+};
+use lightning::ln::{PaymentHash, PaymentSecret};
+use lightning::routing::router::{
+    PaymentParameters, Route, RouteHop, RouteParameters, Router as RouterTrait,
+};
+use serde::{Deserialize, Serialize};
+
+/// How long the payment to ourselves can be received for.
+const PAYMENT_EXPIRY_SECS: u32 = 600;
+
+/// Moving `amount_msat` out of `source_channel_id` and back in through `target_channel_id`, for
+/// at most `max_fee_msat` in routing fees.
+#[derive(Clone, Copy, Debug)]
+pub struct RebalanceParams {
+    pub source_channel_id: [u8; 32],
+    pub target_channel_id: [u8; 32],
+    pub amount_msat: u64,
+    pub max_fee_msat: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rebalance {
+    pub payment_hash: String,
+    pub amount_msat: u64,
+    pub fee_msat: u64,
+    /// The channels the payment goes over, from the source channel back to the target one.
+    pub short_channel_ids: Vec<u64>,
+}
+
+/// Sends the rebalance of `params`, failing without sending when the route would cost more than
+/// its maximum fee.
+pub fn rebalance(
+    channel_manager: &ChannelManager,
+    router: &Router,
+    inbound_payments: &PaymentInfoStorage,
+    outbound_payments: &PaymentInfoStorage,
+    params: RebalanceParams,
+) -> Result<Rebalance, String> {
+    let RebalanceParams {
+        amount_msat,
+        max_fee_msat,
+        ..
+    } = params;
+    let channels = channel_manager.list_usable_channels();
+    let (source, target) = usable_channels(
+        &channels,
+        params.source_channel_id,
+        params.target_channel_id,
+        amount_msat,
+    )?;
+    let forwarding_info = target
+        .counterparty
+        .forwarding_info
+        .as_ref()
+        .ok_or("the target channel's peer hasn't sent us its fees yet")?;
+    let target_scid = target
+        .get_inbound_payment_scid()
+        .ok_or("the target channel has no short channel id yet")?;
+
+    let (payment_hash, payment_secret) = channel_manager
+        .create_inbound_payment(Some(amount_msat), PAYMENT_EXPIRY_SECS, None)
+        .map_err(|()| "failed to create the inbound payment".to_string())?;
+
+    let mut payment_params = PaymentParameters::from_node_id(
+        target.counterparty.node_id,
+        forwarding_info.cltv_expiry_delta as u32,
+    );
+    payment_params.max_path_count = 1;
+    let route_params = RouteParameters {
+        payment_params,
+        final_value_msat: amount_msat + hop_fee(forwarding_info, amount_msat),
+    };
+    let our_node_id = channel_manager.get_our_node_id();
+    let mut route = router
+        .find_route(
+            &our_node_id,
+            &route_params,
+            Some(&[source]),
+            &channel_manager.compute_inflight_htlcs(),
+        )
+        .map_err(|e| e.err)?;
+    close_loop(
+        &mut route,
+        forwarding_info,
+        RouteHop {
+            pubkey: our_node_id,
+            node_features: channel_manager.node_features(),
+            short_channel_id: target_scid,
+            channel_features: channel_manager.channel_features(),
+            fee_msat: amount_msat,
+            cltv_expiry_delta: MIN_FINAL_CLTV_EXPIRY_DELTA as u32,
+        },
+    );
+    let fee_msat = route.get_total_fees();
+    if fee_msat > max_fee_msat {
+        return Err(format!(
+            "route fees of {} msat exceed the maximum of {} msat",
+            fee_msat, max_fee_msat
+        ));
+    }
+
+    record(inbound_payments, payment_hash, payment_secret, amount_msat);
+    record(outbound_payments, payment_hash, payment_secret, amount_msat);
+    let res = channel_manager.send_payment_with_route(
+        &route,
+        payment_hash,
+        RecipientOnionFields::secret_only(payment_secret),
+        PaymentId(payment_hash.0),
+    );
+    if let Err(e) = res {
+        for payments in &[inbound_payments, outbound_payments] {
+            if let Some(payment) = payments.lock().unwrap().get_mut(&payment_hash) {
+                payment.status = HTLCStatus::Failed;
+            }
+        }
+        return Err(format!("failed to send: {:?}", e));
+    }
+    Ok(Rebalance {
+        payment_hash: hex_str(&payment_hash.0),
+        amount_msat,
+        fee_msat,
+        short_channel_ids: route.paths[0]
+            .hops
+            .iter()
+            .map(|hop| hop.short_channel_id)
+            .collect(),
+    })
+}
+
+/// The source and target channels among the usable `channels`, if they can move `amount_msat`.
+fn usable_channels(
+    channels: &[ChannelDetails],
+    source_channel_id: [u8; 32],
+    target_channel_id: [u8; 32],
+    amount_msat: u64,
+) -> Result<(&ChannelDetails, &ChannelDetails), String> {
+    if source_channel_id == target_channel_id {
+        return Err("source and target channels must differ".to_string());
+    }
+    let find = |channel_id: &[u8; 32]| {
+        channels
+            .iter()
+            .find(|chan| chan.channel_id == *channel_id)
+            .ok_or_else(|| format!("no usable channel {}", hex_str(channel_id)))
+    };
+    let source = find(&source_channel_id)?;
+    let target = find(&target_channel_id)?;
+    if source.next_outbound_htlc_limit_msat < amount_msat {
+        return Err(format!(
+            "source channel can send at most {} msat",
+            source.next_outbound_htlc_limit_msat
+        ));
+    }
+    if target.inbound_capacity_msat < amount_msat {
+        return Err(format!(
+            "target channel can receive at most {} msat",
+            target.inbound_capacity_msat
+        ));
+    }
+    Ok((source, target))
+}
+
+/// What the peer charges to forward `amount_msat` to us.
+fn hop_fee(forwarding_info: &CounterpartyForwardingInfo, amount_msat: u64) -> u64 {
+    forwarding_info.fee_base_msat as u64
+        + (amount_msat as u128 * forwarding_info.fee_proportional_millionths as u128 / 1_000_000)
+            as u64
+}
+
+/// Turns a route to the target channel's peer into one coming back to us over that channel,
+/// `hop_to_us` being the last hop, over the target channel and delivering the rebalanced amount.
+fn close_loop(
+    route: &mut Route,
+    forwarding_info: &CounterpartyForwardingInfo,
+    hop_to_us: RouteHop,
+) {
+    let path = &mut route.paths[0];
+    if let Some(peer_hop) = path.hops.last_mut() {
+        peer_hop.fee_msat = hop_fee(forwarding_info, hop_to_us.fee_msat);
+        peer_hop.cltv_expiry_delta = forwarding_info.cltv_expiry_delta as u32;
+    }
+    path.hops.push(hop_to_us);
+    // The parameters were for reaching the peer, and we don't retry rebalances.
+    route.payment_params = None;
+}
+
+fn record(
+    payments: &PaymentInfoStorage,
+    payment_hash: PaymentHash,
+    payment_secret: PaymentSecret,
+    amount_msat: u64,
+) {
+    payments.lock().unwrap().insert(
+        payment_hash,
+        PaymentInfo {
+            preimage: None,
+            secret: Some(payment_secret),
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(Some(amount_msat)),
+            internal: true,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use lightning::ln::features::{ChannelFeatures, NodeFeatures};
+    use lightning::routing::router::Path;
+
+    fn pubkey(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[byte; 32]).unwrap(),
+        )
+    }
+
+    fn hop(node: u8, short_channel_id: u64, fee_msat: u64, cltv_expiry_delta: u32) -> RouteHop {
+        RouteHop {
+            pubkey: pubkey(node),
+            node_features: NodeFeatures::empty(),
+            short_channel_id,
+            channel_features: ChannelFeatures::empty(),
+            fee_msat,
+            cltv_expiry_delta,
+        }
+    }
+
+    fn forwarding_info() -> CounterpartyForwardingInfo {
+        CounterpartyForwardingInfo {
+            fee_base_msat: 1_000,
+            fee_proportional_millionths: 100,
+            cltv_expiry_delta: 40,
+        }
+    }
+
+    /// A route out through our channel 1 to node 2, which forwards over channel 2 to node 3,
+    /// closed back to us (node 1) over channel 3.
+    fn loop_route(amount_msat: u64) -> Route {
+        let mut route = Route {
+            paths: vec![Path {
+                hops: vec![hop(2, 1, 500, 80), hop(3, 2, amount_msat + 2_000, 40)],
+                blinded_tail: None,
+            }],
+            payment_params: Some(PaymentParameters::from_node_id(pubkey(3), 40)),
+        };
+        close_loop(&mut route, &forwarding_info(), hop(1, 3, amount_msat, 24));
+        route
+    }
+
+    #[test]
+    fn hop_fee_is_base_plus_proportional() {
+        assert_eq!(hop_fee(&forwarding_info(), 10_000_000), 2_000);
+        assert_eq!(hop_fee(&forwarding_info(), 0), 1_000);
+    }
+
+    #[test]
+    fn loop_ends_with_us() {
+        let amount_msat = 10_000_000;
+        let route = loop_route(amount_msat);
+        let path = &route.paths[0];
+        assert_eq!(
+            path.hops
+                .iter()
+                .map(|h| h.short_channel_id)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(path.hops.last().unwrap().pubkey, pubkey(1));
+        assert_eq!(path.hops.last().unwrap().cltv_expiry_delta, 24);
+        assert_eq!(path.final_value_msat(), amount_msat);
+        assert!(route.payment_params.is_none());
+    }
+
+    #[test]
+    fn loop_pays_the_last_peer() {
+        let route = loop_route(10_000_000);
+        assert_eq!(route.get_total_fees(), 2_500);
+        assert_eq!(route.paths[0].hops[1].cltv_expiry_delta, 40);
+    }
+
+    #[test]
+    fn source_and_target_must_differ() {
+        assert_eq!(
+            usable_channels(&[], [1; 32], [1; 32], 1_000).unwrap_err(),
+            "source and target channels must differ"
+        );
+    }
+
+    #[test]
+    fn unknown_channels_are_refused() {
+        assert_eq!(
+            usable_channels(&[], [1; 32], [2; 32], 1_000).unwrap_err(),
+            format!("no usable channel {}", hex_str(&[1; 32]))
+        );
+    }
+
+    #[test]
+    fn rebalances_are_recorded_as_internal() {
+        let payments = PaymentInfoStorage::default();
+        record(
+            &payments,
+            PaymentHash([1; 32]),
+            PaymentSecret([2; 32]),
+            1_000,
+        );
+        let payments = payments.lock().unwrap();
+        let payment = &payments[&PaymentHash([1; 32])];
+        assert!(payment.internal);
+        assert!(matches!(payment.status, HTLCStatus::Pending));
+        assert_eq!(payment.amt_msat.0, Some(1_000));
+    }
+}
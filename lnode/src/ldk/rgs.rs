@@ -0,0 +1,169 @@
+//! Rapid Gossip Sync: bootstraps the network graph from snapshots served by an RGS server or
+//! read from disk, so a fresh node can route without waiting for P2P gossip.
+
+use crate::chain_source::http::HttpClient;
+use crate::types::RapidGossipSync;
+use crate::utils::json_file;
+use bitcoin::BlockHash;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+const CONFIG_FILE: &str = "rgs.json";
+const LAST_SYNC_FILE: &str = "rgs_last_sync";
+
+fn default_interval_secs() -> u64 {
+    60 * 60
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RgsSource {
+    /// A snapshot on disk, applied as is whatever the last sync timestamp.
+    File { path: String },
+    /// An RGS server such as `http://127.0.0.1:8011/snapshot`, queried at `{url}/{last_sync}`.
+    Url { url: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RgsConfig {
+    pub source: RgsSource,
+    /// How often to fetch a new snapshot after the one applied on startup.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl RgsConfig {
+    /// Reads `{ldk_data_dir}/rgs.json`, RGS being off when it doesn't exist.
+    pub fn load(ldk_data_dir: &str) -> Option<Self> {
+        json_file::load(
+            ldk_data_dir,
+            CONFIG_FILE,
+            "rapid gossip sync config",
+            "using P2P gossip",
+        )
+    }
+}
+
+impl RgsSource {
+    fn fetch(&self, last_sync: u32) -> io::Result<Vec<u8>> {
+        match self {
+            RgsSource::File { path } => fs::read(path),
+            RgsSource::Url { url } => HttpClient::new(url)?
+                .get(&format!("/{}", last_sync))?
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("No snapshot at {}", url))
+                }),
+        }
+    }
+}
+
+/// Timestamp of the last applied snapshot, 0 if the graph was never rapid synced.
+pub fn last_sync_timestamp(ldk_data_dir: &str) -> u32 {
+    fs::read_to_string(format!("{}/{}", ldk_data_dir, LAST_SYNC_FILE))
+        .ok()
+        .and_then(|timestamp| timestamp.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Fetches and applies the snapshot following the last sync, returning its timestamp.
+pub fn sync(
+    rapid_sync: &RapidGossipSync,
+    source: &RgsSource,
+    genesis_hash: &BlockHash,
+    ldk_data_dir: &str,
+) -> io::Result<u32> {
+    let snapshot = source.fetch(last_sync_timestamp(ldk_data_dir))?;
+    // The graph doesn't check the chain of the snapshot, so a mainnet snapshot would fill a
+    // regtest graph with channels that don't exist.
+    if snapshot.len() < 36 || snapshot[4..36] != genesis_hash[..] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Rapid gossip sync snapshot is for another chain",
+        ));
+    }
+    let timestamp = rapid_sync
+        .update_network_graph(&snapshot)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+    fs::write(
+        format!("{}/{}", ldk_data_dir, LAST_SYNC_FILE),
+        timestamp.to_string(),
+    )?;
+    Ok(timestamp)
+}
+
+/// Applies a new snapshot every `config.interval_secs`, the first one being due an interval
+/// after startup.
+pub async fn periodic_sync(
+    rapid_sync: Arc<RapidGossipSync>,
+    config: RgsConfig,
+    genesis_hash: BlockHash,
+    ldk_data_dir: String,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let rapid_sync = rapid_sync.clone();
+        let source = config.source.clone();
+        let ldk_data_dir = ldk_data_dir.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            sync(&rapid_sync, &source, &genesis_hash, &ldk_data_dir)
+        })
+        .await
+        .unwrap();
+        if let Err(e) = res {
+            println!("ERROR: rapid gossip sync failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NetworkGraph;
+    use crate::utils::disk::FilesystemLogger;
+    use crate::utils::test_utils::TempDataDir;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::Network;
+    use std::time::SystemTime;
+
+    fn snapshot(network: Network, timestamp: u32) -> Vec<u8> {
+        let mut snapshot = vec![76, 68, 75, 1];
+        snapshot.extend_from_slice(&genesis_block(network).header.block_hash()[..]);
+        snapshot.extend_from_slice(&timestamp.to_be_bytes());
+        // No node ids, channel announcements or channel updates.
+        snapshot.extend_from_slice(&[0; 12]);
+        snapshot
+    }
+
+    #[test]
+    fn syncs_from_file_and_persists_timestamp() {
+        let dir = TempDataDir::new("rgs");
+        let ldk_data_dir = dir.path().to_string();
+        let logger = Arc::new(FilesystemLogger::new(ldk_data_dir.clone()));
+        let graph = Arc::new(NetworkGraph::new(Network::Regtest, logger.clone()));
+        let rapid_sync = RapidGossipSync::new(graph, logger);
+        let genesis_hash = genesis_block(Network::Regtest).header.block_hash();
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+
+        let path = format!("{}/snapshot", ldk_data_dir);
+        fs::write(&path, snapshot(Network::Regtest, now)).unwrap();
+        let source = RgsSource::File { path: path.clone() };
+        assert_eq!(last_sync_timestamp(&ldk_data_dir), 0);
+        assert_eq!(
+            sync(&rapid_sync, &source, &genesis_hash, &ldk_data_dir).unwrap(),
+            now
+        );
+        assert_eq!(last_sync_timestamp(&ldk_data_dir), now);
+
+        fs::write(&path, snapshot(Network::Bitcoin, now + 1)).unwrap();
+        assert!(sync(&rapid_sync, &source, &genesis_hash, &ldk_data_dir).is_err());
+        assert_eq!(last_sync_timestamp(&ldk_data_dir), now);
+    }
+}
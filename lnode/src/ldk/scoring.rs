@@ -0,0 +1,238 @@
+//! Tuning and inspection of the [`ProbabilisticScorer`] the router learns channel liquidity
+//! with.
+
+use crate::types::{NetworkGraph, Scorer};
+use crate::utils::json_file;
+use lightning::routing::gossip::NodeId;
+use lightning::routing::scoring::ProbabilisticScoringParameters;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const CONFIG_FILE: &str = "scoring.json";
+
+/// Overrides for [`ProbabilisticScoringParameters`], read from `{ldk_data_dir}/scoring.json`.
+/// Anything left out keeps LDK's default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringConfig {
+    pub base_penalty_msat: Option<u64>,
+    pub base_penalty_amount_multiplier_msat: Option<u64>,
+    pub liquidity_penalty_multiplier_msat: Option<u64>,
+    pub liquidity_offset_half_life_secs: Option<u64>,
+    pub liquidity_penalty_amount_multiplier_msat: Option<u64>,
+    pub historical_liquidity_penalty_multiplier_msat: Option<u64>,
+    pub historical_liquidity_penalty_amount_multiplier_msat: Option<u64>,
+    pub historical_no_updates_half_life_secs: Option<u64>,
+    pub anti_probing_penalty_msat: Option<u64>,
+    pub considered_impossible_penalty_msat: Option<u64>,
+}
+
+impl ScoringConfig {
+    pub fn load(ldk_data_dir: &str) -> Self {
+        json_file::load(
+            ldk_data_dir,
+            CONFIG_FILE,
+            "scoring config",
+            "using defaults",
+        )
+        .unwrap_or_default()
+    }
+
+    pub fn params(&self) -> ProbabilisticScoringParameters {
+        let mut params = ProbabilisticScoringParameters::default();
+        let set = |field: &mut u64, value: Option<u64>| {
+            if let Some(value) = value {
+                *field = value;
+            }
+        };
+        set(&mut params.base_penalty_msat, self.base_penalty_msat);
+        set(
+            &mut params.base_penalty_amount_multiplier_msat,
+            self.base_penalty_amount_multiplier_msat,
+        );
+        set(
+            &mut params.liquidity_penalty_multiplier_msat,
+            self.liquidity_penalty_multiplier_msat,
+        );
+        set(
+            &mut params.liquidity_penalty_amount_multiplier_msat,
+            self.liquidity_penalty_amount_multiplier_msat,
+        );
+        set(
+            &mut params.historical_liquidity_penalty_multiplier_msat,
+            self.historical_liquidity_penalty_multiplier_msat,
+        );
+        set(
+            &mut params.historical_liquidity_penalty_amount_multiplier_msat,
+            self.historical_liquidity_penalty_amount_multiplier_msat,
+        );
+        set(
+            &mut params.anti_probing_penalty_msat,
+            self.anti_probing_penalty_msat,
+        );
+        set(
+            &mut params.considered_impossible_penalty_msat,
+            self.considered_impossible_penalty_msat,
+        );
+        if let Some(secs) = self.liquidity_offset_half_life_secs {
+            params.liquidity_offset_half_life = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.historical_no_updates_half_life_secs {
+            params.historical_no_updates_half_life = Duration::from_secs(secs);
+        }
+        params
+    }
+}
+
+/// What the scorer learned about one direction of a channel.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectionScore {
+    /// The node payments in this direction flow towards.
+    pub target: String,
+    /// Lower and upper bound of the estimated liquidity.
+    pub liquidity_range_msat: Option<(u64, u64)>,
+    /// Historical min and max liquidity offset buckets, in 1/1024ths of the tracked weight.
+    pub historical_buckets: Option<([u16; 8], [u16; 8])>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelScore {
+    pub short_channel_id: u64,
+    pub directions: Vec<DirectionScore>,
+}
+
+/// The scorer's state for `short_channel_id`, `None` if the channel isn't in the graph.
+pub fn channel_score(
+    scorer: &Scorer,
+    network_graph: &NetworkGraph,
+    short_channel_id: u64,
+) -> Option<ChannelScore> {
+    let graph = network_graph.read_only();
+    let channel = graph.channel(short_channel_id)?;
+    let directions = [channel.node_one, channel.node_two]
+        .iter()
+        .map(|target: &NodeId| DirectionScore {
+            target: target.to_string(),
+            liquidity_range_msat: scorer
+                .estimated_channel_liquidity_range(short_channel_id, target),
+            historical_buckets: scorer
+                .historical_estimated_channel_liquidity_probabilities(short_channel_id, target),
+        })
+        .collect();
+    Some(ChannelScore {
+        short_channel_id,
+        directions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::disk::FilesystemLogger;
+    use crate::utils::test_utils::TempDataDir;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use bitcoin::Network;
+    use lightning::ln::features::{ChannelFeatures, NodeFeatures};
+    use lightning::ln::msgs::UnsignedChannelUpdate;
+    use lightning::routing::router::{Path, RouteHop};
+    use lightning::routing::scoring::{ProbabilisticScorer, Score};
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    fn pubkey(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[byte; 32]).unwrap(),
+        )
+    }
+
+    fn add_channel(graph: &NetworkGraph, short_channel_id: u64) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        graph
+            .add_channel_from_partial_announcement(
+                short_channel_id,
+                now,
+                ChannelFeatures::empty(),
+                pubkey(1),
+                pubkey(2),
+            )
+            .unwrap();
+        for flags in 0..2 {
+            graph
+                .update_channel_unsigned(&UnsignedChannelUpdate {
+                    chain_hash: genesis_block(Network::Regtest).header.block_hash(),
+                    short_channel_id,
+                    timestamp: now as u32,
+                    flags,
+                    cltv_expiry_delta: 40,
+                    htlc_minimum_msat: 0,
+                    htlc_maximum_msat: 100_000_000,
+                    fee_base_msat: 0,
+                    fee_proportional_millionths: 0,
+                    excess_data: Vec::new(),
+                })
+                .unwrap();
+        }
+    }
+
+    fn fail_through(scorer: &mut Scorer, short_channel_id: u64) {
+        let path = Path {
+            hops: vec![RouteHop {
+                pubkey: pubkey(2),
+                node_features: NodeFeatures::empty(),
+                short_channel_id,
+                channel_features: ChannelFeatures::empty(),
+                fee_msat: 50_000_000,
+                cltv_expiry_delta: 40,
+            }],
+            blinded_tail: None,
+        };
+        scorer.payment_path_failed(&path, short_channel_id);
+    }
+
+    #[test]
+    fn config_overrides_defaults() {
+        let config: ScoringConfig = serde_json::from_str(
+            r#"{"base_penalty_msat": 10, "liquidity_offset_half_life_secs": 60}"#,
+        )
+        .unwrap();
+        let params = config.params();
+        assert_eq!(params.base_penalty_msat, 10);
+        assert_eq!(params.liquidity_offset_half_life, Duration::from_secs(60));
+        assert_eq!(
+            params.anti_probing_penalty_msat,
+            ProbabilisticScoringParameters::default().anti_probing_penalty_msat
+        );
+    }
+
+    #[test]
+    fn channel_score_shows_what_was_learned() {
+        let dir = TempDataDir::new("scoring");
+        let logger = Arc::new(FilesystemLogger::new(dir.path().to_string()));
+        let graph = Arc::new(NetworkGraph::new(Network::Regtest, logger.clone()));
+        add_channel(&graph, 42);
+        add_channel(&graph, 43);
+        let mut scorer = ProbabilisticScorer::new(
+            ScoringConfig::default().params(),
+            graph.clone(),
+            logger.clone(),
+        );
+        fail_through(&mut scorer, 42);
+
+        assert!(channel_score(&scorer, &graph, 42)
+            .unwrap()
+            .directions
+            .iter()
+            .any(|d| d.liquidity_range_msat.is_some()));
+        assert!(channel_score(&scorer, &graph, 43)
+            .unwrap()
+            .directions
+            .iter()
+            .all(|d| d.liquidity_range_msat.is_none()));
+        assert!(channel_score(&scorer, &graph, 44).is_none());
+    }
+}
@@ -0,0 +1,402 @@
+//! Tor: dialing peers through a SOCKS5 proxy, which `.onion` peers need, and publishing our
+//! listener as a hidden service through the Tor control port.
+
+use crate::cli;
+use crate::types::PeerManager;
+use crate::utils::hex::{hex_str, parse_onion_v3};
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::msgs::NetAddress;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{lookup_host, TcpStream};
+
+const CONFIG_FILE: &str = "tor.json";
+
+/// Where the hidden service's key is kept so its address survives restarts.
+const HIDDEN_SERVICE_FILE: &str = "tor_hidden_service.json";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn default_socks_proxy() -> String {
+    "127.0.0.1:9050".to_string()
+}
+
+/// Tor settings, read from `{ldk_data_dir}/tor.json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TorConfig {
+    #[serde(default = "default_socks_proxy")]
+    pub socks_proxy: String,
+    /// Dial clearnet peers through the proxy too, not only `.onion` ones.
+    #[serde(default)]
+    pub proxy_all: bool,
+    /// Creates a hidden service for our listener when set, e.g. `127.0.0.1:9051`.
+    #[serde(default)]
+    pub control_port: Option<String>,
+    #[serde(default)]
+    pub control_password: Option<String>,
+    /// Tor's `control_auth_cookie`, used instead of a password when set.
+    #[serde(default)]
+    pub cookie_path: Option<String>,
+}
+
+impl TorConfig {
+    /// Reads `{ldk_data_dir}/tor.json`, Tor being off when it doesn't exist. An invalid config is
+    /// an error rather than Tor being off, so that peers are never dialed over clearnet by mistake.
+    pub fn load(ldk_data_dir: &str) -> io::Result<Option<Self>> {
+        let config = match fs::read_to_string(format!("{}/{}", ldk_data_dir, CONFIG_FILE)) {
+            Ok(config) => config,
+            Err(_) => return Ok(None),
+        };
+        serde_json::from_str(&config).map(Some).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid tor config: {}", e),
+            )
+        })
+    }
+
+    async fn proxy_addr(&self) -> io::Result<SocketAddr> {
+        lookup_host(&self.socks_proxy)
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid socks proxy"))
+    }
+}
+
+/// Splits `host:port`, bracketed IPv6 hosts included.
+fn split_host_port(address: &str) -> Option<(&str, u16)> {
+    let (host, port) = address.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Some((host, port.parse().ok()?))
+}
+
+pub fn is_onion(address: &str) -> bool {
+    split_host_port(address).is_some_and(|(host, _)| host.ends_with(".onion"))
+}
+
+/// Whether we could dial `address`, a `host:port`. Names aren't looked up here: that's left to
+/// dialing, and to the proxy when we dial through one so that it doesn't leak to our resolver.
+pub fn is_dialable(address: &str, tor: Option<&TorConfig>) -> bool {
+    match split_host_port(address) {
+        Some((host, _)) if host.ends_with(".onion") => tor.is_some(),
+        Some((host, _)) => !host.is_empty() && !host.contains(char::is_whitespace),
+        None => false,
+    }
+}
+
+fn socks_error(message: &str) -> io::Error {
+    io::Error::other(format!("socks5: {}", message))
+}
+
+/// Opens a stream to `host:port` through the SOCKS5 proxy at `proxy`, leaving name resolution
+/// to the proxy.
+pub async fn socks5_connect(proxy: SocketAddr, host: &str, port: u16) -> io::Result<TcpStream> {
+    if host.len() > 255 {
+        return Err(socks_error("host name too long"));
+    }
+    let mut stream = TcpStream::connect(proxy).await?;
+    // Version 5, offering no authentication only.
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [5, 0] {
+        return Err(socks_error("proxy requires authentication"));
+    }
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        return Err(socks_error("unexpected reply version"));
+    }
+    if reply[1] != 0 {
+        return Err(socks_error(&format!(
+            "connect failed with code {}",
+            reply[1]
+        )));
+    }
+    // Skip the bound address and port.
+    let bound_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => return Err(socks_error("unexpected address type")),
+    };
+    let mut bound = vec![0; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(stream)
+}
+
+/// Connects to `pubkey` at `address`, a `host:port`, through Tor for `.onion` addresses or
+/// when it's configured for all connections. Through Tor, the proxy resolves the host.
+pub async fn connect(
+    peer_manager: Arc<PeerManager>,
+    pubkey: PublicKey,
+    address: &str,
+    tor: Option<&TorConfig>,
+) -> Result<(), String> {
+    let proxied = match tor {
+        Some(tor) if tor.proxy_all || is_onion(address) => Some(tor),
+        Some(_) => None,
+        None if is_onion(address) => {
+            return Err(format!("{} needs tor, which isn't configured", address))
+        }
+        None => None,
+    };
+    let proxy = match proxied {
+        Some(tor) => tor,
+        None => {
+            let addr = lookup_host(address)
+                .await
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or_else(|| format!("couldn't resolve {}", address))?;
+            return cli::do_connect_peer(pubkey, addr, peer_manager)
+                .await
+                .map_err(|_| format!("failed to connect to {}", address));
+        }
+    };
+    let (host, port) =
+        split_host_port(address).ok_or_else(|| format!("invalid address {}", address))?;
+    let proxy_addr = proxy.proxy_addr().await.map_err(|e| e.to_string())?;
+    let stream = tokio::time::timeout(CONNECT_TIMEOUT, socks5_connect(proxy_addr, host, port))
+        .await
+        .map_err(|_| format!("timed out connecting to {} through tor", address))?
+        .map_err(|e| format!("failed to connect to {} through tor: {}", address, e))?;
+    let stream = stream.into_std().map_err(|e| e.to_string())?;
+    let connection_closed =
+        lightning_net_tokio::setup_outbound(Arc::clone(&peer_manager), pubkey, stream);
+    cli::await_connection(pubkey, peer_manager, connection_closed)
+        .await
+        .map_err(|_| format!("failed to connect to {} through tor", address))
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct HiddenService {
+    service_id: String,
+    private_key: String,
+}
+
+async fn control_command(
+    stream: &mut BufReader<TcpStream>,
+    command: &str,
+) -> Result<Vec<String>, String> {
+    stream
+        .get_mut()
+        .write_all(format!("{}\r\n", command).as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if stream
+            .read_line(&mut line)
+            .await
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            return Err("tor closed the control connection".to_string());
+        }
+        let line = line.trim_end().to_string();
+        // The last line of a reply has a space after its status code.
+        let last = line.as_bytes().get(3) == Some(&b' ');
+        if !line.starts_with("250") {
+            return Err(line);
+        }
+        lines.push(line);
+        if last {
+            return Ok(lines);
+        }
+    }
+}
+
+/// Publishes a hidden service forwarding `port` to our listener on `listen_port`, reusing the
+/// key from earlier runs, and returns its address for announcing.
+pub async fn add_hidden_service(
+    tor: &TorConfig,
+    ldk_data_dir: &str,
+    port: u16,
+    listen_port: u16,
+) -> Result<NetAddress, String> {
+    let control_port = tor
+        .control_port
+        .as_ref()
+        .ok_or_else(|| "no tor control port is configured".to_string())?;
+    let stream = TcpStream::connect(control_port)
+        .await
+        .map_err(|e| format!("failed to reach the tor control port: {}", e))?;
+    let mut stream = BufReader::new(stream);
+
+    let auth = match (&tor.cookie_path, &tor.control_password) {
+        (Some(cookie_path), _) => {
+            let cookie = fs::read(cookie_path).map_err(|e| e.to_string())?;
+            format!("AUTHENTICATE {}", hex_str(&cookie))
+        }
+        (None, Some(password)) => format!("AUTHENTICATE \"{}\"", password.replace('"', "\\\"")),
+        (None, None) => "AUTHENTICATE".to_string(),
+    };
+    control_command(&mut stream, &auth)
+        .await
+        .map_err(|e| format!("tor authentication failed: {}", e))?;
+
+    let key_path = format!("{}/{}", ldk_data_dir, HIDDEN_SERVICE_FILE);
+    let stored: Option<HiddenService> = fs::read_to_string(&key_path)
+        .ok()
+        .and_then(|stored| serde_json::from_str(&stored).ok());
+    let key = stored
+        .as_ref()
+        .map_or("NEW:ED25519-V3", |stored| stored.private_key.as_str());
+    let command = format!(
+        "ADD_ONION {} Flags=Detach Port={},127.0.0.1:{}",
+        key, port, listen_port
+    );
+    let service_id = match control_command(&mut stream, &command).await {
+        Ok(reply) => {
+            let field = |name: &str| {
+                reply
+                    .iter()
+                    .find_map(|line| line.strip_prefix(&format!("250-{}=", name)))
+                    .map(|value| value.to_string())
+            };
+            let service_id =
+                field("ServiceID").ok_or_else(|| "tor returned no service id".to_string())?;
+            if let Some(private_key) = field("PrivateKey") {
+                let stored = HiddenService {
+                    service_id: service_id.clone(),
+                    private_key,
+                };
+                fs::write(&key_path, serde_json::to_string_pretty(&stored).unwrap())
+                    .map_err(|e| format!("failed to persist the hidden service key: {}", e))?;
+            }
+            service_id
+        }
+        // Detached services outlive us, so it may still be up from an earlier run.
+        Err(e) if e.contains("collision") && stored.is_some() => stored.unwrap().service_id,
+        Err(e) => return Err(format!("failed to add hidden service: {}", e)),
+    };
+    parse_onion_v3(&format!("{}.onion", service_id), port)
+        .ok_or_else(|| format!("tor returned an invalid service id {}", service_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hex::{net_addr_str, onion_v3_checksum};
+    use crate::utils::test_utils::TempDataDir;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn socks_and_control_port_stand_ins() {
+        let onion = net_addr_str(&NetAddress::OnionV3 {
+            ed25519_pubkey: [7; 32],
+            checksum: onion_v3_checksum(&[7; 32]),
+            version: 3,
+            port: 9735,
+        });
+        let onion = onion.trim_end_matches(":9735").to_string();
+        let expected_host = onion.clone();
+
+        // A SOCKS5 proxy that accepts one connection and echoes what it's sent.
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            stream.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0; 5];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[..4], [5, 1, 0, 3]);
+            let mut host = vec![0; request[4] as usize + 2];
+            stream.read_exact(&mut host).await.unwrap();
+            assert_eq!(&host[..host.len() - 2], expected_host.as_bytes());
+            assert_eq!(host[host.len() - 2..], 9735u16.to_be_bytes());
+            stream
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+            let mut ping = [0; 4];
+            stream.read_exact(&mut ping).await.unwrap();
+            stream.write_all(&ping).await.unwrap();
+        });
+        let mut stream = socks5_connect(proxy_addr, &onion, 9735).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut pong = [0; 4];
+        stream.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"ping");
+
+        // A control port that creates the service once and reports a collision after.
+        let control = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control_addr = control.local_addr().unwrap();
+        let id = onion.trim_end_matches(".onion").to_string();
+        tokio::spawn(async move {
+            for reply in &[
+                format!(
+                    "250-ServiceID={}\r\n250-PrivateKey=ED25519-V3:a2V5\r\n250 OK\r\n",
+                    id
+                ),
+                "550 Unspecified Tor error: Onion address collision\r\n".to_string(),
+            ] {
+                let (stream, _) = control.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                assert_eq!(line, "AUTHENTICATE \"secret\"\r\n");
+                stream.get_mut().write_all(b"250 OK\r\n").await.unwrap();
+                line.clear();
+                stream.read_line(&mut line).await.unwrap();
+                assert!(line.starts_with("ADD_ONION "));
+                assert!(line.ends_with("Port=9735,127.0.0.1:9736\r\n"));
+                stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        let dir = TempDataDir::new("tor");
+        let tor = TorConfig {
+            socks_proxy: proxy_addr.to_string(),
+            proxy_all: false,
+            control_port: Some(control_addr.to_string()),
+            control_password: Some("secret".to_string()),
+            cookie_path: None,
+        };
+        for _ in 0..2 {
+            let addr = add_hidden_service(&tor, dir.path(), 9735, 9736)
+                .await
+                .unwrap();
+            assert_eq!(net_addr_str(&addr), format!("{}:9735", onion));
+        }
+        assert!(is_onion(&format!("{}:9735", onion)));
+        assert!(!is_dialable(&format!("{}:9735", onion), None));
+        assert!(is_dialable(&format!("{}:9735", onion), Some(&tor)));
+    }
+
+    #[test]
+    fn invalid_config_is_an_error() {
+        let dir = TempDataDir::new("tor-config");
+        assert_eq!(TorConfig::load(dir.path()).unwrap(), None);
+        dir.write(CONFIG_FILE, "{\"proxy_all\": true}");
+        let tor = TorConfig::load(dir.path()).unwrap().unwrap();
+        assert!(tor.proxy_all);
+        assert_eq!(tor.socks_proxy, default_socks_proxy());
+        dir.write(CONFIG_FILE, "{\"proxy_all\": \"yes\"}");
+        assert!(TorConfig::load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn dialable_addresses_are_not_looked_up() {
+        // Not a name that resolves, which doesn't matter until it's dialed.
+        assert!(is_dialable("peer.invalid:9735", None));
+        assert!(is_dialable("[::1]:9735", None));
+        assert!(is_dialable("127.0.0.1:9735", None));
+        assert!(!is_dialable("peer.invalid", None));
+        assert!(!is_dialable(":9735", None));
+        assert!(!is_dialable("peer.invalid:port", None));
+    }
+}
@@ -1,22 +1,40 @@
-use crate::types::{ChainMonitor, ChannelManager, OnionMessenger, PaymentInfoStorage, PeerManager};
+use crate::types::{
+    ChainMonitor, ChannelManager, OnionMessenger, PaymentAttemptStorage, PaymentInfoStorage,
+    PeerManager, ProbeStorage, RapidGossipSync,
+};
 use actix_web::web::Data;
 use actix_web::{App, HttpServer};
 use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::network::constants::Network;
 use bitcoin::BlockHash;
+use chain_source::ChainBackendConfig;
 use http_server::routes;
 use http_server::state::HttpServerState;
+use ldk::announce::{self, Announcer};
+use ldk::channel_acceptance::ChannelAcceptor;
 use ldk::core::CoreLDK;
 use ldk::event_handler::handle_ldk_events;
+use ldk::fee_policy::FeePolicyStore;
+use ldk::forwards::ForwardingHistory;
+use ldk::channel_history::{self, ChannelHistory};
+use ldk::gossip::GossipVerifier;
+use ldk::onion_messages::OnionMessageHandler;
+use ldk::lsp::{self, Lsp};
+use ldk::peer_messages::PeerMessageHandler;
+use ldk::peer_store::{self, PeerStore};
+use ldk::connection_hooks::ConnectionHooks;
+use ldk::context::NodeContext;
+use ldk::payments::FeeLimitedRouter;
+use ldk::probing::{self, ProbingConfig};
+use ldk::rgs::{self, RgsConfig};
+use ldk::scoring::ScoringConfig;
+use ldk::tor::{self, TorConfig};
 use lightning::chain::keysinterface::EntropySource;
-use lightning::chain::{self, chainmonitor, ChannelMonitorUpdateStatus, Watch};
+use lightning::chain::{self, chainmonitor, BestBlock, ChannelMonitorUpdateStatus, Filter, Watch};
 use lightning::events::Event;
 use lightning::ln::channelmanager::{self, ChainParameters, ChannelManagerReadArgs};
-use lightning::ln::peer_handler::{IgnoringMessageHandler, MessageHandler};
+use lightning::ln::peer_handler::MessageHandler;
 use lightning::routing::gossip::P2PGossipSync;
-use lightning::routing::router::DefaultRouter;
-use lightning::routing::scoring::ProbabilisticScorer;
-use lightning::routing::scoring::ProbabilisticScoringParameters;
 use lightning::util::config::UserConfig;
 use lightning::util::ser::ReadableArgs;
 use lightning_background_processor::{process_events_async, GossipSync};
@@ -31,13 +49,14 @@ use std::convert::TryInto;
 use std::fs;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 use std::time::{Duration, SystemTime};
 use utils::disk::FilesystemLogger;
-use utils::hex::{ipv_addr, str_to_u8};
+use utils::hex::net_addr_str;
 use utils::{disk, read_network, sweep};
 
 pub mod blockchain;
+pub mod chain_source;
 pub mod cli;
 pub mod http_server;
 pub mod ldk;
@@ -53,30 +72,52 @@ struct NodeConfig<'a> {
     port: u16,
     network: Network,
     node_name: &'a str,
-    announced_listen_addr: &'a str,
+    rgs: Option<RgsConfig>,
+    tor: Option<TorConfig>,
 }
 
-impl Default for NodeConfig<'static> {
-    fn default() -> Self {
-        NodeConfig {
-            ldk_data_dir: format!("{}/.ldk", "."),
+impl NodeConfig<'static> {
+    fn load() -> std::io::Result<Self> {
+        let ldk_data_dir = format!("{}/.ldk", ".");
+        Ok(NodeConfig {
+            rgs: RgsConfig::load(&ldk_data_dir),
+            tor: TorConfig::load(&ldk_data_dir)?,
+            ldk_data_dir,
             port: 9735,
             network: Network::Regtest,
             node_name: "nodenamehjo",
-            announced_listen_addr: "0.0.0.0",
-        }
+        })
     }
 }
 
 pub async fn start_node() {
-    let node_config = NodeConfig::default();
+    let node_config = match NodeConfig::load() {
+        Ok(node_config) => node_config,
+        Err(e) => {
+            println!("ERROR: {}", e);
+            return;
+        }
+    };
     let ldk_data_dir = node_config.ldk_data_dir.clone();
     let port = node_config.port;
     let network = node_config.network;
     let node_name = node_config.node_name;
-    let announced_listen_addr = node_config.announced_listen_addr;
+    let announcer = match Announcer::load(&ldk_data_dir, port) {
+        Ok(announcer) => Arc::new(announcer),
+        Err(e) => {
+            println!("ERROR: {}", e);
+            return;
+        }
+    };
 
-    let n_core_ldk: CoreLDK = match CoreLDK::new().await {
+    let chain_backend = match ChainBackendConfig::load() {
+        Ok(chain_backend) => chain_backend,
+        Err(e) => {
+            println!("ERROR: {}", e);
+            return;
+        }
+    };
+    let n_core_ldk: CoreLDK = match CoreLDK::new(&chain_backend).await {
         Ok(client) => client,
         Err(e) => {
             println!("FAILED TO START CORELDK: {}", e);
@@ -95,7 +136,9 @@ pub async fn start_node() {
     let persister = Arc::new(ldk::persister::persister(&ldk_data_dir)); // 4
 
     let chain_monitor: Arc<ChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
-        None,
+        core_ldk
+            .tx_sync()
+            .map(|tx_sync| tx_sync as Arc<dyn Filter + Send + Sync>),
         broadcaster_interface.clone(),
         logger.clone(),
         fee_estimator.clone(),
@@ -109,32 +152,88 @@ pub async fn start_node() {
     // End step 7
 
     // Start step 8
-    let user_config = UserConfig::default();
+    let channel_history = Arc::new(ChannelHistory::load(&ldk_data_dir));
+    // Every inbound channel goes through our acceptance policy.
+    let channel_acceptor = Arc::new(ChannelAcceptor::load(&ldk_data_dir, channel_history.clone()));
+    // In LSP mode, payments to our clients' intercept SCIDs wait for us to open them a channel.
+    let lsp = Lsp::load(&ldk_data_dir).map(Arc::new);
+    // Forwarding fees and CLTV deltas follow our policy, new channels starting with the global one.
+    let fee_policies = Arc::new(FeePolicyStore::load(&ldk_data_dir));
+    let mut user_config = UserConfig {
+        manually_accept_inbound_channels: true,
+        accept_intercept_htlcs: lsp.is_some(),
+        channel_config: fee_policies.default_config(),
+        ..Default::default()
+    };
+    if let Some(min_confirmations) = channel_acceptor.min_confirmations() {
+        user_config.channel_handshake_config.minimum_depth = min_confirmations;
+    }
+    let forwarding_history = Arc::new(ForwardingHistory::load(&ldk_data_dir));
 
-    let genesis = genesis_block(Network::Regtest).header.block_hash();
+    let genesis = genesis_block(network).header.block_hash();
     let network_graph_path = format!("{}/network_graph", ldk_data_dir.clone());
-    let network_graph = Arc::new(read_network(
-        Path::new(&network_graph_path),
-        genesis,
-        logger.clone(),
-    ));
+    let network_graph = match read_network(Path::new(&network_graph_path), network, logger.clone())
+    {
+        Ok(network_graph) => Arc::new(network_graph),
+        Err(e) => {
+            println!("FAILED TO LOAD NETWORK GRAPH: {}", e);
+            return;
+        }
+    };
 
-    let gossip_sync = Arc::new(P2PGossipSync::new(
-        Arc::clone(&network_graph),
-        None::<Arc<CoreLDK>>,
-        logger.clone(),
-    ));
+    let gossip_sync = Arc::new_cyclic(|gossip_sync| {
+        P2PGossipSync::new(
+            Arc::clone(&network_graph),
+            Some(Arc::new(GossipVerifier::new(
+                Arc::clone(&core_ldk),
+                Arc::clone(&network_graph),
+                Weak::clone(gossip_sync),
+                genesis,
+            ))),
+            logger.clone(),
+        )
+    });
+
+    // Bootstrap the graph from a rapid gossip sync snapshot before anything routes over it.
+    let rapid_gossip_sync = match node_config.rgs.clone() {
+        Some(rgs_config) => {
+            let rapid_gossip_sync = Arc::new(RapidGossipSync::new(
+                Arc::clone(&network_graph),
+                logger.clone(),
+            ));
+            let initial_sync = Arc::clone(&rapid_gossip_sync);
+            let source = rgs_config.source.clone();
+            let data_dir = ldk_data_dir.clone();
+            match tokio::task::spawn_blocking(move || {
+                rgs::sync(&initial_sync, &source, &genesis, &data_dir)
+            })
+            .await
+            .unwrap()
+            {
+                Ok(timestamp) => println!("Rapid gossip synced up to {}", timestamp),
+                Err(e) => println!("ERROR: rapid gossip sync failed: {}", e),
+            }
+            tokio::spawn(rgs::periodic_sync(
+                Arc::clone(&rapid_gossip_sync),
+                rgs_config,
+                genesis,
+                ldk_data_dir.clone(),
+            ));
+            Some(rapid_gossip_sync)
+        }
+        None => None,
+    };
 
     // Step 16. Initialize the ProbabilisticScorer
     let scorer_path = format!("{}/scorer", ldk_data_dir.clone());
-    let params = ProbabilisticScoringParameters::default();
-    let scorer = Arc::new(Mutex::new(ProbabilisticScorer::new(
-        params,
+    let scorer = Arc::new(Mutex::new(disk::read_scorer(
+        Path::new(&scorer_path),
         Arc::clone(&network_graph),
         Arc::clone(&logger),
+        ScoringConfig::load(&ldk_data_dir).params(),
     )));
 
-    let router = Arc::new(DefaultRouter::new(
+    let router = Arc::new(FeeLimitedRouter::new(
         Arc::clone(&network_graph),
         Arc::clone(&logger),
         keys_manager.get_secure_random_bytes(),
@@ -142,9 +241,19 @@ pub async fn start_node() {
     ));
     /* RESTARTING */
     let mut restarting_node = false;
-    let polled_chain_tip = init::validate_best_block_header(core_ldk.as_ref())
-        .await
-        .expect("Failed to fetch best block header and best block");
+    let polled_best_block = match core_ldk.tx_sync() {
+        Some(tx_sync) => {
+            let (header, height) = tokio::task::spawn_blocking(move || tx_sync.client().tip())
+                .await
+                .unwrap()
+                .expect("Failed to fetch best block header and best block");
+            BestBlock::new(header.block_hash(), height)
+        }
+        None => init::validate_best_block_header(core_ldk.as_ref())
+            .await
+            .expect("Failed to fetch best block header and best block")
+            .to_best_block(),
+    };
 
     let (channel_manager_blockhash, channel_manager) = {
         if let Ok(mut f) = fs::File::open(format!("{}/manager", ldk_data_dir.clone())) {
@@ -169,7 +278,6 @@ pub async fn start_node() {
             // We're starting a fresh node.
             restarting_node = false;
 
-            let polled_best_block = polled_chain_tip.to_best_block();
             let polled_best_block_hash = polled_best_block.block_hash();
             let chain_params = ChainParameters {
                 network: Network::Regtest,
@@ -193,7 +301,7 @@ pub async fn start_node() {
 
     /* FRESH CHANNELMANAGER */
 
-    let best_block = polled_chain_tip.to_best_block();
+    let best_block = polled_best_block;
     let best_block_hash = best_block.block_hash();
 
     let (channel_manager_blockhash, mut channel_manager) = {
@@ -247,17 +355,20 @@ pub async fn start_node() {
         ));
     }
 
-    // Save the chain tip to be used in Step 14.
-    chain_tip = Some(
-        init::synchronize_listeners(
-            core_ldk.clone().as_ref(),
-            Network::Testnet,
-            &mut cache,
-            chain_listeners,
-        )
-        .await
-        .unwrap(),
-    );
+    // Save the chain tip to be used in Step 14. Indexer backends catch up through their first
+    // sync instead.
+    if core_ldk.tx_sync().is_none() {
+        chain_tip = Some(
+            init::synchronize_listeners(
+                core_ldk.clone().as_ref(),
+                Network::Testnet,
+                &mut cache,
+                chain_listeners,
+            )
+            .await
+            .unwrap(),
+        );
+    }
 
     // Step 10: Give ChannelMonitors to ChainMonitor
     for item in chain_listener_channel_monitors.drain(..) {
@@ -275,19 +386,30 @@ pub async fn start_node() {
 
     let mut ephemeral_bytes = [0; 32];
     rand::thread_rng().fill_bytes(&mut ephemeral_bytes);
+    let onion_message_handler = Arc::new(OnionMessageHandler::new(ldk_data_dir.clone()));
     let onion_messenger = Arc::new(OnionMessenger::new(
         Arc::clone(&keys_manager),
         Arc::clone(&keys_manager),
         Arc::clone(&logger),
-        IgnoringMessageHandler {},
+        Arc::clone(&onion_message_handler),
     ));
     let channel_manager: Arc<ChannelManager> = Arc::new(channel_manager);
+    if let Err(e) = fee_policies.apply(&channel_manager, None) {
+        println!("ERROR: {}", e);
+    }
+    channel_history.sync(&channel_manager);
+    let peer_message_handler = Arc::new(PeerMessageHandler::new(ldk_data_dir.clone()));
+    let peer_store = Arc::new(PeerStore::load(&ldk_data_dir));
     let lightning_msg_handler = MessageHandler {
         chan_handler: channel_manager.clone(),
         route_handler: gossip_sync.clone(),
-        onion_message_handler: onion_messenger.clone(),
+        onion_message_handler: Arc::new(ConnectionHooks::new(
+            onion_messenger.clone(),
+            peer_message_handler.clone(),
+            peer_store.clone(),
+            channel_acceptor.clone(),
+        )),
     };
-    let ignoring_custom_msg_handler = IgnoringMessageHandler {};
 
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -299,7 +421,7 @@ pub async fn start_node() {
         current_time.try_into().unwrap(),
         &ephemeral_bytes,
         logger.clone(),
-        ignoring_custom_msg_handler,
+        Arc::clone(&peer_message_handler),
         Arc::clone(&keys_manager),
     ));
 
@@ -332,59 +454,90 @@ pub async fn start_node() {
     let channel_manager_listener = channel_manager.clone();
     let chain_monitor_listener = chain_monitor.clone();
     let bitcoind_block_source = core_ldk.clone();
-    tokio::spawn(async move {
-        let chain_poller = poll::ChainPoller::new(bitcoind_block_source.as_ref(), Network::Regtest);
-        let chain_listener = (chain_monitor_listener, channel_manager_listener);
-        let mut spv_client = SpvClient::new(
-            chain_tip.unwrap(),
-            chain_poller,
-            &mut cache,
-            &chain_listener,
-        );
-        loop {
-            spv_client.poll_best_tip().await.unwrap();
-            tokio::time::sleep(Duration::from_secs(1)).await;
-        }
-    });
+    if let Some(tx_sync) = core_ldk.tx_sync() {
+        tokio::spawn(async move {
+            loop {
+                let tx_sync = tx_sync.clone();
+                let channel_manager = channel_manager_listener.clone();
+                let chain_monitor = chain_monitor_listener.clone();
+                let res = tokio::task::spawn_blocking(move || {
+                    tx_sync.sync(&[&*channel_manager, &*chain_monitor])
+                })
+                .await
+                .unwrap();
+                if let Err(e) = res {
+                    println!("ERROR: failed to sync with the chain backend: {}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+    } else {
+        tokio::spawn(async move {
+            let chain_poller =
+                poll::ChainPoller::new(bitcoind_block_source.as_ref(), Network::Regtest);
+            let chain_listener = (chain_monitor_listener, channel_manager_listener);
+            let mut spv_client = SpvClient::new(
+                chain_tip.unwrap(),
+                chain_poller,
+                &mut cache,
+                &chain_listener,
+            );
+            loop {
+                spv_client.poll_best_tip().await.unwrap();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
     let inbound_payments: PaymentInfoStorage = Arc::new(Mutex::new(HashMap::new()));
     let outbound_payments: PaymentInfoStorage = Arc::new(Mutex::new(HashMap::new()));
-    let bdk_wallet = Arc::new(wallet::BitcoinWallet::load_with_mmc(
-        TEST_MNEMONIC.to_string(),
-    ));
+    let probes: ProbeStorage = Arc::new(Mutex::new(HashMap::new()));
+    let payment_attempts: PaymentAttemptStorage = Arc::new(Mutex::new(HashMap::new()));
+    let bdk_wallet = match wallet::BitcoinWallet::load_with_mmc(TEST_MNEMONIC.to_string()) {
+        Ok(wallet) => Arc::new(wallet),
+        Err(e) => {
+            println!("ERROR: failed to load the on-chain wallet: {}", e);
+            return;
+        }
+    };
     // Step 18: Handle LDK Events
-    let arc_bdk_wallet = Arc::clone(&bdk_wallet);
-    let channel_manager_event_listener = Arc::clone(&channel_manager);
-    let bitcoind_client_event_listener = Arc::clone(&core_ldk);
-    let network_graph_event_listener = Arc::clone(&network_graph);
-    let keys_manager_event_listener = Arc::clone(&keys_manager);
-    let inbound_payments_event_listener = Arc::clone(&inbound_payments);
-    let outbound_payments_event_listener = Arc::clone(&outbound_payments);
-    let persister_event_listener = Arc::clone(&persister);
+    let node = Arc::new(NodeContext {
+        channel_manager: channel_manager.clone(),
+        chain_monitor: chain_monitor.clone(),
+        peer_manager: peer_manager.clone(),
+        core: core_ldk.clone(),
+        keys_manager: keys_manager.clone(),
+        network_graph: network_graph.clone(),
+        scorer: scorer.clone(),
+        router: router.clone(),
+        onion_messenger: onion_messenger.clone(),
+        persister: persister.clone(),
+        logger: logger.clone(),
+        inbound_payments: inbound_payments.clone(),
+        outbound_payments: outbound_payments.clone(),
+        probes: probes.clone(),
+        payment_attempts: payment_attempts.clone(),
+        onion_message_handler: onion_message_handler.clone(),
+        peer_message_handler: peer_message_handler.clone(),
+        peer_store: peer_store.clone(),
+        channel_acceptor: channel_acceptor.clone(),
+        lsp: lsp.clone(),
+        fee_policies: fee_policies.clone(),
+        forwarding_history: forwarding_history.clone(),
+        channel_history: channel_history.clone(),
+        bdk_wallet: bdk_wallet.clone(),
+        announcer: announcer.clone(),
+        tor: node_config.tor.clone(),
+        ldk_data_dir: ldk_data_dir.clone(),
+        network,
+        port,
+        node_name: node_name.to_string(),
+    });
 
     // Handle Events
     let event_handler = move |event: Event| {
-        let channel_manager_event_listener = Arc::clone(&channel_manager_event_listener);
-        let bitcoind_client_event_listener = Arc::clone(&bitcoind_client_event_listener);
-        let bitcoind_client_event_listener_1 = Arc::clone(&arc_bdk_wallet);
-        let network_graph_event_listener = Arc::clone(&network_graph_event_listener);
-        let keys_manager_event_listener = Arc::clone(&keys_manager_event_listener);
-        let inbound_payments_event_listener = Arc::clone(&inbound_payments_event_listener);
-        let outbound_payments_event_listener = Arc::clone(&outbound_payments_event_listener);
-        let persister_event_listener = Arc::clone(&persister_event_listener);
-
+        let node = Arc::clone(&node);
         async move {
-            handle_ldk_events(
-                &channel_manager_event_listener,
-                &bitcoind_client_event_listener,
-                &network_graph_event_listener,
-                &keys_manager_event_listener,
-                &inbound_payments_event_listener,
-                &outbound_payments_event_listener,
-                &persister_event_listener,
-                network,
-                event,
-            )
-            .await;
+            handle_ldk_events(&node, event).await;
         }
     };
 
@@ -393,12 +546,16 @@ pub async fn start_node() {
 
     // Step 19. Start Background Processing
     let (bp_exit, bp_exit_check) = tokio::sync::watch::channel(());
+    let bp_gossip_sync = match rapid_gossip_sync {
+        Some(rapid_gossip_sync) => GossipSync::Rapid(rapid_gossip_sync),
+        None => GossipSync::P2P(gossip_sync.clone()),
+    };
     let background_processor = tokio::spawn(process_events_async(
         Arc::clone(&persister),
         event_handler,
         chain_monitor.clone(),
         channel_manager.clone(),
-        GossipSync::p2p(gossip_sync.clone()),
+        bp_gossip_sync,
         peer_manager.clone(),
         logger.clone(),
         Some(scorer.clone()),
@@ -415,70 +572,54 @@ pub async fn start_node() {
     ));
 
     // Regularly reconnect to channel peers.
-    let connect_cm = Arc::clone(&channel_manager);
-    let connect_pm = Arc::clone(&peer_manager);
-    let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
-    let stop_connect = Arc::clone(&stop_listen_connect);
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(1));
-        loop {
-            interval.tick().await;
-            match disk::read_channel_peer_data(Path::new(&peer_data_path)) {
-                Ok(info) => {
-                    let peers = connect_pm.get_peer_node_ids();
-                    for node_id in connect_cm
-                        .list_channels()
-                        .iter()
-                        .map(|chan| chan.counterparty.node_id)
-                        .filter(|id| !peers.iter().any(|(pk, _)| id == pk))
-                    {
-                        if stop_connect.load(Ordering::Acquire) {
-                            return;
-                        }
-                        for (pubkey, peer_addr) in info.iter() {
-                            if *pubkey == node_id {
-                                let _ = cli::do_connect_peer(
-                                    *pubkey,
-                                    peer_addr.clone(),
-                                    Arc::clone(&connect_pm),
-                                )
-                                .await;
-                            }
-                        }
-                    }
+    tokio::spawn(peer_store::reconnect(
+        Arc::clone(&peer_store),
+        Arc::clone(&channel_manager),
+        Arc::clone(&peer_manager),
+        node_config.tor.clone(),
+        Arc::clone(&stop_listen_connect),
+    ));
+
+    if let Some(tor_config) = node_config.tor.as_ref() {
+        if tor_config.control_port.is_some() {
+            match tor::add_hidden_service(tor_config, &ldk_data_dir, port, listen_port).await {
+                Ok(onion) => {
+                    println!("Hidden service at {}", net_addr_str(&onion));
+                    announcer.set_hidden_service(onion);
                 }
-                Err(e) => println!(
-                    "ERROR: errored reading channel peer info from disk: {:?}",
-                    e
-                ),
+                Err(e) => println!("ERROR: {}", e),
             }
         }
-    });
+    }
 
-    // Regularly broadcast our node_announcement. This is only required (or possible) if we have
-    // some public channels.
-    let peer_man = Arc::clone(&peer_manager);
-    let chan_man = Arc::clone(&channel_manager);
-    tokio::spawn(async move {
-        // First wait a minute until we have some peers and maybe have opened a channel.
-        tokio::time::sleep(Duration::from_secs(60)).await;
-        // Then, update our announcement once an hour to keep it fresh but avoid unnecessary churn
-        // in the global gossip network.
-        let mut interval = tokio::time::interval(Duration::from_secs(3600));
-        loop {
-            interval.tick().await;
-            // Don't bother trying to announce if we don't have any public channls, though our
-            // peers should drop such an announcement anyway. Note that announcement may not
-            // propagate until we have a channel with 6+ confirmations.
-            if chan_man.list_channels().iter().any(|chan| chan.is_public) {
-                peer_man.broadcast_node_announcement(
-                    [0; 3],
-                    str_to_u8(&node_name.to_owned()),
-                    ipv_addr(&announced_listen_addr, port),
-                );
-            }
-        }
-    });
+    // Broadcast our node_announcement, hourly and when our addresses change.
+    tokio::spawn(announce::broadcast(
+        Arc::clone(&announcer),
+        Arc::clone(&channel_manager),
+        Arc::clone(&peer_manager),
+        node_name.to_string(),
+    ));
+
+    if let Some(lsp) = lsp.as_ref() {
+        tokio::spawn(lsp::periodic_expiry(
+            Arc::clone(lsp),
+            Arc::clone(&channel_manager),
+        ));
+    }
+    tokio::spawn(channel_history::track(
+        Arc::clone(&channel_history),
+        Arc::clone(&chain_monitor),
+        Arc::clone(&core_ldk),
+    ));
+
+    if let Some(probing_config) = ProbingConfig::load(&ldk_data_dir) {
+        tokio::spawn(probing::periodic_probe(
+            probing_config,
+            Arc::clone(&router),
+            Arc::clone(&channel_manager),
+            Arc::clone(&network_graph),
+        ));
+    }
 
     tokio::spawn(sweep::periodic_sweep(
         ldk_data_dir.clone(),
@@ -486,6 +627,7 @@ pub async fn start_node() {
         Arc::clone(&logger),
         Arc::clone(&persister),
         Arc::clone(&core_ldk),
+        Arc::clone(&bdk_wallet),
     ));
     // Disconnect our peers and stop accepting new connections. This ensures we don't continue
     // updating our channel data after we've stopped the background processor.
@@ -504,11 +646,26 @@ pub async fn start_node() {
         outbound_payments: outbound_payments.clone(),
         onion_messenger: onion_messenger.clone(),
         network_graph: network_graph.clone(),
+        scorer: scorer.clone(),
+        router: router.clone(),
+        probes: probes.clone(),
+        payment_attempts: payment_attempts.clone(),
+        onion_message_handler: onion_message_handler.clone(),
+        peer_message_handler: peer_message_handler.clone(),
+        peer_store: peer_store.clone(),
+        channel_acceptor: channel_acceptor.clone(),
+        lsp: lsp.clone(),
+        fee_policies: fee_policies.clone(),
+        forwarding_history: forwarding_history.clone(),
+        channel_history: channel_history.clone(),
+        bdk_wallet: bdk_wallet.clone(),
+        tor: node_config.tor.clone(),
         channel_manager: channel_manager.clone(),
+        chain_monitor: chain_monitor.clone(),
         network: network.clone(),
         port: port.clone(),
         ldk_data_dir: ldk_data_dir.clone(),
-        announced_listen_addr: announced_listen_addr.to_string(),
+        announcer: announcer.clone(),
         node_name: node_name.to_string(),
     }));
 
@@ -522,6 +679,56 @@ pub async fn start_node() {
             .service(routes::wallet::wallet_list)
             .service(routes::wallet::generate_address)
             .service(routes::wallet::my_wallet_info)
+            .service(routes::ln::channel_score)
+            .service(routes::ln::graph_stats)
+            .service(routes::ln::graph_nodes)
+            .service(routes::ln::graph_channel)
+            .service(routes::ln::query_routes)
+            .service(routes::ln::probe)
+            .service(routes::ln::pay_invoice)
+            .service(routes::ln::keysend)
+            .service(routes::ln::payment_attempts)
+            .service(routes::ln::register_onion_message_type)
+            .service(routes::ln::unregister_onion_message_type)
+            .service(routes::ln::onion_message_types)
+            .service(routes::ln::list_onion_messages)
+            .service(routes::ln::send_onion_message)
+            .service(routes::ln::onion_message_blinded_path)
+            .service(routes::ln::register_peer_message_type)
+            .service(routes::ln::unregister_peer_message_type)
+            .service(routes::ln::peer_message_types)
+            .service(routes::ln::register_peer_feature)
+            .service(routes::ln::unregister_peer_feature)
+            .service(routes::ln::peer_features)
+            .service(routes::ln::list_peer_messages)
+            .service(routes::ln::send_peer_message)
+            .service(routes::ln::list_peer_store)
+            .service(routes::ln::add_peer)
+            .service(routes::ln::remove_peer)
+            .service(routes::ln::announce_addresses)
+            .service(routes::ln::set_announce_addresses)
+            .service(routes::ln::channel_requests)
+            .service(routes::ln::channel_decisions)
+            .service(routes::ln::approve_channel_request)
+            .service(routes::ln::reject_channel_request)
+            .service(routes::ln::list_channels)
+            .service(routes::ln::lsp_register)
+            .service(routes::ln::lsp_invoice)
+            .service(routes::ln::lsp_unregister)
+            .service(routes::ln::lsp_registrations)
+            .service(routes::ln::lsp_intercepts)
+            .service(routes::ln::fee_policy)
+            .service(routes::ln::set_global_fee_policy)
+            .service(routes::ln::set_channel_fee_policy)
+            .service(routes::ln::reset_channel_fee_policy)
+            .service(routes::ln::list_forwards)
+            .service(routes::ln::list_forward_failures)
+            .service(routes::ln::channel_revenue)
+            .service(routes::ln::peer_revenue)
+            .service(routes::ln::rebalance_channels)
+            .service(routes::ln::channel_history)
+            .service(routes::ln::list_balances)
+            .service(routes::wallet::history)
         // .service(blockchain_info)
         // .service(lightning_peers_connect)
         // .service(lightning_peers_list)
@@ -540,9 +747,13 @@ pub async fn start_node() {
 pub async fn main() {
     std::env::set_var("rust_log", "debug");
     env_logger::init();
-    let bdk_wallet = Arc::new(wallet::BitcoinWallet::load_with_mmc(
-        TEST_MNEMONIC.to_string(),
-    ));
+    let bdk_wallet = match wallet::BitcoinWallet::load_with_mmc(TEST_MNEMONIC.to_string()) {
+        Ok(wallet) => Arc::new(wallet),
+        Err(e) => {
+            println!("ERROR: failed to load the on-chain wallet: {}", e);
+            return;
+        }
+    };
     let blockchain_controller = Arc::new(blockchain::BlockchainHandler::new().await.unwrap());
     let my_wall = Data::new(Mutex::new(bdk_wallet.clone()));
     let my_blockchain_controller = Data::new(Mutex::new(blockchain_controller.clone()));
@@ -554,6 +765,21 @@ pub async fn main() {
             .service(routes::wallet::generate_address)
             .service(routes::wallet::my_wallet_info)
             .service(routes::wallet::new_mmc)
+            .service(routes::wallet::create_psbt)
+            .service(routes::wallet::history)
+            .service(routes::wallet::list_utxos)
+            .service(routes::wallet::freeze_utxo)
+            .service(routes::wallet::unfreeze_utxo)
+            .service(routes::wallet::set_label)
+            .service(routes::wallet::export_labels)
+            .service(routes::wallet::import_labels)
+            .service(routes::wallet::sign_psbt)
+            .service(routes::wallet::combine_psbt)
+            .service(routes::wallet::finalize_psbt)
+            .service(routes::wallet::broadcast_psbt)
+            .service(routes::wallet::import_wallet)
+            .service(routes::wallet::restore_wallet)
+            .service(routes::wallet::export_descriptors)
         // .service(blockchain_info)
         // .service(wallet_info)
         // .service(my_wallet_info)
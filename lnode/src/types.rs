@@ -1,12 +1,19 @@
 use crate::ldk::core::CoreLDK;
+use crate::ldk::gossip::GossipVerifier;
+use crate::ldk::onion_messages::OnionMessageHandler;
+use crate::ldk::payments::{FeeLimitedRouter, PaymentAttempt};
+use crate::ldk::connection_hooks::ConnectionHooks;
+use crate::ldk::peer_messages::PeerMessageHandler;
+use crate::ldk::probing::ProbeInfo;
 use crate::utils::disk::FilesystemLogger;
-use lightning::chain::keysinterface::InMemorySigner;
+use lightning::chain::keysinterface::{InMemorySigner, KeysManager};
 use lightning::chain::{chainmonitor, Filter};
-use lightning::ln::channelmanager::SimpleArcChannelManager;
-use lightning::ln::peer_handler::SimpleArcPeerManager;
+use lightning::ln::channelmanager::{self, PaymentId};
+use lightning::ln::peer_handler;
 use lightning::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
-use lightning::onion_message::SimpleArcOnionMessenger;
+use lightning::onion_message;
 use lightning::routing::gossip;
+use lightning::routing::scoring::ProbabilisticScorer;
 use lightning_net_tokio::SocketDescriptor;
 use lightning_persister::FilesystemPersister;
 use std::collections::HashMap;
@@ -14,6 +21,13 @@ use std::sync::{Arc, Mutex};
 
 pub type NetworkGraph = gossip::NetworkGraph<Arc<FilesystemLogger>>;
 
+pub type Scorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
+pub type Router = FeeLimitedRouter;
+
+pub type RapidGossipSync =
+    lightning_rapid_gossip_sync::RapidGossipSync<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
 pub enum HTLCStatus {
     Pending,
     Succeeded,
@@ -37,12 +51,23 @@ pub struct PaymentInfo {
     pub secret: Option<PaymentSecret>,
     pub status: HTLCStatus,
     pub amt_msat: MillisatAmount,
+    /// A rebalance between our own channels, recorded as both sent and received.
+    pub internal: bool,
 }
 
-pub type OnionMessenger = SimpleArcOnionMessenger<FilesystemLogger>;
+pub type OnionMessenger = onion_message::OnionMessenger<
+    Arc<KeysManager>,
+    Arc<KeysManager>,
+    Arc<FilesystemLogger>,
+    Arc<OnionMessageHandler>,
+>;
 
 pub type PaymentInfoStorage = Arc<Mutex<HashMap<PaymentHash, PaymentInfo>>>;
 
+pub type ProbeStorage = Arc<Mutex<HashMap<PaymentId, ProbeInfo>>>;
+
+pub type PaymentAttemptStorage = Arc<Mutex<HashMap<PaymentId, Vec<PaymentAttempt>>>>;
+
 pub type ChainMonitor = chainmonitor::ChainMonitor<
     InMemorySigner,
     Arc<dyn Filter + Send + Sync>,
@@ -52,18 +77,26 @@ pub type ChainMonitor = chainmonitor::ChainMonitor<
     Arc<FilesystemPersister>,
 >;
 
-pub(crate) type ChannelManager = SimpleArcChannelManager<
-    ChainMonitor,
-    CoreLDK, // broadcast
-    CoreLDK, // fee estimate
-    FilesystemLogger,
+pub(crate) type ChannelManager = channelmanager::ChannelManager<
+    Arc<ChainMonitor>,
+    Arc<CoreLDK>, // broadcast
+    Arc<KeysManager>,
+    Arc<KeysManager>,
+    Arc<KeysManager>,
+    Arc<CoreLDK>, // fee estimate
+    Arc<Router>,
+    Arc<FilesystemLogger>,
 >;
 
-pub type PeerManager = SimpleArcPeerManager<
+pub type P2PGossipSync =
+    gossip::P2PGossipSync<Arc<NetworkGraph>, Arc<GossipVerifier>, Arc<FilesystemLogger>>;
+
+pub type PeerManager = peer_handler::PeerManager<
     SocketDescriptor,
-    ChainMonitor,
-    CoreLDK,
-    CoreLDK,
-    CoreLDK,
-    FilesystemLogger,
+    Arc<ChannelManager>,
+    Arc<P2PGossipSync>,
+    Arc<ConnectionHooks>,
+    Arc<FilesystemLogger>,
+    Arc<PeerMessageHandler>,
+    Arc<KeysManager>,
 >;
@@ -1,5 +1,5 @@
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::BlockHash;
+use bitcoin::{BlockHash, Script, TxOut};
 use lightning_block_sync::http::JsonResponse;
 use std::convert::TryInto;
 
@@ -92,3 +92,32 @@ impl TryInto<BlockchainInfo> for JsonResponse {
         })
     }
 }
+
+pub struct BlockHashResponse(pub BlockHash);
+
+impl TryInto<BlockHashResponse> for JsonResponse {
+    type Error = std::io::Error;
+    fn try_into(self) -> std::io::Result<BlockHashResponse> {
+        Ok(BlockHashResponse(
+            BlockHash::from_hex(self.0.as_str().unwrap()).unwrap(),
+        ))
+    }
+}
+
+/// `gettxout`, which answers null for outputs that are spent or never existed.
+pub struct TxOutResponse(pub Option<TxOut>);
+
+impl TryInto<TxOutResponse> for JsonResponse {
+    type Error = std::io::Error;
+    fn try_into(self) -> std::io::Result<TxOutResponse> {
+        if self.0.is_null() {
+            return Ok(TxOutResponse(None));
+        }
+        let value_btc = self.0["value"].as_f64().unwrap();
+        let script_hex = self.0["scriptPubKey"]["hex"].as_str().unwrap();
+        Ok(TxOutResponse(Some(TxOut {
+            value: (value_btc * 100_000_000.0).round() as u64,
+            script_pubkey: Script::from(Vec::<u8>::from_hex(script_hex).unwrap()),
+        })))
+    }
+}
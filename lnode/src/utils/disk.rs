@@ -1,17 +1,21 @@
 use crate::cli;
+use crate::ldk::event_handler::PENDING_SPENDABLE_OUTPUT_DIR;
 use crate::types::NetworkGraph;
 use bitcoin::secp256k1::PublicKey;
+use bitcoin::OutPoint;
 use chrono::Utc;
+use lightning::chain::keysinterface::SpendableOutputDescriptor;
 use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringParameters};
 use lightning::util::logger::{Logger, Record};
-use lightning::util::ser::{ReadableArgs, Writer};
+use lightning::util::ser::{Readable, ReadableArgs, Writer};
 use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Cursor};
 use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
+use wallet_history::LightningRecords;
 
 pub struct FilesystemLogger {
     data_dir: String,
@@ -51,14 +55,6 @@ impl Logger for FilesystemLogger {
             .unwrap();
     }
 }
-pub(crate) fn persist_channel_peer(path: &Path, peer_info: &str) -> std::io::Result<()> {
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)?;
-    file.write_all(format!("{}\n", peer_info).as_bytes())
-}
-
 pub(crate) fn read_channel_peer_data(
     path: &Path,
 ) -> Result<HashMap<PublicKey, SocketAddr>, std::io::Error> {
@@ -83,8 +79,8 @@ pub(crate) fn read_scorer(
     path: &Path,
     graph: Arc<NetworkGraph>,
     logger: Arc<FilesystemLogger>,
+    params: ProbabilisticScoringParameters,
 ) -> ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>> {
-    let params = ProbabilisticScoringParameters::default();
     if let Ok(file) = File::open(path) {
         let args = (params.clone(), Arc::clone(&graph), Arc::clone(&logger));
         if let Ok(scorer) = ProbabilisticScorer::read(&mut BufReader::new(file), args) {
@@ -93,3 +89,45 @@ pub(crate) fn read_scorer(
     }
     ProbabilisticScorer::new(params, graph, logger)
 }
+
+/// Collects the channel funding outpoints and sweepable outputs the node has on disk, so wallet
+/// history can recognise lightning transactions.
+pub(crate) fn read_lightning_records(ldk_data_dir: &str) -> LightningRecords {
+    let mut records = LightningRecords::default();
+    // Monitors are stored under their funding outpoint, as `<txid>_<index>`.
+    if let Ok(dir) = fs::read_dir(format!("{}/monitors", ldk_data_dir)) {
+        for entry in dir.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some((txid, index)) = name.split_once('_') {
+                if let (Ok(txid), Ok(vout)) = (txid.parse(), index.parse()) {
+                    records.funding_outpoints.insert(OutPoint { txid, vout });
+                }
+            }
+        }
+    }
+    for dir in [PENDING_SPENDABLE_OUTPUT_DIR, "spendable_outputs"] {
+        let dir = match fs::read_dir(format!("{}/{}", ldk_data_dir, dir)) {
+            Ok(dir) => dir,
+            Err(_) => continue,
+        };
+        for entry in dir.flatten() {
+            let bytes = match fs::read(entry.path()) {
+                Ok(bytes) => bytes,
+                Err(_) => continue,
+            };
+            let mut reader = Cursor::new(bytes);
+            while (reader.position() as usize) < reader.get_ref().len() {
+                let outpoint = match SpendableOutputDescriptor::read(&mut reader) {
+                    Ok(SpendableOutputDescriptor::StaticOutput { outpoint, .. }) => outpoint,
+                    Ok(SpendableOutputDescriptor::DelayedPaymentOutput(d)) => d.outpoint,
+                    Ok(SpendableOutputDescriptor::StaticPaymentOutput(d)) => d.outpoint,
+                    Err(_) => break,
+                };
+                records
+                    .sweepable_outpoints
+                    .insert(outpoint.into_bitcoin_outpoint());
+            }
+        }
+    }
+    records
+}
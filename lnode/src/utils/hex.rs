@@ -1,6 +1,13 @@
 use bitcoin::secp256k1::PublicKey;
 use lightning::ln::msgs::NetAddress;
-use std::{fmt::Write, net::IpAddr, str::FromStr};
+use lightning::util::ser::Hostname;
+use sha3::{Digest, Sha3_256};
+use std::{
+    convert::TryFrom,
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
 
 pub fn to_vec(hex: &str) -> Option<Vec<u8>> {
     let mut out = Vec::with_capacity(hex.len() / 2);
@@ -52,25 +59,169 @@ pub fn str_to_u8(alias: &str) -> [u8; 32] {
     bytes
 }
 
-pub fn ipv_addr(s: &str, port: u16) -> Vec<NetAddress> {
-    // define a vector
-    let mut addr = Vec::new();
-    match IpAddr::from_str(s) {
+/// Parses an announce address: an IPv4 or IPv6 literal, a v3 `.onion` or a DNS hostname, with
+/// `:port` optional (IPv6 needs brackets for it) and `default_port` used without.
+pub fn parse_net_address(s: &str, default_port: u16) -> Result<NetAddress, String> {
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        let (host, port) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("unclosed bracket in {}", s))?;
+        match port {
+            "" => (host, None),
+            port => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+        }
+    } else {
+        match s.split_once(':') {
+            // More than one colon is a bare IPv6 address.
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (s, None),
+        }
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| format!("invalid port in {}", s))?,
+        None => default_port,
+    };
+    match IpAddr::from_str(host) {
         Ok(IpAddr::V4(a)) => {
-            addr.push(NetAddress::IPv4 {
+            return Ok(NetAddress::IPv4 {
                 addr: a.octets(),
                 port,
-            });
+            })
         }
         Ok(IpAddr::V6(a)) => {
-            addr.push(NetAddress::IPv6 {
+            return Ok(NetAddress::IPv6 {
                 addr: a.octets(),
                 port,
-            });
+            })
         }
-        Err(_) => {
-            println!("ERROR: invalid IPv4 address: {}", s);
+        Err(_) => {}
+    }
+    if host.ends_with(".onion") {
+        return parse_onion_v3(host, port).ok_or_else(|| format!("invalid onion address: {}", s));
+    }
+    // A hostname needs a dot, and an all-numeric one is a mistyped IPv4 address.
+    if !host.contains('.')
+        || host.split('.').any(|label| label.is_empty())
+        || host
+            .split('.')
+            .all(|label| label.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return Err(format!("invalid address: {}", s));
+    }
+    match Hostname::try_from(host.to_string()) {
+        Ok(hostname) => Ok(NetAddress::Hostname { hostname, port }),
+        Err(_) => Err(format!("invalid hostname: {}", s)),
+    }
+}
+
+/// The checksum of a v3 onion address for `ed25519_pubkey`: the first two bytes of
+/// `SHA3-256(".onion checksum" || pubkey || version)`.
+pub fn onion_v3_checksum(ed25519_pubkey: &[u8; 32]) -> u16 {
+    let hash = Sha3_256::new()
+        .chain_update(b".onion checksum")
+        .chain_update(ed25519_pubkey)
+        .chain_update([3])
+        .finalize();
+    u16::from_be_bytes([hash[0], hash[1]])
+}
+
+/// Parses a v3 `.onion` host, rejecting it if its checksum doesn't match.
+pub fn parse_onion_v3(host: &str, port: u16) -> Option<NetAddress> {
+    let bytes = base32_decode(host.strip_suffix(".onion")?)?;
+    if bytes.len() != 35 || bytes[34] != 3 {
+        return None;
+    }
+    let mut ed25519_pubkey = [0; 32];
+    ed25519_pubkey.copy_from_slice(&bytes[..32]);
+    if u16::from_be_bytes([bytes[32], bytes[33]]) != onion_v3_checksum(&ed25519_pubkey) {
+        return None;
+    }
+    Some(NetAddress::OnionV3 {
+        ed25519_pubkey,
+        checksum: u16::from_be_bytes([bytes[32], bytes[33]]),
+        version: bytes[34],
+        port,
+    })
+}
+
+/// Formats an announced address as `host:port`, onion addresses in their `.onion` form.
+pub fn net_addr_str(addr: &NetAddress) -> String {
+    match addr {
+        NetAddress::IPv4 { addr, port } => format!("{}:{}", Ipv4Addr::from(*addr), port),
+        NetAddress::IPv6 { addr, port } => format!("[{}]:{}", Ipv6Addr::from(*addr), port),
+        NetAddress::OnionV2(bytes) => format!(
+            "{}.onion:{}",
+            base32(&bytes[..10]),
+            u16::from_be_bytes([bytes[10], bytes[11]])
+        ),
+        NetAddress::OnionV3 {
+            ed25519_pubkey,
+            checksum,
+            version,
+            port,
+        } => {
+            let mut bytes = ed25519_pubkey.to_vec();
+            bytes.extend_from_slice(&checksum.to_be_bytes());
+            bytes.push(*version);
+            format!("{}.onion:{}", base32(&bytes), port)
         }
-    };
-    addr
+        NetAddress::Hostname { hostname, port } => format!("{}:{}", hostname.as_str(), port),
+    }
+}
+
+/// Lowercase RFC 4648 base32 without padding, as used by onion addresses.
+fn base32(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut res = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            res.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        res.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    res
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = match c {
+            b'a'..=b'z' => c - b'a',
+            b'A'..=b'Z' => c - b'A',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((buffer >> bits) as u8);
+        }
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn onion_checksums_are_checked() {
+        let onion = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        let addr = parse_onion_v3(onion, 443).unwrap();
+        assert_eq!(net_addr_str(&addr), format!("{}:443", onion));
+        // The same key and version with a checksum that doesn't match it.
+        let mistyped = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczaa.onion";
+        assert!(parse_onion_v3(mistyped, 443).is_none());
+        assert!(parse_net_address(&format!("{}:443", mistyped), 9735).is_err());
+    }
 }
@@ -0,0 +1,63 @@
+//! A bounded log of received messages that readers page through by id, waiting for new ones
+//! when they've seen them all.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InboxEntry<T> {
+    pub id: u64,
+    #[serde(flatten)]
+    pub message: T,
+}
+
+pub struct Inbox<T> {
+    entries: Mutex<(u64, VecDeque<InboxEntry<T>>)>,
+    capacity: usize,
+    notify: Notify,
+}
+
+impl<T: Clone> Inbox<T> {
+    /// Keeps the last `capacity` messages, ids starting at 1.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new((0, VecDeque::new())),
+            capacity,
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, message: T) -> u64 {
+        let mut entries = self.entries.lock().unwrap();
+        entries.0 += 1;
+        let id = entries.0;
+        if entries.1.len() == self.capacity {
+            entries.1.pop_front();
+        }
+        entries.1.push_back(InboxEntry { id, message });
+        drop(entries);
+        self.notify.notify_waiters();
+        id
+    }
+
+    /// The messages kept with an id above `after`.
+    pub fn since(&self, after: u64) -> Vec<InboxEntry<T>> {
+        let entries = self.entries.lock().unwrap();
+        entries.1.iter().filter(|e| e.id > after).cloned().collect()
+    }
+
+    /// Like [`Inbox::since`], waiting up to `timeout` for a message if there are none yet.
+    pub async fn wait_since(&self, after: u64, timeout: Duration) -> Vec<InboxEntry<T>> {
+        // Created before checking, so a push in between still wakes us.
+        let notified = self.notify.notified();
+        let entries = self.since(after);
+        if !entries.is_empty() {
+            return entries;
+        }
+        let _ = tokio::time::timeout(timeout, notified).await;
+        self.since(after)
+    }
+}
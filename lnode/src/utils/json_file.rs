@@ -0,0 +1,69 @@
+//! Loading the JSON files kept in the LDK data directory, where a file that doesn't parse is
+//! reported and something else done instead of failing to start.
+
+use serde::de::DeserializeOwned;
+use std::fs;
+
+/// Reads `{ldk_data_dir}/{file}`, none if it doesn't exist or is invalid.
+///
+/// `what` names the file and `fallback` says what we do without it when reporting it invalid.
+pub fn load<T: DeserializeOwned>(
+    ldk_data_dir: &str,
+    file: &str,
+    what: &str,
+    fallback: &str,
+) -> Option<T> {
+    load_checked(ldk_data_dir, file, what, fallback, Ok)
+}
+
+/// Like `load`, with `check` turning what's read into what we keep or rejecting it.
+pub fn load_checked<T: DeserializeOwned, U>(
+    ldk_data_dir: &str,
+    file: &str,
+    what: &str,
+    fallback: &str,
+    check: impl FnOnce(T) -> Result<U, String>,
+) -> Option<U> {
+    let contents = fs::read_to_string(format!("{}/{}", ldk_data_dir, file)).ok()?;
+    match serde_json::from_str(&contents)
+        .map_err(|e| e.to_string())
+        .and_then(check)
+    {
+        Ok(loaded) => Some(loaded),
+        Err(e) => {
+            println!("ERROR: invalid {}, {}: {}", what, fallback, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+
+    fn positive(n: i64) -> Result<i64, String> {
+        if n > 0 {
+            Ok(n)
+        } else {
+            Err(format!("{} is not positive", n))
+        }
+    }
+
+    #[test]
+    fn missing_corrupt_and_rejected_files_load_nothing() {
+        let dir = TempDataDir::new("json-file");
+        let load = |file| load_checked(dir.path(), file, "number", "using none", positive);
+        assert_eq!(load("missing.json"), None);
+        dir.write("corrupt.json", "[");
+        assert_eq!(load("corrupt.json"), None);
+        dir.write("rejected.json", "-1");
+        assert_eq!(load("rejected.json"), None);
+        dir.write("valid.json", "3");
+        assert_eq!(load("valid.json"), Some(3));
+        assert_eq!(
+            super::load::<Vec<u8>>(dir.path(), "valid.json", "list", "using none"),
+            None
+        );
+    }
+}
@@ -1,23 +1,42 @@
 use self::disk::FilesystemLogger;
 use crate::types::NetworkGraph;
-use bitcoin::{BlockHash, Network};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::Network;
 use lightning::util::ser::ReadableArgs;
-use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use std::{fs, io, path::Path, sync::Arc};
 
 pub mod convert;
 pub mod disk;
 pub mod hex;
+pub mod inbox;
+pub mod json_file;
+pub mod registry;
 pub mod sweep;
+#[cfg(test)]
+pub mod test_utils;
 
+/// Loads the graph persisted at `path`, refusing one that was built for another network.
 pub fn read_network(
     path: &Path,
-    genesis_hash: BlockHash,
+    network: Network,
     logger: Arc<FilesystemLogger>,
-) -> NetworkGraph {
-    if let Ok(file) = File::open(path) {
-        if let Ok(graph) = NetworkGraph::read(&mut BufReader::new(file), logger.clone()) {
-            return graph;
+) -> io::Result<NetworkGraph> {
+    let genesis_hash = genesis_block(network).header.block_hash();
+    if let Ok(serialized) = fs::read(path) {
+        // The graph is written as a two byte version prefix followed by its chain's genesis hash.
+        if serialized.len() >= 34 && serialized[2..34] != genesis_hash[..] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The network graph at {} is not for {}",
+                    path.display(),
+                    network
+                ),
+            ));
+        }
+        if let Ok(graph) = NetworkGraph::read(&mut &serialized[..], logger.clone()) {
+            return Ok(graph);
         }
     }
-    NetworkGraph::new(Network::Regtest, logger)
+    Ok(NetworkGraph::new(network, logger))
 }
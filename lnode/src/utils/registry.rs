@@ -0,0 +1,132 @@
+//! The custom message types apps register to receive, persisted so they survive restarts.
+
+use crate::utils::json_file;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::Mutex;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct RegistryFile<T: Ord> {
+    #[serde(default = "BTreeSet::new")]
+    types: BTreeSet<T>,
+}
+
+pub struct TypeRegistry<T> {
+    types: Mutex<BTreeSet<T>>,
+    path: String,
+    /// Rejects the types that can't be registered.
+    check: fn(T) -> Result<(), String>,
+}
+
+impl<T: Copy + Ord + Serialize + DeserializeOwned> TypeRegistry<T> {
+    /// Loads the types registered in `{ldk_data_dir}/{file}`, none if it doesn't exist or is
+    /// invalid.
+    pub fn load(ldk_data_dir: &str, file: &str, check: fn(T) -> Result<(), String>) -> Self {
+        let path = format!("{}/{}", ldk_data_dir, file);
+        let types = json_file::load::<RegistryFile<T>>(
+            ldk_data_dir,
+            file,
+            &path,
+            "no custom types are registered",
+        )
+        .map(|registered| registered.types)
+        .unwrap_or_default();
+        Self {
+            types: Mutex::new(types),
+            path,
+            check,
+        }
+    }
+
+    pub fn contains(&self, ty: T) -> bool {
+        self.types.lock().unwrap().contains(&ty)
+    }
+
+    pub fn types(&self) -> Vec<T> {
+        self.types.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn save(&self, types: BTreeSet<T>) -> Result<(), String> {
+        let registered =
+            serde_json::to_string_pretty(&RegistryFile { types }).map_err(|e| e.to_string())?;
+        fs::write(&self.path, registered).map_err(|e| e.to_string())
+    }
+
+    /// Registers `ty`, returning false if it already was.
+    pub fn register(&self, ty: T) -> Result<bool, String> {
+        (self.check)(ty)?;
+        let mut types = self.types.lock().unwrap();
+        if types.contains(&ty) {
+            return Ok(false);
+        }
+        let mut registered = types.clone();
+        registered.insert(ty);
+        self.save(registered.clone())?;
+        *types = registered;
+        Ok(true)
+    }
+
+    /// Unregisters `ty`, returning false if it wasn't registered.
+    pub fn unregister(&self, ty: T) -> Result<bool, String> {
+        let mut types = self.types.lock().unwrap();
+        if !types.contains(&ty) {
+            return Ok(false);
+        }
+        let mut registered = types.clone();
+        registered.remove(&ty);
+        self.save(registered.clone())?;
+        *types = registered;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::TempDataDir;
+
+    fn odd(ty: u16) -> Result<(), String> {
+        if ty & 1 == 0 {
+            return Err(format!("{} is even", ty));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn registered_types_survive_a_restart() {
+        let dir = TempDataDir::new("registry-restart");
+        let registry = TypeRegistry::load(dir.path(), "types.json", odd);
+        assert_eq!(registry.register(3), Ok(true));
+        assert_eq!(registry.register(3), Ok(false));
+        assert_eq!(registry.register(1), Ok(true));
+        assert!(registry.contains(3));
+        assert!(!registry.contains(5));
+
+        let restarted = TypeRegistry::load(dir.path(), "types.json", odd);
+        assert_eq!(restarted.types(), vec![1, 3]);
+        assert_eq!(restarted.unregister(3), Ok(true));
+        assert_eq!(restarted.unregister(3), Ok(false));
+        assert_eq!(
+            TypeRegistry::load(dir.path(), "types.json", odd).types(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn rejected_types_are_not_registered() {
+        let dir = TempDataDir::new("registry-rejected");
+        let registry = TypeRegistry::load(dir.path(), "types.json", odd);
+        assert!(registry.register(4).is_err());
+        assert!(registry.types().is_empty());
+    }
+
+    #[test]
+    fn failed_saves_change_nothing() {
+        let dir = TempDataDir::new("registry-unwritable");
+        let registry = TypeRegistry::load(&format!("{}/missing", dir.path()), "types.json", odd);
+        assert!(registry.register(3).is_err());
+        assert!(!registry.contains(3));
+    }
+}
@@ -13,6 +13,7 @@ use lightning::util::ser::{Readable, WithoutLength};
 use bitcoin::secp256k1::Secp256k1;
 
 use crate::utils::hex;
+use crate::wallet::BitcoinWallet;
 use crate::CoreLDK;
 use crate::FilesystemLogger;
 use crate::FilesystemPersister;
@@ -31,6 +32,7 @@ pub(crate) const PENDING_SPENDABLE_OUTPUT_DIR: &'static str = "pending_spendable
 pub(crate) async fn periodic_sweep(
 	ldk_data_dir: String, keys_manager: Arc<KeysManager>, logger: Arc<FilesystemLogger>,
 	persister: Arc<FilesystemPersister>, bitcoind_client: Arc<CoreLDK>,
+	bdk_wallet: Arc<BitcoinWallet>,
 ) {
 	// Regularly claim outputs which are exclusively spendable by us and send them to Bitcoin Core.
 	// Note that if you more tightly integrate your wallet with LDK you may not need to do this -
@@ -105,7 +107,14 @@ pub(crate) async fn periodic_sweep(
 					}
 					outputs.push(Readable::read(&mut file).unwrap());
 				}
-				let destination_address = bitcoind_client.get_new_address().await;
+				// Sweep into the BDK wallet, which works whichever chain backend is configured.
+				let destination_address = match bdk_wallet.generate_address() {
+					Ok(address_info) => address_info.address,
+					Err(e) => {
+						lightning::log_error!(logger, "Failed to get a sweep address: {}", e);
+						continue;
+					}
+				};
 				let output_descriptors = &outputs.iter().map(|a| a).collect::<Vec<_>>();
 				let tx_feerate =
 					bitcoind_client.get_est_sat_per_1000_weight(ConfirmationTarget::Background);
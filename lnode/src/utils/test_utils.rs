@@ -0,0 +1,32 @@
+//! Helpers shared by the tests.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A fresh data directory for a test, removed when dropped.
+pub struct TempDataDir(PathBuf);
+
+impl TempDataDir {
+    /// `name` keeps tests running at once apart.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("lnode-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    /// Writes `contents` to `file` in the directory.
+    pub fn write(&self, file: &str, contents: &str) {
+        fs::write(self.0.join(file), contents).unwrap();
+    }
+}
+
+impl Drop for TempDataDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
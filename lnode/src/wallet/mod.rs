@@ -1,35 +1,45 @@
 use bdk::bitcoin::secp256k1::Secp256k1;
-use bdk::bitcoin::util::bip32::{DerivationPath, KeySource};
 use bdk::bitcoin::Network;
 use bdk::bitcoincore_rpc::bitcoincore_rpc_json::{
     FundRawTransactionResult, GetBalancesResult, GetWalletInfoResult, SignRawTransactionResult,
 };
 use bdk::bitcoincore_rpc::{RawTx, RpcApi};
-use bdk::blockchain::rpc::{Auth, RpcBlockchain, RpcConfig};
+use bdk::blockchain::any::AnyBlockchain;
+use bdk::blockchain::rpc::RpcBlockchain;
 use bdk::blockchain::Blockchain;
 use bdk::blockchain::ConfigurableBlockchain;
+use bdk::blockchain::GetHeight;
 use bdk::database::{BatchDatabase, SqliteDatabase};
-use bdk::keys::bip39::{Language, Mnemonic, WordCount};
-use bdk::keys::DescriptorKey::Secret;
-use bdk::keys::{DerivableKey, DescriptorKey, ExtendedKey, GeneratableKey, GeneratedKey};
-use bdk::miniscript::miniscript::Segwitv0;
+use bdk::keys::bip39::Mnemonic;
+use bdk::keys::{DerivableKey, ExtendedKey};
 use bdk::template::Bip84;
 use bdk::wallet::coin_selection::BranchAndBoundCoinSelection;
 use bdk::wallet::tx_builder::CreateTx;
 use bdk::wallet::{wallet_name_from_descriptor, AddressIndex};
 use bdk::wallet::{AddressInfo, SyncOptions};
-use bdk::{sled, SignOptions, TransactionDetails};
+use bdk::{sled, FeeRate, KeychainKind, SignOptions, TransactionDetails};
 use bdk::{TxBuilder, Wallet};
 use bitcoin::psbt::{PartiallySignedTransaction, Psbt};
 use bitcoin::util::bip32::ExtendedPrivKey;
-use bitcoin::{Address, Amount, Transaction, Txid};
+use bitcoin::{Address, Amount, OutPoint, Script, Transaction, Txid};
 use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
+use wallet_descriptors::{
+    store_descriptors, store_keys, stored_descriptors, xpub_descriptors, WalletKeys,
+};
+use wallet_history::labels::{LabelStore, LabelType};
+use wallet_history::{HistoryPage, HistoryQuery, LightningRecords};
+
+use crate::chain_source::ChainBackendConfig;
+
+pub use wallet_descriptors::{multisig_descriptors, ScriptType};
 
 const MIN_FEERATE: u32 = 253;
 
@@ -52,25 +62,33 @@ pub enum Target {
     HighPriority,
 }
 
-#[derive(Debug)]
 pub struct BitcoinRPC {
-    client: RpcBlockchain,
+    client: AnyBlockchain,
 }
 
 impl BitcoinRPC {
-    fn new(wallet_name: &str) -> Self {
-        let blockchain: RpcBlockchain = RpcBlockchain::from_config(&RpcConfig {
-            url: "http://127.0.0.1:18443".to_string(),
-            auth: Auth::UserPass {
-                username: "admin".to_string(),
-                password: "password".to_string(),
-            },
-            network: Network::Regtest,
-            wallet_name: wallet_name.to_string(),
-            sync_params: None,
-        })
-        .unwrap();
-        Self { client: blockchain }
+    fn new(wallet_name: &str) -> Result<Self, bdk::Error> {
+        let client = ChainBackendConfig::load()
+            .map_err(bdk::Error::Generic)?
+            .wallet_blockchain(wallet_name, Network::Regtest)?;
+        Ok(Self { client })
+    }
+
+    /// bitcoind itself, for the calls only its wallet can answer.
+    fn core(&self) -> Result<&RpcBlockchain, bdk::bitcoincore_rpc::Error> {
+        match &self.client {
+            AnyBlockchain::Rpc(client) => Ok(client),
+            #[allow(unreachable_patterns)]
+            _ => Err(bdk::bitcoincore_rpc::Error::ReturnedError(
+                "This call needs the bitcoind chain backend".to_string(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Debug for BitcoinRPC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BitcoinRPC").finish_non_exhaustive()
     }
 }
 
@@ -80,6 +98,20 @@ pub struct BitcoinWallet {
     pub wallet_name: String,
     pub fees: Arc<HashMap<Target, AtomicU32>>,
     pub inner: Mutex<bdk::Wallet<SqliteDatabase>>,
+    /// Coins picked by the user for channels that are still waiting for their funding
+    /// transaction, keyed by `user_channel_id`.
+    pub channel_utxos: Mutex<HashMap<u128, Vec<OutPoint>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UtxoInfo {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub confirmations: u32,
+    pub keychain: KeychainKind,
+    pub address: Option<String>,
+    pub label: Option<String>,
+    pub frozen: bool,
 }
 
 impl BitcoinWallet {
@@ -95,12 +127,12 @@ impl BitcoinWallet {
         outputs: HashMap<String, Amount>,
     ) -> Result<Transaction, bdk::bitcoincore_rpc::Error> {
         self.rpc
-            .client
+            .core()?
             .create_raw_transaction(&[], &outputs, None, None)
     }
 
     pub fn send_raw_tx<R: RawTx>(&self, tx: R) -> Result<Txid, bdk::bitcoincore_rpc::Error> {
-        self.rpc.client.send_raw_transaction(tx)
+        self.rpc.core()?.send_raw_transaction(tx)
     }
 
     pub fn sign_raw_tx<R: RawTx>(
@@ -108,7 +140,7 @@ impl BitcoinWallet {
         tx: R,
     ) -> Result<SignRawTransactionResult, bdk::bitcoincore_rpc::Error> {
         self.rpc
-            .client
+            .core()?
             .sign_raw_transaction_with_wallet(tx, None, None)
     }
 
@@ -128,19 +160,20 @@ impl BitcoinWallet {
             // change address or to a new channel output negotiated with the same node.
             "replaceable": false,
         });
-        self.rpc.client.fund_raw_transaction(tx, None, None)
+        self.rpc.core()?.fund_raw_transaction(tx, None, None)
     }
 
     pub fn wallet_info(&self) -> Result<GetWalletInfoResult, bdk::bitcoincore_rpc::Error> {
-        self.rpc.client.get_wallet_info()
+        self.rpc.core()?.get_wallet_info()
     }
 
     pub fn specific_wallet_info(
         wallet_name: &str,
     ) -> Result<GetWalletInfoResult, bdk::bitcoincore_rpc::Error> {
-        let wallet_rpc = BitcoinRPC::new(&wallet_name);
+        let wallet_rpc = BitcoinRPC::new(wallet_name)
+            .map_err(|e| bdk::bitcoincore_rpc::Error::ReturnedError(e.to_string()))?;
 
-        wallet_rpc.client.get_wallet_info()
+        wallet_rpc.core()?.get_wallet_info()
     }
     // pub fn specific_generate_address(
     //     wallet_name: &str,
@@ -151,11 +184,16 @@ impl BitcoinWallet {
     // }
 
     pub fn get_balances(&self) -> Result<GetBalancesResult, bdk::bitcoincore_rpc::Error> {
-        self.rpc.client.get_balances()
+        self.rpc.core()?.get_balances()
+    }
+
+    /// The balance of the wallet's own coins, as of its last sync.
+    pub fn balance(&self) -> Result<bdk::Balance, bdk::Error> {
+        self.inner.lock().unwrap().get_balance()
     }
 
     pub fn list_wallets(&self) -> Result<Vec<std::string::String>, bdk::bitcoincore_rpc::Error> {
-        self.rpc.client.list_wallets()
+        self.rpc.core()?.list_wallets()
     }
 
     pub fn generate_address(&self) -> Result<AddressInfo, bdk::Error> {
@@ -168,7 +206,10 @@ impl BitcoinWallet {
     //     .finish()
     //     .unwrap();
 
-    pub fn sign_psbt(&self, mut psbt: PartiallySignedTransaction) {
+    pub fn sign_psbt(
+        &self,
+        mut psbt: PartiallySignedTransaction,
+    ) -> Result<(PartiallySignedTransaction, bool), bdk::Error> {
         let wallet = self.inner.lock().unwrap();
 
         // Set signing option
@@ -177,44 +218,240 @@ impl BitcoinWallet {
             ..Default::default()
         };
 
-        // Sign the above psbt with signing option
-
-        let res = wallet.sign(&mut psbt, signopt).unwrap();
-        match res {
-            true => println!("Transaction is fully signed"),
-            false => println!("Transaction is not fully signed"),
-        }
-
-        // Extract the final transaction
-        let tx = psbt.extract_tx();
-        dbg!(&tx.version);
+        // Sign the above psbt with signing option. Watch-only wallets have no signers, so the
+        // psbt is returned untouched and can be handed to an external signer.
+        let finalized = wallet.sign(&mut psbt, signopt)?;
+        Ok((psbt, finalized))
+    }
 
-        // Broadcast the transaction
+    pub fn finalize_psbt(
+        &self,
+        mut psbt: PartiallySignedTransaction,
+    ) -> Result<(PartiallySignedTransaction, bool), bdk::Error> {
+        let wallet = self.inner.lock().unwrap();
+        let finalized = wallet.finalize_psbt(&mut psbt, SignOptions::default())?;
+        Ok((psbt, finalized))
+    }
 
-        match self.rpc.client.broadcast(&tx) {
-            Ok(_txid) => println!("Transaction sent with txid"),
-            Err(e) => println!("Error: {}", e),
+    /// Broadcasts a psbt, finalizing the inputs that carry their signatures but aren't final yet.
+    pub fn broadcast_psbt(&self, psbt: PartiallySignedTransaction) -> Result<Txid, bdk::Error> {
+        let psbt = match unfinalized_input(&psbt) {
+            Some(_) => self.finalize_psbt(psbt)?.0,
+            None => psbt,
+        };
+        if let Some(index) = unfinalized_input(&psbt) {
+            return Err(bdk::Error::Generic(format!(
+                "PSBT is not fully signed, input {} can't be finalized",
+                index
+            )));
         }
+        // Extract the final transaction and broadcast it
+        let tx = psbt.extract_tx();
+        self.rpc.client.broadcast(&tx)?;
+        Ok(tx.txid())
     }
 
-    pub fn send_tx(&self, recipient: Address, amount: u64) {
-        let psbt = self.create_psbt(recipient, amount).unwrap();
-        dbg!(&psbt);
-        self.sign_psbt(psbt); // sign and broadcast
+    pub fn send_tx(
+        &self,
+        recipient: Address,
+        amount: u64,
+        utxos: Option<Vec<OutPoint>>,
+    ) -> Result<Txid, bdk::Error> {
+        let psbt = self.create_psbt(vec![(recipient, amount)], None, utxos)?;
+        let (psbt, finalized) = self.sign_psbt(psbt)?;
+        if !finalized {
+            return Err(bdk::Error::Generic(
+                "Transaction is not fully signed".to_string(),
+            ));
+        }
+        self.broadcast_psbt(psbt)
     }
 
+    /// Builds an unsigned transaction paying `recipients`. Frozen coins are never selected, and
+    /// when `utxos` is given only those coins are spent.
     pub fn create_psbt(
         &self,
-        recipient: Address,
-        amount: u64,
+        recipients: Vec<(Address, u64)>,
+        fee_rate: Option<f32>,
+        utxos: Option<Vec<OutPoint>>,
     ) -> Result<PartiallySignedTransaction, bdk::Error> {
+        let frozen = self.frozen_utxos()?;
         let wallet = self.inner.lock().unwrap();
         let mut tx_builder = wallet.build_tx();
-        tx_builder.set_recipients(vec![(recipient.script_pubkey(), amount)]);
-        let (psbt, _) = tx_builder.finish().unwrap();
+        tx_builder.set_recipients(
+            recipients
+                .into_iter()
+                .map(|(address, amount)| (address.script_pubkey(), amount))
+                .collect(),
+        );
+        if let Some(sat_per_vb) = fee_rate {
+            tx_builder.fee_rate(FeeRate::from_sat_per_vb(sat_per_vb));
+        }
+        Self::select_coins(&mut tx_builder, frozen, utxos)?;
+        let (psbt, _) = tx_builder.finish()?;
         Ok(psbt)
     }
 
+    fn select_coins(
+        tx_builder: &mut TxBuilder<'_, SqliteDatabase, BranchAndBoundCoinSelection, CreateTx>,
+        frozen: Vec<OutPoint>,
+        utxos: Option<Vec<OutPoint>>,
+    ) -> Result<(), bdk::Error> {
+        if let Some(utxos) = utxos {
+            if let Some(outpoint) = utxos.iter().find(|o| frozen.contains(o)) {
+                return Err(bdk::Error::Generic(format!("Coin {} is frozen", outpoint)));
+            }
+            tx_builder.add_utxos(&utxos)?.manually_selected_only();
+        }
+        tx_builder.unspendable(frozen);
+        Ok(())
+    }
+
+    /// Builds and signs the funding transaction of a channel paying `output_script`, honouring
+    /// frozen coins and any coins reserved for `user_channel_id` with `reserve_channel_utxos`.
+    /// The reservation is only released once the transaction is signed.
+    pub fn fund_channel(
+        &self,
+        output_script: Script,
+        channel_value_satoshis: u64,
+        user_channel_id: u128,
+    ) -> Result<Transaction, bdk::Error> {
+        let utxos = self
+            .channel_utxos
+            .lock()
+            .unwrap()
+            .get(&user_channel_id)
+            .cloned();
+        let frozen = self.frozen_utxos()?;
+        self.sync_wallet()?;
+        let wallet = self.inner.lock().unwrap();
+        let mut tx_builder = wallet.build_tx();
+        tx_builder
+            .add_recipient(output_script, channel_value_satoshis)
+            // LDK gives us feerates in satoshis per KW, bdk expects satoshis per vB.
+            .fee_rate(FeeRate::from_sat_per_vb(
+                self.get_est_sat_per_1000_weight(ConfirmationTarget::Normal) as f32 / 250.0,
+            ));
+        Self::select_coins(&mut tx_builder, frozen, utxos)?;
+        let (mut psbt, _) = tx_builder.finish()?;
+        if !wallet.sign(&mut psbt, SignOptions::default())? {
+            return Err(bdk::Error::Generic(
+                "Funding transaction is not fully signed".to_string(),
+            ));
+        }
+        self.channel_utxos.lock().unwrap().remove(&user_channel_id);
+        Ok(psbt.extract_tx())
+    }
+
+    pub fn reserve_channel_utxos(&self, user_channel_id: u128, utxos: Vec<OutPoint>) {
+        self.channel_utxos
+            .lock()
+            .unwrap()
+            .insert(user_channel_id, utxos);
+    }
+
+    pub fn list_utxos(&self) -> Result<Vec<UtxoInfo>, bdk::Error> {
+        let labels = self.labels()?;
+        let tip_height = self.rpc.client.get_height()?;
+        let wallet = self.inner.lock().unwrap();
+        let mut utxos = vec![];
+        for utxo in wallet.list_unspent()? {
+            let confirmations = match wallet.get_tx(&utxo.outpoint.txid, false)? {
+                Some(TransactionDetails {
+                    confirmation_time: Some(block_time),
+                    ..
+                }) => tip_height.saturating_sub(block_time.height) + 1,
+                _ => 0,
+            };
+            utxos.push(UtxoInfo {
+                outpoint: utxo.outpoint,
+                value: utxo.txout.value,
+                confirmations,
+                keychain: utxo.keychain,
+                address: Address::from_script(&utxo.txout.script_pubkey, Network::Regtest)
+                    .ok()
+                    .map(|a| a.to_string()),
+                label: labels
+                    .get(LabelType::Output, &utxo.outpoint.to_string())
+                    .and_then(|l| l.label.clone()),
+                frozen: labels.is_frozen(&utxo.outpoint),
+            });
+        }
+        Ok(utxos)
+    }
+
+    /// Returns a page of the wallet's transactions, categorising the ones that belong to the
+    /// lightning channels in `records`.
+    pub fn history(
+        &self,
+        query: &HistoryQuery,
+        records: &LightningRecords,
+    ) -> Result<HistoryPage, bdk::Error> {
+        let labels = self.labels()?;
+        let txs = self.inner.lock().unwrap().list_transactions(true)?;
+        Ok(wallet_history::history(&txs, &labels, records, query))
+    }
+
+    pub fn labels(&self) -> Result<LabelStore, bdk::Error> {
+        let mut path = dirs_next::home_dir().unwrap();
+        path.push(".bdk-example");
+        path.push(format!("{}.labels.jsonl", self.wallet_name));
+        LabelStore::load(&path).map_err(|e| bdk::Error::Generic(e.to_string()))
+    }
+
+    pub fn frozen_utxos(&self) -> Result<Vec<OutPoint>, bdk::Error> {
+        Ok(self.labels()?.frozen_outpoints())
+    }
+
+    pub fn set_frozen(&self, outpoint: &OutPoint, frozen: bool) -> Result<(), bdk::Error> {
+        self.labels()?
+            .set_spendable(outpoint, !frozen)
+            .map_err(|e| bdk::Error::Generic(e.to_string()))
+    }
+
+    pub fn set_label(
+        &self,
+        label_type: LabelType,
+        reference: &str,
+        label: String,
+    ) -> Result<(), bdk::Error> {
+        self.labels()?
+            .set_label(label_type, reference, label)
+            .map_err(|e| bdk::Error::Generic(e.to_string()))
+    }
+
+    /// Merges BIP329 labels into this wallet and returns how many records were read.
+    pub fn import_labels(&self, jsonl: &str) -> Result<usize, bdk::Error> {
+        let mut labels = self.labels()?;
+        let count = labels
+            .import(jsonl)
+            .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+        labels
+            .persist()
+            .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+        Ok(count)
+    }
+
+    pub fn export_labels(&self) -> Result<String, bdk::Error> {
+        Ok(self.labels()?.export())
+    }
+
+    /// Merges the signatures and metadata of several copies of the same psbt, e.g. the ones
+    /// returned by different hardware signers.
+    pub fn combine_psbts(
+        psbts: Vec<PartiallySignedTransaction>,
+    ) -> Result<PartiallySignedTransaction, bdk::Error> {
+        let mut psbts = psbts.into_iter();
+        let mut combined = match psbts.next() {
+            Some(psbt) => psbt,
+            None => return Err(bdk::Error::Generic("No psbt to combine".to_string())),
+        };
+        for psbt in psbts {
+            combined.combine(psbt)?;
+        }
+        Ok(combined)
+    }
+
     pub fn generate_to_address(
         &self,
         count: u64,
@@ -222,7 +459,7 @@ impl BitcoinWallet {
         let address_info = self.generate_address().unwrap();
         let hashes = self
             .rpc
-            .client
+            .core()?
             .generate_to_address(count, &address_info.address);
         self.sync_wallet().unwrap();
         hashes
@@ -237,7 +474,7 @@ impl BitcoinWallet {
         db_tree
     }
 
-    pub fn load_with_mmc(mnemonic: String) -> Self {
+    pub fn load_with_mmc(mnemonic: String) -> Result<Self, bdk::Error> {
         let xkey: ExtendedKey = Mnemonic::from_str(&mnemonic)
             .unwrap()
             .into_extended_key()
@@ -252,12 +489,12 @@ impl BitcoinWallet {
         )
         .expect("Failed to derive on-chain wallet name");
         // let bdk_data_dir = format!("{}/bdk", "./ldk_node");
-        let mut datadir = dirs_next::home_dir().unwrap();
-        let mut file = File::create(format!("/home/ecode/.bdk-example/{}", &wallet_name)).unwrap();
+        let mut datadir = Self::wallet_dir();
+        fs::create_dir_all(&datadir).map_err(|e| bdk::Error::Generic(e.to_string()))?;
+        let mut file = File::create(datadir.join(&wallet_name)).unwrap();
         file.write_all(mnemonic.as_bytes()).unwrap();
 
         let database_path = format!("{}.sqlite", wallet_name);
-        datadir.push(".bdk-example");
         datadir.push(database_path.clone());
         let database = SqliteDatabase::new(datadir);
 
@@ -273,92 +510,133 @@ impl BitcoinWallet {
         fees.insert(Target::Normal, AtomicU32::new(2000));
         fees.insert(Target::HighPriority, AtomicU32::new(5000));
 
-        Self {
-            rpc: BitcoinRPC::new(&wallet_name),
+        Ok(Self {
+            rpc: BitcoinRPC::new(&wallet_name)?,
             wallet_name: wallet_name.to_string(),
             inner: Mutex::new(bdk_wallet),
             fees: Arc::new(fees),
-        }
+            channel_utxos: Mutex::new(HashMap::new()),
+        })
     }
     // Initialize the on-chain wallet and chain access
 
-    pub fn load_by_wallet_name(wallet_name: String) -> Self {
-        let mut datadir = dirs_next::home_dir().unwrap();
-        let database_path = format!("{}.sqlite", wallet_name);
-        datadir.push(".bdk-example");
-        datadir.push(database_path.clone());
-        let database = SqliteDatabase::new(datadir);
-        let mut file = match File::open(format!("/home/ecode/.bdk-example/{}", &wallet_name)) {
-            Ok(file) => file,
-            Err(_) => panic!("Wallet is not found"),
+    /// Reloads a wallet created, restored or imported earlier. Wallets that were given private keys
+    /// come back able to sign; only watch-only imports come back watch-only.
+    pub fn load_by_wallet_name(wallet_name: String) -> Result<Self, bdk::Error> {
+        let (descriptor, change_descriptor) =
+            stored_descriptors(&Self::wallet_dir(), &wallet_name, Network::Regtest)?;
+        Self::open(descriptor, change_descriptor)
+    }
+    // Initialize the on-chain wallet and chain access
+
+    /// Loads a wallet from arbitrary output descriptors. Descriptors holding only public keys give
+    /// a watch-only wallet that can track funds and build psbts. The descriptors are persisted
+    /// next to the wallet database so that `load_by_wallet_name` finds them again.
+    pub fn load_with_descriptors(
+        descriptor: String,
+        change_descriptor: Option<String>,
+    ) -> Result<Self, bdk::Error> {
+        let wallet_name = wallet_name_from_descriptor(
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
+            Network::Regtest,
+            &Secp256k1::new(),
+        )?;
+        store_descriptors(
+            &Self::wallet_dir(),
+            &wallet_name,
+            &descriptor,
+            change_descriptor.as_deref(),
+            Network::Regtest,
+        )?;
+        Self::open(descriptor, change_descriptor)
+    }
+
+    /// Restores a single-sig wallet from a 12 or 24 word mnemonic and an optional BIP39
+    /// passphrase, using the derivation scheme of `script_type`.
+    pub fn restore_from_mnemonic(
+        mnemonic: &str,
+        passphrase: Option<String>,
+        script_type: ScriptType,
+    ) -> Result<Self, bdk::Error> {
+        let keys = WalletKeys::Mnemonic {
+            mnemonic: mnemonic.to_string(),
+            passphrase,
+            script_type,
         };
-        let mut mnemonic = String::new();
-        file.read_to_string(&mut mnemonic).unwrap();
-        let xkey: ExtendedKey = Mnemonic::from_str(&mnemonic)
-            .unwrap()
-            .into_extended_key()
-            .unwrap();
-        let xprv: ExtendedPrivKey = xkey.into_xprv(Network::Regtest).unwrap();
+        let (descriptor, change_descriptor) = keys.descriptors(Network::Regtest)?;
+        let wallet_name = wallet_name_from_descriptor(
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
+            Network::Regtest,
+            &Secp256k1::new(),
+        )?;
+        store_keys(&Self::wallet_dir(), &wallet_name, &keys)?;
+        Self::open(descriptor, change_descriptor)
+    }
+
+    fn open(descriptor: String, change_descriptor: Option<String>) -> Result<Self, bdk::Error> {
+        let wallet_name = wallet_name_from_descriptor(
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
+            Network::Regtest,
+            &Secp256k1::new(),
+        )?;
+        let database =
+            SqliteDatabase::new(Self::wallet_dir().join(format!("{}.sqlite", wallet_name)));
         let bdk_wallet = bdk::Wallet::new(
-            Bip84(xprv, bdk::KeychainKind::External),
-            Some(Bip84(xprv, bdk::KeychainKind::Internal)),
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
             Network::Regtest,
             database,
-        )
-        .expect("Failed to set up on-chain wallet");
+        )?;
         let mut fees: HashMap<Target, AtomicU32> = HashMap::new();
         fees.insert(Target::Background, AtomicU32::new(MIN_FEERATE));
         fees.insert(Target::Normal, AtomicU32::new(2000));
         fees.insert(Target::HighPriority, AtomicU32::new(5000));
 
-        Self {
-            rpc: BitcoinRPC::new(&wallet_name),
-            wallet_name: wallet_name.to_string(),
+        Ok(Self {
+            rpc: BitcoinRPC::new(&wallet_name)?,
+            wallet_name,
             inner: Mutex::new(bdk_wallet),
             fees: Arc::new(fees),
-        }
+            channel_utxos: Mutex::new(HashMap::new()),
+        })
     }
-    // Initialize the on-chain wallet and chain access
 
-    fn generate_descx(mnemonic: Option<String>) -> (String, String) {
-        let secp = Secp256k1::new();
-        // let passphrase = None;
-        let xkey: ExtendedKey = match mnemonic {
-            Some(mnemonic) => Mnemonic::from_str(&mnemonic)
-                .unwrap()
-                .into_extended_key()
-                .unwrap(),
-            None => Mnemonic::generate((WordCount::Words12, Language::English))
-                .unwrap()
-                .into_extended_key()
-                .unwrap(),
-        };
+    /// Returns the receive and change descriptors with every private key stripped, which is
+    /// enough to rebuild this wallet as watch-only elsewhere.
+    pub fn export_descriptors(&self) -> Result<(String, Option<String>), bdk::Error> {
+        let wallet = self.inner.lock().unwrap();
+        let descriptor = wallet
+            .public_descriptor(bdk::KeychainKind::External)?
+            .map(|d| d.to_string())
+            .unwrap_or_default();
+        let change_descriptor = wallet
+            .public_descriptor(bdk::KeychainKind::Internal)?
+            .map(|d| d.to_string());
+        Ok((descriptor, change_descriptor))
+    }
 
-        // println!("Wallet  mnemonic: {:#}", &mnemonic.to_string());
-        // let xkey: ExtendedKey = (mnemonic, passphrase).into_extended_key().unwrap();
-        let xprv: ExtendedPrivKey = xkey.into_xprv(Network::Regtest).unwrap();
-        // Create derived privkey from the above master privkey
-        // We use the following derivation paths for receive and change keys
-        // receive: "m/84h/1h/0h/0"
-        // change: "m/84h/1h/0h/1"
-        let mut keys = Vec::new();
-        for path in ["m/84h/1h/0h/0", "m/84h/1h/0h/1"] {
-            let deriv_path: DerivationPath = DerivationPath::from_str(path).unwrap();
-            let derived_xprv = &xprv.derive_priv(&secp, &deriv_path).unwrap();
-            let origin: KeySource = (xprv.fingerprint(&secp), deriv_path);
-            let derived_xprv_desc_key: DescriptorKey<Segwitv0> = derived_xprv
-                .into_descriptor_key(Some(origin), DerivationPath::default())
-                .unwrap();
-            // Wrap the derived key with the wpkh() string to produce a descriptor string
-            if let Secret(key, _, _) = derived_xprv_desc_key {
-                let mut desc = "wpkh(".to_string();
-                desc.push_str(&key.to_string());
-                desc.push_str(")");
-                keys.push(desc);
-            }
-        }
-        (keys[0].clone(), keys[1].clone())
+    /// Builds a watch-only BIP84 wallet from an account-level extended public key.
+    pub fn load_with_xpub(xpub: &str) -> Result<Self, bdk::Error> {
+        let (descriptor, change_descriptor) = xpub_descriptors(xpub)?;
+        Self::load_with_descriptors(descriptor, Some(change_descriptor))
     }
+
+    /// Where the wallet databases and what they are reloaded from live.
+    fn wallet_dir() -> PathBuf {
+        let mut path = dirs_next::home_dir().unwrap();
+        path.push(".bdk-example");
+        path
+    }
+}
+
+/// The first input of `psbt` without a final scriptSig or witness.
+fn unfinalized_input(psbt: &PartiallySignedTransaction) -> Option<usize> {
+    psbt.inputs
+        .iter()
+        .position(|input| input.final_script_sig.is_none() && input.final_script_witness.is_none())
 }
 
 impl FeeEstimator for BitcoinWallet {
@@ -395,7 +673,7 @@ mod tests {
         let mmc2: &str =
             "winner maid tower wrong rebuild list net amused okay turtle shrimp swallow";
         // let w1 = BitcoinWallet::load_with_mmc(mmc1.to_string());
-        let w1 = BitcoinWallet::load_by_wallet_name("7a096s3m0f2y89pr".to_string());
+        let w1 = BitcoinWallet::load_by_wallet_name("7a096s3m0f2y89pr".to_string()).unwrap();
         // w2.sync_wallet().unwrap();
         // let w2_address = w2.generate_address().unwrap();
         // w1.send_tx(w2_address.address, 1000);
@@ -409,6 +687,22 @@ mod tests {
         assert!(w1.wallet_name.len() > 0);
         // assert!(w2.wallet_name.len() > 0);
     }
+
+    #[test]
+    fn unsigned_inputs_are_not_final() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime::ZERO,
+            input: vec![bitcoin::TxIn::default(), bitcoin::TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(unfinalized_input(&psbt), Some(0));
+        psbt.inputs[0].final_script_witness = Some(bitcoin::Witness::from_vec(vec![vec![1]]));
+        assert_eq!(unfinalized_input(&psbt), Some(1));
+        psbt.inputs[1].final_script_sig = Some(Script::new());
+        assert_eq!(unfinalized_input(&psbt), None);
+    }
 }
 
 // pub fn get_wallet(&self) -> Wallet<sled::Tree> {
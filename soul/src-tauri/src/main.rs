@@ -6,12 +6,13 @@ pub mod mmc;
 pub mod wallet;
 
 use bdk::{
-    bitcoincore_rpc::bitcoincore_rpc_json::GetWalletInfoResult, wallet::AddressInfo,
-    TransactionDetails,
+    bitcoincore_rpc::bitcoincore_rpc_json::GetWalletInfoResult, keys::bip39::WordCount,
+    wallet::AddressInfo, TransactionDetails,
 };
 use bitcoin::Address;
 use blockchain::WalletList;
 use tauri::Manager;
+use wallet::{HistoryPage, HistoryQuery, ScriptType};
 
 #[tauri::command]
 async fn list_wallets() -> Result<WalletList, ()> {
@@ -58,6 +59,24 @@ async fn list_txs(wallet_name: String) -> Result<Vec<TransactionDetails>, ()> {
     Ok(res)
 }
 
+#[tauri::command]
+async fn wallet_history(
+    wallet_name: String,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<HistoryPage, String> {
+    let wallet = wallet::BitcoinWallet::load_by_wallet_name(wallet_name);
+    let query = HistoryQuery {
+        offset,
+        limit,
+        from,
+        to,
+    };
+    wallet.history(&query).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn load_wallet_with_mmc(mmc: String) -> Result<(), ()> {
     wallet::BitcoinWallet::load_with_mmc(mmc.clone());
@@ -65,8 +84,54 @@ async fn load_wallet_with_mmc(mmc: String) -> Result<(), ()> {
 }
 
 #[tauri::command]
-async fn new_mmc() -> String {
-    mmc::generate_mnemonic()
+async fn restore_wallet(
+    mmc: String,
+    passphrase: Option<String>,
+    script_type: Option<ScriptType>,
+) -> Result<String, String> {
+    let wallet = wallet::BitcoinWallet::restore_from_mnemonic(
+        &mmc,
+        passphrase,
+        script_type.unwrap_or(ScriptType::Bip84),
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(wallet.wallet_name)
+}
+
+#[tauri::command]
+async fn import_descriptors(
+    descriptor: String,
+    change_descriptor: Option<String>,
+) -> Result<String, String> {
+    let wallet = wallet::BitcoinWallet::load_with_descriptors(descriptor, change_descriptor)
+        .map_err(|e| e.to_string())?;
+    Ok(wallet.wallet_name)
+}
+
+#[tauri::command]
+async fn import_multisig(threshold: usize, keys: Vec<String>) -> Result<String, String> {
+    if threshold == 0 || threshold > keys.len() {
+        return Err("Threshold must be between 1 and the number of keys".to_string());
+    }
+    let (descriptor, change_descriptor) = wallet::multisig_descriptors(threshold, &keys);
+    let wallet = wallet::BitcoinWallet::load_with_descriptors(descriptor, Some(change_descriptor))
+        .map_err(|e| e.to_string())?;
+    Ok(wallet.wallet_name)
+}
+
+#[tauri::command]
+async fn export_descriptors(wallet_name: String) -> Result<(String, Option<String>), String> {
+    let wallet = wallet::BitcoinWallet::load_by_wallet_name(wallet_name.clone());
+    wallet.export_descriptors().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn new_mmc(words: Option<usize>) -> Result<String, String> {
+    match words {
+        None | Some(12) => Ok(mmc::generate_mnemonic(WordCount::Words12)),
+        Some(24) => Ok(mmc::generate_mnemonic(WordCount::Words24)),
+        Some(_) => Err("words must be 12 or 24".to_string()),
+    }
 }
 
 fn main() {
@@ -89,7 +154,12 @@ fn main() {
             generate_to_address,
             send_tx,
             list_txs,
+            wallet_history,
             load_wallet_with_mmc,
+            restore_wallet,
+            import_descriptors,
+            import_multisig,
+            export_descriptors,
             new_mmc
         ])
         .run(tauri::generate_context!())
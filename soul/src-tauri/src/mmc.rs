@@ -2,9 +2,9 @@ use bdk::descriptor::Segwitv0;
 use bdk::keys::bip39::{Language, Mnemonic, WordCount};
 use bdk::keys::{GeneratableKey, GeneratedKey};
 
-pub fn generate_mnemonic() -> String {
+pub fn generate_mnemonic(word_count: WordCount) -> String {
     let mmc: GeneratedKey<Mnemonic, Segwitv0> =
-        Mnemonic::generate((WordCount::Words12, Language::English)).unwrap();
+        Mnemonic::generate((word_count, Language::English)).unwrap();
     mmc.word_iter().collect::<Vec<&str>>().join(" ")
 }
 
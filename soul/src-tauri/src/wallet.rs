@@ -6,6 +6,7 @@ use bdk::bitcoincore_rpc::bitcoincore_rpc_json::{
     FundRawTransactionResult, GetBalancesResult, GetWalletInfoResult, SignRawTransactionResult,
 };
 use bdk::bitcoincore_rpc::{Client, RawTx, RpcApi};
+use bdk::blockchain::any::AnyBlockchain;
 use bdk::blockchain::rpc::{Auth, RpcBlockchain, RpcConfig};
 use bdk::blockchain::ConfigurableBlockchain;
 use bdk::blockchain::{noop_progress, Blockchain};
@@ -21,13 +22,21 @@ use bdk::{sled, KeychainKind, LocalUtxo, SignOptions, TransactionDetails};
 use bitcoin::psbt::PartiallySignedTransaction;
 use bitcoin::util::bip32::ExtendedPrivKey;
 use bitcoin::{Address, Amount, Transaction, Txid};
+use chain_backend::ChainBackendConfig;
 use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
+pub use wallet_descriptors::{multisig_descriptors, ScriptType};
+use wallet_descriptors::{store_descriptors, store_keys, stored_descriptors, WalletKeys};
+use wallet_history::labels::LabelStore;
+use wallet_history::LightningRecords;
+pub use wallet_history::{HistoryPage, HistoryQuery};
 
 const MIN_FEERATE: u32 = 253;
 const NETWORK: Network = Network::Regtest;
@@ -51,9 +60,14 @@ pub enum Target {
     HighPriority,
 }
 
-#[derive(Debug)]
 pub struct BitcoinRPC {
-    rpc: RpcBlockchain,
+    rpc: AnyBlockchain,
+}
+
+impl std::fmt::Debug for BitcoinRPC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BitcoinRPC").finish_non_exhaustive()
+    }
 }
 
 fn _generate_descx(mnemonic: Option<String>) -> (String, String) {
@@ -163,28 +177,23 @@ pub fn tomato() {
 
 impl BitcoinRPC {
     fn new(wallet_name: &str) -> Self {
-        let blockchain: RpcBlockchain = RpcBlockchain::from_config(&RpcConfig {
-            url: "http://127.0.0.1:18443".to_string(),
-            auth: Auth::UserPass {
-                username: "admin".to_string(),
-                password: "password".to_string(),
-            },
-            network: NETWORK,
-            wallet_name: wallet_name.to_string(),
-            sync_params: None,
-        })
-        .unwrap();
-        Self { rpc: blockchain }
+        let rpc = ChainBackendConfig::load()
+            .expect("Invalid chain backend config")
+            .wallet_blockchain(wallet_name, NETWORK)
+            .unwrap();
+        Self { rpc }
     }
 
-    // fn new_esplora(wallet_name: &str) {
-    //     let blockchain: EsploraBlockchain = EsploraBlockchain::from_config(&EsploraConfig {
-    //         base_url: "https://blockstream.info/testnet/api".to_string(),
-    //         network: NETWORK,
-    //         wallet_name: wallet_name.to_string(),
-    //         sync_params: None,
-    //     });
-    // }
+    /// bitcoind itself, for the calls only its wallet can answer.
+    fn core(&self) -> Result<&RpcBlockchain, bdk::bitcoincore_rpc::Error> {
+        match &self.rpc {
+            AnyBlockchain::Rpc(rpc) => Ok(rpc),
+            #[allow(unreachable_patterns)]
+            _ => Err(bdk::bitcoincore_rpc::Error::ReturnedError(
+                "This call needs the bitcoind chain backend".to_string(),
+            )),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -208,12 +217,12 @@ impl BitcoinWallet {
         outputs: HashMap<String, Amount>,
     ) -> Result<Transaction, bdk::bitcoincore_rpc::Error> {
         self.blockchain
-            .rpc
+            .core()?
             .create_raw_transaction(&[], &outputs, None, None)
     }
 
     pub fn send_raw_tx<R: RawTx>(&self, tx: R) -> Result<Txid, bdk::bitcoincore_rpc::Error> {
-        self.blockchain.rpc.send_raw_transaction(tx)
+        self.blockchain.core()?.send_raw_transaction(tx)
     }
 
     pub fn sign_raw_tx<R: RawTx>(
@@ -221,7 +230,7 @@ impl BitcoinWallet {
         tx: R,
     ) -> Result<SignRawTransactionResult, bdk::bitcoincore_rpc::Error> {
         self.blockchain
-            .rpc
+            .core()?
             .sign_raw_transaction_with_wallet(tx, None, None)
     }
 
@@ -241,11 +250,11 @@ impl BitcoinWallet {
             // change address or to a new channel output negotiated with the same node.
             "replaceable": false,
         });
-        self.blockchain.rpc.fund_raw_transaction(tx, None, None)
+        self.blockchain.core()?.fund_raw_transaction(tx, None, None)
     }
 
     pub fn wallet_info(&self) -> Result<GetWalletInfoResult, bdk::bitcoincore_rpc::Error> {
-        self.blockchain.rpc.get_wallet_info()
+        self.blockchain.core()?.get_wallet_info()
     }
 
     pub fn specific_wallet_info(
@@ -253,7 +262,7 @@ impl BitcoinWallet {
     ) -> Result<GetWalletInfoResult, bdk::bitcoincore_rpc::Error> {
         let wallet_rpc = BitcoinRPC::new(&wallet_name);
 
-        wallet_rpc.rpc.get_wallet_info()
+        wallet_rpc.core()?.get_wallet_info()
     }
     // pub fn specific_generate_address(
     //     wallet_name: &str,
@@ -268,7 +277,7 @@ impl BitcoinWallet {
     // }
 
     pub fn list_wallets(&self) -> Result<Vec<std::string::String>, bdk::bitcoincore_rpc::Error> {
-        self.blockchain.rpc.list_wallets()
+        self.blockchain.core()?.list_wallets()
     }
 
     pub fn generate_address(&self) -> Result<AddressInfo, bdk::Error> {
@@ -328,8 +337,8 @@ impl BitcoinWallet {
         let address_info = self.generate_address().unwrap();
         let hashes = self
             .blockchain
-            .rpc
-            .generate_to_address(count, &address_info.address)
+            .core()
+            .and_then(|core| core.generate_to_address(count, &address_info.address))
             .unwrap();
         self.sync_wallet().unwrap();
         hashes
@@ -355,6 +364,25 @@ impl BitcoinWallet {
         Ok(txs)
     }
 
+    /// Returns a page of the wallet's transactions with the labels lnode keeps for them. soul runs
+    /// no lightning node, so none of them is told apart as a channel transaction.
+    pub fn history(&self, query: &HistoryQuery) -> Result<HistoryPage, bdk::Error> {
+        self.sync_wallet()?;
+        let labels = self.labels()?;
+        let txs = self.wallet.lock().unwrap().list_transactions(false)?;
+        Ok(wallet_history::history(
+            &txs,
+            &labels,
+            &LightningRecords::default(),
+            query,
+        ))
+    }
+
+    pub fn labels(&self) -> Result<LabelStore, bdk::Error> {
+        let path = Self::data_dir().join(format!("{}.labels.jsonl", self.wallet_name));
+        LabelStore::load(&path).map_err(|e| bdk::Error::Generic(e.to_string()))
+    }
+
     pub fn list_unspent(&self) -> Result<Vec<LocalUtxo>, bdk::Error> {
         self.sync_wallet().unwrap();
         let utxos = self.wallet.lock().unwrap().list_unspent().unwrap();
@@ -377,12 +405,11 @@ impl BitcoinWallet {
         )
         .expect("Failed to derive on-chain wallet name");
         // let bdk_data_dir = format!("{}/bdk", "./ldk_node");
-        let mut datadir = dirs_next::home_dir().unwrap();
-        let mut file = File::create(format!("/home/ecode/.bdk-example/{}", &wallet_name)).unwrap();
+        let mut datadir = Self::data_dir();
+        let mut file = File::create(datadir.join(&wallet_name)).unwrap();
         file.write_all(mnemonic.as_bytes()).unwrap();
 
         let database_path = format!("{}.sqlite", wallet_name);
-        datadir.push(".bdk-example");
         datadir.push(database_path.clone());
         let database = SqliteDatabase::new(datadir);
 
@@ -407,82 +434,106 @@ impl BitcoinWallet {
     }
     // Initialize the on-chain wallet and chain access
 
+    /// Reloads a wallet created, restored or imported earlier, watch-only only if it was imported
+    /// without private keys.
     pub fn load_by_wallet_name(wallet_name: String) -> Self {
-        let mut datadir = dirs_next::home_dir().unwrap();
-        let database_path = format!("{}.sqlite", wallet_name);
-        datadir.push(".bdk-example");
-        datadir.push(database_path.clone());
-        let database = SqliteDatabase::new(datadir);
-        let mut file = match File::open(format!("/home/ecode/.bdk-example/{}", &wallet_name)) {
-            Ok(file) => file,
-            Err(_) => panic!("Wallet is not found"),
+        let (descriptor, change_descriptor) =
+            match stored_descriptors(&Self::data_dir(), &wallet_name, NETWORK) {
+                Ok(descriptors) => descriptors,
+                Err(_) => panic!("Wallet is not found"),
+            };
+        Self::open(descriptor, change_descriptor).expect("Failed to set up on-chain wallet")
+    }
+    // Initialize the on-chain wallet and chain access
+
+    /// Loads a wallet from arbitrary output descriptors, e.g. taproot or `wsh(sortedmulti(..))`,
+    /// and keeps them next to the wallet database for `load_by_wallet_name`.
+    pub fn load_with_descriptors(
+        descriptor: String,
+        change_descriptor: Option<String>,
+    ) -> Result<Self, bdk::Error> {
+        let wallet_name = wallet_name_from_descriptor(
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
+            NETWORK,
+            &Secp256k1::new(),
+        )?;
+        store_descriptors(
+            &Self::data_dir(),
+            &wallet_name,
+            &descriptor,
+            change_descriptor.as_deref(),
+            NETWORK,
+        )?;
+        Self::open(descriptor, change_descriptor)
+    }
+
+    pub fn restore_from_mnemonic(
+        mnemonic: &str,
+        passphrase: Option<String>,
+        script_type: ScriptType,
+    ) -> Result<Self, bdk::Error> {
+        let keys = WalletKeys::Mnemonic {
+            mnemonic: mnemonic.to_string(),
+            passphrase,
+            script_type,
         };
-        let mut mnemonic = String::new();
-        file.read_to_string(&mut mnemonic).unwrap();
-        let xkey: ExtendedKey = Mnemonic::from_str(&mnemonic)
-            .unwrap()
-            .into_extended_key()
-            .unwrap();
-        let xprv: ExtendedPrivKey = xkey.into_xprv(NETWORK).unwrap();
+        let (descriptor, change_descriptor) = keys.descriptors(NETWORK)?;
+        let wallet_name = wallet_name_from_descriptor(
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
+            NETWORK,
+            &Secp256k1::new(),
+        )?;
+        store_keys(&Self::data_dir(), &wallet_name, &keys)?;
+        Self::open(descriptor, change_descriptor)
+    }
+
+    fn open(descriptor: String, change_descriptor: Option<String>) -> Result<Self, bdk::Error> {
+        let wallet_name = wallet_name_from_descriptor(
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
+            NETWORK,
+            &Secp256k1::new(),
+        )?;
+        let database =
+            SqliteDatabase::new(Self::data_dir().join(format!("{}.sqlite", wallet_name)));
         let bdk_wallet = bdk::Wallet::new(
-            Bip84(xprv, bdk::KeychainKind::External),
-            Some(Bip84(xprv, bdk::KeychainKind::Internal)),
+            descriptor.as_str(),
+            change_descriptor.as_deref(),
             NETWORK,
             database,
-        )
-        .expect("Failed to set up on-chain wallet");
+        )?;
         let mut fees: HashMap<Target, AtomicU32> = HashMap::new();
         fees.insert(Target::Background, AtomicU32::new(MIN_FEERATE));
         fees.insert(Target::Normal, AtomicU32::new(2000));
         fees.insert(Target::HighPriority, AtomicU32::new(5000));
 
-        Self {
+        Ok(Self {
             blockchain: BitcoinRPC::new(&wallet_name),
-            wallet_name: wallet_name.to_string(),
+            wallet_name,
             wallet: Mutex::new(bdk_wallet),
             fees: Arc::new(fees),
-        }
+        })
     }
-    // Initialize the on-chain wallet and chain access
 
-    fn generate_descx(mnemonic: Option<String>) -> (String, String) {
-        let secp = Secp256k1::new();
-        // let passphrase = None;
-        let xkey: ExtendedKey = match mnemonic {
-            Some(mnemonic) => Mnemonic::from_str(&mnemonic)
-                .unwrap()
-                .into_extended_key()
-                .unwrap(),
-            None => Mnemonic::generate((WordCount::Words12, Language::English))
-                .unwrap()
-                .into_extended_key()
-                .unwrap(),
-        };
+    /// Returns the receive and change descriptors without private keys.
+    pub fn export_descriptors(&self) -> Result<(String, Option<String>), bdk::Error> {
+        let wallet = self.wallet.lock().unwrap();
+        let descriptor = wallet
+            .public_descriptor(KeychainKind::External)?
+            .map(|d| d.to_string())
+            .unwrap_or_default();
+        let change_descriptor = wallet
+            .public_descriptor(KeychainKind::Internal)?
+            .map(|d| d.to_string());
+        Ok((descriptor, change_descriptor))
+    }
 
-        // println!("Wallet  mnemonic: {:#}", &mnemonic.to_string());
-        // let xkey: ExtendedKey = (mnemonic, passphrase).into_extended_key().unwrap();
-        let xprv: ExtendedPrivKey = xkey.into_xprv(NETWORK).unwrap();
-        // Create derived privkey from the above master privkey
-        // We use the following derivation paths for receive and change keys
-        // receive: "m/84h/1h/0h/0"
-        // change: "m/84h/1h/0h/1"
-        let mut keys = Vec::new();
-        for path in ["m/84h/1h/0h/0", "m/84h/1h/0h/1"] {
-            let deriv_path: DerivationPath = DerivationPath::from_str(path).unwrap();
-            let derived_xprv = &xprv.derive_priv(&secp, &deriv_path).unwrap();
-            let origin: KeySource = (xprv.fingerprint(&secp), deriv_path);
-            let derived_xprv_desc_key: DescriptorKey<Segwitv0> = derived_xprv
-                .into_descriptor_key(Some(origin), DerivationPath::default())
-                .unwrap();
-            // Wrap the derived key with the wpkh() string to produce a descriptor string
-            if let Secret(key, _, _) = derived_xprv_desc_key {
-                let mut desc = "wpkh(".to_string();
-                desc.push_str(&key.to_string());
-                desc.push_str(")");
-                keys.push(desc);
-            }
-        }
-        (keys[0].clone(), keys[1].clone())
+    fn data_dir() -> PathBuf {
+        let mut path = dirs_next::home_dir().unwrap();
+        path.push(".bdk-example");
+        path
     }
 }
 
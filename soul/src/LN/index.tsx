@@ -7,7 +7,7 @@ export const LightningNodeInfo = ({ nodeInfo }: { nodeInfo: LNodeInfo }) => {
         network,
         port,
         node_name,
-        announced_listen_addr,
+        announce_addresses,
         num_usable_channels,
         num_channels,
         local_balance_msat,
@@ -20,7 +20,7 @@ export const LightningNodeInfo = ({ nodeInfo }: { nodeInfo: LNodeInfo }) => {
             <div>Network: {network}</div>
             <div>Port: {port}</div>
             <div>Node Name: {node_name}</div>
-            <div>Announced Listen Addr: {announced_listen_addr}</div>
+            <div>Announce Addresses: {announce_addresses.join(", ")}</div>
             <div>Num Usable Channels: {num_usable_channels}</div>
             <div>Num Channels: {num_channels}</div>
             <div>Local Balance Msat: {local_balance_msat}</div>
@@ -9,7 +9,7 @@ export interface LNodeInfo {
     network: string;
     port: number;
     node_name: String;
-    announced_listen_addr: String;
+    announce_addresses: string[];
     num_usable_channels: number;
     num_channels: number;
     local_balance_msat: number;
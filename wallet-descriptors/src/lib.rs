@@ -0,0 +1,481 @@
+//! Output descriptors for the wallets lnode and soul restore, import and persist.
+//!
+//! A wallet is reloaded by name from one of two files next to its database. Signing wallets keep
+//! their keys in `<wallet_name>`: the bare mnemonic of a default BIP84 wallet, or [`WalletKeys`]
+//! as JSON for a restored one. Watch-only imports keep their public descriptors in
+//! `<wallet_name>.descriptor`. The keys win when both exist, since a watch-only import and a
+//! restore of the same wallet share its name.
+
+use bdk::bitcoin::secp256k1::Secp256k1;
+use bdk::bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bdk::bitcoin::Network;
+use bdk::descriptor::IntoWalletDescriptor;
+use bdk::keys::bip39::Mnemonic;
+use bdk::keys::{DerivableKey, ExtendedKey};
+use bdk::miniscript::miniscript::Segwitv0;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptType {
+    /// Nested segwit, `sh(wpkh(..))`
+    Bip49,
+    /// Native segwit, `wpkh(..)`
+    Bip84,
+    /// Taproot single key, `tr(..)`
+    Bip86,
+}
+
+impl ScriptType {
+    fn purpose(&self) -> u32 {
+        match self {
+            ScriptType::Bip49 => 49,
+            ScriptType::Bip84 => 84,
+            ScriptType::Bip86 => 86,
+        }
+    }
+
+    fn wrap(&self, key: &str) -> String {
+        match self {
+            ScriptType::Bip49 => format!("sh(wpkh({}))", key),
+            ScriptType::Bip84 => format!("wpkh({})", key),
+            ScriptType::Bip86 => format!("tr({})", key),
+        }
+    }
+}
+
+impl FromStr for ScriptType {
+    type Err = bdk::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bip49" => Ok(ScriptType::Bip49),
+            "bip84" => Ok(ScriptType::Bip84),
+            "bip86" => Ok(ScriptType::Bip86),
+            _ => Err(bdk::Error::Generic(format!("Unknown script type {}", s))),
+        }
+    }
+}
+
+/// What a signing wallet is rebuilt from when it is reloaded by name.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WalletKeys {
+    Mnemonic {
+        mnemonic: String,
+        passphrase: Option<String>,
+        script_type: ScriptType,
+    },
+    /// Descriptors holding private keys.
+    Descriptors {
+        descriptor: String,
+        change_descriptor: Option<String>,
+    },
+}
+
+impl WalletKeys {
+    /// Reads a keys file, which holds only the mnemonic for a default BIP84 wallet.
+    pub fn parse(keys: &str) -> Result<Self, bdk::Error> {
+        match serde_json::from_str(keys) {
+            Ok(keys) => Ok(keys),
+            Err(_) => Ok(WalletKeys::Mnemonic {
+                mnemonic: keys.trim().to_string(),
+                passphrase: None,
+                script_type: ScriptType::Bip84,
+            }),
+        }
+    }
+
+    /// The receive and change descriptors, with their private keys.
+    pub fn descriptors(&self, network: Network) -> Result<(String, Option<String>), bdk::Error> {
+        match self {
+            WalletKeys::Mnemonic {
+                mnemonic,
+                passphrase,
+                script_type,
+            } => {
+                let (descriptor, change_descriptor) =
+                    generate_descriptors(mnemonic, passphrase.clone(), *script_type, network)?;
+                Ok((descriptor, Some(change_descriptor)))
+            }
+            WalletKeys::Descriptors {
+                descriptor,
+                change_descriptor,
+            } => Ok((descriptor.clone(), change_descriptor.clone())),
+        }
+    }
+}
+
+/// Derives the account level key at `m/purpose'/coin_type'/0'` and returns the receive and change
+/// descriptors, including private keys and key origin.
+pub fn generate_descriptors(
+    mnemonic: &str,
+    passphrase: Option<String>,
+    script_type: ScriptType,
+    network: Network,
+) -> Result<(String, String), bdk::Error> {
+    let secp = Secp256k1::new();
+    let mnemonic = Mnemonic::from_str(mnemonic).map_err(|e| bdk::Error::Generic(e.to_string()))?;
+    let xkey: ExtendedKey<Segwitv0> = (mnemonic, passphrase).into_extended_key()?;
+    let xprv: ExtendedPrivKey = xkey
+        .into_xprv(network)
+        .ok_or_else(|| bdk::Error::Generic("Failed to derive master key".to_string()))?;
+
+    let coin_type = match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    let account_path = format!("{}h/{}h/0h", script_type.purpose(), coin_type);
+    let deriv_path = DerivationPath::from_str(&format!("m/{}", account_path))?;
+    let account_xprv = xprv.derive_priv(&secp, &deriv_path)?;
+    let origin = format!("[{}/{}]", xprv.fingerprint(&secp), account_path);
+
+    let descriptor = script_type.wrap(&format!("{}{}/0/*", origin, account_xprv));
+    let change_descriptor = script_type.wrap(&format!("{}{}/1/*", origin, account_xprv));
+    Ok((descriptor, change_descriptor))
+}
+
+/// Returns `wsh(sortedmulti(..))` receive and change descriptors for account-level extended keys
+/// of every cosigner. Keys may carry a key origin, e.g. `[d34db33f/48h/1h/0h/2h]tpub..`.
+pub fn multisig_descriptors(threshold: usize, keys: &[String]) -> (String, String) {
+    let keychain = |index: u32| {
+        keys.iter()
+            .map(|key| format!("{}/{}/*", key, index))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    (
+        format!("wsh(sortedmulti({},{}))", threshold, keychain(0)),
+        format!("wsh(sortedmulti({},{}))", threshold, keychain(1)),
+    )
+}
+
+/// Returns the receive and change `wpkh` descriptors for an account-level xpub/tpub.
+pub fn xpub_descriptors(xpub: &str) -> Result<(String, String), bdk::Error> {
+    let xpub = ExtendedPubKey::from_str(xpub)?;
+    Ok((format!("wpkh({}/0/*)", xpub), format!("wpkh({}/1/*)", xpub)))
+}
+
+/// `descriptor` with every private key replaced by its public key, which is all a wallet needs to
+/// be reloaded watch-only. The wallet name derived from it stays the same.
+pub fn public_descriptor(descriptor: &str, network: Network) -> Result<String, bdk::Error> {
+    let (descriptor, _) = descriptor.into_wallet_descriptor(&Secp256k1::new(), network)?;
+    Ok(descriptor.to_string())
+}
+
+/// Whether `descriptor` holds any private key.
+pub fn has_private_keys(descriptor: &str, network: Network) -> Result<bool, bdk::Error> {
+    let (_, keys) = descriptor.into_wallet_descriptor(&Secp256k1::new(), network)?;
+    Ok(!keys.is_empty())
+}
+
+fn keys_path(dir: &Path, wallet_name: &str) -> PathBuf {
+    dir.join(wallet_name)
+}
+
+fn watch_only_path(dir: &Path, wallet_name: &str) -> PathBuf {
+    dir.join(format!("{}.descriptor", wallet_name))
+}
+
+fn io_error(e: std::io::Error) -> bdk::Error {
+    bdk::Error::Generic(e.to_string())
+}
+
+/// Persists the keys of a signing wallet in `dir`.
+pub fn store_keys(dir: &Path, wallet_name: &str, keys: &WalletKeys) -> Result<(), bdk::Error> {
+    fs::create_dir_all(dir).map_err(io_error)?;
+    let keys = serde_json::to_string(keys).map_err(|e| bdk::Error::Generic(e.to_string()))?;
+    fs::write(keys_path(dir, wallet_name), keys).map_err(io_error)
+}
+
+/// Persists what `wallet_name` is reloaded from in `dir`: the descriptors themselves when they
+/// hold private keys, so the wallet can still sign, their public part for a watch-only import.
+pub fn store_descriptors(
+    dir: &Path,
+    wallet_name: &str,
+    descriptor: &str,
+    change_descriptor: Option<&str>,
+    network: Network,
+) -> Result<(), bdk::Error> {
+    let mut private = has_private_keys(descriptor, network)?;
+    if let Some(change_descriptor) = change_descriptor {
+        private |= has_private_keys(change_descriptor, network)?;
+    }
+    if private {
+        let keys = WalletKeys::Descriptors {
+            descriptor: descriptor.to_string(),
+            change_descriptor: change_descriptor.map(str::to_string),
+        };
+        return store_keys(dir, wallet_name, &keys);
+    }
+    fs::create_dir_all(dir).map_err(io_error)?;
+    let mut descriptors = public_descriptor(descriptor, network)?;
+    if let Some(change_descriptor) = change_descriptor {
+        descriptors.push('\n');
+        descriptors.push_str(&public_descriptor(change_descriptor, network)?);
+    }
+    fs::write(watch_only_path(dir, wallet_name), descriptors).map_err(io_error)
+}
+
+/// The descriptors `wallet_name` is reloaded with from `dir`, with private keys unless it was
+/// imported watch-only.
+pub fn stored_descriptors(
+    dir: &Path,
+    wallet_name: &str,
+    network: Network,
+) -> Result<(String, Option<String>), bdk::Error> {
+    if let Ok(keys) = fs::read_to_string(keys_path(dir, wallet_name)) {
+        return WalletKeys::parse(&keys)?.descriptors(network);
+    }
+    match fs::read_to_string(watch_only_path(dir, wallet_name)) {
+        Ok(descriptors) => {
+            let mut lines = descriptors.lines();
+            let descriptor = lines.next().unwrap_or_default().to_string();
+            let change_descriptor = lines.next().map(|d| d.to_string());
+            Ok((descriptor, change_descriptor))
+        }
+        Err(_) => Err(bdk::Error::Generic("Wallet is not found".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::template::Bip84;
+    use bdk::wallet::wallet_name_from_descriptor;
+
+    /// A fresh directory for one test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "wallet-descriptors-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const TPUB: &str = "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq";
+    const MNEMONIC: &str =
+        "winner maid tower wrong rebuild list net amused okay turtle shrimp swallow";
+
+    #[test]
+    fn watch_only_descriptors_from_xpub() {
+        let (descriptor, change_descriptor) = xpub_descriptors(TPUB).unwrap();
+        assert_eq!(descriptor, format!("wpkh({}/0/*)", TPUB));
+        assert_eq!(change_descriptor, format!("wpkh({}/1/*)", TPUB));
+        assert!(wallet_name_from_descriptor(
+            descriptor.as_str(),
+            Some(change_descriptor.as_str()),
+            Network::Regtest,
+            &Secp256k1::new(),
+        )
+        .is_ok());
+        assert!(xpub_descriptors("not an xpub").is_err());
+    }
+
+    #[test]
+    fn bip84_descriptors_match_the_template() {
+        // lnode builds its default wallet from the template, restoring it from the mnemonic must
+        // land on the same wallet name and database.
+        let secp = Secp256k1::new();
+        let xkey: ExtendedKey = Mnemonic::from_str(MNEMONIC)
+            .unwrap()
+            .into_extended_key()
+            .unwrap();
+        let xprv = xkey.into_xprv(Network::Regtest).unwrap();
+        let (descriptor, change_descriptor) =
+            generate_descriptors(MNEMONIC, None, ScriptType::Bip84, Network::Regtest).unwrap();
+        assert_eq!(
+            wallet_name_from_descriptor(
+                descriptor.as_str(),
+                Some(change_descriptor.as_str()),
+                Network::Regtest,
+                &secp,
+            )
+            .unwrap(),
+            wallet_name_from_descriptor(
+                Bip84(xprv, bdk::KeychainKind::External),
+                Some(Bip84(xprv, bdk::KeychainKind::Internal)),
+                Network::Regtest,
+                &secp,
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn script_types_and_passphrase_change_the_descriptors() {
+        let secp = Secp256k1::new();
+        let (taproot, _) =
+            generate_descriptors(MNEMONIC, None, ScriptType::Bip86, Network::Regtest).unwrap();
+        assert!(taproot.starts_with("tr(") && taproot.contains("/86h/1h/0h]"));
+        assert!(
+            wallet_name_from_descriptor(taproot.as_str(), None, Network::Regtest, &secp).is_ok()
+        );
+
+        let (nested, _) =
+            generate_descriptors(MNEMONIC, None, ScriptType::Bip49, Network::Regtest).unwrap();
+        assert!(nested.starts_with("sh(wpkh("));
+
+        let (descriptor, _) =
+            generate_descriptors(MNEMONIC, None, ScriptType::Bip84, Network::Regtest).unwrap();
+        let (with_passphrase, _) = generate_descriptors(
+            MNEMONIC,
+            Some("hunter2".to_string()),
+            ScriptType::Bip84,
+            Network::Regtest,
+        )
+        .unwrap();
+        assert_ne!(with_passphrase, descriptor);
+        assert!(
+            generate_descriptors("not a mnemonic", None, ScriptType::Bip84, Network::Regtest)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn script_types_parse() {
+        assert_eq!(ScriptType::from_str("bip86").unwrap(), ScriptType::Bip86);
+        assert!(ScriptType::from_str("bip44").is_err());
+    }
+
+    #[test]
+    fn sortedmulti_descriptors() {
+        let tpub2 = "tpubD6NzVbkrYhZ4WQdzxL7NmJN7b85ePo4p6RSj9QQHF7te2RR9iUeVSGgnGkoUsB9LBRosgvNbjRv9bcsJgzgBd7QKuxDm23ZewkTRzNSLEDr";
+        let keys = vec![TPUB.to_string(), tpub2.to_string()];
+        let (descriptor, change_descriptor) = multisig_descriptors(2, &keys);
+        assert_eq!(
+            descriptor,
+            format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", TPUB, tpub2)
+        );
+        assert!(wallet_name_from_descriptor(
+            descriptor.as_str(),
+            Some(change_descriptor.as_str()),
+            Network::Regtest,
+            &Secp256k1::new(),
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn public_descriptors_keep_no_private_keys_and_the_wallet_name() {
+        let secp = Secp256k1::new();
+        let (descriptor, change_descriptor) =
+            generate_descriptors(MNEMONIC, None, ScriptType::Bip84, Network::Regtest).unwrap();
+        assert!(descriptor.contains("tprv"));
+        let public = public_descriptor(&descriptor, Network::Regtest).unwrap();
+        let public_change = public_descriptor(&change_descriptor, Network::Regtest).unwrap();
+        assert!(!public.contains("tprv") && public.contains("tpub"));
+        assert_eq!(
+            wallet_name_from_descriptor(
+                public.as_str(),
+                Some(public_change.as_str()),
+                Network::Regtest,
+                &secp,
+            )
+            .unwrap(),
+            wallet_name_from_descriptor(
+                descriptor.as_str(),
+                Some(change_descriptor.as_str()),
+                Network::Regtest,
+                &secp,
+            )
+            .unwrap()
+        );
+        // Already public descriptors come back unchanged, apart from the checksum.
+        let (watch_only, _) = xpub_descriptors(TPUB).unwrap();
+        assert!(public_descriptor(&watch_only, Network::Regtest)
+            .unwrap()
+            .starts_with(&watch_only));
+        assert!(public_descriptor("wpkh(nonsense)", Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn restored_wallets_reload_with_their_private_keys() {
+        let dir = TempDir::new("restored");
+        let keys = WalletKeys::Mnemonic {
+            mnemonic: MNEMONIC.to_string(),
+            passphrase: Some("hunter2".to_string()),
+            script_type: ScriptType::Bip86,
+        };
+        store_keys(&dir.0, "restored", &keys).unwrap();
+        let (descriptor, change_descriptor) =
+            stored_descriptors(&dir.0, "restored", Network::Regtest).unwrap();
+        assert!(descriptor.starts_with("tr(") && descriptor.contains("tprv"));
+        assert_eq!(
+            (descriptor, change_descriptor),
+            keys.descriptors(Network::Regtest).unwrap()
+        );
+    }
+
+    #[test]
+    fn a_watch_only_import_does_not_shadow_the_keys() {
+        let dir = TempDir::new("shadowed");
+        // The default wallet writes its bare mnemonic.
+        fs::create_dir_all(&dir.0).unwrap();
+        fs::write(dir.0.join("wallet"), MNEMONIC).unwrap();
+        let (descriptor, change_descriptor) =
+            generate_descriptors(MNEMONIC, None, ScriptType::Bip84, Network::Regtest).unwrap();
+        let public = public_descriptor(&descriptor, Network::Regtest).unwrap();
+        store_descriptors(&dir.0, "wallet", &public, None, Network::Regtest).unwrap();
+        assert_eq!(
+            stored_descriptors(&dir.0, "wallet", Network::Regtest).unwrap(),
+            (descriptor, Some(change_descriptor))
+        );
+    }
+
+    #[test]
+    fn imported_descriptors_reload_as_imported() {
+        let dir = TempDir::new("imported");
+        let (watch_only, watch_only_change) = xpub_descriptors(TPUB).unwrap();
+        store_descriptors(
+            &dir.0,
+            "watch-only",
+            &watch_only,
+            Some(&watch_only_change),
+            Network::Regtest,
+        )
+        .unwrap();
+        let (descriptor, change_descriptor) =
+            stored_descriptors(&dir.0, "watch-only", Network::Regtest).unwrap();
+        assert!(descriptor.starts_with(&watch_only));
+        assert!(change_descriptor.unwrap().starts_with(&watch_only_change));
+        assert!(!dir.0.join("watch-only").exists());
+
+        let (private, private_change) =
+            generate_descriptors(MNEMONIC, None, ScriptType::Bip49, Network::Regtest).unwrap();
+        store_descriptors(
+            &dir.0,
+            "signing",
+            &private,
+            Some(&private_change),
+            Network::Regtest,
+        )
+        .unwrap();
+        assert_eq!(
+            stored_descriptors(&dir.0, "signing", Network::Regtest).unwrap(),
+            (private, Some(private_change))
+        );
+        assert!(!dir.0.join("signing.descriptor").exists());
+    }
+
+    #[test]
+    fn unknown_wallets_are_not_found() {
+        let dir = TempDir::new("unknown");
+        assert!(stored_descriptors(&dir.0, "nothing", Network::Regtest).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use bdk::bitcoin::OutPoint;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The record types defined by BIP329.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+impl FromStr for LabelType {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tx" => Ok(LabelType::Tx),
+            "addr" => Ok(LabelType::Addr),
+            "pubkey" => Ok(LabelType::Pubkey),
+            "input" => Ok(LabelType::Input),
+            "output" => Ok(LabelType::Output),
+            "xpub" => Ok(LabelType::Xpub),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown label type {}", s),
+            )),
+        }
+    }
+}
+
+/// A single BIP329 record. Frozen coins are `output` records with `spendable` set to false, so
+/// exporting the labels also carries the freeze state to other wallets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub label_type: LabelType,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+/// Labels of a single wallet, persisted as BIP329 JSON lines.
+pub struct LabelStore {
+    path: PathBuf,
+    labels: Vec<Label>,
+}
+
+impl LabelStore {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut store = LabelStore {
+            path: path.to_path_buf(),
+            labels: Vec::new(),
+        };
+        if path.exists() {
+            store.import(&fs::read_to_string(path)?)?;
+        }
+        Ok(store)
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    pub fn get(&self, label_type: LabelType, reference: &str) -> Option<&Label> {
+        self.labels
+            .iter()
+            .find(|l| l.label_type == label_type && l.reference == reference)
+    }
+
+    fn entry(&mut self, label_type: LabelType, reference: &str) -> &mut Label {
+        match self
+            .labels
+            .iter()
+            .position(|l| l.label_type == label_type && l.reference == reference)
+        {
+            Some(index) => &mut self.labels[index],
+            None => {
+                self.labels.push(Label {
+                    label_type,
+                    reference: reference.to_string(),
+                    label: None,
+                    origin: None,
+                    spendable: None,
+                });
+                self.labels.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub fn set_label(
+        &mut self,
+        label_type: LabelType,
+        reference: &str,
+        label: String,
+    ) -> io::Result<()> {
+        self.entry(label_type, reference).label = Some(label);
+        self.persist()
+    }
+
+    pub fn set_spendable(&mut self, outpoint: &OutPoint, spendable: bool) -> io::Result<()> {
+        self.entry(LabelType::Output, &outpoint.to_string())
+            .spendable = Some(spendable);
+        self.persist()
+    }
+
+    pub fn is_frozen(&self, outpoint: &OutPoint) -> bool {
+        self.get(LabelType::Output, &outpoint.to_string())
+            .map(|l| l.spendable == Some(false))
+            .unwrap_or(false)
+    }
+
+    pub fn frozen_outpoints(&self) -> Vec<OutPoint> {
+        self.labels
+            .iter()
+            .filter(|l| l.label_type == LabelType::Output && l.spendable == Some(false))
+            .filter_map(|l| l.reference.parse().ok())
+            .collect()
+    }
+
+    /// Merges BIP329 JSON lines into the store, later records overriding earlier ones. Returns
+    /// the number of records read.
+    pub fn import(&mut self, jsonl: &str) -> io::Result<usize> {
+        let mut count = 0;
+        for line in jsonl.lines().filter(|l| !l.trim().is_empty()) {
+            let record: Label = serde_json::from_str(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let entry = self.entry(record.label_type, &record.reference);
+            if record.label.is_some() {
+                entry.label = record.label;
+            }
+            if record.origin.is_some() {
+                entry.origin = record.origin;
+            }
+            if record.spendable.is_some() {
+                entry.spendable = record.spendable;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn export(&self) -> String {
+        let mut jsonl = String::new();
+        for label in self.labels.iter() {
+            jsonl.push_str(&serde_json::to_string(label).unwrap());
+            jsonl.push('\n');
+        }
+        jsonl
+    }
+
+    pub fn persist(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, self.export())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn bip329_roundtrip_and_freeze() {
+        let dir = TempDir::new("labels");
+        let path = dir.join("labels.jsonl");
+        let outpoint: OutPoint =
+            "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1"
+                .parse()
+                .unwrap();
+
+        let mut store = LabelStore::load(&path).unwrap();
+        store
+            .set_label(
+                LabelType::Output,
+                &outpoint.to_string(),
+                "cold storage".to_string(),
+            )
+            .unwrap();
+        store.set_spendable(&outpoint, false).unwrap();
+        assert!(store.is_frozen(&outpoint));
+        assert_eq!(store.frozen_outpoints(), vec![outpoint]);
+
+        let reloaded = LabelStore::load(&path).unwrap();
+        assert_eq!(reloaded.labels(), store.labels());
+        assert_eq!(
+            reloaded.export(),
+            format!(
+                "{{\"type\":\"output\",\"ref\":\"{}\",\"label\":\"cold storage\",\"spendable\":false}}\n",
+                outpoint
+            )
+        );
+
+        let mut imported = LabelStore::load(&dir.join("imported.jsonl")).unwrap();
+        let count = imported
+            .import(&format!(
+                "{}{{\"type\":\"tx\",\"ref\":\"{}\",\"label\":\"rent\"}}\n",
+                reloaded.export(),
+                outpoint.txid
+            ))
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(imported.is_frozen(&outpoint));
+        assert_eq!(
+            imported
+                .get(LabelType::Tx, &outpoint.txid.to_string())
+                .and_then(|l| l.label.clone()),
+            Some("rent".to_string())
+        );
+        assert!(imported.import("{\"type\":\"bogus\"}").is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! The transaction history of the wallets lnode and soul load, with their BIP329 labels and the
+//! lightning channels their transactions belong to.
+
+use bdk::bitcoin::{OutPoint, Transaction, Txid};
+use bdk::TransactionDetails;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+pub mod labels;
+#[cfg(test)]
+mod test_utils;
+
+use labels::{LabelStore, LabelType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxDirection {
+    Incoming,
+    Outgoing,
+    /// Every output went back to the wallet, only the fee was spent.
+    SelfTransfer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxCategory {
+    Onchain,
+    ChannelFunding,
+    CooperativeClose,
+    ForceClose,
+    ForceCloseSweep,
+}
+
+/// Outpoints the lightning node knows about, used to tell channel transactions apart from
+/// regular on-chain payments.
+#[derive(Debug, Default)]
+pub struct LightningRecords {
+    /// Funding outpoints of every channel we still have a monitor for.
+    pub funding_outpoints: HashSet<OutPoint>,
+    /// Outputs handed to us by LDK after a channel closed, which the sweeper claims.
+    pub sweepable_outpoints: HashSet<OutPoint>,
+}
+
+impl LightningRecords {
+    pub fn categorise(&self, txid: &Txid, tx: Option<&Transaction>) -> TxCategory {
+        if self.funding_outpoints.iter().any(|o| o.txid == *txid) {
+            return TxCategory::ChannelFunding;
+        }
+        let tx = match tx {
+            Some(tx) => tx,
+            None => return TxCategory::Onchain,
+        };
+        let inputs = || tx.input.iter().map(|i| &i.previous_output);
+        if inputs().any(|o| self.sweepable_outpoints.contains(o)) {
+            TxCategory::ForceCloseSweep
+        } else if inputs().any(|o| self.funding_outpoints.contains(o)) {
+            // Commitment transactions encode the obscured commitment number in the locktime,
+            // with the upper byte set to 0x20. A mutual close never does.
+            if tx.lock_time.0 >> 24 == 0x20 {
+                TxCategory::ForceClose
+            } else {
+                TxCategory::CooperativeClose
+            }
+        } else {
+            TxCategory::Onchain
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    /// Unix timestamp, inclusive.
+    pub from: Option<u64>,
+    /// Unix timestamp, exclusive.
+    pub to: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub txid: Txid,
+    pub direction: TxDirection,
+    /// Net amount that left or entered the wallet, excluding the fee.
+    pub amount: u64,
+    pub fee: Option<u64>,
+    pub confirmation_height: Option<u32>,
+    pub confirmation_time: Option<u64>,
+    pub label: Option<String>,
+    pub category: TxCategory,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub total: usize,
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl HistoryEntry {
+    pub fn new(details: &TransactionDetails, category: TxCategory, label: Option<String>) -> Self {
+        let fee = details.fee.unwrap_or(0);
+        let (direction, amount) = if details.received >= details.sent {
+            (TxDirection::Incoming, details.received - details.sent)
+        } else if details.sent - details.received == fee {
+            (TxDirection::SelfTransfer, 0)
+        } else {
+            (TxDirection::Outgoing, details.sent - details.received - fee)
+        };
+        HistoryEntry {
+            txid: details.txid,
+            direction,
+            amount,
+            fee: details.fee,
+            confirmation_height: details.confirmation_time.as_ref().map(|t| t.height),
+            confirmation_time: details.confirmation_time.as_ref().map(|t| t.timestamp),
+            label,
+            category,
+        }
+    }
+}
+
+/// Orders `entries` newest first, with unconfirmed transactions on top, drops the ones outside
+/// the query's date range and returns the requested page.
+pub fn paginate(mut entries: Vec<HistoryEntry>, query: &HistoryQuery) -> HistoryPage {
+    entries.retain(|e| match e.confirmation_time {
+        Some(time) => {
+            query.from.is_none_or(|from| time >= from) && query.to.is_none_or(|to| time < to)
+        }
+        // Unconfirmed transactions are happening now, so they only fall outside an open-ended
+        // range.
+        None => query.to.is_none(),
+    });
+    entries.sort_by(|a, b| {
+        b.confirmation_time
+            .unwrap_or(u64::MAX)
+            .cmp(&a.confirmation_time.unwrap_or(u64::MAX))
+    });
+    let total = entries.len();
+    let entries = entries
+        .into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect();
+    HistoryPage { total, entries }
+}
+
+/// Builds a page of history from a wallet's transactions, labelled from `labels` and categorised
+/// with `records`.
+pub fn history(
+    txs: &[TransactionDetails],
+    labels: &LabelStore,
+    records: &LightningRecords,
+    query: &HistoryQuery,
+) -> HistoryPage {
+    let entries = txs
+        .iter()
+        .map(|details| {
+            HistoryEntry::new(
+                details,
+                records.categorise(&details.txid, details.transaction.as_ref()),
+                labels
+                    .get(LabelType::Tx, &details.txid.to_string())
+                    .and_then(|l| l.label.clone()),
+            )
+        })
+        .collect();
+    paginate(entries, query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use bdk::bitcoin::{PackedLockTime, Sequence, TxIn, Witness};
+    use bdk::BlockTime;
+    use std::str::FromStr;
+
+    fn details(txid: &str, received: u64, sent: u64, time: Option<u64>) -> TransactionDetails {
+        TransactionDetails {
+            transaction: None,
+            txid: Txid::from_str(txid).unwrap(),
+            received,
+            sent,
+            fee: Some(200),
+            confirmation_time: time.map(|timestamp| BlockTime {
+                height: timestamp as u32 / 600,
+                timestamp,
+            }),
+        }
+    }
+
+    fn spending(outpoint: OutPoint, lock_time: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(lock_time),
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Default::default(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn categorise_and_paginate_history() {
+        let funding = OutPoint::from_str(
+            "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0",
+        )
+        .unwrap();
+        let sweepable = OutPoint::from_str(
+            "a91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1",
+        )
+        .unwrap();
+        let records = LightningRecords {
+            funding_outpoints: vec![funding].into_iter().collect(),
+            sweepable_outpoints: vec![sweepable].into_iter().collect(),
+        };
+        let other =
+            Txid::from_str("b91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd")
+                .unwrap();
+        assert_eq!(
+            records.categorise(&funding.txid, None),
+            TxCategory::ChannelFunding
+        );
+        assert_eq!(
+            records.categorise(&other, Some(&spending(funding, 0))),
+            TxCategory::CooperativeClose
+        );
+        assert_eq!(
+            records.categorise(&other, Some(&spending(funding, 0x2000_0001))),
+            TxCategory::ForceClose
+        );
+        assert_eq!(
+            records.categorise(&other, Some(&spending(sweepable, 0))),
+            TxCategory::ForceCloseSweep
+        );
+        assert_eq!(records.categorise(&other, None), TxCategory::Onchain);
+
+        let outgoing = details(
+            "c91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd",
+            1_000,
+            11_200,
+            Some(1_000),
+        );
+        let entry = HistoryEntry::new(&outgoing, TxCategory::Onchain, None);
+        assert_eq!(entry.direction, TxDirection::Outgoing);
+        assert_eq!(entry.amount, 10_000);
+        let consolidation = details(
+            "d91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd",
+            9_800,
+            10_000,
+            Some(3_000),
+        );
+        assert_eq!(
+            HistoryEntry::new(&consolidation, TxCategory::Onchain, None).direction,
+            TxDirection::SelfTransfer
+        );
+        let unconfirmed = details(
+            "e91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd",
+            5_000,
+            0,
+            None,
+        );
+
+        let entries = || {
+            vec![&outgoing, &consolidation, &unconfirmed]
+                .into_iter()
+                .map(|d| HistoryEntry::new(d, TxCategory::Onchain, None))
+                .collect::<Vec<_>>()
+        };
+        let page = paginate(
+            entries(),
+            &HistoryQuery {
+                offset: Some(1),
+                limit: Some(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(page.total, 3);
+        assert_eq!(page.entries[0].txid, consolidation.txid);
+        let page = paginate(
+            entries(),
+            &HistoryQuery {
+                from: Some(500),
+                to: Some(2_000),
+                ..Default::default()
+            },
+        );
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].txid, outgoing.txid);
+    }
+
+    #[test]
+    fn history_is_labelled() {
+        let labelled = details(
+            "c91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd",
+            5_000,
+            0,
+            Some(1_000),
+        );
+        let dir = TempDir::new("history");
+        let mut labels = LabelStore::load(&dir.join("labels.jsonl")).unwrap();
+        labels
+            .import(&format!(
+                "{{\"type\":\"tx\",\"ref\":\"{}\",\"label\":\"salary\"}}",
+                labelled.txid
+            ))
+            .unwrap();
+        let page = history(
+            &[labelled],
+            &labels,
+            &LightningRecords::default(),
+            &HistoryQuery::default(),
+        );
+        assert_eq!(page.entries[0].label.as_deref(), Some("salary"));
+        assert_eq!(page.entries[0].category, TxCategory::Onchain);
+    }
+}
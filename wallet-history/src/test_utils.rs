@@ -0,0 +1,28 @@
+//! Helpers shared by the tests.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A fresh directory for a test, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` keeps tests running at once apart.
+    pub fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("wallet-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}